@@ -299,6 +299,7 @@ pub fn get_parser(state: &SystemState) -> Command<Message> {
             "copy_value",
             "pause_simulation",
             "unpause_simulation",
+            "reset_simulation",
             "undo",
             "redo",
             #[cfg(not(target_arch = "wasm32"))]
@@ -773,6 +774,7 @@ pub fn get_parser(state: &SystemState) -> Command<Message> {
                 "viewport_remove" => Some(Command::Terminal(Message::RemoveViewport)),
                 "pause_simulation" => Some(Command::Terminal(Message::PauseSimulation)),
                 "unpause_simulation" => Some(Command::Terminal(Message::UnpauseSimulation)),
+                "reset_simulation" => Some(Command::Terminal(Message::ResetSimulation)),
                 "undo" => Some(Command::Terminal(Message::Undo(1))),
                 "redo" => Some(Command::Terminal(Message::Redo(1))),
                 "wcp_server_start" => Some(Command::Terminal(Message::StartWcpServer {
@@ -1,15 +1,43 @@
-use serde::Serialize;
+use serde::{ser::SerializeSeq as _, Deserialize, Serialize, Serializer};
 
 use super::timestamp::CxxrtlTimestamp;
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 #[allow(non_camel_case_types, unused)]
-pub(crate) enum Diagnostic {
+pub enum Diagnostic {
     assert,
     assume,
     print,
 }
 
+/// Identifies an item in commands which operate on lists of items
+#[derive(Debug, Clone)]
+pub(crate) enum ItemDesignator {
+    /// A whole item
+    Item(String),
+    /// The rows `first` to `last` (inclusive) of a memory
+    Rows { name: String, first: u32, last: u32 },
+}
+
+impl Serialize for ItemDesignator {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            ItemDesignator::Item(name) => {
+                let mut seq = serializer.serialize_seq(Some(1))?;
+                seq.serialize_element(name)?;
+                seq.end()
+            }
+            ItemDesignator::Rows { name, first, last } => {
+                let mut seq = serializer.serialize_seq(Some(3))?;
+                seq.serialize_element(name)?;
+                seq.serialize_element(first)?;
+                seq.serialize_element(last)?;
+                seq.end()
+            }
+        }
+    }
+}
+
 #[derive(Serialize, Debug)]
 #[serde(tag = "command")]
 #[allow(non_camel_case_types)]
@@ -30,7 +58,7 @@ pub(crate) enum CxxrtlCommand {
     },
     reference_items {
         reference: String,
-        items: Vec<Vec<String>>,
+        items: Vec<ItemDesignator>,
     },
     run_simulation {
         until_time: Option<CxxrtlTimestamp>,
@@ -38,4 +66,18 @@ pub(crate) enum CxxrtlCommand {
         sample_item_values: bool,
     },
     pause_simulation,
+    /// Restarts the simulation from time zero. Not all agents support this, in which case
+    /// an error is returned
+    reset_simulation,
+    /// Moves the simulation back to `to_time`. Only available if the agent lists it among
+    /// its commands in the greeting
+    rewind_simulation {
+        to_time: CxxrtlTimestamp,
+    },
+    /// Overrides the current values of `items` until the simulation is resumed
+    set_item_values {
+        items: Vec<ItemDesignator>,
+        item_values: String,
+        item_values_encoding: &'static str,
+    },
 }
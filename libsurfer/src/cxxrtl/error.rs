@@ -0,0 +1,86 @@
+use derive_more::Display;
+
+/// Errors which occur while setting up or talking to a cxxrtl agent
+#[derive(Debug, Display)]
+pub enum CxxrtlError {
+    /// The agent could not be reached at `address`
+    #[display("Failed to connect to {address}: {source}")]
+    Connect {
+        address: String,
+        source: std::io::Error,
+    },
+    /// The connection was established, but the greeting could not be exchanged
+    #[display("cxxrtl handshake failed: {_0}")]
+    Handshake(String),
+    /// Reading from or writing to an established connection failed
+    #[display("cxxrtl connection failed: {_0}")]
+    Io(std::io::Error),
+    /// The agent sent a message which does not follow the protocol
+    #[display("cxxrtl protocol error: {_0}")]
+    Protocol(String),
+}
+
+impl std::error::Error for CxxrtlError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CxxrtlError::Connect { source, .. } => Some(source),
+            CxxrtlError::Io(source) => Some(source),
+            CxxrtlError::Handshake(_) | CxxrtlError::Protocol(_) => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for CxxrtlError {
+    fn from(e: std::io::Error) -> Self {
+        CxxrtlError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for CxxrtlError {
+    fn from(e: serde_json::Error) -> Self {
+        CxxrtlError::Protocol(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::error::Error;
+
+    use super::*;
+
+    #[test]
+    fn io_failures_keep_their_source() {
+        let e = CxxrtlError::from(std::io::Error::new(
+            std::io::ErrorKind::BrokenPipe,
+            "pipe closed",
+        ));
+        assert!(matches!(e, CxxrtlError::Io(_)));
+        assert_eq!(e.to_string(), "cxxrtl connection failed: pipe closed");
+        assert_eq!(e.source().unwrap().to_string(), "pipe closed");
+    }
+
+    #[test]
+    fn malformed_messages_are_protocol_errors() {
+        let e = CxxrtlError::from(serde_json::from_str::<serde_json::Value>("{").unwrap_err());
+        assert!(matches!(e, CxxrtlError::Protocol(_)));
+        assert!(e.source().is_none());
+    }
+
+    #[test]
+    fn connect_errors_mention_the_address() {
+        let e = CxxrtlError::Connect {
+            address: "localhost:6618".to_string(),
+            source: std::io::ErrorKind::ConnectionRefused.into(),
+        };
+        assert!(e
+            .to_string()
+            .starts_with("Failed to connect to localhost:6618"));
+        assert!(e.source().is_some());
+    }
+
+    #[test]
+    fn errors_convert_into_reports() {
+        let report = color_eyre::Report::from(CxxrtlError::Handshake("no greeting".to_string()));
+        assert_eq!(format!("{report}"), "cxxrtl handshake failed: no greeting");
+    }
+}
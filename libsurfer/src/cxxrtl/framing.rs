@@ -0,0 +1,187 @@
+//! Splitting of the byte stream exchanged with cxxrtl into messages. Every message is
+//! terminated by a null byte, independently of how the transport chunks the stream.
+//!
+//! The protocol defines no other framing, so there is nothing to negotiate with the agent.
+//! Messages are still buffered whole before they are parsed. Large messages such as
+//! `query_interval` responses arrive over many reads, so the decoder only scans newly
+//! received bytes for the terminator and hands over the buffer it collected a message in
+//! instead of copying it.
+use color_eyre::{eyre::Context, Result};
+
+/// Collects bytes received from cxxrtl until complete messages are available
+#[derive(Default)]
+pub struct FrameDecoder {
+    buf: Vec<u8>,
+}
+
+fn find_terminator(bytes: &[u8]) -> Option<usize> {
+    bytes.iter().position(|c| *c == b'\0')
+}
+
+impl FrameDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends `bytes` to the received data and returns the messages which were completed
+    /// by them
+    pub fn push(&mut self, bytes: &[u8]) -> Result<Vec<String>> {
+        // Everything received before has already been searched for a terminator
+        let search_from = self.buf.len();
+        self.buf.extend_from_slice(bytes);
+
+        let mut new_messages = vec![];
+        if let Some(offset) = find_terminator(&self.buf[search_from..]) {
+            // The first message may have been collected over many pushes, so it takes over
+            // the buffer rather than being copied
+            let rest = self.buf.split_off(search_from + offset + 1);
+            let mut first = std::mem::replace(&mut self.buf, rest);
+            // The null byte should not be part of this or the next message
+            first.pop();
+            new_messages.push(first);
+
+            // Further messages are all contained in `bytes`
+            let mut start = 0;
+            while let Some(offset) = find_terminator(&self.buf[start..]) {
+                new_messages.push(self.buf[start..start + offset].to_vec());
+                start += offset + 1;
+            }
+            self.buf.drain(..start);
+        }
+
+        new_messages
+            .into_iter()
+            .map(|message| {
+                String::from_utf8(message).context("Got non-utf8 characters from cxxrtl")
+            })
+            .collect()
+    }
+}
+
+/// Returns the bytes to send for `message`
+pub fn encode(message: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(message.len() + 1);
+    bytes.extend_from_slice(message.as_bytes());
+    bytes.push(b'\0');
+    bytes
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A message based socket which delivers whatever was sent to it in frames of at
+    /// most `frame_size` bytes, like a WebSocket peer which fragments the stream
+    struct MockSocket {
+        frame_size: usize,
+        pending: Vec<u8>,
+    }
+
+    impl MockSocket {
+        fn new(frame_size: usize) -> Self {
+            Self {
+                frame_size,
+                pending: vec![],
+            }
+        }
+
+        fn send(&mut self, message: &str) {
+            self.pending.extend(encode(message));
+        }
+
+        fn frames(&mut self) -> Vec<Vec<u8>> {
+            let frames = self
+                .pending
+                .chunks(self.frame_size)
+                .map(|chunk| chunk.to_vec())
+                .collect();
+            self.pending.clear();
+            frames
+        }
+    }
+
+    fn receive_all(socket: &mut MockSocket) -> Vec<String> {
+        let mut decoder = FrameDecoder::new();
+        socket
+            .frames()
+            .iter()
+            .flat_map(|frame| decoder.push(frame).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn messages_are_null_terminated() {
+        assert_eq!(
+            encode(r#"{"type":"greeting"}"#),
+            b"{\"type\":\"greeting\"}\0"
+        );
+    }
+
+    #[test]
+    fn messages_split_across_frames_are_reassembled() {
+        let mut socket = MockSocket::new(3);
+        socket.send(r#"{"type":"greeting","version":0}"#);
+        socket.send(r#"{"type":"event"}"#);
+        assert_eq!(
+            receive_all(&mut socket),
+            vec![r#"{"type":"greeting","version":0}"#, r#"{"type":"event"}"#]
+        );
+    }
+
+    #[test]
+    fn multiple_messages_in_one_frame_are_split() {
+        let mut socket = MockSocket::new(1024);
+        socket.send("a");
+        socket.send("");
+        socket.send("b");
+        assert_eq!(receive_all(&mut socket), vec!["a", "", "b"]);
+    }
+
+    #[test]
+    fn incomplete_messages_are_kept_until_terminated() {
+        let mut decoder = FrameDecoder::new();
+        assert!(decoder.push(b"{\"type\"").unwrap().is_empty());
+        assert_eq!(decoder.push(b":1}\0{").unwrap(), vec!["{\"type\":1}"]);
+        assert_eq!(decoder.push(b"}\0").unwrap(), vec!["{}"]);
+    }
+
+    /// A `query_interval` response of about `size` bytes
+    fn large_response(size: usize) -> String {
+        format!(
+            r#"{{"type":"response","command":"query_interval","samples":[{{"time":"0.0","item_values":"{}"}}]}}"#,
+            "A".repeat(size)
+        )
+    }
+
+    #[test]
+    fn multi_megabyte_messages_are_reassembled_from_small_frames() {
+        let response = large_response(8 * 1024 * 1024);
+        let mut socket = MockSocket::new(1024);
+        socket.send(&response);
+        socket.send(r#"{"type":"event"}"#);
+        let received = receive_all(&mut socket);
+        assert_eq!(received.len(), 2);
+        assert!(received[0] == response);
+        assert_eq!(received[1], r#"{"type":"event"}"#);
+    }
+
+    #[test]
+    fn multi_megabyte_messages_are_split_from_one_frame() {
+        let response = large_response(4 * 1024 * 1024);
+        let mut socket = MockSocket::new(usize::MAX);
+        socket.send("a");
+        socket.send(&response);
+        socket.send("b");
+        let received = receive_all(&mut socket);
+        assert_eq!(received.len(), 3);
+        assert_eq!(received[0], "a");
+        assert!(received[1] == response);
+        assert_eq!(received[2], "b");
+    }
+
+    #[test]
+    fn non_utf8_messages_are_rejected() {
+        let mut decoder = FrameDecoder::new();
+        assert!(decoder.push(&[0xff, 0xfe, 0]).is_err());
+    }
+}
@@ -0,0 +1,159 @@
+use num::BigUint;
+
+/// A set of closed time intervals, in femtoseconds. Intervals are kept sorted, and
+/// overlapping or adjacent intervals are merged.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IntervalSet {
+    intervals: Vec<(BigUint, BigUint)>,
+}
+
+impl IntervalSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.intervals.is_empty()
+    }
+
+    pub fn intervals(&self) -> &[(BigUint, BigUint)] {
+        &self.intervals
+    }
+
+    /// The last time covered by the set
+    pub fn end(&self) -> Option<&BigUint> {
+        self.intervals.last().map(|(_, end)| end)
+    }
+
+    /// Adds `[start, end]` to the set, merging it with the intervals it overlaps or
+    /// is adjacent to
+    pub fn insert(&mut self, start: BigUint, end: BigUint) {
+        if start > end {
+            return;
+        }
+        let (mut start, mut end) = (start, end);
+        let mut result = Vec::with_capacity(self.intervals.len() + 1);
+        let mut placed = false;
+        for (s, e) in self.intervals.drain(..) {
+            if &e + 1u32 < start {
+                result.push((s, e));
+            } else if &end + 1u32 < s {
+                if !placed {
+                    result.push((start.clone(), end.clone()));
+                    placed = true;
+                }
+                result.push((s, e));
+            } else {
+                start = start.min(s);
+                end = end.max(e);
+            }
+        }
+        if !placed {
+            result.push((start, end));
+        }
+        self.intervals = result;
+    }
+
+    /// Removes everything after `time` from the set
+    pub fn truncate(&mut self, time: &BigUint) {
+        self.intervals.retain(|(start, _)| start <= time);
+        if let Some((_, end)) = self.intervals.last_mut() {
+            if &*end > time {
+                *end = time.clone();
+            }
+        }
+    }
+
+    /// Returns the parts of `[start, end]` which are not covered by the set
+    pub fn missing(&self, start: &BigUint, end: &BigUint) -> Vec<(BigUint, BigUint)> {
+        let mut gaps = vec![];
+        let mut cursor = start.clone();
+        for (s, e) in &self.intervals {
+            if &cursor > end {
+                break;
+            }
+            if e < &cursor {
+                continue;
+            }
+            if s > &cursor {
+                gaps.push((cursor.clone(), (s - 1u32).min(end.clone())));
+            }
+            cursor = e + 1u32;
+        }
+        if &cursor <= end {
+            gaps.push((cursor, end.clone()));
+        }
+        gaps
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn set(intervals: &[(u32, u32)]) -> IntervalSet {
+        let mut result = IntervalSet::new();
+        for (start, end) in intervals {
+            result.insert(BigUint::from(*start), BigUint::from(*end));
+        }
+        result
+    }
+
+    fn ranges(intervals: &[(BigUint, BigUint)]) -> Vec<(u32, u32)> {
+        intervals
+            .iter()
+            .map(|(s, e)| (s.try_into().unwrap(), e.try_into().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn disjoint_intervals_are_kept_sorted() {
+        assert_eq!(
+            ranges(set(&[(20, 30), (0, 5), (10, 12)]).intervals()),
+            vec![(0, 5), (10, 12), (20, 30)]
+        );
+    }
+
+    #[test]
+    fn overlapping_and_adjacent_intervals_are_merged() {
+        assert_eq!(ranges(set(&[(0, 10), (5, 20)]).intervals()), vec![(0, 20)]);
+        assert_eq!(ranges(set(&[(0, 10), (11, 20)]).intervals()), vec![(0, 20)]);
+        assert_eq!(
+            ranges(set(&[(0, 5), (10, 15), (20, 25), (4, 21)]).intervals()),
+            vec![(0, 25)]
+        );
+    }
+
+    #[test]
+    fn truncating_drops_and_shortens_later_intervals() {
+        let mut covered = set(&[(0, 5), (10, 20), (30, 40)]);
+        covered.truncate(&BigUint::from(15u32));
+        assert_eq!(ranges(covered.intervals()), vec![(0, 5), (10, 15)]);
+        covered.truncate(&BigUint::from(7u32));
+        assert_eq!(ranges(covered.intervals()), vec![(0, 5)]);
+    }
+
+    #[test]
+    fn partially_overlapping_query_only_misses_the_uncovered_part() {
+        let covered = set(&[(0, 100)]);
+        assert_eq!(
+            ranges(&covered.missing(&BigUint::from(50u32), &BigUint::from(150u32))),
+            vec![(101, 150)]
+        );
+        assert!(covered
+            .missing(&BigUint::from(10u32), &BigUint::from(90u32))
+            .is_empty());
+    }
+
+    #[test]
+    fn gaps_between_intervals_are_missing() {
+        let mut covered = set(&[(0, 10), (20, 30), (40, 50)]);
+        let gaps = covered.missing(&BigUint::from(5u32), &BigUint::from(45u32));
+        assert_eq!(ranges(&gaps), vec![(11, 19), (31, 39)]);
+
+        for (start, end) in gaps {
+            covered.insert(start, end);
+        }
+        assert_eq!(ranges(covered.intervals()), vec![(0, 50)]);
+    }
+}
@@ -1,6 +1,4 @@
-use std::{collections::VecDeque, io::Write};
-
-use color_eyre::{eyre::Context, Result};
+use color_eyre::Result;
 use log::{error, info, trace};
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
@@ -8,11 +6,20 @@ use tokio::{
 };
 
 use crate::channels::IngressSender;
+use crate::cxxrtl::framing::{self, FrameDecoder};
+
+/// The default maximum number of queued messages which are written before flushing
+const DEFAULT_BATCH_SIZE: usize = 32;
+/// How many bytes are read from cxxrtl at once. Responses to queries of large designs span
+/// megabytes, so reading them in small pieces is slow.
+const READ_BUFFER_SIZE: usize = 64 * 1024;
 
 pub struct CxxrtlWorker<W, R> {
     write: W,
     read: R,
-    read_buf: VecDeque<u8>,
+    decoder: FrameDecoder,
+    /// The maximum number of messages to write between flushes
+    batch_size: usize,
 
     sc_channel: IngressSender<String>,
     cs_channel: mpsc::Receiver<String>,
@@ -32,20 +39,30 @@ where
         Self {
             write,
             read,
-            read_buf: VecDeque::new(),
+            decoder: FrameDecoder::new(),
+            batch_size: DEFAULT_BATCH_SIZE,
             sc_channel,
             cs_channel,
         }
     }
 
+    /// Sets the maximum number of queued messages which are written before the writer is
+    /// flushed. A batch size of 1 flushes after every message. Connections always use
+    /// [`DEFAULT_BATCH_SIZE`], this is for testing the batching.
+    #[cfg(test)]
+    pub(crate) fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
     pub(crate) async fn start(mut self) {
         info!("cxxrtl worker is up-and-running");
-        let mut buf = [0; 1024];
+        let mut buf = vec![0; READ_BUFFER_SIZE];
         loop {
             tokio::select! {
                 rx = self.cs_channel.recv() => {
                     if let Some(msg) = rx {
-                        if let Err(e) =  self.send_message(msg).await {
+                        if let Err(e) = self.send_messages(msg).await {
                             error!("Failed to send message {e:#?}");
                         }
                     }
@@ -54,7 +71,7 @@ where
                     match count {
                         Ok(count) => {
                             trace!("CXXRTL Read {count} from reader");
-                            match self.process_stream(count, &mut buf).await {
+                            match self.process_stream(&buf[0..count]).await {
                                 Ok(msgs) => {
                                     for msg in msgs {
                                         self.sc_channel.send(msg).await.unwrap();
@@ -75,37 +92,110 @@ where
         }
     }
 
-    async fn process_stream(&mut self, count: usize, buf: &mut [u8; 1024]) -> Result<Vec<String>> {
-        if count != 0 {
-            self.read_buf
-                .write_all(&buf[0..count])
-                .context("Failed to read from cxxrtl tcp socket")?;
+    async fn process_stream(&mut self, bytes: &[u8]) -> Result<Vec<String>> {
+        self.decoder.push(bytes)
+    }
+
+    /// Writes `first` along with any messages that are already queued behind it, up to the
+    /// batch size, and flushes once all of them are written
+    async fn send_messages(&mut self, first: String) -> Result<()> {
+        self.write_message(first).await?;
+        for _ in 1..self.batch_size {
+            let Ok(message) = self.cs_channel.try_recv() else {
+                break;
+            };
+            self.write_message(message).await?;
+        }
+        self.write.flush().await?;
+
+        Ok(())
+    }
+
+    async fn write_message(&mut self, message: String) -> Result<()> {
+        self.write.write_all(&framing::encode(&message)).await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    use super::*;
+
+    /// A writer which records the written bytes and how many times it was flushed
+    #[derive(Default)]
+    struct FlushCounter {
+        written: Vec<u8>,
+        flushes: usize,
+    }
+
+    impl tokio::io::AsyncWrite for FlushCounter {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            self.written.extend_from_slice(buf);
+            Poll::Ready(Ok(buf.len()))
         }
 
-        let mut new_messages = vec![];
+        fn poll_flush(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            self.flushes += 1;
+            Poll::Ready(Ok(()))
+        }
 
-        while let Some(idx) = self
-            .read_buf
-            .iter()
-            .enumerate()
-            .find(|(_i, c)| **c == b'\0')
-        {
-            let message = self.read_buf.drain(0..idx.0).collect::<Vec<_>>();
-            // The null byte should not be part of this or the next message message
-            self.read_buf.pop_front();
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
 
-            new_messages
-                .push(String::from_utf8(message).context("Got non-utf8 characters from cxxrtl")?)
+    /// Queues `count` messages and sends them the way the worker does when it receives the
+    /// first one
+    async fn send_queued(batch_size: usize, count: usize) -> FlushCounter {
+        let (sc_tx, _sc_rx) = mpsc::channel(100);
+        let (cs_tx, mut cs_rx) = mpsc::channel(100);
+        for i in 0..count {
+            cs_tx.send(format!("msg{i}")).await.unwrap();
         }
+        let first = cs_rx.recv().await.unwrap();
+        let mut worker = CxxrtlWorker::new(
+            FlushCounter::default(),
+            tokio::io::empty(),
+            IngressSender::new(sc_tx),
+            cs_rx,
+        )
+        .with_batch_size(batch_size);
 
-        Ok(new_messages)
+        let mut next = Some(first);
+        while let Some(msg) = next {
+            worker.send_messages(msg).await.unwrap();
+            next = worker.cs_channel.try_recv().ok();
+        }
+        worker.write
     }
 
-    async fn send_message(&mut self, message: String) -> Result<()> {
-        self.write.write_all(message.as_bytes()).await?;
-        self.write.write_all(b"\0").await?;
-        self.write.flush().await?;
+    #[tokio::test]
+    async fn queued_messages_are_flushed_together() {
+        let written = send_queued(DEFAULT_BATCH_SIZE, 10).await;
+        assert!(written.flushes < 10);
+        assert_eq!(written.flushes, 1);
+        let expected = (0..10).map(|i| format!("msg{i}\0")).collect::<String>();
+        assert_eq!(String::from_utf8(written.written).unwrap(), expected);
+    }
 
-        Ok(())
+    #[tokio::test]
+    async fn batches_are_limited_to_the_batch_size() {
+        let written = send_queued(4, 10).await;
+        assert_eq!(written.flushes, 3);
+        let expected = (0..10).map(|i| format!("msg{i}\0")).collect::<String>();
+        assert_eq!(String::from_utf8(written.written).unwrap(), expected);
     }
 }
@@ -1,7 +1,13 @@
 pub mod command;
 pub mod cs_message;
+pub mod error;
+pub mod framing;
+pub mod intervals;
 #[cfg(not(target_arch = "wasm32"))]
 pub mod io_worker;
+pub mod names;
 pub mod query_container;
 pub mod sc_message;
 pub mod timestamp;
+#[cfg(target_arch = "wasm32")]
+pub mod websocket;
@@ -0,0 +1,52 @@
+//! Conversion between hierarchical names and their cxxrtl representation.
+//!
+//! cxxrtl separates the components of a hierarchical name by a single space and does not
+//! define any escaping, so names are kept in that plain form. Indices like `genblk[0]` are
+//! part of their component. Escaping for display is left to the UI.
+
+/// Splits a cxxrtl name into its path components. The empty name is the root scope
+/// and has no components.
+pub fn parse_path(name: &str) -> Vec<String> {
+    name.split(' ')
+        .filter(|component| !component.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Joins path components into a cxxrtl name. This is the inverse of [`parse_path`].
+pub fn path_repr<S: AsRef<str>>(components: &[S]) -> String {
+    components
+        .iter()
+        .map(AsRef::as_ref)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn components_are_separated_by_spaces() {
+        assert_eq!(parse_path("top dut counter"), vec!["top", "dut", "counter"]);
+        assert!(parse_path("").is_empty());
+    }
+
+    #[test]
+    fn bracketed_indices_are_kept_in_their_component() {
+        assert_eq!(parse_path("top genblk[0] x"), vec!["top", "genblk[0]", "x"]);
+    }
+
+    #[test]
+    fn backslashes_are_not_escapes() {
+        assert_eq!(parse_path(r"top \esc x"), vec!["top", r"\esc", "x"]);
+        assert_eq!(path_repr(&["top", r"\esc"]), r"top \esc");
+    }
+
+    #[test]
+    fn repr_round_trips() {
+        for components in [vec!["top", "genblk[0]", "x"], vec![r"\top", "a"], vec![]] {
+            assert_eq!(parse_path(&path_repr(&components)), components);
+        }
+    }
+}
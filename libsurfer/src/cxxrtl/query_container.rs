@@ -1,11 +1,20 @@
+//! Storage for the values of queried signals, which are decoded in the background.
+//!
+//! Locking: the value list is the only lock in the cxxrtl container, all other state is
+//! owned by the container and accessed through `&mut self`. Readers only hold the lock
+//! for the duration of a single lookup, and the decoding task takes the write lock once
+//! per response, after all values have been decoded. Neither calls back into the container
+//! while holding the lock, so no other lock is ever taken while it is held.
 use std::{
     collections::{BTreeMap, HashMap},
+    ops::Bound,
     sync::Arc,
 };
 
 use base64::{prelude::BASE64_STANDARD, Engine as _};
 use futures::executor::block_on;
-use num::{bigint::ToBigInt as _, BigInt, BigUint};
+use log::error;
+use num::{bigint::ToBigInt as _, BigInt, BigUint, Zero as _};
 use rayon::iter::{IntoParallelRefIterator as _, ParallelIterator as _};
 use surfer_translation_types::VariableValue;
 use tokio::sync::RwLock;
@@ -37,17 +46,42 @@ impl QueryContainer {
         variables: Vec<VariableRef>,
         item_info: Arc<HashMap<VariableRef, CxxrtlItem>>,
         data: Vec<CxxrtlSample>,
+        encoding: ValueEncoding,
         msg_sender: std::sync::mpsc::Sender<Message>,
     ) {
         let variable_values = self.variable_values.clone();
 
-        let task = fill_variable_values(variables, item_info, data, variable_values, msg_sender);
+        let task = fill_variable_values(
+            variables,
+            item_info,
+            data,
+            encoding,
+            variable_values,
+            msg_sender,
+        );
         #[cfg(target_arch = "wasm32")]
         wasm_bindgen_futures::spawn_local(task);
         #[cfg(not(target_arch = "wasm32"))]
         tokio::task::spawn(task);
     }
 
+    /// Returns a container with the values up to and including `time`. Values which are
+    /// still being decoded only end up in `self`.
+    pub fn until(&self, time: &BigInt) -> Self {
+        let values = block_on(self.variable_values.read());
+        QueryContainer {
+            variable_values: Arc::new(RwLock::new(
+                values
+                    .range(..=time)
+                    .map(|(t, v)| (t.clone(), v.clone()))
+                    .collect(),
+            )),
+        }
+    }
+
+    /// Returns the raw value of `var` at `query_time` as received from cxxrtl. Values are
+    /// formatted by the translators, so changing how a variable is displayed reuses the
+    /// cached samples.
     pub fn query(&self, var: &VariableRef, query_time: BigInt) -> QueryResult {
         let values = block_on(self.variable_values.read());
 
@@ -69,57 +103,283 @@ impl QueryContainer {
             QueryResult::default()
         }
     }
+
+    /// Measures the period of `clock` as the time between its two most recent rising
+    /// edges. Returns `None` if the clock has not been loaded or has not yet had two
+    /// rising edges
+    pub fn clock_period(&self, clock: &VariableRef) -> Option<BigUint> {
+        let values = block_on(self.variable_values.read());
+
+        let mut was_high = None;
+        let rising_edges = values
+            .iter()
+            .filter_map(|(time, value_map)| {
+                let is_high = match value_map.get(clock)? {
+                    VariableValue::BigUint(v) => !v.is_zero(),
+                    VariableValue::String(s) => s.contains('1'),
+                };
+                let rising = is_high && was_high == Some(false);
+                was_high = Some(is_high);
+                rising.then_some(time)
+            })
+            .collect::<Vec<_>>();
+
+        match rising_edges.as_slice() {
+            [.., prev, last] => (*last - *prev).to_biguint(),
+            _ => None,
+        }
+    }
+
+    /// Returns the period of `var` if it only takes the values 0 and 1 and all of its
+    /// rising edges are equally far apart, which takes at least three rising edges.
+    /// Clocks which are gated in the cached samples have no regular period.
+    pub fn regular_period(&self, var: &VariableRef) -> Option<BigUint> {
+        let values = block_on(self.variable_values.read());
+
+        let mut was_high = None;
+        let mut rising_edges = vec![];
+        for (time, value_map) in values.iter() {
+            let Some(value) = value_map.get(var) else {
+                continue;
+            };
+            let is_high = match value {
+                VariableValue::BigUint(v) if v.bits() <= 1 => !v.is_zero(),
+                VariableValue::String(s) if s == "0" || s == "1" => s == "1",
+                _ => return None,
+            };
+            if is_high && was_high == Some(false) {
+                rising_edges.push(time);
+            }
+            was_high = Some(is_high);
+        }
+
+        let periods = rising_edges
+            .windows(2)
+            .map(|edges| edges[1] - edges[0])
+            .collect::<Vec<_>>();
+        match periods.as_slice() {
+            [first, rest @ ..] if !rest.is_empty() && rest.iter().all(|p| p == first) => {
+                first.to_biguint()
+            }
+            _ => None,
+        }
+    }
+
+    /// Samples `var` at `start`, `start + step`, ... up to and including `end`. Each sample
+    /// is the value held at that time, i.e. the value of the most recent transition at or
+    /// before it. Grid points before the first transition of `var` are skipped. Four-state
+    /// values which are not fully known are sampled as strings.
+    pub fn sample_grid(
+        &self,
+        var: &VariableRef,
+        start: &BigUint,
+        end: &BigUint,
+        step: &BigUint,
+    ) -> Vec<(BigUint, VariableValue)> {
+        if step.is_zero() {
+            return vec![];
+        }
+        let values = block_on(self.variable_values.read());
+
+        let mut result = vec![];
+        let mut time = start.clone();
+        while &time <= end {
+            let held = values
+                .range(..=time.to_bigint().unwrap())
+                .rev()
+                .find_map(|(_, value_map)| value_map.get(var));
+            if let Some(value) = held {
+                result.push((time.clone(), value.clone()));
+            }
+            time += step;
+        }
+        result
+    }
+
+    /// Returns the first time after `after` at which `var` changes value. Samples where
+    /// the value of `var` is unchanged, because other signals changed, are skipped.
+    /// Returns `None` if there is no further transition in the cached samples.
+    pub fn next_transition(&self, var: &VariableRef, after: BigInt) -> Option<BigInt> {
+        let values = block_on(self.variable_values.read());
+
+        let mut held = values
+            .range(..=after.clone())
+            .rev()
+            .find_map(|(_, value_map)| value_map.get(var));
+        values
+            .range((Bound::Excluded(after), Bound::Unbounded))
+            .find_map(|(time, value_map)| {
+                let value = value_map.get(var)?;
+                let changed = held != Some(value);
+                held = Some(value);
+                changed.then(|| time.clone())
+            })
+    }
+
+    /// Returns the last time before `before` at which `var` changed value, counting its
+    /// first cached sample as a transition. Returns `None` if there is no such time in the
+    /// cached samples.
+    pub fn prev_transition(&self, var: &VariableRef, before: BigInt) -> Option<BigInt> {
+        let values = block_on(self.variable_values.read());
+
+        let mut later: Option<(&BigInt, &VariableValue)> = None;
+        for (time, value_map) in values.range(..before).rev() {
+            let Some(value) = value_map.get(var) else {
+                continue;
+            };
+            if let Some((later_time, later_value)) = later {
+                if later_value != value {
+                    return Some(later_time.clone());
+                }
+            }
+            later = Some((time, value));
+        }
+        later.map(|(time, _)| time.clone())
+    }
+
+    #[cfg(test)]
+    pub(crate) fn insert(&self, time: BigInt, values: HashMap<VariableRef, VariableValue>) {
+        block_on(self.variable_values.write()).insert(time, values);
+    }
+}
+
+/// How item values are encoded in `query_interval` responses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ValueEncoding {
+    /// One bit per bit of the item, see [`decode_values`]
+    TwoState,
+    /// Two bits per bit of the item, see [`decode_four_state_values`]. Only requested if
+    /// the agent lists it among its features.
+    FourState,
+}
+
+impl ValueEncoding {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            ValueEncoding::TwoState => "base64(u32)",
+            ValueEncoding::FourState => "base64(u32x2)",
+        }
+    }
+
+    /// Decodes the values of items with the given `widths` from `item_values`. Returns
+    /// `None` if the data is malformed.
+    pub(crate) fn decode(&self, item_values: &str, widths: &[u32]) -> Option<Vec<VariableValue>> {
+        match self {
+            ValueEncoding::TwoState => Some(
+                decode_values(item_values, widths)?
+                    .into_iter()
+                    .map(VariableValue::BigUint)
+                    .collect(),
+            ),
+            ValueEncoding::FourState => decode_four_state_values(item_values, widths),
+        }
+    }
+}
+
+/// The number of bytes of the u32s needed to hold a value of `width` bits
+fn packed_size(width: u32) -> usize {
+    4 * (1 + (width.max(1) - 1) as usize / 32)
+}
+
+/// Decodes the values of items with the given `widths` from `item_values`, which is
+/// encoded like the samples of `query_interval`. Each value is packed into as many u32s as
+/// needed to hold it, least significant u32 first, so values wider than 32 bits are
+/// preserved. Returns `None` if the data is malformed.
+pub(crate) fn decode_values(item_values: &str, widths: &[u32]) -> Option<Vec<BigUint>> {
+    let bytes = BASE64_STANDARD.decode(item_values).ok()?;
+    let mut offset = 0;
+    widths
+        .iter()
+        .map(|width| {
+            let size = packed_size(*width);
+            let value = BigUint::from_bytes_le(bytes.get(offset..offset + size)?);
+            offset += size;
+            Some(value)
+        })
+        .collect()
+}
+
+/// Decodes the values of items with the given `widths` from `item_values`, in which each
+/// value is packed like in [`decode_values`] twice: first the value bits, then the
+/// unknown bits. Like the `aval`/`bval` pairs of VPI, a bit is `x` if both are set and `z`
+/// if only the unknown bit is set.
+///
+/// Values without unknown bits are returned as [`VariableValue::BigUint`], others as
+/// strings of `0`, `1`, `x` and `z`, most significant bit first.
+pub(crate) fn decode_four_state_values(
+    item_values: &str,
+    widths: &[u32],
+) -> Option<Vec<VariableValue>> {
+    let bytes = BASE64_STANDARD.decode(item_values).ok()?;
+    let mut offset = 0;
+    widths
+        .iter()
+        .map(|width| {
+            let size = packed_size(*width);
+            let value = BigUint::from_bytes_le(bytes.get(offset..offset + size)?);
+            let unknown = BigUint::from_bytes_le(bytes.get(offset + size..offset + 2 * size)?);
+            offset += 2 * size;
+            if unknown.is_zero() {
+                return Some(VariableValue::BigUint(value));
+            }
+            let bits = (0..u64::from(*width))
+                .rev()
+                .map(|bit| match (value.bit(bit), unknown.bit(bit)) {
+                    (false, false) => '0',
+                    (true, false) => '1',
+                    (false, true) => 'z',
+                    (true, true) => 'x',
+                })
+                .collect();
+            Some(VariableValue::String(bits))
+        })
+        .collect()
+}
+
+/// Decodes `count` values of `width` bits from `item_values`, see [`decode_values`]
+pub(crate) fn decode_rows(item_values: &str, width: u32, count: usize) -> Option<Vec<BigUint>> {
+    decode_values(item_values, &vec![width; count])
 }
 
 async fn fill_variable_values(
     variables: Vec<VariableRef>,
     item_info: Arc<HashMap<VariableRef, CxxrtlItem>>,
     data: Vec<CxxrtlSample>,
+    encoding: ValueEncoding,
     variable_values: ValueList,
     msg_sender: std::sync::mpsc::Sender<Message>,
 ) {
     let work = move || {
-        // Once we base64 decode the cxxrtl data, we'll end up with a bunch of u32s, where
-        // the variables are packed next to each other. We'll start off computing the offset
-        // of each variable for later use
-        let mut offset = 0;
-        let mut ranges = vec![];
-        for variable in &variables {
-            let this_size_bits = &item_info[variable].width;
-            let this_size_u32 = 1 + ((this_size_bits - 1) / 32);
-            ranges.push((offset * 4) as usize..((offset + this_size_u32) * 4) as usize);
-            offset += this_size_u32;
-        }
-
-        data.par_iter().for_each(|sample| {
-            let u8s = BASE64_STANDARD
-                .decode(&sample.item_values)
-                .map_err(|e| {
-                    panic!(
-                        "Got non-base64 data from cxxrtl at time {}. {e}",
-                        sample.time
-                    )
-                })
-                .unwrap();
+        let widths = variables
+            .iter()
+            .map(|variable| item_info[variable].width)
+            .collect::<Vec<_>>();
 
-            let values = ranges
-                .iter()
-                .zip(&variables)
-                .map(|(range, var)| {
-                    let value = BigUint::from_bytes_le(&u8s[range.clone()]);
+        let decoded = data
+            .par_iter()
+            .filter_map(|sample| {
+                let Some(decoded) = encoding.decode(&sample.item_values, &widths) else {
+                    error!("Got malformed data from cxxrtl at time {}", sample.time);
+                    return None;
+                };
 
+                let values = variables
+                    .iter()
                     // FIXME: Probably shouldn't have this indexed by the variable ref here so we can
                     // avoid the clone
-                    (var.clone(), VariableValue::BigUint(value))
-                })
-                .collect::<HashMap<_, _>>();
+                    .cloned()
+                    .zip(decoded)
+                    .collect::<HashMap<_, _>>();
+                Some((sample.time.as_femtoseconds().to_bigint().unwrap(), values))
+            })
+            .collect::<Vec<_>>();
 
-            block_on(variable_values.write())
-                .insert(sample.time.as_femtoseconds().to_bigint().unwrap(), values);
-            msg_sender
-                .send(Message::InvalidateDrawCommands)
-                .expect("Message receiver disconnected");
-        });
+        // Decoding happens without the lock, so that readers are only blocked while the
+        // decoded values are inserted
+        block_on(variable_values.write()).extend(decoded);
+        msg_sender
+            .send(Message::InvalidateDrawCommands)
+            .expect("Message receiver disconnected");
 
         if let Some(ctx) = EGUI_CONTEXT.read().unwrap().as_ref() {
             ctx.request_repaint();
@@ -133,3 +393,236 @@ async fn fill_variable_values(
     #[cfg(not(target_arch = "wasm32"))]
     tokio::task::spawn_blocking(work);
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::wave_container::VariableRefExt as _;
+
+    fn container_with_values(var: &VariableRef, values: &[(u32, u32)]) -> QueryContainer {
+        let container = QueryContainer::empty();
+        for (time, value) in values {
+            container.insert(
+                BigInt::from(*time),
+                HashMap::from([(var.clone(), VariableValue::BigUint(BigUint::from(*value)))]),
+            );
+        }
+        container
+    }
+
+    #[test]
+    fn clock_period_is_measured_between_rising_edges() {
+        let clk = VariableRef::from_hierarchy_string("top.clk");
+        let container = container_with_values(&clk, &[(0, 0), (5, 1), (10, 0), (15, 1), (20, 0)]);
+        assert_eq!(container.clock_period(&clk), Some(BigUint::from(10u32)));
+    }
+
+    #[test]
+    fn clock_period_requires_two_rising_edges() {
+        let clk = VariableRef::from_hierarchy_string("top.clk");
+        let container = container_with_values(&clk, &[(0, 0), (5, 1), (10, 0)]);
+        assert_eq!(container.clock_period(&clk), None);
+        assert_eq!(
+            container.clock_period(&VariableRef::from_hierarchy_string("top.other")),
+            None
+        );
+    }
+
+    #[test]
+    fn regular_period_requires_equally_spaced_edges() {
+        let clk = VariableRef::from_hierarchy_string("top.clk");
+        let toggling = (0..8).map(|n| (n * 5, n % 2)).collect::<Vec<_>>();
+        let container = container_with_values(&clk, &toggling);
+        assert_eq!(container.regular_period(&clk), Some(BigUint::from(10u32)));
+
+        // Gated for one period between 10 and 30
+        let gated = [(0, 0), (5, 1), (10, 0), (25, 1), (30, 0), (35, 1)];
+        let container = container_with_values(&clk, &gated);
+        assert_eq!(container.regular_period(&clk), None);
+
+        // A single period is not enough to tell
+        let container = container_with_values(&clk, &[(0, 0), (5, 1), (10, 0), (15, 1)]);
+        assert_eq!(container.regular_period(&clk), None);
+
+        let counter = (0..8).map(|n| (n * 5, n)).collect::<Vec<_>>();
+        let container = container_with_values(&clk, &counter);
+        assert_eq!(container.regular_period(&clk), None);
+    }
+
+    #[test]
+    fn wide_values_are_decoded() {
+        let value_64 = BigUint::from(0x0123_4567_89ab_cdefu64);
+        let value_128 = BigUint::from(0xfedc_ba98_7654_3210_0123_4567_89ab_cdefu128);
+        let value_1 = BigUint::from(1u32);
+
+        let bytes = [
+            value_64.to_bytes_le(),
+            value_128.to_bytes_le(),
+            vec![1, 0, 0, 0],
+        ]
+        .concat();
+        assert_eq!(
+            decode_values(&BASE64_STANDARD.encode(bytes), &[64, 128, 1]),
+            Some(vec![value_64, value_128, value_1])
+        );
+    }
+
+    #[test]
+    fn partially_filled_words_are_decoded() {
+        // 33 bits occupy two u32s, of which the upper is mostly padding
+        let bytes = [0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x00, 0x00];
+        assert_eq!(
+            decode_values(&BASE64_STANDARD.encode(bytes), &[33]),
+            Some(vec![BigUint::from(0x1_ffff_ffffu64)])
+        );
+        assert_eq!(
+            decode_values(&BASE64_STANDARD.encode(bytes), &[33, 1]),
+            None
+        );
+    }
+
+    /// Encodes `(value, unknown)` planes of values which fit in a single u32
+    fn four_state_bytes(planes: &[(u32, u32)]) -> String {
+        BASE64_STANDARD.encode(
+            planes
+                .iter()
+                .flat_map(|(value, unknown)| [value.to_le_bytes(), unknown.to_le_bytes()])
+                .flatten()
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    #[test]
+    fn four_state_values_keep_unknown_bits() {
+        let item_values = four_state_bytes(&[(0b1010, 0b1100), (0b01, 0)]);
+        assert_eq!(
+            decode_four_state_values(&item_values, &[4, 2]),
+            Some(vec![
+                VariableValue::String("xz10".to_string()),
+                VariableValue::BigUint(BigUint::from(1u32)),
+            ])
+        );
+        assert_eq!(
+            ValueEncoding::FourState.decode(&item_values, &[4, 2]),
+            decode_four_state_values(&item_values, &[4, 2])
+        );
+    }
+
+    #[test]
+    fn four_state_values_wider_than_a_word_are_decoded() {
+        // 33 bits in two u32s per plane, with only the top bit unknown
+        let bytes = [
+            [0xff, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00],
+            [0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00],
+        ]
+        .concat();
+        assert_eq!(
+            decode_four_state_values(&BASE64_STANDARD.encode(bytes), &[33]),
+            Some(vec![VariableValue::String(format!("z{}", "1".repeat(32)))])
+        );
+        // The unknown plane is missing
+        assert_eq!(
+            decode_four_state_values(&BASE64_STANDARD.encode([1, 0, 0, 0]), &[1]),
+            None
+        );
+    }
+
+    #[test]
+    fn two_state_encoding_is_decoded_as_integers() {
+        let item_values = BASE64_STANDARD.encode([5, 0, 0, 0]);
+        assert_eq!(
+            ValueEncoding::TwoState.decode(&item_values, &[3]),
+            Some(vec![VariableValue::BigUint(BigUint::from(5u32))])
+        );
+    }
+
+    #[test]
+    fn grid_samples_hold_values_between_transitions() {
+        let var = VariableRef::from_hierarchy_string("top.counter");
+        let container = container_with_values(&var, &[(10, 1), (25, 2), (30, 3)]);
+
+        let samples = container.sample_grid(
+            &var,
+            &BigUint::from(0u32),
+            &BigUint::from(40u32),
+            &BigUint::from(10u32),
+        );
+        assert_eq!(
+            samples,
+            [(10u32, 1u32), (20, 1), (30, 3), (40, 3)].map(|(time, value)| (
+                BigUint::from(time),
+                VariableValue::BigUint(BigUint::from(value))
+            ))
+        );
+        assert!(container
+            .sample_grid(
+                &var,
+                &BigUint::from(0u32),
+                &BigUint::from(40u32),
+                &BigUint::from(0u32)
+            )
+            .is_empty());
+    }
+
+    #[test]
+    fn grid_samples_keep_four_state_values() {
+        let var = VariableRef::from_hierarchy_string("top.counter");
+        let container = container_with_values(&var, &[(0, 1)]);
+        container.insert(
+            BigInt::from(10),
+            HashMap::from([(var.clone(), VariableValue::String("1x0z".to_string()))]),
+        );
+
+        assert_eq!(
+            container.sample_grid(
+                &var,
+                &BigUint::from(0u32),
+                &BigUint::from(20u32),
+                &BigUint::from(10u32),
+            ),
+            vec![
+                (
+                    BigUint::from(0u32),
+                    VariableValue::BigUint(BigUint::from(1u32))
+                ),
+                (
+                    BigUint::from(10u32),
+                    VariableValue::String("1x0z".to_string())
+                ),
+                (
+                    BigUint::from(20u32),
+                    VariableValue::String("1x0z".to_string())
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn transitions_skip_unchanged_samples() {
+        let var = VariableRef::from_hierarchy_string("top.counter");
+        // The sample at 20 only exists because another signal changed
+        let container = container_with_values(&var, &[(0, 0), (10, 1), (20, 1), (30, 2)]);
+
+        let next = |after: i32| container.next_transition(&var, BigInt::from(after));
+        assert_eq!(next(0), Some(BigInt::from(10)));
+        assert_eq!(next(5), Some(BigInt::from(10)));
+        assert_eq!(next(10), Some(BigInt::from(30)));
+        assert_eq!(next(30), None);
+
+        let prev = |before: i32| container.prev_transition(&var, BigInt::from(before));
+        assert_eq!(prev(35), Some(BigInt::from(30)));
+        assert_eq!(prev(30), Some(BigInt::from(10)));
+        assert_eq!(prev(25), Some(BigInt::from(10)));
+        assert_eq!(prev(10), Some(BigInt::from(0)));
+        assert_eq!(prev(0), None);
+    }
+
+    #[test]
+    fn unknown_signals_have_no_transitions() {
+        let var = VariableRef::from_hierarchy_string("top.counter");
+        let container = container_with_values(&var, &[(0, 0), (10, 1)]);
+        let other = VariableRef::from_hierarchy_string("top.other");
+        assert_eq!(container.next_transition(&other, BigInt::from(0)), None);
+        assert_eq!(container.prev_transition(&other, BigInt::from(20)), None);
+    }
+}
@@ -4,16 +4,49 @@ use serde::{Deserialize, Serialize};
 
 use crate::cxxrtl_container::{CxxrtlItem, CxxrtlScope};
 
-use super::timestamp::CxxrtlTimestamp;
+use super::{command::Diagnostic, timestamp::CxxrtlTimestamp};
+
+/// An assertion, assumption or print emitted by the simulation
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct CxxrtlDiagnostic {
+    #[serde(rename = "type")]
+    pub kind: Diagnostic,
+    pub text: String,
+    pub src: String,
+}
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct CxxrtlSample {
     pub time: CxxrtlTimestamp,
     pub item_values: String,
+    /// Only sent if diagnostics were requested in the query
+    #[serde(default)]
+    pub diagnostics: Vec<CxxrtlDiagnostic>,
 }
 
 #[derive(Deserialize, Debug)]
-pub(crate) struct Features {}
+pub(crate) struct Features {
+    /// The smallest time step of the simulation, which agents may report to suggest a
+    /// unit for displaying time
+    #[serde(default)]
+    pub time_precision: Option<CxxrtlTimestamp>,
+    /// The encodings the agent can use for item values
+    #[serde(default)]
+    pub item_values_encoding: Vec<String>,
+}
+
+/// What a cxxrtl agent reported about itself in its greeting
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AgentInfo {
+    /// The protocol version spoken by the agent
+    pub version: i64,
+    /// The name of the agent implementation, if it sent one
+    pub agent: Option<String>,
+    /// The commands the agent supports
+    pub commands: Vec<String>,
+    /// The events the agent may send
+    pub events: Vec<String>,
+}
 
 #[derive(Deserialize, Debug, Clone)]
 #[allow(non_camel_case_types)]
@@ -47,6 +80,9 @@ pub(crate) enum CommandResponse {
     pause_simulation {
         time: CxxrtlTimestamp,
     },
+    set_item_values,
+    reset_simulation,
+    rewind_simulation,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -82,6 +118,9 @@ pub(crate) struct Error {
 pub(crate) enum SCMessage {
     greeting {
         version: i64,
+        /// Not part of the protocol, but sent by some agents to identify themselves
+        #[serde(default)]
+        agent: Option<String>,
         commands: Vec<String>,
         events: Vec<String>,
         features: Features,
@@ -0,0 +1,128 @@
+//! A transport which talks to cxxrtl over a browser WebSocket, for use in the web build
+//! where native sockets are not available. Messages use the same null byte framing as
+//! the native transports, regardless of how the stream is split into WebSocket frames.
+use std::{cell::RefCell, rc::Rc};
+
+use log::{error, info};
+use tokio::sync::{mpsc, oneshot};
+use wasm_bindgen::{closure::Closure, JsCast};
+use web_sys::{BinaryType, CloseEvent, Event, MessageEvent, WebSocket};
+
+use crate::channels::{IngressReceiver, IngressSender};
+use crate::cxxrtl::error::CxxrtlError;
+use crate::cxxrtl::framing::{self, FrameDecoder};
+
+/// Connects to the cxxrtl server at `url` and returns the channels over which messages to
+/// and from the server are exchanged. Resolves once the connection is open.
+pub(crate) async fn connect(
+    url: &str,
+) -> Result<(mpsc::Sender<String>, IngressReceiver<String>), CxxrtlError> {
+    let connect_error = |reason: String| CxxrtlError::Connect {
+        address: url.to_string(),
+        source: std::io::Error::other(reason),
+    };
+    let socket = WebSocket::new(url).map_err(|e| connect_error(format!("{e:?}")))?;
+    socket.set_binary_type(BinaryType::Arraybuffer);
+
+    let (sc_tx, sc_rx) = mpsc::channel(100);
+    let (cs_tx, mut cs_rx) = mpsc::channel::<String>(100);
+    let sc_tx = IngressSender::new(sc_tx);
+    // Messages are received in callbacks which can not wait for space in the channel, so
+    // they are queued here and forwarded in order by a separate task
+    let (received_tx, mut received_rx) = mpsc::unbounded_channel();
+    // Dropped once the socket closes, which ends the forwarding task and with it the
+    // channel the container reads from, so that it notices the disconnect
+    let received_tx = Rc::new(RefCell::new(Some(received_tx)));
+    wasm_bindgen_futures::spawn_local(async move {
+        while let Some(msg) = received_rx.recv().await {
+            if sc_tx.send(msg).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut decoder = FrameDecoder::new();
+
+    let on_message_tx = received_tx.clone();
+    let onmessage = Closure::<dyn FnMut(MessageEvent)>::new(move |event: MessageEvent| {
+        let data = event.data();
+        let bytes = if let Some(text) = data.as_string() {
+            text.into_bytes()
+        } else if let Ok(buffer) = data.dyn_into::<js_sys::ArrayBuffer>() {
+            js_sys::Uint8Array::new(&buffer).to_vec()
+        } else {
+            error!("Got unexpected WebSocket message from cxxrtl");
+            return;
+        };
+        match decoder.push(&bytes) {
+            Ok(messages) => {
+                if let Some(tx) = on_message_tx.borrow().as_ref() {
+                    for msg in messages {
+                        let _ = tx.send(msg);
+                    }
+                }
+            }
+            Err(e) => error!("Failed to process cxxrtl message ({e:#?})"),
+        }
+    });
+    socket.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
+    onmessage.forget();
+
+    let on_close_tx = received_tx.clone();
+    let onclose = Closure::<dyn FnMut(CloseEvent)>::new(move |event: CloseEvent| {
+        info!(
+            "cxxrtl WebSocket closed ({}: {})",
+            event.code(),
+            event.reason()
+        );
+        on_close_tx.borrow_mut().take();
+    });
+    socket.set_onclose(Some(onclose.as_ref().unchecked_ref()));
+    onclose.forget();
+
+    let (open_tx, open_rx) = oneshot::channel();
+    let open_tx = Rc::new(RefCell::new(Some(open_tx)));
+    let on_open_tx = open_tx.clone();
+    let onopen = Closure::<dyn FnMut(Event)>::new(move |_: Event| {
+        if let Some(tx) = on_open_tx.borrow_mut().take() {
+            let _ = tx.send(Ok(()));
+        }
+    });
+    let onerror = Closure::<dyn FnMut(Event)>::new(move |_: Event| {
+        if let Some(tx) = open_tx.borrow_mut().take() {
+            let _ = tx.send(Err(()));
+        } else {
+            error!("cxxrtl WebSocket error");
+            received_tx.borrow_mut().take();
+        }
+    });
+    socket.set_onopen(Some(onopen.as_ref().unchecked_ref()));
+    socket.set_onerror(Some(onerror.as_ref().unchecked_ref()));
+    onopen.forget();
+    onerror.forget();
+
+    match open_rx.await {
+        Ok(Ok(())) => info!("cxxrtl WebSocket connected to {url}"),
+        _ => {
+            return Err(connect_error(
+                "the WebSocket could not be opened".to_string(),
+            ))
+        }
+    }
+
+    wasm_bindgen_futures::spawn_local(async move {
+        while let Some(msg) = cs_rx.recv().await {
+            // Messages sent to a closed socket are discarded without an error
+            if socket.ready_state() != WebSocket::OPEN {
+                break;
+            }
+            if let Err(e) = socket.send_with_u8_array(&framing::encode(&msg)) {
+                error!("Failed to send message {e:?}");
+                break;
+            }
+        }
+        let _ = socket.close();
+    });
+
+    Ok((cs_tx, IngressReceiver::new(sc_rx)))
+}
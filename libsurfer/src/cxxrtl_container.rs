@@ -1,48 +1,187 @@
+use base64::{prelude::BASE64_STANDARD, Engine as _};
 use futures::executor::block_on;
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{BTreeMap, HashMap, VecDeque},
     sync::Arc,
 };
 use tokio::sync::mpsc;
 
-use color_eyre::Result;
-use log::{error, info};
-use num::{
-    bigint::{ToBigInt, ToBigUint},
-    BigUint,
-};
+use color_eyre::{eyre::anyhow, Result};
+use log::{error, info, warn};
+use num::{bigint::ToBigInt, BigInt, BigUint, One, ToPrimitive, Zero};
 use serde::Deserialize;
-use surfer_translation_types::VariableEncoding;
+use surfer_translation_types::{VariableDirection, VariableEncoding, VariableType, VariableValue};
+use web_time::{Duration, Instant};
 
 use crate::wave_container::ScopeRefExt;
 use crate::{
     channels::IngressReceiver,
     cxxrtl::{
-        command::CxxrtlCommand,
+        command::{CxxrtlCommand, ItemDesignator},
         cs_message::CSMessage,
-        query_container::QueryContainer,
+        error::CxxrtlError,
+        intervals::IntervalSet,
+        names::parse_path,
+        query_container::{decode_rows, decode_values, QueryContainer, ValueEncoding},
         sc_message::{
-            CommandResponse, CxxrtlSimulationStatus, Event, SCMessage, SimulationStatusType,
+            AgentInfo, CommandResponse, CxxrtlDiagnostic, CxxrtlSimulationStatus, Event, SCMessage,
+            SimulationStatusType,
         },
         timestamp::CxxrtlTimestamp,
     },
     message::Message,
+    time::TimeUnit,
     wave_container::{
         QueryResult, ScopeId, ScopeRef, SimulationStatus, VarId, VariableMeta, VariableRef,
         VariableRefExt,
     },
 };
 
-const DEFAULT_REFERENCE: &str = "ALL_VARIABLES";
+/// How long to wait for cxxrtl to acknowledge a `reference_items` command before resending it
+const REFERENCE_TIMEOUT: Duration = Duration::from_secs(2);
+/// How long to wait for a response to a command before giving up on it
+const DEFAULT_COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+/// How often to request the simulation status while the simulation is running
+const STATUS_POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// How far `unpause` runs the simulation by default, in femtoseconds
+const DEFAULT_RUN_STEP: u64 = 100_000_000;
 
 type Callback = Box<dyn FnOnce(CommandResponse, &mut CxxrtlData) + Sync + Send>;
 
-#[derive(Deserialize, Debug, Clone)]
-pub(crate) struct CxxrtlScope {}
+#[derive(Deserialize, Debug, Clone, Default)]
+pub(crate) struct CxxrtlScope {
+    /// The kind of scope, e.g. `module`
+    #[serde(rename = "type", default)]
+    pub scope_type: Option<String>,
+    /// Where the module of the scope is defined
+    #[serde(default)]
+    pub definition: Option<CxxrtlScopeSource>,
+    /// Where the scope is instantiated
+    #[serde(default)]
+    pub instantiation: Option<CxxrtlScopeSource>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub(crate) struct CxxrtlScopeSource {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub src: Option<String>,
+    #[serde(default)]
+    pub attributes: Option<HashMap<String, serde_json::Value>>,
+}
+
+impl CxxrtlScope {
+    /// The attributes of the scope as strings, where those of the instantiation take
+    /// precedence over those of the definition. The source locations and the name of the
+    /// module are included as `src` and `module`, the kind of scope as `type`.
+    fn attributes(&self) -> HashMap<String, String> {
+        let mut result = HashMap::new();
+        if let Some(scope_type) = &self.scope_type {
+            result.insert("type".to_string(), scope_type.clone());
+        }
+        for source in [&self.definition, &self.instantiation]
+            .into_iter()
+            .flatten()
+        {
+            for (name, value) in source.attributes.iter().flatten() {
+                if let Some(value) = attribute_string(value) {
+                    result.insert(name.clone(), value);
+                }
+            }
+            if let Some(src) = &source.src {
+                result.insert("src".to_string(), src.clone());
+            }
+        }
+        if let Some(name) = self.definition.as_ref().and_then(|d| d.name.as_ref()) {
+            result.insert("module".to_string(), name.clone());
+        }
+        result
+    }
+}
+
+/// Returns the value of an attribute, which cxxrtl sends as `{"type": ..., "value": ...}`
+fn attribute_string(value: &serde_json::Value) -> Option<String> {
+    match value {
+        serde_json::Value::String(s) => Some(s.clone()),
+        serde_json::Value::Object(attr) => attribute_string(attr.get("value")?),
+        serde_json::Value::Null => None,
+        other => Some(other.to_string()),
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[allow(non_camel_case_types)]
+pub enum CxxrtlItemType {
+    node,
+    memory,
+    /// Item types added in later protocol versions
+    #[serde(other)]
+    unknown,
+}
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Debug, Clone, Default)]
 pub struct CxxrtlItem {
     pub width: u32,
+    #[serde(rename = "type", default)]
+    pub item_type: Option<CxxrtlItemType>,
+    /// The number of rows if the item is a memory
+    #[serde(default)]
+    pub depth: Option<u32>,
+    #[serde(default)]
+    pub input: bool,
+    #[serde(default)]
+    pub output: bool,
+    #[serde(default)]
+    pub attributes: HashMap<String, serde_json::Value>,
+}
+
+impl CxxrtlItem {
+    fn is_memory(&self) -> bool {
+        self.depth.is_some() || matches!(self.item_type, Some(CxxrtlItemType::memory))
+    }
+
+    fn direction(&self) -> Option<VariableDirection> {
+        match (self.input, self.output) {
+            (true, true) => Some(VariableDirection::InOut),
+            (true, false) => Some(VariableDirection::Input),
+            (false, true) => Some(VariableDirection::Output),
+            (false, false) => None,
+        }
+    }
+
+    fn variable_type(&self) -> Option<VariableType> {
+        match self.item_type {
+            Some(CxxrtlItemType::node) if self.input || self.output => Some(VariableType::Port),
+            Some(CxxrtlItemType::memory) => Some(VariableType::SparseArray),
+            _ => None,
+        }
+    }
+
+    /// The symbolic names of the values of an enum item, keyed by the binary value padded
+    /// to the width of the item. Yosys records these as `enum_value_<bits>` attributes,
+    /// whose values are escaped identifiers. Empty if the item is not an enum.
+    fn enum_map(&self) -> HashMap<String, String> {
+        self.attributes
+            .iter()
+            .filter_map(|(name, value)| {
+                let bits = name.strip_prefix("enum_value_")?;
+                if bits.is_empty() || !bits.chars().all(|c| c == '0' || c == '1') {
+                    return None;
+                }
+                let label = match value {
+                    serde_json::Value::String(label) => label.as_str(),
+                    serde_json::Value::Object(attr) => attr.get("value")?.as_str()?,
+                    _ => return None,
+                };
+                let label = label.strip_prefix('\\').unwrap_or(label);
+                Some((
+                    format!("{bits:0>width$}", width = self.width as usize),
+                    label.to_string(),
+                ))
+            })
+            .collect()
+    }
 }
 
 /// A piece of data which we cache from Cxxrtl
@@ -85,6 +224,14 @@ impl<T> CachedData<T> {
             CachedData::Filled(val) => Some(val.clone()),
         }
     }
+
+    /// Gives up on a pending request, so that the data is requested again on the next
+    /// fetch
+    fn cancel_waiting(&mut self) {
+        if let CachedData::Waiting { prev } = self {
+            *self = CachedData::Uncached { prev: prev.clone() };
+        }
+    }
 }
 
 impl<T> CachedData<T>
@@ -110,25 +257,169 @@ where
     }
 }
 
+/// A group of signals which are referenced and queried together. Signals are grouped
+/// by the scope they are in, so that changing the signals of one scope does not require
+/// re-querying the others.
+struct Reference {
+    signals: Vec<VariableRef>,
+    /// The time ranges whose values have been received. Since values do not change once
+    /// they have been simulated, only the ranges outside of this have to be queried as
+    /// the simulation advances. The values themselves are stored in `values`.
+    covered: IntervalSet,
+    /// The time ranges which have been queried but not received yet
+    requested: IntervalSet,
+    /// Incremented whenever the covered ranges are invalidated, so that responses to
+    /// queries made before that can be discarded
+    generation: u64,
+    /// Diagnostics emitted in the covered ranges, if they were requested
+    diagnostics: Vec<(BigUint, CxxrtlDiagnostic)>,
+    values: QueryContainer,
+    /// The generation and send time of the most recent `reference_items` command which
+    /// has not been acknowledged yet
+    pending: Option<(u64, Instant)>,
+}
+
+impl Reference {
+    fn new() -> Self {
+        Self {
+            signals: vec![],
+            covered: IntervalSet::new(),
+            requested: IntervalSet::new(),
+            generation: 0,
+            diagnostics: vec![],
+            values: QueryContainer::empty(),
+            pending: None,
+        }
+    }
+
+    /// Forgets which ranges have been queried, so that all values are requested again.
+    /// The previous values are kept until they are replaced.
+    fn invalidate(&mut self) {
+        self.covered = IntervalSet::new();
+        self.requested = IntervalSet::new();
+        self.diagnostics.clear();
+        self.generation += 1;
+    }
+
+    /// Forgets the values after `time`, which are requested again when needed
+    fn forget_after(&mut self, time: &BigUint) {
+        self.covered.truncate(time);
+        self.requested = IntervalSet::new();
+        self.diagnostics.retain(|(t, _)| t <= time);
+        self.generation += 1;
+        self.values = self.values.until(&time.to_bigint().unwrap());
+    }
+}
+
+fn is_child_scope(scope: &ScopeRef, parent: &ScopeRef) -> bool {
+    scope.strs().len() == parent.strs().len() + 1 && scope.strs().starts_with(parent.strs())
+}
+
+/// The name of the reference which `variable` is queried through
+fn reference_name(variable: &VariableRef) -> String {
+    format!("scope:{}", variable.path.cxxrtl_repr())
+}
+
+/// Notifications about the state of the connection which embedders can subscribe to
+/// via [`CxxrtlContainer::subscribe`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CxxrtlEvent {
+    /// The list of scopes has been received from the simulator
+    ScopesLoaded,
+    /// The list of all items has been received from the simulator
+    ItemsLoaded,
+}
+
+/// How [`CxxrtlContainer::run_until_value`] ended, sent as [`Message::CxxrtlRunUntilValue`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RunUntilValueOutcome {
+    /// The variable had the value at `time`, in femtoseconds
+    Reached { time: BigUint },
+    /// The maximum number of steps was taken without the variable taking the value
+    StepLimit { time: BigUint },
+    /// The simulation finished without the variable taking the value
+    Finished { time: BigUint },
+    /// The value could not be checked, either because the variable is not referenced or
+    /// because a command failed
+    Failed,
+}
+
+/// The progress of [`CxxrtlContainer::run_until_value`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ValueWatchPhase {
+    /// The next step is to be taken once the simulation is paused
+    Step,
+    /// A step was taken from `from`, the value is checked once the simulation pauses after it
+    Check { from: BigUint },
+    /// The value has been queried and the response is pending
+    Checking,
+}
+
+/// A value which the simulation is stepped towards by [`CxxrtlContainer::run_until_value`]
+struct ValueWatch {
+    variable: VariableRef,
+    value: BigUint,
+    /// The steps which may still be taken before giving up
+    steps_left: u64,
+    phase: ValueWatchPhase,
+}
+
+/// The kinds of data which are fetched from the simulator on demand, see
+/// [`Message::CxxrtlLoading`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CxxrtlLoadKind {
+    Scopes,
+    Items,
+    Query,
+    Status,
+}
+
+impl CxxrtlLoadKind {
+    pub fn description(&self) -> &'static str {
+        match self {
+            CxxrtlLoadKind::Scopes => "scopes",
+            CxxrtlLoadKind::Items => "variables",
+            CxxrtlLoadKind::Query => "values",
+            CxxrtlLoadKind::Status => "the simulation status",
+        }
+    }
+}
+
+/// Tells the UI that a request for `kind` data has been sent and is waiting for a response
+fn notify_loading(msg_channel: &std::sync::mpsc::Sender<Message>, kind: CxxrtlLoadKind) {
+    let _ = msg_channel.send(Message::CxxrtlLoading(kind));
+}
+
 pub struct CxxrtlData {
     scopes_cache: CachedData<HashMap<ScopeRef, CxxrtlScope>>,
+    /// The direct children of scopes which have been expanded, for when the whole
+    /// hierarchy has not been listed
+    child_scope_cache: HashMap<ScopeRef, CachedData<HashMap<ScopeRef, CxxrtlScope>>>,
     module_item_cache: HashMap<ScopeRef, CachedData<HashMap<VariableRef, CxxrtlItem>>>,
     all_items_cache: CachedData<HashMap<VariableRef, CxxrtlItem>>,
 
-    /// We use the CachedData system to keep track of if we have sent a query request,
-    /// but the actual data is stored in the interval_query_cache.
-    ///
-    /// The held value in the query result is the end timestamp of the current current
-    /// interval_query_cache
-    query_result: CachedData<CxxrtlTimestamp>,
-    interval_query_cache: QueryContainer,
-
-    loaded_signals: Vec<VariableRef>,
-    signal_index_map: HashMap<VariableRef, usize>,
+    references: HashMap<String, Reference>,
+    reference_generation: u64,
+    /// The contents of referenced memories at each time they changed. Memories are
+    /// referenced separately from the signals in `references`
+    memories: HashMap<VariableRef, CachedData<BTreeMap<BigUint, Vec<BigUint>>>>,
 
     simulation_status: CachedData<CxxrtlSimulationStatus>,
+    /// Incremented whenever the simulation is reset, so that responses to queries made
+    /// before the reset can be told apart and discarded
+    simulation_epoch: u64,
+    /// Set when the simulation was reset, the references are sent again on the next
+    /// tick since the simulator has been reinitialized
+    references_lost: bool,
+    /// The time the simulation runs until, while it is running
+    run_until: Option<CxxrtlTimestamp>,
+    value_watch: Option<ValueWatch>,
+    /// The cache of the command whose response is being handled, which is reset if the
+    /// response is not the expected one
+    handled_command: CommandCache,
 
     msg_channel: std::sync::mpsc::Sender<Message>,
+    event_listeners: Vec<std::sync::mpsc::Sender<CxxrtlEvent>>,
 }
 
 impl CxxrtlData {
@@ -141,54 +432,262 @@ impl CxxrtlData {
         }
     }
 
+    /// Sends `event` to all subscribers, dropping those which have gone away
+    fn emit(&mut self, event: CxxrtlEvent) {
+        self.event_listeners
+            .retain(|listener| listener.send(event.clone()).is_ok());
+    }
+
     pub fn on_simulation_status_update(&mut self, status: CxxrtlSimulationStatus) {
+        // Values which have already been simulated do not change, so the queried ranges
+        // stay valid. The newly simulated range is queried on the next redraw.
+        if !matches!(status.status, SimulationStatusType::running) {
+            self.run_until = None;
+        }
         self.simulation_status = CachedData::filled(status);
         self.trigger_redraw();
-        self.invalidate_query_result();
+    }
+
+    /// Called when a command times out or fails. The cache which the command was going
+    /// to fill is reset so that it is fetched again.
+    fn cancel_waiting_caches(&mut self, cache: &CommandCache) {
+        match cache {
+            CommandCache::Scopes(None) => self.scopes_cache.cancel_waiting(),
+            CommandCache::Scopes(Some(scope)) => {
+                for (parent, cache) in &mut self.child_scope_cache {
+                    if parent.cxxrtl_repr() == *scope {
+                        cache.cancel_waiting();
+                    }
+                }
+            }
+            CommandCache::Items(None) => self.all_items_cache.cancel_waiting(),
+            CommandCache::Items(Some(scope)) => {
+                for (module, cache) in &mut self.module_item_cache {
+                    if module.cxxrtl_repr() == *scope {
+                        cache.cancel_waiting();
+                    }
+                }
+            }
+            CommandCache::Status => self.simulation_status.cancel_waiting(),
+            CommandCache::Query(name) => {
+                if let Some(reference) = self.references.get_mut(name) {
+                    reference.requested = IntervalSet::new();
+                }
+                for (variable, memory) in &mut self.memories {
+                    if format!("memory:{}", variable.cxxrtl_repr()) == *name {
+                        memory.cancel_waiting();
+                    }
+                }
+            }
+            CommandCache::Nothing => {}
+        }
+        // The failed command may have been stepping or checking the watched value, which
+        // would then never advance
+        if self.value_watch.is_some() {
+            self.stop_value_watch(RunUntilValueOutcome::Failed);
+        }
+    }
+
+    /// Called when a command fails. The cache it was going to fill is reset so that it is
+    /// fetched again.
+    fn on_failed_command(&mut self, error: String, cache: &CommandCache) {
+        error!("{error}");
+        self.cancel_waiting_caches(cache);
+        let _ = self.msg_channel.send(Message::Error(anyhow!(error)));
+    }
+
+    /// Called when the agent responds to a command with a response to a different command.
+    /// Like [`CxxrtlData::on_failed_command`], the cache of the command is reset.
+    fn on_protocol_error(&mut self, got: String, expected: &str) {
+        error!("Got unexpected CXXRTL response. Got {got} expected {expected}");
+        let cache = std::mem::replace(&mut self.handled_command, CommandCache::Nothing);
+        self.cancel_waiting_caches(&cache);
+        let _ = self.msg_channel.send(Message::CxxrtlProtocolError {
+            got,
+            expected: expected.to_string(),
+        });
+    }
+
+    /// Forgets all values after the simulation was moved back to `time`, where it is
+    /// paused
+    fn restart_from(&mut self, time: CxxrtlTimestamp) {
+        self.simulation_epoch += 1;
+        self.run_until = None;
+        let femtoseconds = time.as_femtoseconds();
+        self.simulation_status = CachedData::filled(CxxrtlSimulationStatus {
+            status: SimulationStatusType::paused,
+            latest_time: time,
+        });
+        for reference in self.references.values_mut() {
+            reference.forget_after(&femtoseconds);
+        }
+        // Memories are referenced again the next time they are queried
+        self.memories.clear();
+        self.trigger_redraw();
+    }
+
+    /// Forgets all values after the simulation was reinitialized, which also drops the
+    /// references
+    fn restart(&mut self) {
+        self.restart_from(CxxrtlTimestamp::zero());
+        for reference in self.references.values_mut() {
+            reference.invalidate();
+            reference.values = QueryContainer::empty();
+        }
+        self.references_lost = true;
+    }
+
+    /// Ends `run_until_value` with `outcome`
+    fn stop_value_watch(&mut self, outcome: RunUntilValueOutcome) {
+        self.value_watch = None;
+        let _ = self.msg_channel.send(Message::CxxrtlRunUntilValue(outcome));
     }
 
     pub fn invalidate_query_result(&mut self) {
-        self.query_result = self.query_result.make_uncached();
+        for reference in self.references.values_mut() {
+            reference.invalidate();
+        }
+        for memory in self.memories.values_mut() {
+            *memory = memory.make_uncached();
+        }
         self.trigger_redraw();
-        // self.interval_query_cache.invalidate();
     }
 }
 
+/// Destructures `$response` with `$expected`, or reports a protocol error and returns
+/// from the callback if the agent responded with something else
 macro_rules! expect_response {
-    ($expected:pat, $response:expr) => {
+    ($expected:pat, $response:expr, $data:expr) => {
         let $expected = $response else {
-            log::error!(
-                "Got unexpected response. Got {:?} expected {}",
-                $response,
-                stringify!(expected)
-            );
+            $data.on_protocol_error(format!("{:?}", $response), stringify!($expected));
             return;
         };
     };
 }
 
+/// The cache which a command fills, which is reset if the command fails or times out
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum CommandCache {
+    /// The scopes below the given scope, or all scopes
+    Scopes(Option<String>),
+    /// The items in the given scope, or all items
+    Items(Option<String>),
+    Status,
+    /// The values of the named reference
+    Query(String),
+    Nothing,
+}
+
+impl CommandCache {
+    fn of(command: &CxxrtlCommand) -> Self {
+        match command {
+            CxxrtlCommand::list_scopes { scope } => CommandCache::Scopes(scope.clone()),
+            CxxrtlCommand::list_items { scope } => CommandCache::Items(scope.clone()),
+            CxxrtlCommand::get_simulation_status => CommandCache::Status,
+            CxxrtlCommand::query_interval {
+                items: Some(name), ..
+            } => CommandCache::Query(name.clone()),
+            _ => CommandCache::Nothing,
+        }
+    }
+}
+
+struct PendingCommand {
+    /// `None` once the command has timed out, in which case its response is discarded
+    callback: Option<Callback>,
+    cache: CommandCache,
+    sent: Instant,
+}
+
 struct CSSender {
     cs_messages: mpsc::Sender<String>,
-    callback_queue: VecDeque<Callback>,
+    callback_queue: VecDeque<PendingCommand>,
+    /// Set once the worker has hung up. Commands are dropped from then on
+    disconnected: bool,
+    command_timeout: Duration,
 }
 
 impl CSSender {
+    fn new(cs_messages: mpsc::Sender<String>) -> Self {
+        Self {
+            cs_messages,
+            callback_queue: VecDeque::new(),
+            disconnected: false,
+            command_timeout: DEFAULT_COMMAND_TIMEOUT,
+        }
+    }
+
+    /// Gives up on all commands which have been waiting for longer than the command
+    /// timeout. Returns the caches of the commands that timed out.
+    fn time_out_commands(&mut self) -> Vec<CommandCache> {
+        let mut timed_out = vec![];
+        for cmd in self.callback_queue.iter_mut() {
+            if cmd.callback.is_some() && cmd.sent.elapsed() >= self.command_timeout {
+                cmd.callback = None;
+                timed_out.push(cmd.cache.clone());
+            }
+        }
+        timed_out
+    }
+
     fn run_command<F>(&mut self, command: CxxrtlCommand, f: F)
     where
         F: 'static + FnOnce(CommandResponse, &mut CxxrtlData) + Sync + Send,
     {
-        self.callback_queue.push_back(Box::new(f));
+        if self.disconnected {
+            return;
+        }
+        let cache = CommandCache::of(&command);
         let json = serde_json::to_string(&CSMessage::command(command))
             .expect("Failed to encode cxxrtl command");
-        block_on(self.cs_messages.send(json)).unwrap();
+        if block_on(self.cs_messages.send(json)).is_err() {
+            error!("CXXRTL command channel disconnected");
+            self.disconnected = true;
+            return;
+        }
+        self.callback_queue.push_back(PendingCommand {
+            callback: Some(Box::new(f)),
+            cache,
+            sent: Instant::now(),
+        });
     }
 }
 
+/// How far [`CxxrtlContainer::step`] advances the simulation. Set by either
+/// [`CxxrtlContainer::set_step_clock`] or [`CxxrtlContainer::set_step_period`], whichever
+/// was called last.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum StepSize {
+    /// The time resolution of cxxrtl
+    Resolution,
+    /// One period of the clock, or the time resolution until the period is known
+    Clock(VariableRef),
+    /// A fixed duration in femtoseconds
+    Period(BigUint),
+}
+
 pub struct CxxrtlContainer {
     data: CxxrtlData,
     sending: CSSender,
     sc_messages: IngressReceiver<String>,
     disconnected_reported: bool,
+    /// How far each `step` advances the simulation
+    step_size: StepSize,
+    /// How far `unpause` runs the simulation, in femtoseconds
+    run_step: BigUint,
+    /// The time precision reported in the greeting of the agent
+    time_precision: Option<CxxrtlTimestamp>,
+    /// Whether the agent advertised `rewind_simulation` in its greeting
+    can_rewind: bool,
+    /// What the agent reported about itself in its greeting, `None` until it arrives
+    agent_info: Option<AgentInfo>,
+    /// The encoding of queried values, four-state if the agent supports it
+    value_encoding: ValueEncoding,
+    reference_timeout: Duration,
+    /// Whether to request diagnostics when querying signal values
+    request_diagnostics: bool,
+    status_poll_interval: Duration,
+    last_status_poll: Instant,
 }
 
 impl CxxrtlContainer {
@@ -196,24 +695,34 @@ impl CxxrtlContainer {
         msg_channel: std::sync::mpsc::Sender<Message>,
         sending: CSSender,
         sc_messages: IngressReceiver<String>,
-    ) -> Result<Self> {
+    ) -> Result<Self, CxxrtlError> {
         info!("Sending cxxrtl greeting");
         sending
             .cs_messages
-            .send(serde_json::to_string(&CSMessage::greeting { version: 0 }).unwrap())
+            .send(serde_json::to_string(&CSMessage::greeting { version: 0 })?)
             .await
-            .unwrap();
+            .map_err(|_| {
+                CxxrtlError::Handshake(
+                    "the connection closed before the greeting was sent".to_string(),
+                )
+            })?;
 
         let data = CxxrtlData {
             scopes_cache: CachedData::empty(),
+            child_scope_cache: HashMap::new(),
             module_item_cache: HashMap::new(),
             all_items_cache: CachedData::empty(),
-            query_result: CachedData::empty(),
-            interval_query_cache: QueryContainer::empty(),
-            loaded_signals: vec![],
-            signal_index_map: HashMap::new(),
+            references: HashMap::new(),
+            reference_generation: 0,
+            memories: HashMap::new(),
             simulation_status: CachedData::empty(),
+            simulation_epoch: 0,
+            references_lost: false,
+            run_until: None,
+            value_watch: None,
+            handled_command: CommandCache::Nothing,
             msg_channel: msg_channel.clone(),
+            event_listeners: vec![],
         };
 
         let result = Self {
@@ -221,6 +730,16 @@ impl CxxrtlContainer {
             sc_messages,
             sending,
             disconnected_reported: false,
+            step_size: StepSize::Resolution,
+            run_step: BigUint::from(DEFAULT_RUN_STEP),
+            time_precision: None,
+            can_rewind: false,
+            agent_info: None,
+            value_encoding: ValueEncoding::TwoState,
+            reference_timeout: REFERENCE_TIMEOUT,
+            request_diagnostics: false,
+            status_poll_interval: STATUS_POLL_INTERVAL,
+            last_status_poll: Instant::now(),
         };
 
         info!("cxxrtl connected");
@@ -232,15 +751,95 @@ impl CxxrtlContainer {
     pub async fn new_tcp(
         addr: &str,
         msg_channel: std::sync::mpsc::Sender<Message>,
-    ) -> Result<Self> {
-        use color_eyre::eyre::Context;
+    ) -> Result<Self, CxxrtlError> {
+        let stream = tokio::net::TcpStream::connect(addr)
+            .await
+            .map_err(|source| CxxrtlError::Connect {
+                address: addr.to_string(),
+                source,
+            })?;
 
-        use crate::channels::IngressSender;
-        use crate::cxxrtl::io_worker;
+        Self::new_stream(stream, msg_channel).await
+    }
 
-        let stream = tokio::net::TcpStream::connect(addr)
+    #[cfg(unix)]
+    pub async fn new_unix(
+        path: &str,
+        msg_channel: std::sync::mpsc::Sender<Message>,
+    ) -> Result<Self, CxxrtlError> {
+        let stream = tokio::net::UnixStream::connect(path)
             .await
-            .with_context(|| format!("Failed to connect to {addr}"))?;
+            .map_err(|source| CxxrtlError::Connect {
+                address: format!("unix socket {path}"),
+                source,
+            })?;
+
+        Self::new_stream(stream, msg_channel).await
+    }
+
+    /// Spawns the cxxrtl agent `binary` with `args` and the additional environment
+    /// variables `env`, and communicates with it over its stdin and stdout. Everything the
+    /// agent prints to stderr is logged.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn new_stdio(
+        binary: &str,
+        args: &[String],
+        env: &[(String, String)],
+        msg_channel: std::sync::mpsc::Sender<Message>,
+    ) -> Result<Self, CxxrtlError> {
+        use std::process::Stdio;
+        use tokio::io::AsyncBufReadExt as _;
+
+        let mut child = tokio::process::Command::new(binary)
+            .args(args)
+            .envs(env.iter().map(|(k, v)| (k, v)))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|source| CxxrtlError::Connect {
+                address: format!("process {binary}"),
+                source,
+            })?;
+
+        let (Some(stdin), Some(stdout), Some(stderr)) =
+            (child.stdin.take(), child.stdout.take(), child.stderr.take())
+        else {
+            return Err(CxxrtlError::Handshake(format!(
+                "the standard streams of {binary} are not available"
+            )));
+        };
+
+        let name = binary.to_string();
+        tokio::spawn(async move {
+            let mut lines = tokio::io::BufReader::new(stderr).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                warn!("{name}: {line}");
+            }
+        });
+        let name = binary.to_string();
+        tokio::spawn(async move {
+            match child.wait().await {
+                Ok(status) => info!("{name} exited with {status}"),
+                Err(e) => error!("Failed to wait for {name} ({e})"),
+            }
+        });
+
+        Self::new_stream(tokio::io::join(stdout, stdin), msg_channel).await
+    }
+
+    /// Starts an IO worker which communicates with cxxrtl over `stream`
+    #[cfg(not(target_arch = "wasm32"))]
+    async fn new_stream<S>(
+        stream: S,
+        msg_channel: std::sync::mpsc::Sender<Message>,
+    ) -> Result<Self, CxxrtlError>
+    where
+        S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + 'static,
+    {
+        use crate::channels::IngressSender;
+        use crate::cxxrtl::io_worker;
 
         let (read, write) = tokio::io::split(stream);
 
@@ -252,33 +851,43 @@ impl CxxrtlContainer {
 
         Self::new(
             msg_channel,
-            CSSender {
-                cs_messages: cs_tx,
-                callback_queue: VecDeque::new(),
-            },
+            CSSender::new(cs_tx),
             IngressReceiver::new(sc_rx),
         )
         .await
     }
 
+    /// Connects to a cxxrtl server at `url` using the browser WebSocket API
     #[cfg(target_arch = "wasm32")]
-    pub async fn new_wasm_mailbox(msg_channel: std::sync::mpsc::Sender<Message>) -> Result<Self> {
-        use color_eyre::eyre::anyhow;
+    pub async fn new_websocket(
+        url: &str,
+        msg_channel: std::sync::mpsc::Sender<Message>,
+    ) -> Result<Self, CxxrtlError> {
+        use crate::cxxrtl::websocket;
+
+        let (cs_tx, sc_rx) = websocket::connect(url).await?;
+
+        Self::new(msg_channel, CSSender::new(cs_tx), sc_rx).await
+    }
 
+    #[cfg(target_arch = "wasm32")]
+    pub async fn new_wasm_mailbox(
+        msg_channel: std::sync::mpsc::Sender<Message>,
+    ) -> Result<Self, CxxrtlError> {
         use crate::wasm_api::{CXXRTL_CS_HANDLER, CXXRTL_SC_HANDLER};
 
         let result = Self::new(
             msg_channel,
-            CSSender {
-                cs_messages: CXXRTL_CS_HANDLER.tx.clone(),
-                callback_queue: VecDeque::new(),
-            },
+            CSSender::new(CXXRTL_CS_HANDLER.tx.clone()),
             CXXRTL_SC_HANDLER
                 .rx
                 .write()
                 .await
                 .take()
-                .ok_or_else(|| anyhow!("The wasm mailbox has already been consumed."))?,
+                .ok_or_else(|| CxxrtlError::Connect {
+                    address: "wasm mailbox".to_string(),
+                    source: std::io::Error::other("The wasm mailbox has already been consumed."),
+                })?,
         )
         .await;
 
@@ -298,19 +907,67 @@ impl CxxrtlContainer {
                         }
                     };
                     match msg {
-                        SCMessage::greeting { .. } => {
-                            info!("Received cxxrtl greeting")
+                        SCMessage::greeting {
+                            version,
+                            agent,
+                            commands,
+                            events,
+                            features,
+                        } => {
+                            info!("Received cxxrtl greeting");
+                            self.time_precision = features.time_precision;
+                            self.can_rewind = commands.iter().any(|c| c == "rewind_simulation");
+                            self.agent_info = Some(AgentInfo {
+                                version,
+                                agent,
+                                commands,
+                                events,
+                            });
+                            let four_state = ValueEncoding::FourState.name();
+                            self.value_encoding = if features
+                                .item_values_encoding
+                                .iter()
+                                .any(|e| e == four_state)
+                            {
+                                ValueEncoding::FourState
+                            } else {
+                                ValueEncoding::TwoState
+                            };
+                            if let Some(unit) = self.time_unit() {
+                                let _ = self.data.msg_channel.send(Message::SetTimeUnit(unit));
+                            }
                         }
                         SCMessage::response(response) => {
-                            if let Some(cb) = self.sending.callback_queue.pop_front() {
-                                cb(response, &mut self.data)
-                            } else {
-                                error!("Got a CXXRTL message with no corresponding callback")
+                            match self.sending.callback_queue.pop_front() {
+                                Some(PendingCommand {
+                                    callback: Some(cb),
+                                    cache,
+                                    ..
+                                }) => {
+                                    self.data.handled_command = cache;
+                                    cb(response, &mut self.data);
+                                    self.data.handled_command = CommandCache::Nothing;
+                                }
+                                Some(PendingCommand { callback: None, .. }) => {
+                                    warn!(
+                                        "Discarding a CXXRTL response to a command which timed out"
+                                    )
+                                }
+                                None => {
+                                    error!("Got a CXXRTL message with no corresponding callback")
+                                }
                             };
                         }
                         SCMessage::error(e) => {
-                            error!("CXXRTL error: '{}'", e.message);
-                            self.sending.callback_queue.pop_front();
+                            let cache = self
+                                .sending
+                                .callback_queue
+                                .pop_front()
+                                .map_or(CommandCache::Nothing, |cmd| cmd.cache);
+                            self.data.on_failed_command(
+                                format!("CXXRTL error: '{}'", e.message),
+                                &cache,
+                            );
                         }
                         SCMessage::event(event) => match event {
                             Event::simulation_paused { time, cause: _ } => {
@@ -334,42 +991,133 @@ impl CxxrtlContainer {
                     break;
                 }
                 Err(mpsc::error::TryRecvError::Disconnected) => {
-                    if !self.disconnected_reported {
-                        error!("CXXRTL sender disconnected");
-                        self.disconnected_reported = true;
-                    }
+                    self.on_disconnected();
                     break;
                 }
             }
         }
+        if self.sending.disconnected {
+            self.on_disconnected();
+        }
+
+        let timed_out = self.sending.time_out_commands();
+        if !timed_out.is_empty() {
+            for cache in &timed_out {
+                self.data.cancel_waiting_caches(cache);
+            }
+            let _ = self.data.msg_channel.send(Message::Error(anyhow!(
+                "{} CXXRTL command(s) got no response within {:?}",
+                timed_out.len(),
+                self.sending.command_timeout
+            )));
+        }
+
+        // Keep track of how far a running simulation has progressed
+        if self.data.run_until.is_some()
+            && self.last_status_poll.elapsed() >= self.status_poll_interval
+            && !matches!(self.data.simulation_status, CachedData::Waiting { .. })
+        {
+            self.last_status_poll = Instant::now();
+            self.data.simulation_status = self.data.simulation_status.make_uncached();
+            self.raw_simulation_status();
+        }
+
+        let timed_out = self
+            .data
+            .references
+            .iter()
+            .filter(|(_, r)| {
+                r.pending
+                    .is_some_and(|(_, sent)| sent.elapsed() >= self.reference_timeout)
+            })
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>();
+        for name in timed_out {
+            warn!("cxxrtl did not acknowledge the item references of {name}, resending them");
+            self.send_reference_items(&name);
+        }
+
+        self.resend_lost_references();
+        self.advance_value_watch();
+    }
+
+    fn on_disconnected(&mut self) {
+        if !self.disconnected_reported {
+            error!("CXXRTL sender disconnected");
+            self.disconnected_reported = true;
+            // If the receiver is gone, the app is shutting down
+            let _ = self.data.msg_channel.send(Message::CxxrtlDisconnected);
+        }
+    }
+
+    /// Sets how long to wait for a response to a command before giving up on it
+    pub fn set_command_timeout(&mut self, timeout: Duration) {
+        self.sending.command_timeout = timeout;
+    }
+
+    /// Returns false once the connection to the cxxrtl agent has been lost, which includes
+    /// the worker forwarding commands to it having shut down
+    pub fn is_connected(&self) -> bool {
+        !self.disconnected_reported
+            && !self.sending.disconnected
+            && !self.sending.cs_messages.is_closed()
+    }
+
+    /// Returns whether any command sent to the agent is still waiting for its response
+    pub fn is_loading(&self) -> bool {
+        self.sending
+            .callback_queue
+            .iter()
+            .any(|cmd| cmd.callback.is_some())
+    }
+
+    /// Returns what the agent reported about itself, or `None` until its greeting has
+    /// been received
+    pub fn agent_info(&self) -> Option<AgentInfo> {
+        self.agent_info.clone()
+    }
+
+    /// Returns the unit matching the time precision reported by the agent, or `None` if
+    /// it did not report one or it is not a whole unit. Timestamps themselves are always
+    /// in femtoseconds.
+    pub fn time_unit(&self) -> Option<TimeUnit> {
+        let mut femtoseconds = self.time_precision.as_ref()?.as_femtoseconds();
+        let mut exponent = -15;
+        while !femtoseconds.is_zero() && (&femtoseconds % 10u32).is_zero() && exponent < 0 {
+            femtoseconds /= 10u32;
+            exponent += 1;
+        }
+        (femtoseconds.is_one() && exponent % 3 == 0).then(|| TimeUnit::from_exponent(exponent))
+    }
+
+    /// Returns a receiver for [`CxxrtlEvent`]s emitted from now on
+    pub fn subscribe(&mut self) -> std::sync::mpsc::Receiver<CxxrtlEvent> {
+        let (tx, rx) = std::sync::mpsc::channel();
+        self.data.event_listeners.push(tx);
+        rx
+    }
+
+    /// Requests the scope and item lists up front so that they are available before
+    /// they are first needed. Subscribers are notified once each list has arrived.
+    pub fn warm_caches(&mut self) {
+        self.get_scopes();
+        self.fetch_all_items();
     }
 
     fn get_scopes(&mut self) -> Arc<HashMap<ScopeRef, CxxrtlScope>> {
         self.data
             .scopes_cache
             .fetch_if_needed(|| {
+                notify_loading(&self.data.msg_channel, CxxrtlLoadKind::Scopes);
                 self.sending.run_command(
                     CxxrtlCommand::list_scopes { scope: None },
                     |response, data| {
-                        expect_response!(CommandResponse::list_scopes { scopes }, response);
+                        expect_response!(CommandResponse::list_scopes { scopes }, response, data);
 
-                        let scopes = scopes
-                            .into_iter()
-                            .map(|(name, s)| {
-                                (
-                                    ScopeRef {
-                                        strs: name
-                                            .split(' ')
-                                            .map(std::string::ToString::to_string)
-                                            .collect(),
-                                        id: ScopeId::None,
-                                    },
-                                    s,
-                                )
-                            })
-                            .collect();
+                        let scopes = Self::scope_list_to_hash_map(scopes);
 
                         data.scopes_cache = CachedData::filled(scopes);
+                        data.emit(CxxrtlEvent::ScopesLoaded);
                     },
                 );
             })
@@ -383,14 +1131,16 @@ impl CxxrtlContainer {
         self.data
             .all_items_cache
             .fetch_if_needed(|| {
+                notify_loading(&self.data.msg_channel, CxxrtlLoadKind::Items);
                 self.sending.run_command(
                     CxxrtlCommand::list_items { scope: None },
                     |response, data| {
-                        expect_response!(CommandResponse::list_items { items }, response);
+                        expect_response!(CommandResponse::list_items { items }, response, data);
 
                         let items = Self::item_list_to_hash_map(items);
 
                         data.all_items_cache = CachedData::filled(items);
+                        data.emit(CxxrtlEvent::ItemsLoaded);
                     },
                 );
             })
@@ -401,14 +1151,16 @@ impl CxxrtlContainer {
         self.data
             .all_items_cache
             .fetch_if_needed(|| {
+                notify_loading(&self.data.msg_channel, CxxrtlLoadKind::Items);
                 self.sending.run_command(
                     CxxrtlCommand::list_items { scope: None },
                     |response, data| {
-                        expect_response!(CommandResponse::list_items { items }, response);
+                        expect_response!(CommandResponse::list_items { items }, response, data);
 
                         let items = Self::item_list_to_hash_map(items);
 
                         data.all_items_cache = CachedData::filled(items);
+                        data.emit(CxxrtlEvent::ItemsLoaded);
                     },
                 );
             })
@@ -422,13 +1174,14 @@ impl CxxrtlContainer {
             .entry(scope.clone())
             .or_insert(CachedData::empty())
             .fetch_if_needed(|| {
+                notify_loading(&self.data.msg_channel, CxxrtlLoadKind::Items);
                 let scope = scope.clone();
                 self.sending.run_command(
                     CxxrtlCommand::list_items {
                         scope: Some(scope.cxxrtl_repr()),
                     },
                     move |response, data| {
-                        expect_response!(CommandResponse::list_items { items }, response);
+                        expect_response!(CommandResponse::list_items { items }, response, data);
 
                         let items = Self::item_list_to_hash_map(items);
 
@@ -441,32 +1194,86 @@ impl CxxrtlContainer {
         result.unwrap_or_default()
     }
 
+    /// Lists the direct children of `parent` without fetching the rest of the hierarchy
+    fn fetch_child_scopes(
+        &mut self,
+        parent: &ScopeRef,
+    ) -> Option<Arc<HashMap<ScopeRef, CxxrtlScope>>> {
+        self.data
+            .child_scope_cache
+            .entry(parent.clone())
+            .or_insert(CachedData::empty())
+            .fetch_if_needed(|| {
+                notify_loading(&self.data.msg_channel, CxxrtlLoadKind::Scopes);
+                let parent = parent.clone();
+                self.sending.run_command(
+                    CxxrtlCommand::list_scopes {
+                        scope: Some(parent.cxxrtl_repr()),
+                    },
+                    move |response, data| {
+                        expect_response!(CommandResponse::list_scopes { scopes }, response, data);
+
+                        // Only keep the direct children, in case the agent also lists
+                        // deeper scopes
+                        let children = Self::scope_list_to_hash_map(scopes)
+                            .into_iter()
+                            .filter(|(scope, _)| is_child_scope(scope, &parent))
+                            .collect();
+                        data.child_scope_cache
+                            .insert(parent, CachedData::filled(children));
+                        data.trigger_redraw();
+                    },
+                );
+            })
+    }
+
+    /// Looks up `scope` among the children of its parent. Unless the whole hierarchy has
+    /// already been listed, only the children of the parent are requested.
+    fn listed_scope(&mut self, scope: &ScopeRef) -> Option<CxxrtlScope> {
+        let (_, parent) = scope.strs().split_last()?;
+        let siblings = match self.data.scopes_cache.get() {
+            Some(all) => all,
+            None => self.fetch_child_scopes(&ScopeRef::from_strs(parent))?,
+        };
+        siblings.get(scope).cloned()
+    }
+
+    fn scope_list_to_hash_map(
+        scopes: HashMap<String, CxxrtlScope>,
+    ) -> HashMap<ScopeRef, CxxrtlScope> {
+        scopes
+            .into_iter()
+            .map(|(name, s)| {
+                (
+                    ScopeRef {
+                        strs: parse_path(&name),
+                        id: ScopeId::None,
+                    },
+                    s,
+                )
+            })
+            .collect()
+    }
+
     fn item_list_to_hash_map(
         items: HashMap<String, CxxrtlItem>,
     ) -> HashMap<VariableRef, CxxrtlItem> {
         items
             .into_iter()
             .filter_map(|(k, v)| {
-                let sp = k.split(' ').collect::<Vec<_>>();
-
-                if sp.is_empty() {
+                let mut path = parse_path(&k);
+                let Some(name) = path.pop() else {
                     error!("Found an empty variable name and scope");
-                    None
-                } else {
-                    Some((
-                        VariableRef {
-                            path: ScopeRef::from_strs(
-                                &sp[0..sp.len() - 1]
-                                    .iter()
-                                    .map(std::string::ToString::to_string)
-                                    .collect::<Vec<_>>(),
-                            ),
-                            name: sp.last().unwrap().to_string(),
-                            id: VarId::None,
-                        },
-                        v,
-                    ))
-                }
+                    return None;
+                };
+                Some((
+                    VariableRef {
+                        path: ScopeRef::from_strs(&path),
+                        name,
+                        id: VarId::None,
+                    },
+                    v,
+                ))
             })
             .collect()
     }
@@ -484,63 +1291,101 @@ impl CxxrtlContainer {
     }
 
     pub fn root_modules(&mut self) -> Vec<ScopeRef> {
-        // In the cxxrtl protocol, the root scope is always ""
-        if self.scopes().is_some() {
-            vec![ScopeRef {
-                strs: vec![],
-                id: ScopeId::None,
-            }]
+        // In the cxxrtl protocol, the root scope is always "". It is only returned once
+        // the agent has listed what it contains.
+        let root = ScopeRef {
+            strs: vec![],
+            id: ScopeId::None,
+        };
+        let listed =
+            self.data.scopes_cache.get().is_some() || self.fetch_child_scopes(&root).is_some();
+        if listed {
+            vec![root]
         } else {
             vec![]
         }
     }
 
     pub fn module_exists(&mut self, module: &ScopeRef) -> bool {
-        self.scopes().is_some_and(|s| s.contains_key(module))
+        module.strs().is_empty() || self.listed_scope(module).is_some()
     }
 
-    pub fn child_scopes(&mut self, parent: &ScopeRef) -> Vec<ScopeRef> {
-        self.scopes()
-            .map(|scopes| {
-                scopes
-                    .keys()
-                    .filter_map(|scope| {
-                        if scope.strs().len() == parent.strs().len() + 1 {
-                            if scope.strs()[0..parent.strs().len()]
-                                == parent.strs()[0..parent.strs().len()]
-                            {
-                                Some(scope.clone())
-                            } else {
-                                None
-                            }
-                        } else {
-                            None
-                        }
-                    })
-                    .collect()
-            })
+    /// Returns the attributes which cxxrtl reports for `scope`, such as its source
+    /// location in `src`. Empty if the scope is unknown or has not been listed yet.
+    pub fn scope_attributes(&mut self, scope: &ScopeRef) -> HashMap<String, String> {
+        self.listed_scope(scope)
+            .map(|s| s.attributes())
             .unwrap_or_default()
     }
 
+    /// Returns the direct children of `parent`. Unless the whole hierarchy has already
+    /// been listed, only the children of `parent` are requested from the simulator.
+    pub fn child_scopes(&mut self, parent: &ScopeRef) -> Vec<ScopeRef> {
+        let scopes = match self.data.scopes_cache.get() {
+            Some(all) => all,
+            None => self.fetch_child_scopes(parent).unwrap_or_default(),
+        };
+        scopes
+            .keys()
+            .filter(|scope| is_child_scope(scope, parent))
+            .cloned()
+            .collect()
+    }
+
     pub fn variables_in_module(&mut self, module: &ScopeRef) -> Vec<VariableRef> {
         self.fetch_items_in_module(module).keys().cloned().collect()
     }
 
+    /// Returns the number of variables in `scope` if the items of the scope, or of the
+    /// whole design, have already been listed. Since the protocol has no way to count
+    /// items without listing them, this never fetches anything and returns `None`
+    /// otherwise, so that listing can be deferred until the scope is expanded.
+    pub fn variable_count(&mut self, scope: &ScopeRef) -> Option<usize> {
+        if let Some(items) = self
+            .data
+            .module_item_cache
+            .get(scope)
+            .and_then(|cache| cache.get())
+        {
+            return Some(items.len());
+        }
+        self.data
+            .all_items_cache
+            .get()
+            .map(|items| items.keys().filter(|var| &var.path == scope).count())
+    }
+
     pub fn no_variables_in_module(&mut self, module: &ScopeRef) -> bool {
         self.fetch_items_in_module(module).is_empty()
     }
 
-    pub fn variable_meta(&mut self, variable: &VariableRef) -> Result<VariableMeta> {
-        Ok(self
-            .fetch_item(variable)
-            .map(|item| VariableMeta {
+    /// Returns true if `var` is a memory rather than a scalar signal. Items already listed
+    /// for the module of `var` are used if available, otherwise all items are fetched.
+    /// Returns false while the items are being fetched.
+    pub fn is_memory(&mut self, var: &VariableRef) -> bool {
+        if let Some(item) = self
+            .data
+            .module_item_cache
+            .get(&var.path)
+            .and_then(|cache| cache.get())
+            .and_then(|items| items.get(var).cloned())
+        {
+            return item.is_memory();
+        }
+        self.fetch_item(var).is_some_and(|item| item.is_memory())
+    }
+
+    pub fn variable_meta(&mut self, variable: &VariableRef) -> Result<VariableMeta> {
+        Ok(self
+            .fetch_item(variable)
+            .map(|item| VariableMeta {
                 var: variable.clone(),
                 num_bits: Some(item.width),
-                variable_type: None,
+                variable_type: item.variable_type(),
                 variable_type_name: None,
                 index: None,
-                direction: None,
-                enum_map: Default::default(),
+                direction: item.direction(),
+                enum_map: item.enum_map(),
                 encoding: VariableEncoding::BitVector,
             })
             .unwrap_or_else(|| VariableMeta {
@@ -556,7 +1401,107 @@ impl CxxrtlContainer {
     }
 
     pub fn max_displayed_timestamp(&self) -> Option<CxxrtlTimestamp> {
-        self.data.query_result.get().map(|t| (*t).clone())
+        self.data
+            .references
+            .values()
+            .filter_map(|r| r.covered.end())
+            .max()
+            .cloned()
+            .map(CxxrtlTimestamp::from_femtoseconds)
+    }
+
+    /// Samples the loaded values of `variable` at every `step` femtoseconds from `start` to
+    /// `end`, carrying the most recent value forward between transitions. Only values
+    /// which have already been queried are used. See [`QueryContainer::sample_grid`].
+    pub fn sample_grid(
+        &self,
+        variable: &VariableRef,
+        start: &BigUint,
+        end: &BigUint,
+        step: &BigUint,
+    ) -> Vec<(CxxrtlTimestamp, VariableValue)> {
+        let Some(reference) = self.data.references.get(&reference_name(variable)) else {
+            return vec![];
+        };
+        reference
+            .values
+            .sample_grid(variable, start, end, step)
+            .into_iter()
+            .map(|(time, value)| (CxxrtlTimestamp::from_femtoseconds(time), value))
+            .collect()
+    }
+
+    /// Returns the first time after `after` at which `variable` changes, among the values
+    /// which have been queried so far. See [`QueryContainer::next_transition`].
+    pub fn next_transition(&self, variable: &VariableRef, after: BigInt) -> Option<BigInt> {
+        self.data
+            .references
+            .get(&reference_name(variable))?
+            .values
+            .next_transition(variable, after)
+    }
+
+    /// Guesses the primary clock among the loaded signals as the one which toggles at the
+    /// shortest regular period in the values queried so far, see
+    /// [`QueryContainer::regular_period`]. Returns the clock along with its period in
+    /// femtoseconds, or `None` if no signal toggles regularly.
+    pub fn detect_clock(&self) -> Option<(VariableRef, BigUint)> {
+        self.data
+            .references
+            .values()
+            .flat_map(|reference| {
+                reference.signals.iter().filter_map(|signal| {
+                    Some((signal.clone(), reference.values.regular_period(signal)?))
+                })
+            })
+            .min_by(|(a, a_period), (b, b_period)| {
+                a_period
+                    .cmp(b_period)
+                    .then_with(|| a.full_path_string().cmp(&b.full_path_string()))
+            })
+    }
+
+    /// Returns the last time before `before` at which `variable` changed, among the values
+    /// which have been queried so far. See [`QueryContainer::prev_transition`].
+    pub fn prev_transition(&self, variable: &VariableRef, before: BigInt) -> Option<BigInt> {
+        self.data
+            .references
+            .get(&reference_name(variable))?
+            .values
+            .prev_transition(variable, before)
+    }
+
+    /// Enables or disables requesting diagnostics along with the signal values.
+    /// Changing this re-queries all signals.
+    pub fn set_request_diagnostics(&mut self, request_diagnostics: bool) {
+        if self.request_diagnostics != request_diagnostics {
+            self.request_diagnostics = request_diagnostics;
+            self.data.invalidate_query_result();
+        }
+    }
+
+    /// Returns the diagnostics which were emitted between `range.start` and `range.end`.
+    /// Diagnostics are only available after enabling them with
+    /// [`Self::set_request_diagnostics`].
+    pub fn diagnostics_in_interval(
+        &self,
+        range: std::ops::Range<BigUint>,
+    ) -> Vec<(BigUint, CxxrtlDiagnostic)> {
+        // Diagnostics are not specific to the queried items, so every reference receives
+        // the same ones. Use the reference which has been queried the furthest.
+        self.data
+            .references
+            .values()
+            .filter(|r| !r.covered.is_empty())
+            .max_by_key(|r| r.covered.end())
+            .map(|r| {
+                r.diagnostics
+                    .iter()
+                    .filter(|(time, _)| range.contains(time))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
     }
 
     pub fn max_timestamp(&mut self) -> Option<CxxrtlTimestamp> {
@@ -572,70 +1517,239 @@ impl CxxrtlContainer {
         // that we'll early return with no value
         let max_timestamp = self.max_timestamp()?;
         let info = self.fetch_all_items()?;
-        let loaded_signals = self.data.loaded_signals.clone();
 
-        let res = self
-            .data
-            .query_result
-            .fetch_if_needed(|| {
-                info!("Running query variable");
+        let name = reference_name(variable);
+        let reference = self.data.references.get_mut(&name)?;
 
-                self.sending.run_command(
-                    CxxrtlCommand::query_interval {
-                        interval: (CxxrtlTimestamp::zero(), max_timestamp.clone()),
-                        collapse: true,
-                        items: Some(DEFAULT_REFERENCE.to_string()),
-                        item_values_encoding: "base64(u32)",
-                        diagnostics: false,
-                    },
-                    move |response, data| {
-                        expect_response!(CommandResponse::query_interval { samples }, response);
-
-                        data.query_result = CachedData::filled(max_timestamp);
-                        data.interval_query_cache.populate(
-                            loaded_signals.clone(),
-                            info,
-                            samples,
-                            data.msg_channel.clone(),
-                        );
-                    },
-                );
-            })
-            .map(|_cached| {
-                // If we get here, the cache is valid and we we should look into the
-                // interval_query_cache for the query result
-                self.data
-                    .interval_query_cache
-                    .query(variable, time.to_bigint().unwrap())
-            })
-            .unwrap_or_default();
-        Some(res)
+        let mut known = reference.covered.clone();
+        for (start, end) in reference.requested.intervals() {
+            known.insert(start.clone(), end.clone());
+        }
+        for (start, end) in known.missing(&BigUint::zero(), &max_timestamp.as_femtoseconds()) {
+            info!("Querying {name} from {start} to {end} fs");
+            if reference.requested.is_empty() {
+                notify_loading(&self.data.msg_channel, CxxrtlLoadKind::Query);
+            }
+            reference.requested.insert(start.clone(), end.clone());
+
+            let name = name.clone();
+            let signals = reference.signals.clone();
+            let info = info.clone();
+            let generation = reference.generation;
+            let epoch = self.data.simulation_epoch;
+            let encoding = self.value_encoding;
+            self.sending.run_command(
+                CxxrtlCommand::query_interval {
+                    interval: (
+                        CxxrtlTimestamp::from_femtoseconds(start.clone()),
+                        CxxrtlTimestamp::from_femtoseconds(end.clone()),
+                    ),
+                    collapse: true,
+                    items: Some(name.clone()),
+                    item_values_encoding: encoding.name(),
+                    diagnostics: self.request_diagnostics,
+                },
+                move |response, data| {
+                    expect_response!(CommandResponse::query_interval { samples }, response, data);
+
+                    if data.simulation_epoch != epoch {
+                        return;
+                    }
+                    let Some(reference) = data.references.get_mut(&name) else {
+                        return;
+                    };
+                    if reference.generation != generation {
+                        return;
+                    }
+                    reference
+                        .diagnostics
+                        .extend(samples.iter().flat_map(|sample| {
+                            sample
+                                .diagnostics
+                                .iter()
+                                .map(|d| (sample.time.as_femtoseconds(), d.clone()))
+                        }));
+                    reference.covered.insert(start, end);
+                    reference.values.populate(
+                        signals,
+                        info,
+                        samples,
+                        encoding,
+                        data.msg_channel.clone(),
+                    );
+                },
+            );
+        }
+
+        Some(reference.values.query(variable, time.to_bigint().unwrap()))
+    }
+
+    /// Returns the contents of the memory `variable` at `time`, one value per row. Scalar
+    /// items are returned as a single row. Returns `None` while the contents are being
+    /// fetched.
+    pub fn query_memory(&mut self, variable: &VariableRef, time: &BigUint) -> Option<Vec<BigUint>> {
+        let max_timestamp = self.max_timestamp()?;
+        let item = self.fetch_all_items()?.get(variable)?.clone();
+
+        let Some(depth) = item.depth else {
+            self.load_variables([variable].into_iter());
+            return match self.query_variable(variable, time)?.current {
+                Some((_, VariableValue::BigUint(value))) => Some(vec![value]),
+                _ => None,
+            };
+        };
+
+        let name = format!("memory:{}", variable.cxxrtl_repr());
+        if !self.data.memories.contains_key(variable) {
+            self.data
+                .memories
+                .insert(variable.clone(), CachedData::empty());
+            self.sending.run_command(
+                CxxrtlCommand::reference_items {
+                    reference: name.clone(),
+                    items: vec![ItemDesignator::Rows {
+                        name: variable.cxxrtl_repr(),
+                        first: 0,
+                        last: depth.saturating_sub(1),
+                    }],
+                },
+                |_, _| {},
+            );
+        }
+
+        let variable = variable.clone();
+        let epoch = self.data.simulation_epoch;
+        let contents = self.data.memories.get_mut(&variable)?.fetch_if_needed(|| {
+            notify_loading(&self.data.msg_channel, CxxrtlLoadKind::Query);
+            self.sending.run_command(
+                CxxrtlCommand::query_interval {
+                    interval: (CxxrtlTimestamp::zero(), max_timestamp),
+                    collapse: true,
+                    items: Some(name),
+                    item_values_encoding: "base64(u32)",
+                    diagnostics: false,
+                },
+                move |response, data| {
+                    expect_response!(CommandResponse::query_interval { samples }, response, data);
+
+                    if data.simulation_epoch != epoch {
+                        return;
+                    }
+                    // Memories are typically small enough that decoding them in
+                    // place is cheaper than spawning a task
+                    let contents = samples
+                        .iter()
+                        .filter_map(|sample| {
+                            let rows = decode_rows(&sample.item_values, item.width, depth as usize);
+                            if rows.is_none() {
+                                error!("Got malformed memory contents from cxxrtl");
+                            }
+                            Some((sample.time.as_femtoseconds(), rows?))
+                        })
+                        .collect();
+                    data.memories.insert(variable, CachedData::filled(contents));
+                    data.trigger_redraw();
+                },
+            );
+        })?;
+
+        contents
+            .range(..=time.clone())
+            .next_back()
+            .map(|(_, rows)| rows.clone())
     }
 
     pub fn load_variables<S: AsRef<VariableRef>, T: Iterator<Item = S>>(&mut self, variables: T) {
-        let data = &mut self.data;
+        let mut changed = vec![];
+        for variable in variables {
+            let varref = variable.as_ref();
+            let name = reference_name(varref);
+
+            let reference = self
+                .data
+                .references
+                .entry(name.clone())
+                .or_insert_with(Reference::new);
+            if !reference.signals.contains(varref) {
+                reference.signals.push(varref.clone());
+                if !changed.contains(&name) {
+                    changed.push(name);
+                }
+            }
+        }
+
+        for name in changed {
+            self.send_reference_items(&name);
+        }
+    }
+
+    /// Stops referencing `variables`, so that their values are no longer queried. References
+    /// which lose signals are re-sent with the remaining ones and queried again.
+    pub fn unload_variables<I: Iterator<Item = VariableRef>>(&mut self, variables: I) {
+        let mut changed = vec![];
         for variable in variables {
-            let varref = variable.as_ref().clone();
+            let name = reference_name(&variable);
+            let Some(reference) = self.data.references.get_mut(&name) else {
+                continue;
+            };
+            let len_before = reference.signals.len();
+            reference.signals.retain(|s| s != &variable);
+            if reference.signals.len() != len_before && !changed.contains(&name) {
+                changed.push(name);
+            }
+        }
 
-            if !data.signal_index_map.contains_key(&varref) {
-                let idx = data.loaded_signals.len();
-                data.signal_index_map.insert(varref.clone(), idx);
-                data.loaded_signals.push(varref.clone());
+        for name in changed {
+            let Some(reference) = self.data.references.get_mut(&name) else {
+                continue;
+            };
+            if reference.signals.is_empty() {
+                self.data.references.remove(&name);
+                self.sending.run_command(
+                    CxxrtlCommand::reference_items {
+                        reference: name,
+                        items: vec![],
+                    },
+                    |_, _| {},
+                );
+            } else {
+                reference.invalidate();
+                self.send_reference_items(&name);
             }
         }
+    }
+
+    fn send_reference_items(&mut self, name: &str) {
+        let data = &mut self.data;
+        let Some(reference) = data.references.get_mut(name) else {
+            return;
+        };
+        data.reference_generation += 1;
+        let generation = data.reference_generation;
+        reference.pending = Some((generation, Instant::now()));
 
+        let name = name.to_string();
         self.sending.run_command(
             CxxrtlCommand::reference_items {
-                reference: DEFAULT_REFERENCE.to_string(),
-                items: data
-                    .loaded_signals
+                reference: name.clone(),
+                items: reference
+                    .signals
                     .iter()
-                    .map(|s| vec![s.cxxrtl_repr()])
+                    .map(|s| ItemDesignator::Item(s.cxxrtl_repr()))
                     .collect(),
             },
-            |_response, data| {
-                info!("Item references updated");
-                data.invalidate_query_result();
+            move |_response, data| {
+                info!("Item references of {name} updated");
+                if let Some(reference) = data.references.get_mut(&name) {
+                    if reference
+                        .pending
+                        .is_some_and(|(pending, _)| pending == generation)
+                    {
+                        reference.pending = None;
+                    }
+                    reference.invalidate();
+                }
+                data.trigger_redraw();
             },
         );
     }
@@ -644,9 +1758,14 @@ impl CxxrtlContainer {
         self.data
             .simulation_status
             .fetch_if_needed(|| {
+                notify_loading(&self.data.msg_channel, CxxrtlLoadKind::Status);
                 self.sending
                     .run_command(CxxrtlCommand::get_simulation_status, |response, data| {
-                        expect_response!(CommandResponse::get_simulation_status(status), response);
+                        expect_response!(
+                            CommandResponse::get_simulation_status(status),
+                            response,
+                            data
+                        );
 
                         data.on_simulation_status_update(status);
                     });
@@ -663,36 +1782,293 @@ impl CxxrtlContainer {
     }
 
     pub fn unpause(&mut self) {
-        let duration = self
+        let start = self
             .raw_simulation_status()
-            .map(|s| {
-                CxxrtlTimestamp::from_femtoseconds(
-                    s.latest_time.as_femtoseconds() + 100_000_000u32.to_biguint().unwrap(),
-                )
-            })
-            .unwrap_or_else(|| {
-                CxxrtlTimestamp::from_femtoseconds(100_000_000u32.to_biguint().unwrap())
-            });
+            .map(|s| s.latest_time.as_femtoseconds())
+            .unwrap_or_default();
+        let duration = CxxrtlTimestamp::from_femtoseconds(start + &self.run_step);
+
+        self.run_until(duration);
+    }
+
+    /// Sets how far in femtoseconds the simulation runs each time it is unpaused. A zero
+    /// step would make no progress, so it is ignored.
+    pub fn set_run_step(&mut self, femtoseconds: BigUint) {
+        if femtoseconds.is_zero() {
+            warn!("Ignoring a run step of zero, the simulation would not progress");
+            return;
+        }
+        self.run_step = femtoseconds;
+    }
+
+    /// Designates the clock which determines how far the simulation advances for each
+    /// `step`, replacing any step period. The clock is loaded in order to measure its
+    /// period. If no clock is set, or its period is not known yet, steps advance by the
+    /// time resolution of cxxrtl.
+    pub fn set_step_clock(&mut self, clock: Option<VariableRef>) {
+        if let Some(clock) = &clock {
+            self.load_variables([clock].into_iter());
+        }
+        self.step_size = clock.map_or(StepSize::Resolution, StepSize::Clock);
+    }
+
+    /// Sets a fixed duration in femtoseconds for each `step`, replacing any step clock.
+    /// Without a period, steps advance by the time resolution of cxxrtl.
+    pub fn set_step_period(&mut self, period: Option<BigUint>) {
+        self.step_size = period.map_or(StepSize::Resolution, StepSize::Period);
+    }
+
+    fn step_duration(&self) -> BigUint {
+        match &self.step_size {
+            StepSize::Resolution => None,
+            StepSize::Clock(clock) => self
+                .data
+                .references
+                .get(&reference_name(clock))
+                .and_then(|reference| reference.values.clock_period(clock)),
+            StepSize::Period(period) => Some(period.clone()),
+        }
+        .unwrap_or_else(BigUint::one)
+    }
+
+    /// Runs the simulation for `steps` step periods after which it pauses again. Stepping
+    /// is only possible while the simulation is paused, since the current time is not
+    /// known while it is running.
+    pub fn step(&mut self, steps: u64) {
+        let Some(status) = self.raw_simulation_status() else {
+            warn!("Cannot step the simulation before its status is known");
+            return;
+        };
+        match status.status {
+            SimulationStatusType::paused => {}
+            SimulationStatusType::running => {
+                warn!("Cannot step the simulation while it is running");
+                return;
+            }
+            SimulationStatusType::finished => {
+                warn!("Cannot step a finished simulation");
+                return;
+            }
+        }
+
+        let until = CxxrtlTimestamp::from_femtoseconds(
+            status.latest_time.as_femtoseconds() + self.step_duration() * steps,
+        );
+        self.run_until(until);
+    }
+
+    /// Moves the simulation back by `steps` step periods, if the agent supports rewinding.
+    /// Like stepping forward, this is only possible while the simulation is paused.
+    pub fn step_back(&mut self, steps: u64) {
+        if !self.can_rewind {
+            let _ = self.data.msg_channel.send(Message::Error(anyhow!(
+                "The cxxrtl agent does not support stepping back (no rewind_simulation command)"
+            )));
+            return;
+        }
+        let Some(status) = self.raw_simulation_status() else {
+            warn!("Cannot step the simulation back before its status is known");
+            return;
+        };
+        if !matches!(status.status, SimulationStatusType::paused) {
+            warn!("Can only step the simulation back while it is paused");
+            return;
+        }
+
+        let latest = status.latest_time.as_femtoseconds();
+        let distance = self.step_duration() * steps;
+        let to_time = CxxrtlTimestamp::from_femtoseconds(if latest > distance {
+            latest - distance
+        } else {
+            BigUint::zero()
+        });
+        self.sending.run_command(
+            CxxrtlCommand::rewind_simulation {
+                to_time: to_time.clone(),
+            },
+            move |response, data| {
+                expect_response!(CommandResponse::rewind_simulation, response, data);
+
+                info!("Simulation rewound");
+                data.restart_from(to_time);
+            },
+        );
+    }
+
+    /// Steps the simulation until `variable` has `value`, checking it after each step and
+    /// giving up after `max_steps` steps. [`Message::CxxrtlRunUntilValue`] is sent when
+    /// stepping stops. Like stepping, this is only possible while the simulation is paused.
+    pub fn run_until_value(&mut self, variable: &VariableRef, value: BigUint, max_steps: u64) {
+        match self.raw_simulation_status().map(|status| status.status) {
+            Some(SimulationStatusType::paused) => {}
+            _ => {
+                warn!("Can only run until a value while the simulation is paused");
+                return;
+            }
+        }
+        self.load_variables([variable].into_iter());
+        self.data.value_watch = Some(ValueWatch {
+            variable: variable.clone(),
+            value,
+            steps_left: max_steps,
+            phase: ValueWatchPhase::Step,
+        });
+        self.advance_value_watch();
+    }
+
+    /// Takes the next step of `run_until_value` or checks the value after the previous one,
+    /// depending on how far it has come
+    fn advance_value_watch(&mut self) {
+        let Some((phase, steps_left)) = self
+            .data
+            .value_watch
+            .as_ref()
+            .map(|watch| (watch.phase.clone(), watch.steps_left))
+        else {
+            return;
+        };
+        if phase == ValueWatchPhase::Checking {
+            return;
+        }
+        let Some(status) = self.raw_simulation_status() else {
+            return;
+        };
+        let latest = status.latest_time.as_femtoseconds();
+        match status.status {
+            SimulationStatusType::running => return,
+            SimulationStatusType::finished => {
+                self.data
+                    .stop_value_watch(RunUntilValueOutcome::Finished { time: latest });
+                return;
+            }
+            SimulationStatusType::paused => {}
+        }
+
+        match phase {
+            ValueWatchPhase::Step if steps_left == 0 => {
+                self.data
+                    .stop_value_watch(RunUntilValueOutcome::StepLimit { time: latest });
+            }
+            ValueWatchPhase::Step => {
+                if let Some(watch) = &mut self.data.value_watch {
+                    watch.steps_left -= 1;
+                    watch.phase = ValueWatchPhase::Check {
+                        from: latest.clone(),
+                    };
+                }
+                self.step(1);
+            }
+            // The simulation has not run yet
+            ValueWatchPhase::Check { from } if latest <= from => {}
+            ValueWatchPhase::Check { .. } => self.check_watched_value(latest),
+            ValueWatchPhase::Checking => {}
+        }
+    }
+
+    /// Queries the value of the watched variable at `time`, ending `run_until_value` if it
+    /// has the watched value
+    fn check_watched_value(&mut self, time: BigUint) {
+        let Some(info) = self.fetch_all_items() else {
+            return;
+        };
+        let Some(watch) = &mut self.data.value_watch else {
+            return;
+        };
+        let name = reference_name(&watch.variable);
+        let Some(index) = self
+            .data
+            .references
+            .get(&name)
+            .and_then(|reference| reference.signals.iter().position(|s| s == &watch.variable))
+        else {
+            warn!(
+                "Cannot check the value of {}",
+                watch.variable.full_path_string()
+            );
+            self.data.stop_value_watch(RunUntilValueOutcome::Failed);
+            return;
+        };
+        let reference = &self.data.references[&name];
+        let widths = reference
+            .signals
+            .iter()
+            .map(|signal| info.get(signal).map_or(1, |item| item.width))
+            .collect::<Vec<_>>();
+        watch.phase = ValueWatchPhase::Checking;
+
+        let timestamp = CxxrtlTimestamp::from_femtoseconds(time.clone());
+        self.sending.run_command(
+            CxxrtlCommand::query_interval {
+                interval: (timestamp.clone(), timestamp),
+                collapse: true,
+                items: Some(name),
+                item_values_encoding: ValueEncoding::TwoState.name(),
+                diagnostics: false,
+            },
+            move |response, data| {
+                expect_response!(CommandResponse::query_interval { samples }, response, data);
+
+                let Some(watch) = &mut data.value_watch else {
+                    return;
+                };
+                let current = samples
+                    .last()
+                    .and_then(|sample| decode_values(&sample.item_values, &widths))
+                    .and_then(|values| values.get(index).cloned());
+                if current.as_ref() == Some(&watch.value) {
+                    data.stop_value_watch(RunUntilValueOutcome::Reached { time });
+                } else {
+                    watch.phase = ValueWatchPhase::Step;
+                }
+            },
+        );
+    }
 
+    fn run_until(&mut self, until_time: CxxrtlTimestamp) {
+        self.data.run_until = Some(until_time.clone());
+        self.last_status_poll = Instant::now();
         let cmd = CxxrtlCommand::run_simulation {
-            until_time: Some(duration),
+            until_time: Some(until_time),
             until_diagnostics: vec![],
             sample_item_values: true,
         };
 
         self.sending.run_command(cmd, |_, data| {
+            let latest_time = data
+                .simulation_status
+                .get()
+                .map(|s| s.latest_time.clone())
+                .unwrap_or_else(CxxrtlTimestamp::zero);
             data.simulation_status = CachedData::filled(CxxrtlSimulationStatus {
                 status: SimulationStatusType::running,
-                latest_time: CxxrtlTimestamp::zero(),
+                latest_time,
             });
             info!("Unpausing simulation");
         });
     }
 
+    /// How far the running simulation has progressed towards the time it runs until,
+    /// between 0 and 1. `None` if the simulation is not running.
+    pub fn run_progress(&self) -> Option<f64> {
+        let until = self.data.run_until.as_ref()?.as_femtoseconds();
+        let latest = self
+            .data
+            .simulation_status
+            .get()?
+            .latest_time
+            .as_femtoseconds();
+        if until.is_zero() {
+            return Some(1.0);
+        }
+        Some((latest.to_f64()? / until.to_f64()?).clamp(0.0, 1.0))
+    }
+
     pub fn pause(&mut self) {
+        // Pausing by hand also stops `run_until_value` from taking further steps
+        self.data.value_watch = None;
         self.sending
             .run_command(CxxrtlCommand::pause_simulation, |response, data| {
-                expect_response!(CommandResponse::pause_simulation { time }, response);
+                expect_response!(CommandResponse::pause_simulation { time }, response, data);
 
                 data.on_simulation_status_update(CxxrtlSimulationStatus {
                     status: SimulationStatusType::paused,
@@ -700,4 +2076,1880 @@ impl CxxrtlContainer {
                 });
             });
     }
+
+    /// Restarts the simulation from time zero. All sampled values are discarded and the
+    /// loaded signals are referenced again once the simulator has been reinitialized.
+    pub fn reset(&mut self) {
+        self.sending
+            .run_command(CxxrtlCommand::reset_simulation, |response, data| {
+                expect_response!(CommandResponse::reset_simulation, response, data);
+
+                info!("Simulation reset");
+                data.restart();
+            });
+    }
+
+    /// Sends the references again if the simulator lost them when it was restarted
+    fn resend_lost_references(&mut self) {
+        if !std::mem::take(&mut self.data.references_lost) {
+            return;
+        }
+        let names = self.data.references.keys().cloned().collect::<Vec<_>>();
+        for name in names {
+            self.send_reference_items(&name);
+        }
+    }
+
+    /// Sets `variable` to `value`, truncated to the width of the variable. The value
+    /// is only written once the item list has been loaded.
+    pub fn poke_variable(&mut self, variable: &VariableRef, value: &BigUint) {
+        let Some(width) = self
+            .fetch_all_items()
+            .and_then(|items| items.get(variable).map(|item| item.width))
+        else {
+            warn!(
+                "Cannot poke {} before its width is known",
+                variable.full_path_string()
+            );
+            return;
+        };
+
+        // Values are sent in the same packing as they are queried, as a list of u32
+        let num_u32s = 1 + ((width.max(1) - 1) / 32) as usize;
+        let masked = value & ((BigUint::one() << width) - BigUint::one());
+        let mut bytes = masked.to_bytes_le();
+        bytes.resize(num_u32s * 4, 0);
+
+        self.sending.run_command(
+            CxxrtlCommand::set_item_values {
+                items: vec![ItemDesignator::Item(variable.cxxrtl_repr())],
+                item_values: BASE64_STANDARD.encode(bytes),
+                item_values_encoding: "base64(u32)",
+            },
+            |response, data| {
+                expect_response!(CommandResponse::set_item_values, response, data);
+
+                data.invalidate_query_result();
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use num::BigInt;
+
+    use super::*;
+    use crate::{channels::IngressSender, cxxrtl::command::Diagnostic};
+
+    struct TestConnection {
+        container: CxxrtlContainer,
+        cs_messages: mpsc::Receiver<String>,
+        sc_messages: IngressSender<String>,
+        messages: std::sync::mpsc::Receiver<Message>,
+    }
+
+    impl TestConnection {
+        fn new() -> Self {
+            let (msg_tx, msg_rx) = std::sync::mpsc::channel();
+            let (cs_tx, cs_rx) = mpsc::channel(100);
+            let (sc_tx, sc_rx) = mpsc::channel(100);
+            let container = block_on(CxxrtlContainer::new(
+                msg_tx,
+                CSSender::new(cs_tx),
+                IngressReceiver::new(sc_rx),
+            ))
+            .unwrap();
+
+            Self {
+                container,
+                cs_messages: cs_rx,
+                sc_messages: IngressSender::new(sc_tx),
+                messages: msg_rx,
+            }
+        }
+
+        /// Returns all client to server messages sent so far
+        fn sent_messages(&mut self) -> Vec<serde_json::Value> {
+            let mut result = vec![];
+            while let Ok(msg) = self.cs_messages.try_recv() {
+                result.push(serde_json::from_str(&msg).unwrap());
+            }
+            result
+        }
+
+        /// Sends a server to client message to the container and processes it
+        fn receive(&mut self, message: &str) {
+            block_on(self.sc_messages.send(message.to_string())).unwrap();
+            self.container.tick();
+        }
+
+        fn set_status(&mut self, latest_time: u64) {
+            self.container.data.simulation_status = CachedData::filled(CxxrtlSimulationStatus {
+                status: SimulationStatusType::paused,
+                latest_time: CxxrtlTimestamp::from_femtoseconds(BigUint::from(latest_time)),
+            });
+        }
+    }
+
+    fn run_simulation_until(messages: &[serde_json::Value]) -> Vec<String> {
+        messages
+            .iter()
+            .filter(|m| m["command"] == "run_simulation")
+            .map(|m| m["until_time"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn unix_socket_connection_sends_greeting() {
+        use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cxxrtl.sock");
+        let listener = tokio::net::UnixListener::bind(&path).unwrap();
+
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut received = vec![];
+            while !received.ends_with(b"\0") {
+                let mut buf = [0; 256];
+                let count = stream.read(&mut buf).await.unwrap();
+                assert_ne!(count, 0, "Client disconnected before sending a greeting");
+                received.extend_from_slice(&buf[..count]);
+            }
+            stream
+                .write_all(
+                    br#"{"type":"greeting","version":0,"commands":[],"events":[],"features":{}}"#,
+                )
+                .await
+                .unwrap();
+            stream.write_all(b"\0").await.unwrap();
+            received
+        });
+
+        let (msg_tx, _msg_rx) = std::sync::mpsc::channel();
+        let _container = CxxrtlContainer::new_unix(path.to_str().unwrap(), msg_tx)
+            .await
+            .unwrap();
+
+        let received = server.await.unwrap();
+        let greeting: serde_json::Value =
+            serde_json::from_slice(&received[..received.len() - 1]).unwrap();
+        assert_eq!(greeting["type"], "greeting");
+        assert_eq!(greeting["version"], 0);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn unix_socket_connection_failure_mentions_path() {
+        let (msg_tx, _msg_rx) = std::sync::mpsc::channel();
+        let Err(e) = CxxrtlContainer::new_unix("/nonexistent/cxxrtl.sock", msg_tx).await else {
+            panic!("Connected to a nonexistent socket");
+        };
+        assert!(matches!(e, CxxrtlError::Connect { .. }));
+        assert!(format!("{e:#}").contains("/nonexistent/cxxrtl.sock"));
+    }
+
+    #[test]
+    fn closed_connection_fails_the_handshake() {
+        let (msg_tx, _msg_rx) = std::sync::mpsc::channel();
+        let (cs_tx, cs_rx) = mpsc::channel(100);
+        let (_sc_tx, sc_rx) = mpsc::channel(100);
+        drop(cs_rx);
+        let Err(e) = block_on(CxxrtlContainer::new(
+            msg_tx,
+            CSSender::new(cs_tx),
+            IngressReceiver::new(sc_rx),
+        )) else {
+            panic!("Handshake succeeded without a connection");
+        };
+        assert!(matches!(e, CxxrtlError::Handshake(_)));
+    }
+
+    #[test]
+    fn reported_time_precision_selects_the_time_unit() {
+        let mut conn = TestConnection::new();
+        conn.receive(
+            r#"{"type":"greeting","version":0,"commands":[],"events":[],"features":{"time_precision":"0.000000001000000"}}"#,
+        );
+        assert_eq!(conn.container.time_unit(), Some(TimeUnit::NanoSeconds));
+        assert!(conn
+            .messages
+            .try_iter()
+            .any(|m| matches!(m, Message::SetTimeUnit(TimeUnit::NanoSeconds))));
+    }
+
+    #[test]
+    fn four_state_values_are_requested_when_supported() {
+        let mut conn = TestConnection::new();
+        conn.receive(
+            r#"{"type":"greeting","version":0,"commands":[],"events":[],"features":{"item_values_encoding":["base64(u32)","base64(u32x2)"]}}"#,
+        );
+        assert_eq!(conn.container.value_encoding, ValueEncoding::FourState);
+
+        let mut conn = TestConnection::new();
+        conn.receive(
+            r#"{"type":"greeting","version":0,"commands":[],"events":[],"features":{"item_values_encoding":["base64(u32)"]}}"#,
+        );
+        assert_eq!(conn.container.value_encoding, ValueEncoding::TwoState);
+    }
+
+    #[test]
+    fn greeting_reports_agent_info() {
+        let mut conn = TestConnection::new();
+        assert_eq!(conn.container.agent_info(), None);
+
+        conn.receive(
+            r#"{"type":"greeting","version":0,"agent":"yosys-cxxrtl 0.45","commands":["list_scopes","rewind_simulation"],"events":["simulation_paused"],"features":{}}"#,
+        );
+        assert_eq!(
+            conn.container.agent_info(),
+            Some(AgentInfo {
+                version: 0,
+                agent: Some("yosys-cxxrtl 0.45".to_string()),
+                commands: vec!["list_scopes".to_string(), "rewind_simulation".to_string()],
+                events: vec!["simulation_paused".to_string()],
+            })
+        );
+
+        let mut conn = TestConnection::new();
+        conn.receive(r#"{"type":"greeting","version":0,"commands":[],"events":[],"features":{}}"#);
+        assert_eq!(conn.container.agent_info().unwrap().agent, None);
+    }
+
+    #[test]
+    fn time_unit_is_unknown_without_reported_precision() {
+        let mut conn = TestConnection::new();
+        conn.receive(r#"{"type":"greeting","version":0,"commands":[],"events":[],"features":{}}"#);
+        assert_eq!(conn.container.time_unit(), None);
+
+        // Precisions which are not a whole unit are not surfaced either
+        conn.container.time_precision =
+            Some(CxxrtlTimestamp::from_femtoseconds(BigUint::from(10_000u32)));
+        assert_eq!(conn.container.time_unit(), None);
+    }
+
+    #[test]
+    fn dropping_the_worker_disconnects() {
+        let TestConnection {
+            container,
+            cs_messages,
+            sc_messages: _sc_messages,
+            messages: _messages,
+        } = TestConnection::new();
+        assert!(container.is_connected());
+
+        // No command has to fail for the closed channel to be noticed
+        drop(cs_messages);
+        assert!(!container.is_connected());
+    }
+
+    #[test]
+    fn commands_after_worker_shutdown_are_dropped() {
+        let TestConnection {
+            mut container,
+            cs_messages,
+            sc_messages: _sc_messages,
+            messages,
+        } = TestConnection::new();
+        assert!(container.is_connected());
+
+        drop(cs_messages);
+        container.pause();
+        container.pause();
+        container.tick();
+
+        assert!(!container.is_connected());
+        assert!(container.sending.callback_queue.is_empty());
+        assert_eq!(
+            messages
+                .try_iter()
+                .filter(|m| matches!(m, Message::CxxrtlDisconnected))
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn unacknowledged_references_are_resent() {
+        let mut conn = TestConnection::new();
+        conn.container.reference_timeout = Duration::ZERO;
+        let count_references = |messages: &[serde_json::Value]| {
+            messages
+                .iter()
+                .filter(|m| m["command"] == "reference_items")
+                .count()
+        };
+
+        conn.container
+            .load_variables([VariableRef::from_hierarchy_string("top.a")].iter());
+        assert_eq!(count_references(&conn.sent_messages()), 1);
+
+        // The response is lost to an error, so the references are sent again
+        conn.receive(r#"{"type": "error", "error": "", "message": "hiccup"}"#);
+        assert_eq!(count_references(&conn.sent_messages()), 1);
+
+        conn.receive(r#"{"type": "response", "command": "reference_items"}"#);
+        conn.container.tick();
+        assert_eq!(count_references(&conn.sent_messages()), 0);
+        assert!(conn.container.data.references["scope:top"]
+            .pending
+            .is_none());
+    }
+
+    #[test]
+    fn failed_commands_only_reset_their_own_cache() {
+        let mut conn = TestConnection::new();
+        assert!(conn.container.max_timestamp().is_none());
+        conn.container.get_scopes();
+
+        conn.receive(r#"{"type": "error", "error": "", "message": "hiccup"}"#);
+        assert!(matches!(
+            conn.container.data.simulation_status,
+            CachedData::Uncached { .. }
+        ));
+        assert!(matches!(
+            conn.container.data.scopes_cache,
+            CachedData::Waiting { .. }
+        ));
+    }
+
+    #[test]
+    fn late_responses_after_timeout_are_discarded() {
+        let mut conn = TestConnection::new();
+        conn.container.set_command_timeout(Duration::ZERO);
+
+        assert!(conn.container.max_timestamp().is_none());
+        conn.container.tick();
+        assert!(matches!(
+            conn.container.data.simulation_status,
+            CachedData::Uncached { .. }
+        ));
+        assert!(conn
+            .messages
+            .try_iter()
+            .any(|m| matches!(m, Message::Error(_))));
+
+        let response = r#"{
+            "type": "response",
+            "command": "get_simulation_status",
+            "status": "paused",
+            "latest_time": "0.000000000000100"
+        }"#;
+        conn.receive(response);
+        assert!(matches!(
+            conn.container.data.simulation_status,
+            CachedData::Uncached { .. }
+        ));
+
+        assert!(conn.container.max_timestamp().is_none());
+        conn.receive(response);
+        assert_eq!(
+            conn.container.max_timestamp().map(|t| t.as_femtoseconds()),
+            Some(BigUint::from(100u32))
+        );
+    }
+
+    #[test]
+    fn signals_are_referenced_per_scope() {
+        let mut conn = TestConnection::new();
+        conn.container.load_variables(
+            [
+                VariableRef::from_hierarchy_string("top.a.x"),
+                VariableRef::from_hierarchy_string("top.b.y"),
+                VariableRef::from_hierarchy_string("top.a.z"),
+            ]
+            .iter(),
+        );
+
+        let mut references = conn
+            .sent_messages()
+            .into_iter()
+            .filter(|m| m["command"] == "reference_items")
+            .map(|m| (m["reference"].clone(), m["items"].clone()))
+            .collect::<Vec<_>>();
+        references.sort_by_key(|(name, _)| name.to_string());
+
+        assert_eq!(
+            references,
+            vec![
+                (
+                    serde_json::json!("scope:top a"),
+                    serde_json::json!([["top a x"], ["top a z"]])
+                ),
+                (
+                    serde_json::json!("scope:top b"),
+                    serde_json::json!([["top b y"]])
+                ),
+            ]
+        );
+        assert_eq!(conn.container.data.references.len(), 2);
+    }
+
+    #[test]
+    fn unloaded_signals_are_no_longer_referenced() {
+        let mut conn = TestConnection::new();
+        let x = VariableRef::from_hierarchy_string("top.a.x");
+        let y = VariableRef::from_hierarchy_string("top.a.y");
+        let z = VariableRef::from_hierarchy_string("top.a.z");
+        conn.container
+            .load_variables([x.clone(), y.clone(), z.clone()].iter());
+        conn.sent_messages();
+
+        conn.container.unload_variables([y].into_iter());
+
+        let references = conn
+            .sent_messages()
+            .into_iter()
+            .filter(|m| m["command"] == "reference_items")
+            .map(|m| (m["reference"].clone(), m["items"].clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            references,
+            vec![(
+                serde_json::json!("scope:top a"),
+                serde_json::json!([["top a x"], ["top a z"]])
+            )]
+        );
+        assert_eq!(
+            conn.container.data.references["scope:top a"].signals,
+            vec![x, z]
+        );
+    }
+
+    #[test]
+    fn unloading_all_signals_of_a_scope_drops_its_reference() {
+        let mut conn = TestConnection::new();
+        let x = VariableRef::from_hierarchy_string("top.a.x");
+        conn.container.load_variables([x.clone()].iter());
+        conn.sent_messages();
+
+        conn.container.unload_variables([x].into_iter());
+
+        let references = conn
+            .sent_messages()
+            .into_iter()
+            .filter(|m| m["command"] == "reference_items")
+            .map(|m| m["items"].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(references, vec![serde_json::json!([])]);
+        assert!(conn.container.data.references.is_empty());
+    }
+
+    #[test]
+    fn step_without_clock_advances_by_time_resolution() {
+        let mut conn = TestConnection::new();
+        conn.set_status(1000);
+        conn.container.step(1);
+
+        assert_eq!(
+            run_simulation_until(&conn.sent_messages()),
+            vec!["0.000000000001001"]
+        );
+    }
+
+    #[test]
+    fn transitions_are_found_in_queried_values() {
+        let mut conn = TestConnection::new();
+        let clk = VariableRef::from_hierarchy_string("top.clk");
+        conn.container.set_step_clock(Some(clk.clone()));
+        for (time, value) in [(0u32, 0u32), (5, 1), (10, 0)] {
+            conn.container.data.references["scope:top"].values.insert(
+                BigInt::from(time),
+                HashMap::from([(clk.clone(), VariableValue::BigUint(BigUint::from(value)))]),
+            );
+        }
+
+        assert_eq!(
+            conn.container.next_transition(&clk, BigInt::from(5)),
+            Some(BigInt::from(10))
+        );
+        assert_eq!(
+            conn.container.prev_transition(&clk, BigInt::from(10)),
+            Some(BigInt::from(5))
+        );
+        assert_eq!(conn.container.next_transition(&clk, BigInt::from(10)), None);
+        assert_eq!(
+            conn.container.next_transition(
+                &VariableRef::from_hierarchy_string("other.x"),
+                BigInt::from(0)
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn unpause_runs_for_the_run_step() {
+        let mut conn = TestConnection::new();
+        conn.set_status(1000);
+        conn.container.unpause();
+
+        conn.set_status(1000);
+        conn.container.set_run_step(BigUint::from(5000u32));
+        conn.container.unpause();
+
+        // A zero step is ignored
+        conn.set_status(1000);
+        conn.container.set_run_step(BigUint::zero());
+        conn.container.unpause();
+
+        assert_eq!(
+            run_simulation_until(&conn.sent_messages()),
+            vec![
+                "0.000000100001000",
+                "0.000000000006000",
+                "0.000000000006000"
+            ]
+        );
+    }
+
+    #[test]
+    fn step_with_clock_advances_by_one_period() {
+        let mut conn = TestConnection::new();
+        conn.set_status(1000);
+
+        let clk = VariableRef::from_hierarchy_string("top.clk");
+        conn.container.set_step_clock(Some(clk.clone()));
+        for (time, value) in [(0u32, 0u32), (5, 1), (10, 0), (15, 1)] {
+            conn.container.data.references["scope:top"].values.insert(
+                BigInt::from(time),
+                HashMap::from([(clk.clone(), VariableValue::BigUint(BigUint::from(value)))]),
+            );
+        }
+
+        conn.container.step(1);
+        conn.container.step(3);
+
+        assert_eq!(
+            run_simulation_until(&conn.sent_messages()),
+            vec!["0.000000000001010", "0.000000000001030"]
+        );
+    }
+
+    #[test]
+    fn the_last_step_setting_decides_the_step_size() {
+        let mut conn = TestConnection::new();
+        let clk = VariableRef::from_hierarchy_string("top.clk");
+        conn.container.set_step_period(Some(BigUint::from(300u32)));
+        conn.container.set_step_clock(Some(clk.clone()));
+        for (time, value) in [(0u32, 0u32), (5, 1), (10, 0), (15, 1)] {
+            conn.container.data.references["scope:top"].values.insert(
+                BigInt::from(time),
+                HashMap::from([(clk.clone(), VariableValue::BigUint(BigUint::from(value)))]),
+            );
+        }
+        assert_eq!(conn.container.step_duration(), BigUint::from(10u32));
+
+        conn.container.set_step_period(Some(BigUint::from(300u32)));
+        assert_eq!(conn.container.step_duration(), BigUint::from(300u32));
+        conn.container.set_step_period(None);
+        assert_eq!(conn.container.step_duration(), BigUint::one());
+    }
+
+    #[test]
+    fn the_fastest_regular_clock_is_detected() {
+        let mut conn = TestConnection::new();
+        let clk = VariableRef::from_hierarchy_string("top.clk");
+        let slow = VariableRef::from_hierarchy_string("top.slow_clk");
+        let gated = VariableRef::from_hierarchy_string("top.gated_clk");
+        conn.container
+            .load_variables([clk.clone(), slow.clone(), gated.clone()].iter());
+        assert_eq!(conn.container.detect_clock(), None);
+
+        for time in (0u32..80).step_by(5) {
+            let bit = |high: bool| VariableValue::BigUint(BigUint::from(u32::from(high)));
+            conn.container.data.references["scope:top"].values.insert(
+                BigInt::from(time),
+                HashMap::from([
+                    (clk.clone(), bit(time % 10 == 5)),
+                    (slow.clone(), bit(time % 20 >= 10)),
+                    (
+                        gated.clone(),
+                        bit(time % 10 == 5 && !(20..40).contains(&time)),
+                    ),
+                ]),
+            );
+        }
+        assert_eq!(
+            conn.container.detect_clock(),
+            Some((clk.clone(), BigUint::from(10u32)))
+        );
+
+        let values = &conn.container.data.references["scope:top"].values;
+        assert_eq!(values.regular_period(&slow), Some(BigUint::from(20u32)));
+        assert_eq!(values.regular_period(&gated), None);
+    }
+
+    #[tokio::test]
+    async fn diagnostics_are_returned_with_query_results() {
+        let mut conn = TestConnection::new();
+        conn.set_status(30);
+        conn.container.data.all_items_cache = CachedData::filled(HashMap::from([(
+            VariableRef::from_hierarchy_string("top.a"),
+            CxxrtlItem {
+                width: 1,
+                ..Default::default()
+            },
+        )]));
+        conn.container.set_request_diagnostics(true);
+
+        let a = VariableRef::from_hierarchy_string("top.a");
+        conn.container.load_variables([a.clone()].iter());
+        conn.receive(r#"{"type": "response", "command": "reference_items"}"#);
+        conn.container.query_variable(&a, &BigUint::from(0u32));
+
+        let queries = conn
+            .sent_messages()
+            .into_iter()
+            .filter(|m| m["command"] == "query_interval")
+            .collect::<Vec<_>>();
+        assert_eq!(queries.len(), 1);
+        assert_eq!(queries[0]["diagnostics"], true);
+
+        conn.receive(
+            r#"{
+                "type": "response",
+                "command": "query_interval",
+                "samples": [
+                    {"time": "0.000000000000000", "item_values": "AAAAAA=="},
+                    {
+                        "time": "0.000000000000020",
+                        "item_values": "AQAAAA==",
+                        "diagnostics": [
+                            {"type": "print", "text": "hello\n", "src": "top.v:3"}
+                        ]
+                    }
+                ]
+            }"#,
+        );
+
+        let print = CxxrtlDiagnostic {
+            kind: Diagnostic::print,
+            text: "hello\n".to_string(),
+            src: "top.v:3".to_string(),
+        };
+        assert_eq!(
+            conn.container
+                .diagnostics_in_interval(BigUint::from(0u32)..BigUint::from(30u32)),
+            vec![(BigUint::from(20u32), print)]
+        );
+        assert!(conn
+            .container
+            .diagnostics_in_interval(BigUint::from(0u32)..BigUint::from(20u32))
+            .is_empty());
+
+        // Wait for the values to be decoded before the message receiver is dropped
+        for _ in 0..100 {
+            let result = conn.container.query_variable(&a, &BigUint::from(25u32));
+            if result.and_then(|r| r.current).is_some() {
+                return;
+            }
+            tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
+        }
+        panic!("Values were never populated");
+    }
+
+    #[test]
+    fn poked_values_are_truncated_to_item_width() {
+        let mut conn = TestConnection::new();
+        let a = VariableRef::from_hierarchy_string("top.a");
+        let b = VariableRef::from_hierarchy_string("top.b");
+        conn.container.data.all_items_cache = CachedData::filled(HashMap::from([
+            (
+                a.clone(),
+                CxxrtlItem {
+                    width: 4,
+                    ..Default::default()
+                },
+            ),
+            (
+                b.clone(),
+                CxxrtlItem {
+                    width: 40,
+                    ..Default::default()
+                },
+            ),
+        ]));
+
+        conn.container.poke_variable(&a, &BigUint::from(0x1fu32));
+        conn.container
+            .poke_variable(&b, &BigUint::from(0x1_0000_0001u64));
+
+        assert_eq!(
+            conn.sent_messages()[1..],
+            [
+                serde_json::json!({
+                    "command": "set_item_values",
+                    "items": [["top a"]],
+                    "item_values": "DwAAAA==",
+                    "item_values_encoding": "base64(u32)",
+                }),
+                serde_json::json!({
+                    "command": "set_item_values",
+                    "items": [["top b"]],
+                    "item_values": "AQAAAAEAAAA=",
+                    "item_values_encoding": "base64(u32)",
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn poking_invalidates_query_results() {
+        let mut conn = TestConnection::new();
+        let a = VariableRef::from_hierarchy_string("top.a");
+        conn.container.data.all_items_cache = CachedData::filled(HashMap::from([(
+            a.clone(),
+            CxxrtlItem {
+                width: 1,
+                ..Default::default()
+            },
+        )]));
+        conn.container.load_variables([a.clone()].iter());
+        conn.receive(r#"{"type": "response", "command": "reference_items"}"#);
+        conn.container
+            .data
+            .references
+            .get_mut("scope:top")
+            .unwrap()
+            .covered
+            .insert(BigUint::zero(), BigUint::from(10u32));
+
+        conn.container.poke_variable(&a, &BigUint::one());
+        conn.receive(r#"{"type": "response", "command": "set_item_values"}"#);
+
+        assert!(conn.container.data.references["scope:top"]
+            .covered
+            .is_empty());
+    }
+
+    /// Answers the `run_simulation` command of a step to `time` and the pause after it, then
+    /// the query of the watched counter with `value`
+    fn step_with_counter_value(conn: &mut TestConnection, time: u32, value: u8) {
+        let time = format!("0.{time:015}");
+        conn.receive(r#"{"type": "response", "command": "run_simulation"}"#);
+        conn.receive(&format!(
+            r#"{{"type": "event", "event": "simulation_paused", "time": "{time}", "cause": "until_time"}}"#
+        ));
+        let item_values = BASE64_STANDARD.encode([value, 0, 0, 0]);
+        conn.receive(&format!(
+            r#"{{"type": "response", "command": "query_interval", "samples": [{{"time": "{time}", "item_values": "{item_values}"}}]}}"#
+        ));
+    }
+
+    #[test]
+    fn unexpected_responses_are_reported() {
+        let mut conn = TestConnection::new();
+        conn.container.reset();
+        conn.receive(r#"{"type": "response", "command": "run_simulation"}"#);
+
+        let errors = conn
+            .messages
+            .try_iter()
+            .filter_map(|m| match m {
+                Message::CxxrtlProtocolError { got, expected } => Some((got, expected)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            vec![(
+                "run_simulation".to_string(),
+                "CommandResponse::reset_simulation".to_string()
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn changing_the_format_of_cached_values_does_not_query_again() {
+        use crate::translation::all_translators;
+        use crate::wave_data::variable_translator;
+        use surfer_translation_types::{Translator, ValueRepr};
+
+        let (mut conn, counter) = counter_connection();
+        conn.container.load_variables([counter.clone()].iter());
+        conn.receive(r#"{"type": "response", "command": "reference_items"}"#);
+        conn.container
+            .query_variable(&counter, &BigUint::from(50u32));
+        let item_values = BASE64_STANDARD.encode([0x2a, 0, 0, 0]);
+        conn.receive(&format!(
+            r#"{{"type": "response", "command": "query_interval", "samples": [{{"time": "0.000000000000000", "item_values": "{item_values}"}}]}}"#
+        ));
+        assert_eq!(
+            conn.container.data.references[&reference_name(&counter)]
+                .covered
+                .intervals(),
+            &[(BigUint::zero(), BigUint::from(100u32))]
+        );
+        // The values are decoded in the background
+        for _ in 0..500 {
+            if conn
+                .container
+                .query_variable(&counter, &BigUint::from(50u32))
+                .is_some_and(|result| result.current.is_some())
+            {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        conn.sent_messages();
+
+        let translators = all_translators();
+        let meta = conn.container.variable_meta(&counter).unwrap();
+        for (format, expected) in [("Hexadecimal", "2a"), ("Binary", "00101010")] {
+            let translator =
+                variable_translator(Some(&format.to_string()), &[], &translators, || {
+                    Ok(meta.clone())
+                });
+            let (_, value) = conn
+                .container
+                .query_variable(&counter, &BigUint::from(50u32))
+                .and_then(|result| result.current)
+                .unwrap();
+            let ValueRepr::String(formatted) = translator.translate(&meta, &value).unwrap().val
+            else {
+                panic!("{format} should format the value as a string");
+            };
+            assert_eq!(formatted, expected);
+        }
+
+        assert!(!conn
+            .sent_messages()
+            .iter()
+            .any(|m| m["command"] == "query_interval"));
+    }
+
+    fn counter_connection() -> (TestConnection, VariableRef) {
+        let mut conn = TestConnection::new();
+        let counter = VariableRef::from_hierarchy_string("top.counter");
+        conn.container.data.all_items_cache = CachedData::filled(HashMap::from([(
+            counter.clone(),
+            CxxrtlItem {
+                width: 8,
+                ..Default::default()
+            },
+        )]));
+        conn.set_status(100);
+        (conn, counter)
+    }
+
+    fn run_until_value_outcomes(conn: &TestConnection) -> Vec<RunUntilValueOutcome> {
+        conn.messages
+            .try_iter()
+            .filter_map(|m| match m {
+                Message::CxxrtlRunUntilValue(outcome) => Some(outcome),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn running_until_a_value_stops_when_it_is_reached() {
+        let (mut conn, counter) = counter_connection();
+        conn.container
+            .run_until_value(&counter, BigUint::from(2u32), 10);
+        conn.receive(r#"{"type": "response", "command": "reference_items"}"#);
+
+        step_with_counter_value(&mut conn, 101, 1);
+        assert!(run_until_value_outcomes(&conn).is_empty());
+        step_with_counter_value(&mut conn, 102, 2);
+
+        assert_eq!(
+            run_until_value_outcomes(&conn),
+            vec![RunUntilValueOutcome::Reached {
+                time: BigUint::from(102u32)
+            }]
+        );
+        let sent = conn.sent_messages();
+        assert_eq!(
+            run_simulation_until(&sent),
+            vec!["0.000000000000101", "0.000000000000102"]
+        );
+        assert_eq!(
+            sent.iter()
+                .filter(|m| m["command"] == "query_interval")
+                .map(|m| m["interval"][0].as_str().unwrap())
+                .collect::<Vec<_>>(),
+            vec!["0.000000000000101", "0.000000000000102"]
+        );
+    }
+
+    #[test]
+    fn running_until_a_value_gives_up_after_the_step_limit() {
+        let (mut conn, counter) = counter_connection();
+        conn.container
+            .run_until_value(&counter, BigUint::from(2u32), 2);
+        conn.receive(r#"{"type": "response", "command": "reference_items"}"#);
+
+        step_with_counter_value(&mut conn, 101, 0);
+        step_with_counter_value(&mut conn, 102, 1);
+
+        assert_eq!(
+            run_until_value_outcomes(&conn),
+            vec![RunUntilValueOutcome::StepLimit {
+                time: BigUint::from(102u32)
+            }]
+        );
+        assert_eq!(run_simulation_until(&conn.sent_messages()).len(), 2);
+    }
+
+    #[test]
+    fn running_until_a_value_fails_when_a_command_fails() {
+        let (mut conn, counter) = counter_connection();
+        conn.container
+            .run_until_value(&counter, BigUint::from(2u32), 10);
+        conn.receive(r#"{"type": "response", "command": "reference_items"}"#);
+        conn.receive(r#"{"type": "response", "command": "run_simulation"}"#);
+        conn.receive(
+            r#"{"type": "event", "event": "simulation_paused", "time": "0.000000000000101", "cause": "until_time"}"#,
+        );
+
+        conn.receive(r#"{"type": "error", "error": "", "message": "hiccup"}"#);
+        assert_eq!(
+            run_until_value_outcomes(&conn),
+            vec![RunUntilValueOutcome::Failed]
+        );
+        assert!(conn.container.data.value_watch.is_none());
+    }
+
+    #[test]
+    fn step_with_period_runs_one_period_and_pauses() {
+        let mut conn = TestConnection::new();
+        conn.set_status(1000);
+        conn.container.set_step_period(Some(BigUint::from(250u32)));
+        conn.container.step(1);
+
+        let runs = conn
+            .sent_messages()
+            .into_iter()
+            .filter(|m| m["command"] == "run_simulation")
+            .collect::<Vec<_>>();
+        assert_eq!(
+            runs,
+            vec![serde_json::json!({
+                "command": "run_simulation",
+                "until_time": "0.000000000001250",
+                "until_diagnostics": [],
+                "sample_item_values": true,
+            })]
+        );
+
+        conn.receive(r#"{"type": "response", "command": "run_simulation"}"#);
+        conn.receive(
+            r#"{
+                "type": "event",
+                "event": "simulation_paused",
+                "time": "0.000000000001250",
+                "cause": "until_time"
+            }"#,
+        );
+        let status = conn.container.raw_simulation_status().unwrap();
+        assert!(matches!(status.status, SimulationStatusType::paused));
+        assert_eq!(status.latest_time.as_femtoseconds(), BigUint::from(1250u32));
+    }
+
+    #[test]
+    fn step_is_ignored_while_running() {
+        let mut conn = TestConnection::new();
+        conn.set_status(1000);
+        conn.container.step(1);
+        conn.receive(r#"{"type": "response", "command": "run_simulation"}"#);
+        conn.container.step(1);
+
+        assert_eq!(run_simulation_until(&conn.sent_messages()).len(), 1);
+    }
+
+    #[test]
+    fn memory_item_descriptors_are_parsed() {
+        let response: CommandResponse = serde_json::from_str(
+            r#"{
+                "command": "list_items",
+                "items": {
+                    "top mem": {
+                        "src": "top.v:4",
+                        "type": "memory",
+                        "lsb_at": 0,
+                        "width": 8,
+                        "zero_at": 0,
+                        "depth": 16,
+                        "settable": true,
+                        "attributes": {}
+                    },
+                    "top a": {
+                        "type": "node",
+                        "lsb_at": 0,
+                        "width": 1,
+                        "input": false,
+                        "output": false,
+                        "settable": false,
+                        "attributes": {}
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+        let CommandResponse::list_items { items } = response else {
+            panic!("Expected a list_items response")
+        };
+        let items = CxxrtlContainer::item_list_to_hash_map(items);
+
+        let mem = &items[&VariableRef::from_hierarchy_string("top.mem")];
+        assert_eq!((mem.width, mem.depth), (8, Some(16)));
+        let a = &items[&VariableRef::from_hierarchy_string("top.a")];
+        assert_eq!((a.width, a.depth), (1, None));
+    }
+
+    #[test]
+    fn scope_attributes_are_reported() {
+        let mut conn = TestConnection::new();
+        conn.container.get_scopes();
+        conn.sent_messages();
+        conn.receive(
+            r#"{"type":"response","command":"list_scopes","scopes":{
+                "top": {"type": "module", "definition": {"src": null, "name": "top", "attributes": {}}, "instantiation": null},
+                "top sub": {
+                    "type": "module",
+                    "definition": {"src": "sub.v:1.1-9.10", "name": "sub", "attributes": {"keep": {"type": "unsigned_int", "value": 1}}},
+                    "instantiation": {"src": "top.v:12.5-12.20", "attributes": {"src": {"type": "string", "value": "top.v:12"}}}
+                },
+                "top bare": {}
+            }}"#,
+        );
+
+        let sub = ScopeRef::from_strs(&["top", "sub"]);
+        let attributes = conn.container.scope_attributes(&sub);
+        assert_eq!(attributes["src"], "top.v:12.5-12.20");
+        assert_eq!(attributes["module"], "sub");
+        assert_eq!(attributes["type"], "module");
+        assert_eq!(attributes["keep"], "1");
+
+        let top = conn
+            .container
+            .scope_attributes(&ScopeRef::from_strs(&["top"]));
+        assert!(!top.contains_key("src"));
+        assert!(conn
+            .container
+            .scope_attributes(&ScopeRef::from_strs(&["top", "bare"]))
+            .is_empty());
+        assert!(conn
+            .container
+            .scope_attributes(&ScopeRef::from_strs(&["missing"]))
+            .is_empty());
+    }
+
+    #[test]
+    fn enum_values_are_parsed_from_attributes() {
+        let item: CxxrtlItem = serde_json::from_str(
+            r#"{
+                "type": "node",
+                "lsb_at": 0,
+                "width": 2,
+                "settable": false,
+                "attributes": {
+                    "enum_value_0": {"type": "string", "value": "\\IDLE"},
+                    "enum_value_11": {"type": "string", "value": "\\BUSY"},
+                    "src": {"type": "string", "value": "top.v:3"}
+                }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            item.enum_map(),
+            HashMap::from([
+                ("00".to_string(), "IDLE".to_string()),
+                ("11".to_string(), "BUSY".to_string())
+            ])
+        );
+
+        let plain: CxxrtlItem = serde_json::from_str(r#"{"width": 2}"#).unwrap();
+        assert!(plain.enum_map().is_empty());
+    }
+
+    #[test]
+    fn memory_contents_are_queried_by_rows() {
+        let mut conn = TestConnection::new();
+        conn.set_status(100);
+        let mem = VariableRef::from_hierarchy_string("top.mem");
+        conn.container.data.all_items_cache = CachedData::filled(HashMap::from([(
+            mem.clone(),
+            CxxrtlItem {
+                width: 8,
+                depth: Some(3),
+                ..Default::default()
+            },
+        )]));
+
+        assert_eq!(
+            conn.container.query_memory(&mem, &BigUint::from(50u32)),
+            None
+        );
+        let sent = conn.sent_messages();
+        let reference = sent
+            .iter()
+            .find(|m| m["command"] == "reference_items")
+            .unwrap();
+        assert_eq!(reference["reference"], "memory:top mem");
+        assert_eq!(reference["items"], serde_json::json!([["top mem", 0, 2]]));
+
+        conn.receive(r#"{"type": "response", "command": "reference_items"}"#);
+        conn.receive(
+            r#"{
+                "type": "response",
+                "command": "query_interval",
+                "samples": [{"time": "0.000000000000000", "item_values": "AQAAAAIAAAADAAAA"}]
+            }"#,
+        );
+        assert_eq!(
+            conn.container.query_memory(&mem, &BigUint::from(50u32)),
+            Some(vec![1u32, 2, 3].into_iter().map(BigUint::from).collect())
+        );
+    }
+
+    #[test]
+    fn memories_are_distinguished_from_scalars() {
+        let mut conn = TestConnection::new();
+        let mem = VariableRef::from_hierarchy_string("top.mem");
+        let a = VariableRef::from_hierarchy_string("top.a");
+
+        let list_items_sent = |conn: &mut TestConnection| {
+            conn.sent_messages()
+                .into_iter()
+                .filter(|m| m["command"] == "list_items")
+                .count()
+        };
+
+        assert!(!conn.container.is_memory(&mem));
+        assert_eq!(list_items_sent(&mut conn), 1);
+
+        conn.receive(
+            r#"{
+                "type": "response",
+                "command": "list_items",
+                "items": {
+                    "top mem": {"type": "memory", "width": 8, "depth": 16},
+                    "top a": {"type": "node", "width": 1}
+                }
+            }"#,
+        );
+        assert!(conn.container.is_memory(&mem));
+        assert!(!conn.container.is_memory(&a));
+        assert_eq!(list_items_sent(&mut conn), 0);
+    }
+
+    #[test]
+    fn variable_counts_only_use_listed_items() {
+        let mut conn = TestConnection::new();
+        let top = ScopeRef::from_strs(&["top"]);
+        let sub = ScopeRef::from_strs(&["top", "sub"]);
+        assert_eq!(conn.container.variable_count(&top), None);
+        assert!(!conn
+            .sent_messages()
+            .iter()
+            .any(|m| m["command"] == "list_items"));
+
+        conn.container.data.all_items_cache = CachedData::filled(HashMap::from([
+            (
+                VariableRef::from_hierarchy_string("top.a"),
+                CxxrtlItem::default(),
+            ),
+            (
+                VariableRef::from_hierarchy_string("top.b"),
+                CxxrtlItem::default(),
+            ),
+            (
+                VariableRef::from_hierarchy_string("top.sub.c"),
+                CxxrtlItem::default(),
+            ),
+        ]));
+        assert_eq!(conn.container.variable_count(&top), Some(2));
+        assert_eq!(conn.container.variable_count(&sub), Some(1));
+
+        // Items listed for the module take precedence
+        conn.container.data.module_item_cache.insert(
+            top.clone(),
+            CachedData::filled(HashMap::from([(
+                VariableRef::from_hierarchy_string("top.a"),
+                CxxrtlItem::default(),
+            )])),
+        );
+        assert_eq!(
+            conn.container.variable_count(&top),
+            Some(conn.container.variables_in_module(&top).len())
+        );
+        assert_eq!(conn.container.variable_count(&top), Some(1));
+        assert!(!conn
+            .sent_messages()
+            .iter()
+            .any(|m| m["command"] == "list_items"));
+    }
+
+    #[test]
+    fn memory_check_uses_cached_module_items() {
+        let mut conn = TestConnection::new();
+        let mem = VariableRef::from_hierarchy_string("top.mem");
+        let a = VariableRef::from_hierarchy_string("top.a");
+        conn.container.data.module_item_cache.insert(
+            mem.path.clone(),
+            CachedData::filled(HashMap::from([
+                (
+                    mem.clone(),
+                    CxxrtlItem {
+                        width: 8,
+                        depth: Some(16),
+                        ..Default::default()
+                    },
+                ),
+                (
+                    a.clone(),
+                    CxxrtlItem {
+                        width: 1,
+                        ..Default::default()
+                    },
+                ),
+            ])),
+        );
+
+        assert!(conn.container.is_memory(&mem));
+        assert!(!conn.container.is_memory(&a));
+        assert!(!conn
+            .sent_messages()
+            .iter()
+            .any(|m| m["command"] == "list_items"));
+    }
+
+    #[test]
+    fn scope_fetch_emits_one_loading_message() {
+        let mut conn = TestConnection::new();
+        conn.container.modules();
+        conn.container.modules();
+
+        let loading = conn
+            .messages
+            .try_iter()
+            .filter(|m| matches!(m, Message::CxxrtlLoading(CxxrtlLoadKind::Scopes)))
+            .count();
+        assert_eq!(loading, 1);
+
+        conn.receive(r#"{"type": "response", "command": "list_scopes", "scopes": {"top": {}}}"#);
+        conn.container.modules();
+        assert!(!conn
+            .messages
+            .try_iter()
+            .any(|m| matches!(m, Message::CxxrtlLoading(_))));
+    }
+
+    #[test]
+    fn loading_lasts_until_the_response_arrives() {
+        let mut conn = TestConnection::new();
+        assert!(!conn.container.is_loading());
+
+        conn.container.modules();
+        assert!(conn.container.is_loading());
+
+        conn.receive(r#"{"type": "response", "command": "list_scopes", "scopes": {"top": {}}}"#);
+        assert!(!conn.container.is_loading());
+    }
+
+    fn meta_of_item(item: &str) -> VariableMeta {
+        let mut conn = TestConnection::new();
+        let response: CommandResponse = serde_json::from_str(&format!(
+            r#"{{"command": "list_items", "items": {{"top x": {item}}}}}"#
+        ))
+        .unwrap();
+        let CommandResponse::list_items { items } = response else {
+            panic!("Expected a list_items response")
+        };
+        conn.container.data.all_items_cache =
+            CachedData::filled(CxxrtlContainer::item_list_to_hash_map(items));
+        conn.container
+            .variable_meta(&VariableRef::from_hierarchy_string("top.x"))
+            .unwrap()
+    }
+
+    #[test]
+    fn input_port_meta() {
+        let meta = meta_of_item(
+            r#"{"type": "node", "lsb_at": 0, "width": 1, "input": true, "output": false}"#,
+        );
+        assert_eq!(meta.direction, Some(VariableDirection::Input));
+        assert_eq!(meta.variable_type, Some(VariableType::Port));
+    }
+
+    #[test]
+    fn output_port_meta() {
+        let meta = meta_of_item(
+            r#"{"type": "node", "lsb_at": 0, "width": 8, "input": false, "output": true}"#,
+        );
+        assert_eq!(meta.direction, Some(VariableDirection::Output));
+        assert_eq!(meta.variable_type, Some(VariableType::Port));
+        assert_eq!(meta.num_bits, Some(8));
+    }
+
+    #[test]
+    fn internal_signal_meta() {
+        let meta = meta_of_item(r#"{"width": 4}"#);
+        assert_eq!(meta.direction, None);
+        assert_eq!(meta.variable_type, None);
+    }
+
+    #[test]
+    fn warming_caches_emits_loaded_events() {
+        let mut conn = TestConnection::new();
+        let events = conn.container.subscribe();
+        conn.container.warm_caches();
+
+        let commands = conn
+            .sent_messages()
+            .into_iter()
+            .skip(1)
+            .map(|m| m["command"].as_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(commands, vec!["list_scopes", "list_items"]);
+        assert!(events.try_recv().is_err());
+
+        conn.receive(r#"{"type": "response", "command": "list_scopes", "scopes": {"top": {}}}"#);
+        conn.receive(
+            r#"{"type": "response", "command": "list_items", "items": {"top x": {"width": 1}}}"#,
+        );
+
+        assert_eq!(
+            events.try_iter().collect::<Vec<_>>(),
+            vec![CxxrtlEvent::ScopesLoaded, CxxrtlEvent::ItemsLoaded]
+        );
+    }
+
+    #[test]
+    fn reset_clears_caches_and_rereferences_signals() {
+        let mut conn = TestConnection::new();
+        conn.set_status(30);
+        let a = VariableRef::from_hierarchy_string("top.a");
+        conn.container.data.all_items_cache = CachedData::filled(HashMap::from([(
+            a.clone(),
+            CxxrtlItem {
+                width: 1,
+                ..Default::default()
+            },
+        )]));
+        conn.container.load_variables([a.clone()].iter());
+        conn.receive(r#"{"type": "response", "command": "reference_items"}"#);
+        conn.container.query_variable(&a, &BigUint::from(0u32));
+        conn.container
+            .data
+            .memories
+            .insert(a.clone(), CachedData::filled(BTreeMap::new()));
+        conn.sent_messages();
+
+        conn.container.reset();
+        assert_eq!(
+            conn.sent_messages()
+                .into_iter()
+                .map(|m| m["command"].as_str().unwrap().to_string())
+                .collect::<Vec<_>>(),
+            vec!["reset_simulation"]
+        );
+        // Nothing changes until the simulator confirms the reset
+        assert!(!conn.container.data.memories.is_empty());
+        assert_eq!(
+            conn.container
+                .raw_simulation_status()
+                .unwrap()
+                .latest_time
+                .as_femtoseconds(),
+            BigUint::from(30u32)
+        );
+
+        // The query made before the reset is answered first, the reset discards its values
+        conn.receive(
+            r#"{
+                "type": "response",
+                "command": "query_interval",
+                "samples": [{"time": "0.000000000000000", "item_values": "AQAAAA=="}]
+            }"#,
+        );
+        conn.receive(r#"{"type": "response", "command": "reset_simulation"}"#);
+
+        let commands = conn
+            .sent_messages()
+            .into_iter()
+            .map(|m| m["command"].as_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(commands, vec!["reference_items"]);
+        assert!(conn.container.data.memories.is_empty());
+        let status = conn.container.raw_simulation_status().unwrap();
+        assert!(matches!(status.status, SimulationStatusType::paused));
+        assert_eq!(status.latest_time.as_femtoseconds(), BigUint::from(0u32));
+        assert!(conn.container.data.references["scope:top"]
+            .covered
+            .is_empty());
+    }
+
+    #[test]
+    fn step_back_rewinds_when_supported() {
+        let mut conn = TestConnection::new();
+        conn.receive(
+            r#"{"type":"greeting","version":0,"commands":["rewind_simulation"],"events":[],"features":{}}"#,
+        );
+        conn.set_status(1000);
+        conn.container.set_step_period(Some(BigUint::from(300u32)));
+
+        conn.container.step_back(2);
+        // Nothing changes until the agent has rewound
+        let status = conn.container.raw_simulation_status().unwrap();
+        assert_eq!(status.latest_time.as_femtoseconds(), BigUint::from(1000u32));
+        conn.receive(r#"{"type": "response", "command": "rewind_simulation"}"#);
+        let status = conn.container.raw_simulation_status().unwrap();
+        assert!(matches!(status.status, SimulationStatusType::paused));
+        assert_eq!(status.latest_time.as_femtoseconds(), BigUint::from(400u32));
+
+        // Stepping back past the start stops at time zero
+        conn.container.step_back(5);
+
+        let rewinds = conn
+            .sent_messages()
+            .into_iter()
+            .filter(|m| m["command"] == "rewind_simulation")
+            .map(|m| m["to_time"].as_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(rewinds, vec!["0.000000000000400", "0.000000000000000"]);
+    }
+
+    #[test]
+    fn failed_rewinds_keep_the_simulation_state() {
+        let (mut conn, counter) = counter_connection();
+        conn.receive(
+            r#"{"type":"greeting","version":0,"commands":["rewind_simulation"],"events":[],"features":{}}"#,
+        );
+        conn.set_status(1000);
+        conn.container.set_step_period(Some(BigUint::from(300u32)));
+        conn.container.load_variables([counter.clone()].iter());
+        conn.receive(r#"{"type": "response", "command": "reference_items"}"#);
+        conn.container
+            .data
+            .references
+            .get_mut("scope:top")
+            .unwrap()
+            .covered
+            .insert(BigUint::zero(), BigUint::from(1000u32));
+
+        conn.container.step_back(2);
+        conn.receive(r#"{"type": "error", "error": "", "message": "hiccup"}"#);
+
+        assert_eq!(
+            conn.container.data.references["scope:top"]
+                .covered
+                .intervals(),
+            &[(BigUint::zero(), BigUint::from(1000u32))]
+        );
+        let status = conn.container.raw_simulation_status().unwrap();
+        assert_eq!(status.latest_time.as_femtoseconds(), BigUint::from(1000u32));
+    }
+
+    #[test]
+    fn step_back_keeps_the_values_before_the_rewind() {
+        let (mut conn, counter) = counter_connection();
+        conn.receive(
+            r#"{"type":"greeting","version":0,"commands":["rewind_simulation"],"events":[],"features":{}}"#,
+        );
+        conn.set_status(1000);
+        conn.container.set_step_period(Some(BigUint::from(300u32)));
+        conn.container.load_variables([counter.clone()].iter());
+        conn.receive(r#"{"type": "response", "command": "reference_items"}"#);
+        conn.container
+            .data
+            .references
+            .get_mut("scope:top")
+            .unwrap()
+            .covered
+            .insert(BigUint::zero(), BigUint::from(1000u32));
+
+        conn.container.step_back(2);
+        conn.receive(r#"{"type": "response", "command": "rewind_simulation"}"#);
+
+        assert_eq!(
+            conn.container.data.references["scope:top"]
+                .covered
+                .intervals(),
+            &[(BigUint::zero(), BigUint::from(400u32))]
+        );
+        conn.container
+            .query_variable(&counter, &BigUint::from(200u32));
+        assert!(!conn
+            .sent_messages()
+            .iter()
+            .any(|m| m["command"] == "query_interval"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn stdio_agents_get_their_arguments_and_environment() {
+        // The agent greets with the command given as its argument, which shows that
+        // both the arguments and the environment were passed on
+        let script = r#"printf '%s\0' "$(printf "$GREETING" "$1")"; cat > /dev/null"#;
+        let (msg_tx, _msg_rx) = std::sync::mpsc::channel();
+        let mut container = CxxrtlContainer::new_stdio(
+            "sh",
+            &[
+                "-c".to_string(),
+                script.to_string(),
+                "sh".to_string(),
+                "rewind_simulation".to_string(),
+            ],
+            &[(
+                "GREETING".to_string(),
+                r#"{"type":"greeting","version":0,"commands":["%s"],"events":[],"features":{}}"#
+                    .to_string(),
+            )],
+            msg_tx,
+        )
+        .await
+        .unwrap();
+
+        for _ in 0..500 {
+            container.tick();
+            if container.can_rewind {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(container.can_rewind);
+        assert!(container.is_connected());
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[tokio::test]
+    async fn missing_stdio_agents_name_the_binary() {
+        let (msg_tx, _msg_rx) = std::sync::mpsc::channel();
+        let Err(e) = CxxrtlContainer::new_stdio("surfer-no-such-agent", &[], &[], msg_tx).await
+        else {
+            panic!("Spawning a missing binary should fail");
+        };
+        assert!(matches!(e, CxxrtlError::Connect { .. }));
+        assert!(e.to_string().contains("surfer-no-such-agent"));
+    }
+
+    #[test]
+    fn step_back_reports_missing_rewind_support() {
+        let mut conn = TestConnection::new();
+        conn.receive(r#"{"type":"greeting","version":0,"commands":[],"events":[],"features":{}}"#);
+        conn.set_status(1000);
+
+        conn.container.step_back(1);
+
+        assert!(conn.sent_messages().is_empty());
+        assert_eq!(
+            conn.container
+                .raw_simulation_status()
+                .unwrap()
+                .latest_time
+                .as_femtoseconds(),
+            BigUint::from(1000u32)
+        );
+        assert!(conn
+            .messages
+            .try_iter()
+            .any(|m| matches!(m, Message::Error(e) if e.to_string().contains("stepping back"))));
+    }
+
+    #[test]
+    fn child_scopes_of_indexed_generate_blocks() {
+        let mut conn = TestConnection::new();
+        conn.container.child_scopes(&ScopeRef::empty());
+        conn.receive(r#"{"type": "response", "command": "list_scopes", "scopes": {"top": {}}}"#);
+        assert_eq!(
+            conn.container.child_scopes(&ScopeRef::empty()),
+            vec![ScopeRef::from_strs(&["top"])]
+        );
+
+        conn.container.child_scopes(&ScopeRef::from_strs(&["top"]));
+        conn.receive(
+            r#"{
+                "type": "response",
+                "command": "list_scopes",
+                "scopes": {"top genblk[0]": {}, "top genblk[1]": {}, "top genblk[0] inner": {}}
+            }"#,
+        );
+        let mut children = conn
+            .container
+            .child_scopes(&ScopeRef::from_strs(&["top"]))
+            .into_iter()
+            .map(|scope| scope.strs().to_vec())
+            .collect::<Vec<_>>();
+        children.sort();
+        assert_eq!(
+            children,
+            vec![vec!["top", "genblk[0]"], vec!["top", "genblk[1]"]]
+        );
+
+        let genblk = ScopeRef::from_strs(&["top", "genblk[0]"]);
+        assert!(conn.container.module_exists(&genblk));
+        conn.container.child_scopes(&genblk);
+        conn.receive(
+            r#"{"type": "response", "command": "list_scopes", "scopes": {"top genblk[0] inner": {}}}"#,
+        );
+        assert_eq!(
+            conn.container.child_scopes(&genblk),
+            vec![ScopeRef::from_strs(&["top", "genblk[0]", "inner"])]
+        );
+    }
+
+    #[test]
+    fn items_in_indexed_generate_blocks_are_parsed() {
+        let items = CxxrtlContainer::item_list_to_hash_map(HashMap::from([(
+            "top genblk[0] x".to_string(),
+            CxxrtlItem::default(),
+        )]));
+        let variable = items.keys().next().unwrap();
+        assert_eq!(variable.path, ScopeRef::from_strs(&["top", "genblk[0]"]));
+        assert_eq!(variable.name, "x");
+    }
+
+    #[tokio::test]
+    async fn only_newly_simulated_ranges_are_queried() {
+        let mut conn = TestConnection::new();
+        conn.set_status(100);
+        let a = VariableRef::from_hierarchy_string("top.a");
+        conn.container.data.all_items_cache = CachedData::filled(HashMap::from([(
+            a.clone(),
+            CxxrtlItem {
+                width: 1,
+                ..Default::default()
+            },
+        )]));
+        conn.container.load_variables([a.clone()].iter());
+        conn.receive(r#"{"type": "response", "command": "reference_items"}"#);
+
+        let queried_intervals = |conn: &mut TestConnection| {
+            conn.sent_messages()
+                .into_iter()
+                .filter(|m| m["command"] == "query_interval")
+                .map(|m| m["interval"].clone())
+                .collect::<Vec<_>>()
+        };
+
+        conn.container.query_variable(&a, &BigUint::zero());
+        // Values which are already being queried are not requested again
+        conn.container.query_variable(&a, &BigUint::zero());
+        assert_eq!(
+            queried_intervals(&mut conn),
+            vec![serde_json::json!([
+                "0.000000000000000",
+                "0.000000000000100"
+            ])]
+        );
+        conn.receive(
+            r#"{
+                "type": "response",
+                "command": "query_interval",
+                "samples": [{"time": "0.000000000000000", "item_values": "AAAAAA=="}]
+            }"#,
+        );
+
+        conn.container
+            .data
+            .on_simulation_status_update(CxxrtlSimulationStatus {
+                status: SimulationStatusType::paused,
+                latest_time: CxxrtlTimestamp::from_femtoseconds(BigUint::from(250u32)),
+            });
+        conn.container.query_variable(&a, &BigUint::zero());
+        assert_eq!(
+            queried_intervals(&mut conn),
+            vec![serde_json::json!([
+                "0.000000000000101",
+                "0.000000000000250"
+            ])]
+        );
+        conn.receive(
+            r#"{
+                "type": "response",
+                "command": "query_interval",
+                "samples": [{"time": "0.000000000000101", "item_values": "AQAAAA=="}]
+            }"#,
+        );
+        assert_eq!(
+            conn.container
+                .max_displayed_timestamp()
+                .map(|t| t.as_femtoseconds()),
+            Some(BigUint::from(250u32))
+        );
+
+        // Wait for the values to be decoded before the message receiver is dropped
+        for _ in 0..100 {
+            let result = conn.container.query_variable(&a, &BigUint::from(200u32));
+            if result.and_then(|r| r.current).is_some() {
+                return;
+            }
+            tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
+        }
+        panic!("Values were never populated");
+    }
+
+    #[test]
+    fn expanding_a_scope_only_lists_its_children() {
+        let mut conn = TestConnection::new();
+        let top = ScopeRef::from_strs(&["top"]);
+
+        assert!(conn.container.child_scopes(&top).is_empty());
+        let requests = conn
+            .sent_messages()
+            .into_iter()
+            .filter(|m| m["command"] == "list_scopes")
+            .map(|m| m["scope"].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(requests, vec![serde_json::json!("top")]);
+
+        conn.receive(
+            r#"{
+                "type": "response",
+                "command": "list_scopes",
+                "scopes": {"top a": {}, "top b": {}, "top a x": {}}
+            }"#,
+        );
+        let mut children = conn.container.child_scopes(&top);
+        children.sort_by_key(|scope| scope.cxxrtl_repr());
+        assert_eq!(
+            children,
+            vec![
+                ScopeRef::from_strs(&["top", "a"]),
+                ScopeRef::from_strs(&["top", "b"])
+            ]
+        );
+        assert!(conn
+            .container
+            .module_exists(&ScopeRef::from_strs(&["top", "b"])));
+        assert!(conn.sent_messages().is_empty());
+        assert!(matches!(
+            conn.container.data.scopes_cache,
+            CachedData::Uncached { prev: None }
+        ));
+    }
+
+    #[test]
+    fn unexpected_status_response_makes_status_refetchable() {
+        let mut conn = TestConnection::new();
+        assert!(conn.container.raw_simulation_status().is_none());
+        assert!(matches!(
+            conn.container.data.simulation_status,
+            CachedData::Waiting { .. }
+        ));
+        conn.sent_messages();
+
+        conn.receive(r#"{"type": "response", "command": "run_simulation"}"#);
+        assert!(matches!(
+            conn.container.data.simulation_status,
+            CachedData::Uncached { .. }
+        ));
+        assert!(conn
+            .messages
+            .try_iter()
+            .any(|m| matches!(m, Message::Error(_))));
+
+        conn.container.raw_simulation_status();
+        let commands = conn
+            .sent_messages()
+            .into_iter()
+            .map(|m| m["command"].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(commands, vec![serde_json::json!("get_simulation_status")]);
+    }
+
+    #[test]
+    fn error_responses_make_status_refetchable() {
+        let mut conn = TestConnection::new();
+        conn.container.raw_simulation_status();
+        conn.receive(r#"{"type": "error", "error": "", "message": "hiccup"}"#);
+        assert!(matches!(
+            conn.container.data.simulation_status,
+            CachedData::Uncached { .. }
+        ));
+        assert!(conn
+            .messages
+            .try_iter()
+            .any(|m| matches!(m, Message::Error(_))));
+    }
+
+    #[test]
+    fn querying_while_status_is_in_flight_does_not_block() {
+        let mut conn = TestConnection::new();
+        let var = VariableRef::from_hierarchy_string("top.a");
+        conn.container.data.all_items_cache = CachedData::filled(HashMap::from([(
+            var.clone(),
+            CxxrtlItem {
+                width: 1,
+                ..Default::default()
+            },
+        )]));
+        conn.container.load_variables([var.clone()].iter());
+
+        // Requests the status, which stays in flight
+        assert!(conn.container.simulation_status().is_none());
+        let (done_tx, done_rx) = std::sync::mpsc::channel();
+        let mut container = conn.container;
+        let handle = std::thread::spawn(move || {
+            let result = container.query_variable(&var, &BigUint::from(5u32));
+            done_tx.send(result.is_none()).unwrap();
+            container
+        });
+        assert_eq!(
+            done_rx.recv_timeout(std::time::Duration::from_secs(5)),
+            Ok(true)
+        );
+        let mut container = handle.join().unwrap();
+
+        // Only the status was requested, the values are queried once it is known
+        let status_requests = std::iter::from_fn(|| conn.cs_messages.try_recv().ok())
+            .map(|m| serde_json::from_str::<serde_json::Value>(&m).unwrap())
+            .filter(|m| m["command"] != "reference_items")
+            .map(|m| m["command"].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            status_requests,
+            vec![serde_json::json!("get_simulation_status")]
+        );
+        assert!(container.simulation_status().is_none());
+    }
+
+    #[test]
+    fn intermediate_statuses_update_run_progress() {
+        let mut conn = TestConnection::new();
+        conn.container.status_poll_interval = Duration::ZERO;
+        conn.set_status(100);
+        assert_eq!(conn.container.run_progress(), None);
+
+        conn.container.set_step_period(Some(BigUint::from(900u32)));
+        conn.container.step(1);
+        conn.receive(r#"{"type": "response", "command": "run_simulation"}"#);
+        assert_eq!(conn.container.run_progress(), Some(0.1));
+
+        let polls = |conn: &mut TestConnection| {
+            conn.sent_messages()
+                .into_iter()
+                .filter(|m| m["command"] == "get_simulation_status")
+                .count()
+        };
+        assert_eq!(polls(&mut conn), 1);
+        conn.receive(
+            r#"{
+                "type": "response",
+                "command": "get_simulation_status",
+                "status": "running",
+                "latest_time": "0.000000000000500"
+            }"#,
+        );
+        assert_eq!(conn.container.run_progress(), Some(0.5));
+
+        conn.receive(
+            r#"{
+                "type": "event",
+                "event": "simulation_paused",
+                "time": "0.000000000001000",
+                "cause": "until_time"
+            }"#,
+        );
+        assert_eq!(conn.container.run_progress(), None);
+        // Polling stops once the simulation is paused
+        conn.sent_messages();
+        conn.container.tick();
+        assert_eq!(polls(&mut conn), 0);
+    }
 }
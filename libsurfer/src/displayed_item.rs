@@ -16,6 +16,8 @@ use crate::{
 };
 
 const DEFAULT_DIVIDER_NAME: &str = "";
+/// The range which item height scaling factors are clamped to
+pub const HEIGHT_SCALING_FACTOR_RANGE: std::ops::RangeInclusive<f32> = 0.5..=16.0;
 
 /// Key for the [`crate::wave_data::WaveData::displayed_items`] hash map
 #[derive(Serialize, Deserialize, Debug, Copy, Clone, Eq, PartialEq, Hash, PartialOrd, Ord)]
@@ -69,6 +71,16 @@ pub struct FieldFormat {
     pub format: String,
 }
 
+/// How the values of a variable are drawn
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum VariableRenderMode {
+    /// Values are drawn as boxes containing the translated value
+    #[default]
+    Default,
+    /// Numeric values are plotted as a line, scaled to the values in the visible window
+    Analog,
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct DisplayedVariable {
     pub variable_ref: VariableRef,
@@ -82,6 +94,8 @@ pub struct DisplayedVariable {
     pub format: Option<String>,
     pub field_formats: Vec<FieldFormat>,
     pub height_scaling_factor: Option<f32>,
+    #[serde(default)]
+    pub render_mode: VariableRenderMode,
 }
 
 impl DisplayedVariable {
@@ -128,6 +142,7 @@ impl DisplayedVariable {
             format: self.format,
             field_formats: self.field_formats,
             height_scaling_factor: self.height_scaling_factor,
+            render_mode: self.render_mode,
         }
     }
 }
@@ -190,6 +205,8 @@ pub struct DisplayedPlaceholder {
     pub format: Option<String>,
     pub field_formats: Vec<FieldFormat>,
     pub height_scaling_factor: Option<f32>,
+    #[serde(default)]
+    pub render_mode: VariableRenderMode,
 }
 
 impl DisplayedPlaceholder {
@@ -209,6 +226,7 @@ impl DisplayedPlaceholder {
             format: self.format,
             field_formats: self.field_formats,
             height_scaling_factor: self.height_scaling_factor,
+            render_mode: self.render_mode,
         }
     }
 }
@@ -422,7 +440,16 @@ impl DisplayedItem {
         .unwrap_or(1.0)
     }
 
+    /// Sets the height scaling factor of variables and placeholders, clamped to
+    /// [`HEIGHT_SCALING_FACTOR_RANGE`]
     pub fn set_height_scaling_factor(&mut self, scale: f32) {
+        if scale.is_nan() {
+            return;
+        }
+        let scale = scale.clamp(
+            *HEIGHT_SCALING_FACTOR_RANGE.start(),
+            *HEIGHT_SCALING_FACTOR_RANGE.end(),
+        );
         match self {
             DisplayedItem::Variable(variable) => variable.height_scaling_factor = Some(scale),
             DisplayedItem::Placeholder(placeholder) => {
@@ -431,6 +458,22 @@ impl DisplayedItem {
             _ => {}
         }
     }
+
+    pub fn render_mode(&self) -> VariableRenderMode {
+        match self {
+            DisplayedItem::Variable(variable) => variable.render_mode,
+            DisplayedItem::Placeholder(placeholder) => placeholder.render_mode,
+            _ => VariableRenderMode::Default,
+        }
+    }
+
+    pub fn set_render_mode(&mut self, render_mode: VariableRenderMode) {
+        match self {
+            DisplayedItem::Variable(variable) => variable.render_mode = render_mode,
+            DisplayedItem::Placeholder(placeholder) => placeholder.render_mode = render_mode,
+            _ => {}
+        }
+    }
 }
 
 pub fn draw_rename_window(
@@ -477,6 +477,41 @@ impl DisplayedItemTree {
         Ok(())
     }
 
+    /// Move an item (and its subtree) so that it ends up at `position` in the list of all
+    /// items. `position` is clamped to the positions available to the subtree.
+    ///
+    /// The item takes the level of the item which follows it after the move, so it joins
+    /// the group it is moved into.
+    pub fn move_to_position(
+        &mut self,
+        ItemIndex(idx): ItemIndex,
+        position: usize,
+    ) -> Result<(), MoveError> {
+        if idx >= self.items.len() {
+            return Err(MoveError::InvalidIndex);
+        }
+        let len = self.subtree_end(idx) - idx;
+        let position = position.min(self.items.len() - len);
+        // `position` is an index into the items without the moved subtree
+        let (before, next) = if position <= idx {
+            (position, position)
+        } else {
+            (position + len, position + len)
+        };
+        let level = if position == 0 {
+            0
+        } else {
+            self.items.get(next).map_or(0, |node| node.level)
+        };
+        self.move_items(
+            vec![ItemIndex(idx)],
+            TargetPosition {
+                before: ItemIndex(before),
+                level,
+            },
+        )
+    }
+
     /// Return the range of valid levels for inserting above `item`, given the visible nodes
     ///
     /// `f` will be called with what will become the in-order predecessor node
@@ -1453,4 +1488,36 @@ mod tests {
             0..4
         );
     }
+
+    #[test]
+    fn test_move_to_position() {
+        let refs = |tree: &DisplayedItemTree| tree.items.iter().map(|x| x.item_ref.0).collect_vec();
+        let levels = |tree: &DisplayedItemTree| tree.items.iter().map(|x| x.level).collect_vec();
+
+        let mut tree = test_tree();
+        tree.move_to_position(ItemIndex(5), 0).unwrap();
+        assert_eq!(refs(&tree), vec![3, 30, 31, 0, 1, 2, 20, 200, 4, 5]);
+        assert_eq!(levels(&tree), vec![0, 1, 1, 0, 0, 0, 1, 2, 0, 0]);
+
+        let mut tree = test_tree();
+        tree.move_to_position(ItemIndex(0), 7).unwrap();
+        assert_eq!(refs(&tree), vec![1, 2, 20, 200, 3, 30, 31, 0, 4, 5]);
+        assert_eq!(tree.items[7].level, 0);
+
+        // Moving in front of a group member moves the item into the group
+        let mut tree = test_tree();
+        tree.move_to_position(ItemIndex(8), 7).unwrap();
+        assert_eq!(refs(&tree), vec![0, 1, 2, 20, 200, 3, 30, 4, 31, 5]);
+        assert_eq!(tree.items[7].level, 1);
+
+        let mut tree = test_tree();
+        tree.move_to_position(ItemIndex(0), 100).unwrap();
+        assert_eq!(refs(&tree), vec![1, 2, 20, 200, 3, 30, 31, 4, 5, 0]);
+        assert_eq!(tree.items[9].level, 0);
+
+        assert_eq!(
+            test_tree().move_to_position(ItemIndex(10), 0),
+            Err(MoveError::InvalidIndex)
+        );
+    }
 }
@@ -17,12 +17,15 @@ use std::collections::HashMap;
 use std::f32::consts::PI;
 use surfer_translation_types::{
     SubFieldFlatTranslationResult, TranslatedValue, ValueKind, VariableInfo, VariableType,
+    VariableValue,
 };
 
 use crate::clock_highlighting::draw_clock_edge;
 use crate::config::SurferTheme;
 use crate::data_container::DataContainer;
-use crate::displayed_item::{DisplayedFieldRef, DisplayedItemRef, DisplayedVariable};
+use crate::displayed_item::{
+    DisplayedFieldRef, DisplayedItemRef, DisplayedVariable, VariableRenderMode,
+};
 use crate::displayed_item_tree::VisibleItemIndex;
 use crate::transaction_container::{TransactionRef, TransactionStreamRef};
 use crate::translation::{TranslationResultExt, TranslatorList, ValueKindExt, VariableInfoExt};
@@ -42,6 +45,8 @@ pub struct DrawnRegion {
     /// between the previous and next pixels. Only used by the bool drawing logic to
     /// draw draw a vertical line and prevent apparent aliasing
     force_anti_alias: bool,
+    /// The numeric value of the region, only used when drawing analog variables
+    numeric: Option<f64>,
 }
 
 /// List of values to draw for a variable. It is an ordered list of values that should
@@ -49,6 +54,7 @@ pub struct DrawnRegion {
 pub struct DrawingCommands {
     is_bool: bool,
     is_clock: bool,
+    is_analog: bool,
     values: Vec<(f32, DrawnRegion)>,
 }
 
@@ -58,6 +64,7 @@ impl DrawingCommands {
             values: vec![],
             is_bool: true,
             is_clock: false,
+            is_analog: false,
         }
     }
 
@@ -66,6 +73,7 @@ impl DrawingCommands {
             values: vec![],
             is_bool: true,
             is_clock: true,
+            is_analog: false,
         }
     }
 
@@ -74,6 +82,16 @@ impl DrawingCommands {
             values: vec![],
             is_bool: false,
             is_clock: false,
+            is_analog: false,
+        }
+    }
+
+    pub fn new_analog() -> Self {
+        Self {
+            values: vec![],
+            is_bool: false,
+            is_clock: false,
+            is_analog: true,
         }
     }
 
@@ -121,6 +139,8 @@ fn variable_draw_commands(
         }
     };
 
+    let analog = displayed_variable.render_mode == VariableRenderMode::Analog;
+
     let displayed_field_ref: DisplayedFieldRef = display_id.into();
     let translator = waves.variable_translator(&displayed_field_ref, translators);
     // we need to get the variable info here to get the correct info for aliases
@@ -209,8 +229,12 @@ fn variable_draw_commands(
         );
 
         for SubFieldFlatTranslationResult { names, value } in fields {
+            // Only the root variable is drawn as a line in analog mode, since the
+            // translated fields are not necessarily numeric
+            let is_analog = analog && names.is_empty();
             let entry = local_commands.entry(names.clone()).or_insert_with(|| {
                 match info.get_subinfo(&names) {
+                    _ if is_analog => DrawingCommands::new_analog(),
                     VariableInfo::Bool => DrawingCommands::new_bool(),
                     VariableInfo::Clock => DrawingCommands::new_clock(),
                     _ => DrawingCommands::new_wide(),
@@ -253,6 +277,10 @@ fn variable_draw_commands(
                     DrawnRegion {
                         inner: value,
                         force_anti_alias: anti_alias && !new_value,
+                        numeric: match &val {
+                            VariableValue::BigUint(v) if is_analog => v.to_f64(),
+                            _ => None,
+                        },
                     },
                 ));
             }
@@ -772,12 +800,15 @@ impl SystemState {
         #[cfg(feature = "performance_plot")]
         self.timing.borrow_mut().end("Wave drawing");
 
-        waves.draw_graphics(
+        let graphic_bounds = waves.draw_graphics(
             &mut ctx,
             response.rect.size(),
             &waves.viewports[viewport_idx],
             &self.user.config.theme,
         );
+        self.graphic_bounds
+            .borrow_mut()
+            .insert(viewport_idx, graphic_bounds);
 
         waves.draw_cursor(
             &self.user.config.theme,
@@ -924,6 +955,10 @@ impl SystemState {
                                 &self.user.config.theme.variable_default
                             }
                         });
+                        if commands.is_analog {
+                            self.draw_analog(commands, color, y_offset, height_scaling_factor, ctx);
+                            continue;
+                        }
                         for (old, new) in commands.values.iter().zip(commands.values.iter().skip(1))
                         {
                             if commands.is_bool {
@@ -1234,6 +1269,47 @@ impl SystemState {
         }
     }
 
+    /// Draws the numeric values of `commands` as a line, scaled such that the smallest
+    /// value is at the bottom of the row and the largest at the top
+    fn draw_analog(
+        &self,
+        commands: &DrawingCommands,
+        color: Color32,
+        offset: f32,
+        height_scaling_factor: f32,
+        ctx: &mut DrawingContext,
+    ) {
+        let (min, max) = commands
+            .values
+            .iter()
+            .filter_map(|(_, region)| region.numeric)
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), v| {
+                (min.min(v), max.max(v))
+            });
+        let stroke = Stroke {
+            color,
+            width: self.user.config.theme.linewidth,
+        };
+        let trace_coords =
+            |x, y| (ctx.to_screen)(x, y * ctx.cfg.line_height * height_scaling_factor + offset);
+
+        // Regions without a numeric value, e.g. ones containing x or z, interrupt the line
+        let mut points = vec![];
+        for ((old_x, prev_region), (new_x, _)) in commands.values.iter().tuple_windows() {
+            if let Some(value) = prev_region.numeric {
+                let y = analog_y(value, min, max);
+                points.push(trace_coords(*old_x, y));
+                points.push(trace_coords(*new_x, y));
+            } else if !points.is_empty() {
+                ctx.painter
+                    .add(PathShape::line(std::mem::take(&mut points), stroke));
+            }
+        }
+        if !points.is_empty() {
+            ctx.painter.add(PathShape::line(points, stroke));
+        }
+    }
+
     fn draw_bool_transition(
         &self,
         ((old_x, prev_region), (new_x, new_region)): (&(f32, DrawnRegion), &(f32, DrawnRegion)),
@@ -1556,6 +1632,16 @@ impl VariableExt for String {
     }
 }
 
+/// Maps `value` to a y coordinate within a row where 0 is the top and 1 is the bottom,
+/// such that `min` is at the bottom and `max` is at the top
+fn analog_y(value: f64, min: f64, max: f64) -> f32 {
+    if max > min {
+        (1. - (value - min) / (max - min)) as f32
+    } else {
+        0.5
+    }
+}
+
 fn handle_transaction_tooltip(
     response: Response,
     waves: &WaveData,
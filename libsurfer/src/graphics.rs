@@ -1,16 +1,18 @@
+use std::str::FromStr;
+
 use eframe::{
-    emath::{Align, Align2},
-    epaint::{Color32, CubicBezierShape, FontId, Shape, Stroke, Vec2},
+    emath::{Align, Align2, Pos2, Rect},
+    epaint::{Color32, CornerRadiusF32, CubicBezierShape, FontId, Shape, Stroke, StrokeKind, Vec2},
 };
 use num::BigInt;
 use serde::{Deserialize, Serialize};
 
 use crate::{
     config::SurferTheme, displayed_item::DisplayedItemRef, view::DrawingContext,
-    viewport::Viewport, wave_data::WaveData,
+    viewport::Viewport, wave_data::WaveData, SystemState,
 };
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub enum Direction {
     North,
     East,
@@ -18,6 +20,22 @@ pub enum Direction {
     West,
 }
 
+impl FromStr for Direction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "North" => Ok(Direction::North),
+            "East" => Ok(Direction::East),
+            "South" => Ok(Direction::South),
+            "West" => Ok(Direction::West),
+            _ => Err(format!(
+                "Unknown direction '{s}', expected one of North, East, South, West"
+            )),
+        }
+    }
+}
+
 impl Direction {
     pub fn as_vector(&self) -> Vec2 {
         match self {
@@ -27,43 +45,163 @@ impl Direction {
             Direction::West => Vec2::new(1., 0.),
         }
     }
+
+    /// The alignment of a label placed at a point approached from this direction, which
+    /// keeps the label on the side of the point facing away from the approach
+    pub fn label_align(&self) -> Align2 {
+        match self {
+            Direction::North => Align2([Align::Center, Align::TOP]),
+            Direction::East => Align2([Align::LEFT, Align::Center]),
+            Direction::South => Align2([Align::Center, Align::BOTTOM]),
+            Direction::West => Align2([Align::RIGHT, Align::Center]),
+        }
+    }
+}
+
+/// The distance from an arrow end point to its bezier control point
+const ARROW_CONTROL_LENGTH: f32 = 30.;
+
+/// The canvas geometry of a [`Graphic::TextArrow`]
+#[derive(Debug, PartialEq)]
+pub struct TextArrowGeometry {
+    /// The cubic bezier points from the source to the destination
+    pub points: [Pos2; 4],
+    /// Where the label is placed
+    pub label_pos: Pos2,
+    /// How the label is aligned relative to `label_pos`
+    pub label_align: Align2,
+}
+
+impl TextArrowGeometry {
+    /// Computes the geometry of an arrow from `from` to `to` in canvas coordinates. The
+    /// arrow leaves and enters its end points along the given directions, so vertically
+    /// stacked items can be connected with `North`/`South` ends.
+    pub fn new(from: Pos2, from_dir: &Direction, to: Pos2, to_dir: &Direction) -> Self {
+        Self {
+            points: [
+                from,
+                from + from_dir.as_vector() * ARROW_CONTROL_LENGTH,
+                to + to_dir.as_vector() * ARROW_CONTROL_LENGTH,
+                to,
+            ],
+            label_pos: to,
+            label_align: to_dir.label_align(),
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub enum Anchor {
     Top,
     Center,
     Bottom,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl FromStr for Anchor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Top" => Ok(Anchor::Top),
+            "Center" => Ok(Anchor::Center),
+            "Bottom" => Ok(Anchor::Bottom),
+            _ => Err(format!(
+                "Unknown anchor '{s}', expected one of Top, Center, Bottom"
+            )),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct GraphicsY {
     pub item: DisplayedItemRef,
     pub anchor: Anchor,
 }
 
 /// A point used to place graphics.
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct GrPoint {
     /// Timestamp at which to place the graphic
     pub x: BigInt,
     pub y: GraphicsY,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, PartialOrd, Eq, Ord, Hash, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, PartialOrd, Eq, Ord, Hash, Debug, Clone, Copy)]
 pub struct GraphicId(pub usize);
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+pub enum LineStyle {
+    #[default]
+    Solid,
+    Dashed,
+    Dotted,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub enum Graphic {
     TextArrow {
         from: (GrPoint, Direction),
         to: (GrPoint, Direction),
         text: String,
+        /// The color of the arrow and text, the theme's don't care color if `None`
+        #[serde(default)]
+        color: Option<Color32>,
+        /// The size of the text, 15 if `None`
+        #[serde(default)]
+        font_size: Option<f32>,
+        /// The color of the text, `color` if `None`
+        #[serde(default)]
+        text_color: Option<Color32>,
     },
     Text {
         pos: (GrPoint, Direction),
         text: String,
     },
+    /// A vertical line spanning the full height of the canvas, with an optional label at
+    /// the top
+    VLine {
+        time: BigInt,
+        color: Option<Color32>,
+        label: Option<String>,
+        style: LineStyle,
+    },
+    /// A straight line between two points
+    Line {
+        from: GrPoint,
+        to: GrPoint,
+        /// The theme's don't care color if `None`
+        color: Option<Color32>,
+        thickness: f32,
+    },
+    /// A rectangle between two corners, optionally filled
+    Rect {
+        top_left: GrPoint,
+        bottom_right: GrPoint,
+        /// The color of the outline, the theme's don't care color if `None`
+        color: Option<Color32>,
+        fill: Option<Color32>,
+    },
+}
+
+impl Graphic {
+    /// The x coordinate of a vertical line graphic, or `None` if this is not a vertical
+    /// line
+    pub fn vline_x(
+        &self,
+        viewport: &Viewport,
+        view_width: f32,
+        num_timestamps: &BigInt,
+    ) -> Option<f32> {
+        match self {
+            Graphic::VLine { time, .. } => {
+                Some(viewport.pixel_from_time(time, view_width, num_timestamps))
+            }
+            Graphic::TextArrow { .. }
+            | Graphic::Text { .. }
+            | Graphic::Line { .. }
+            | Graphic::Rect { .. } => None,
+        }
+    }
 }
 
 impl WaveData {
@@ -82,22 +220,48 @@ impl WaveData {
             .map(|point| point - self.top_item_draw_offset)
     }
 
+    /// The screen position of `point`, or `None` if its item is not visible
+    fn graphic_point_to_screen(
+        &self,
+        point: &GrPoint,
+        ctx: &DrawingContext,
+        view_width: f32,
+        viewport: &Viewport,
+        num_timestamps: &BigInt,
+    ) -> Option<Pos2> {
+        let x = viewport.pixel_from_time(&point.x, view_width, num_timestamps);
+        let y = self.get_item_y(&point.y)?;
+        Some((ctx.to_screen)(x, y))
+    }
+
+    /// Draws all graphics and returns the screen space bounds of each drawn graphic, in the
+    /// order they were drawn
     pub(crate) fn draw_graphics(
         &self,
         ctx: &mut DrawingContext,
         size: Vec2,
         viewport: &Viewport,
         theme: &SurferTheme,
-    ) {
+    ) -> Vec<(GraphicId, Rect)> {
         let color = theme.variable_dontcare;
         let num_timestamps = self.num_timestamps().unwrap_or(1.into());
-        for g in self.graphics.values() {
+        // Lines and rectangles may extend past the canvas, so keep them within it
+        let clipped_painter = ctx.painter.with_clip_rect(Rect::from_two_pos(
+            (ctx.to_screen)(0., 0.),
+            (ctx.to_screen)(size.x, size.y),
+        ));
+        let mut bounds = vec![];
+        for (id, g) in &self.graphics {
             match g {
                 Graphic::TextArrow {
                     from: (from_point, from_dir),
                     to: (to_point, to_dir),
                     text,
+                    color: arrow_color,
+                    font_size,
+                    text_color,
                 } => {
+                    let color = arrow_color.unwrap_or(color);
                     let from_x = viewport.pixel_from_time(&from_point.x, size.x, &num_timestamps);
                     let from_y = self.get_item_y(&from_point.y);
 
@@ -105,33 +269,29 @@ impl WaveData {
                     let to_y = self.get_item_y(&to_point.y);
 
                     if let (Some(from_y), Some(to_y)) = (from_y, to_y) {
-                        let from_dir = from_dir.as_vector() * 30.;
-                        let to_dir_vec = to_dir.as_vector() * 30.;
+                        let geometry = TextArrowGeometry::new(
+                            Pos2::new(from_x, from_y),
+                            from_dir,
+                            Pos2::new(to_x, to_y),
+                            to_dir,
+                        );
                         let shape = Shape::CubicBezier(CubicBezierShape {
-                            points: [
-                                (ctx.to_screen)(from_x, from_y),
-                                (ctx.to_screen)(from_x + from_dir.x, from_y + from_dir.y),
-                                (ctx.to_screen)(to_x + to_dir_vec.x, to_y + to_dir_vec.y),
-                                (ctx.to_screen)(to_x, to_y),
-                            ],
+                            points: geometry.points.map(|p| (ctx.to_screen)(p.x, p.y)),
                             closed: false,
                             fill: Color32::TRANSPARENT,
                             stroke: Stroke { width: 3., color }.into(),
                         });
+                        let arrow_rect = shape.visual_bounding_rect();
                         ctx.painter.add(shape);
 
-                        ctx.painter.text(
-                            (ctx.to_screen)(to_x, to_y),
-                            match to_dir {
-                                Direction::North => Align2([Align::Center, Align::TOP]),
-                                Direction::East => Align2([Align::LEFT, Align::Center]),
-                                Direction::South => Align2([Align::Center, Align::BOTTOM]),
-                                Direction::West => Align2([Align::RIGHT, Align::Center]),
-                            },
+                        let text_rect = ctx.painter.text(
+                            (ctx.to_screen)(geometry.label_pos.x, geometry.label_pos.y),
+                            geometry.label_align,
                             text,
-                            FontId::monospace(15.),
-                            color,
+                            FontId::monospace(font_size.unwrap_or(15.)),
+                            text_color.unwrap_or(color),
                         );
+                        bounds.push((*id, arrow_rect.union(text_rect)));
                     }
                 }
                 Graphic::Text {
@@ -141,21 +301,311 @@ impl WaveData {
                     let to_x = viewport.pixel_from_time(&pos.x, size.x, &num_timestamps);
                     let to_y = self.get_item_y(&pos.y);
                     if let Some(to_y) = to_y {
-                        ctx.painter.text(
+                        let text_rect = ctx.painter.text(
                             (ctx.to_screen)(to_x, to_y),
-                            match dir {
-                                Direction::North => Align2([Align::Center, Align::TOP]),
-                                Direction::East => Align2([Align::LEFT, Align::Center]),
-                                Direction::South => Align2([Align::Center, Align::BOTTOM]),
-                                Direction::West => Align2([Align::RIGHT, Align::Center]),
-                            },
+                            dir.label_align(),
                             text,
                             FontId::monospace(15.),
                             color,
                         );
+                        bounds.push((*id, text_rect));
+                    }
+                }
+                Graphic::VLine {
+                    time: _,
+                    color: line_color,
+                    label,
+                    style,
+                } => {
+                    let Some(x) = g.vline_x(viewport, size.x, &num_timestamps) else {
+                        continue;
+                    };
+                    // Lines outside of the visible time range are not drawn, so that they
+                    // can not be hit either
+                    if !(0. ..=size.x).contains(&x) {
+                        continue;
+                    }
+                    let line_color = line_color.unwrap_or(theme.foreground);
+                    let stroke = Stroke {
+                        width: 2.,
+                        color: line_color,
+                    };
+                    let points = [(ctx.to_screen)(x, 0.), (ctx.to_screen)(x, size.y)];
+                    match style {
+                        LineStyle::Solid => {
+                            ctx.painter.line_segment(points, stroke);
+                        }
+                        LineStyle::Dashed => {
+                            ctx.painter
+                                .extend(Shape::dashed_line(&points, stroke, 6., 4.));
+                        }
+                        LineStyle::Dotted => {
+                            ctx.painter
+                                .extend(Shape::dotted_line(&points, line_color, 4., 1.));
+                        }
+                    }
+
+                    let mut line_rect = Rect::from_two_pos(points[0], points[1]).expand(2.);
+                    if let Some(label) = label {
+                        let label_rect = ctx.painter.text(
+                            (ctx.to_screen)(x + 3., 0.),
+                            Align2::LEFT_TOP,
+                            label,
+                            FontId::monospace(15.),
+                            line_color,
+                        );
+                        line_rect = line_rect.union(label_rect);
+                    }
+                    bounds.push((*id, line_rect));
+                }
+                Graphic::Line {
+                    from,
+                    to,
+                    color: line_color,
+                    thickness,
+                } => {
+                    let from =
+                        self.graphic_point_to_screen(from, ctx, size.x, viewport, &num_timestamps);
+                    let to =
+                        self.graphic_point_to_screen(to, ctx, size.x, viewport, &num_timestamps);
+                    if let (Some(from), Some(to)) = (from, to) {
+                        clipped_painter.line_segment(
+                            [from, to],
+                            Stroke {
+                                width: *thickness,
+                                color: line_color.unwrap_or(color),
+                            },
+                        );
+                        // Make thin lines possible to hit
+                        let margin = (thickness / 2.).max(2.);
+                        bounds.push((*id, Rect::from_two_pos(from, to).expand(margin)));
+                    }
+                }
+                Graphic::Rect {
+                    top_left,
+                    bottom_right,
+                    color: rect_color,
+                    fill,
+                } => {
+                    let top_left = self.graphic_point_to_screen(
+                        top_left,
+                        ctx,
+                        size.x,
+                        viewport,
+                        &num_timestamps,
+                    );
+                    let bottom_right = self.graphic_point_to_screen(
+                        bottom_right,
+                        ctx,
+                        size.x,
+                        viewport,
+                        &num_timestamps,
+                    );
+                    if let (Some(top_left), Some(bottom_right)) = (top_left, bottom_right) {
+                        let rect = Rect::from_two_pos(top_left, bottom_right);
+                        bounds.push((*id, rect));
+                        clipped_painter.rect(
+                            rect,
+                            CornerRadiusF32::ZERO,
+                            fill.unwrap_or(Color32::TRANSPARENT),
+                            Stroke::new(2., rect_color.unwrap_or(color)),
+                            StrokeKind::Middle,
+                        );
                     }
                 }
             }
         }
+        bounds
+    }
+}
+
+impl SystemState {
+    /// Returns the id of the topmost graphic drawn in the last frame whose bounds contain the
+    /// screen position `pos`. Viewports are searched in order.
+    pub fn graphic_at(&self, pos: Pos2) -> Option<GraphicId> {
+        self.graphic_bounds.borrow().values().find_map(|bounds| {
+            // Later graphics are drawn on top of earlier ones
+            bounds
+                .iter()
+                .rev()
+                .find(|(_, rect)| rect.contains(pos))
+                .map(|(id, _)| *id)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn vline_is_placed_at_its_time() {
+        let vline = Graphic::VLine {
+            time: BigInt::from(25),
+            color: None,
+            label: Some("here".to_string()),
+            style: LineStyle::Solid,
+        };
+        let viewport = Viewport::default();
+        assert_eq!(
+            vline.vline_x(&viewport, 1000., &BigInt::from(100)),
+            Some(250.)
+        );
+        assert_eq!(
+            vline.vline_x(&viewport, 500., &BigInt::from(50)),
+            Some(250.)
+        );
+    }
+
+    #[test]
+    fn anchors_and_directions_are_parsed_by_name() {
+        assert!(matches!("Top".parse(), Ok(Anchor::Top)));
+        assert!(matches!("Bottom".parse(), Ok(Anchor::Bottom)));
+        assert!(matches!("North".parse(), Ok(Direction::North)));
+        assert!(matches!("West".parse(), Ok(Direction::West)));
+    }
+
+    #[test]
+    fn unknown_anchors_and_directions_are_rejected() {
+        let anchor: Result<Anchor, _> = "top".parse();
+        assert_eq!(
+            anchor.unwrap_err(),
+            "Unknown anchor 'top', expected one of Top, Center, Bottom"
+        );
+        let direction: Result<Direction, _> = "Up".parse();
+        assert_eq!(
+            direction.unwrap_err(),
+            "Unknown direction 'Up', expected one of North, East, South, West"
+        );
+    }
+
+    fn point(time: u32, item: usize, anchor: Anchor) -> GrPoint {
+        GrPoint {
+            x: BigInt::from(time),
+            y: GraphicsY {
+                item: DisplayedItemRef(item),
+                anchor,
+            },
+        }
+    }
+
+    fn round_trip(graphic: &Graphic) -> Graphic {
+        serde_json::from_str(&serde_json::to_string(graphic).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn vlines_survive_serialization() {
+        let vline = Graphic::VLine {
+            time: BigInt::from(-3),
+            color: Some(Color32::from_rgb(255, 0, 0)),
+            label: Some("reset".to_string()),
+            style: LineStyle::Dashed,
+        };
+        assert_eq!(round_trip(&vline), vline);
+    }
+
+    #[test]
+    fn lines_survive_serialization() {
+        let line = Graphic::Line {
+            from: point(5, 1, Anchor::Center),
+            to: point(20, 2, Anchor::Top),
+            color: Some(Color32::from_rgba_unmultiplied(255, 0, 0, 128)),
+            thickness: 1.5,
+        };
+        assert_eq!(round_trip(&line), line);
+    }
+
+    #[test]
+    fn text_arrow_styles_survive_serialization() {
+        let arrow = Graphic::TextArrow {
+            from: (point(5, 1, Anchor::Center), Direction::East),
+            to: (point(20, 2, Anchor::Center), Direction::West),
+            text: "here".to_string(),
+            color: None,
+            font_size: Some(24.),
+            text_color: Some(Color32::from_rgba_unmultiplied(255, 255, 0, 255)),
+        };
+        assert_eq!(round_trip(&arrow), arrow);
+    }
+
+    #[test]
+    fn text_arrow_styles_default_to_none() {
+        let arrow = Graphic::TextArrow {
+            from: (point(5, 1, Anchor::Center), Direction::East),
+            to: (point(20, 2, Anchor::Center), Direction::West),
+            text: "here".to_string(),
+            color: None,
+            font_size: Some(24.),
+            text_color: None,
+        };
+        // Graphics serialized before the styles were added lack the fields
+        let mut json = serde_json::to_value(&arrow).unwrap();
+        let fields = json["TextArrow"].as_object_mut().unwrap();
+        fields.remove("font_size");
+        fields.remove("text_color");
+
+        let Graphic::TextArrow {
+            font_size,
+            text_color,
+            ..
+        } = serde_json::from_value(json).unwrap()
+        else {
+            panic!("Expected a text arrow")
+        };
+        assert_eq!(font_size, None);
+        assert_eq!(text_color, None);
+    }
+
+    #[test]
+    fn north_south_arrows_bend_vertically() {
+        // From the bottom of an item down to the top of the item below it
+        let geometry = TextArrowGeometry::new(
+            Pos2::new(100., 40.),
+            &Direction::South,
+            Pos2::new(120., 80.),
+            &Direction::North,
+        );
+        assert_eq!(
+            geometry.points,
+            [
+                Pos2::new(100., 40.),
+                Pos2::new(100., 70.),
+                Pos2::new(120., 50.),
+                Pos2::new(120., 80.),
+            ]
+        );
+        assert_eq!(geometry.label_pos, Pos2::new(120., 80.));
+        assert_eq!(geometry.label_align, Align2([Align::Center, Align::TOP]));
+    }
+
+    #[test]
+    fn east_west_arrows_keep_their_geometry() {
+        let geometry = TextArrowGeometry::new(
+            Pos2::new(10., 20.),
+            &Direction::East,
+            Pos2::new(200., 20.),
+            &Direction::West,
+        );
+        assert_eq!(
+            geometry.points,
+            [
+                Pos2::new(10., 20.),
+                Pos2::new(-20., 20.),
+                Pos2::new(230., 20.),
+                Pos2::new(200., 20.),
+            ]
+        );
+        assert_eq!(geometry.label_align, Align2([Align::RIGHT, Align::Center]));
+    }
+
+    #[test]
+    fn rects_survive_serialization() {
+        let rect = Graphic::Rect {
+            top_left: point(5, 1, Anchor::Top),
+            bottom_right: point(20, 3, Anchor::Bottom),
+            color: None,
+            fill: Some(Color32::from_rgb(0, 0, 255)),
+        };
+        assert_eq!(round_trip(&rect), rect);
     }
 }
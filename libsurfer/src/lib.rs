@@ -26,7 +26,10 @@ pub mod menus;
 pub mod message;
 pub mod mousegestures;
 pub mod overview;
+pub mod query_stream;
 pub mod remote;
+#[cfg(any(target_arch = "wasm32", test))]
+pub mod screenshot;
 pub mod state;
 pub mod state_util;
 pub mod statusbar;
@@ -84,9 +87,10 @@ use surfer_translation_types::Translator;
 pub use system_state::SystemState;
 #[cfg(target_arch = "wasm32")]
 use tokio_stream as _;
-use wcp::{proto::WcpCSMessage, proto::WcpEvent, proto::WcpSCMessage};
+use wcp::{proto::WcpCSMessage, proto::WcpEvent, proto::WcpSCMessage, proto::WcpTagged};
 
 use crate::config::{SurferConfig, SurferTheme};
+use crate::cxxrtl_container::RunUntilValueOutcome;
 use crate::dialog::{OpenSiblingStateFileDialog, ReloadWaveformDialog};
 use crate::displayed_item::{DisplayedFieldRef, DisplayedItem, DisplayedItemRef, FieldFormat};
 use crate::displayed_item_tree::VisibleItemIndex;
@@ -102,7 +106,7 @@ use crate::wasm_util::{perform_work, UrlArgs};
 use crate::wave_container::VariableRefExt;
 use crate::wave_container::{ScopeRefExt, WaveContainer};
 use crate::wave_data::{ScopeType, WaveData};
-use crate::wave_source::{LoadOptions, WaveFormat, WaveSource};
+use crate::wave_source::{LoadOptions, LoadProgress, LoadProgressStatus, WaveFormat, WaveSource};
 use crate::wellen::convert_format;
 
 lazy_static! {
@@ -119,8 +123,10 @@ lazy_static! {
 
 #[cfg(target_arch = "wasm32")]
 lazy_static! {
-    pub(crate) static ref WCP_CS_HANDLER: IngressHandler<WcpCSMessage> = IngressHandler::new();
-    pub(crate) static ref WCP_SC_HANDLER: GlobalChannelTx<WcpSCMessage> = GlobalChannelTx::new();
+    pub(crate) static ref WCP_CS_HANDLER: IngressHandler<WcpTagged<WcpCSMessage>> =
+        IngressHandler::new();
+    pub(crate) static ref WCP_SC_HANDLER: GlobalChannelTx<WcpTagged<WcpSCMessage>> =
+        GlobalChannelTx::new();
 }
 
 #[derive(Default)]
@@ -214,10 +220,10 @@ pub struct Channels {
     pub msg_sender: Sender<Message>,
     pub msg_receiver: Receiver<Message>,
     #[cfg(target_arch = "wasm32")]
-    wcp_c2s_receiver: Option<IngressReceiver<WcpCSMessage>>,
+    wcp_c2s_receiver: Option<IngressReceiver<WcpTagged<WcpCSMessage>>>,
     #[cfg(not(target_arch = "wasm32"))]
-    wcp_c2s_receiver: Option<tokio::sync::mpsc::Receiver<WcpCSMessage>>,
-    wcp_s2c_sender: Option<tokio::sync::mpsc::Sender<WcpSCMessage>>,
+    wcp_c2s_receiver: Option<tokio::sync::mpsc::Receiver<WcpTagged<WcpCSMessage>>>,
+    wcp_s2c_sender: Option<tokio::sync::mpsc::Sender<WcpTagged<WcpSCMessage>>>,
 }
 impl Channels {
     fn new() -> Self {
@@ -288,22 +294,7 @@ impl SystemState {
                 }
             }
             Message::AddVariables(vars) => {
-                if !vars.is_empty() {
-                    let undo_msg = if vars.len() == 1 {
-                        format!("Add variable {}", vars[0].name)
-                    } else {
-                        format!("Add {} variables", vars.len())
-                    };
-                    self.save_current_canvas(undo_msg);
-                    if let Some(waves) = self.user.waves.as_mut() {
-                        if let (Some(cmd), _) = waves.add_variables(&self.translators, vars, None) {
-                            self.load_variables(cmd);
-                        }
-                        self.invalidate_draw_commands();
-                    } else {
-                        error!("Could not load signals, no waveform loaded");
-                    }
-                }
+                self.add_variables(vars);
             }
             Message::AddDivider(name, vidx) => {
                 self.save_current_canvas("Add divider".into());
@@ -420,6 +411,12 @@ impl SystemState {
             Message::SetNameAlignRight(align_right) => {
                 self.user.align_names_right = Some(align_right);
             }
+            Message::SetPcSignal(name) => {
+                self.pc_signal = name;
+            }
+            Message::SetImemSignal(name) => {
+                self.imem_signal = name;
+            }
             Message::FocusItem(idx) => {
                 let Some(waves) = self.user.waves.as_mut() else {
                     return;
@@ -742,6 +739,13 @@ impl SystemState {
                             else {
                                 return;
                             };
+                            if !self.translators.is_valid_translator(&meta, &format) {
+                                warn!(
+                                    "{format} can not translate {}, keeping the current format",
+                                    variable.variable_ref.full_path_string(),
+                                );
+                                return;
+                            }
                             let translator = self.translators.get_translator(&format);
                             let new_info = translator.variable_info(&meta).unwrap();
 
@@ -879,6 +883,19 @@ impl SystemState {
                     }
                 };
             }
+            Message::ItemRenderModeChange(vidx, render_mode) => {
+                if let Some(waves) = self.user.waves.as_mut() {
+                    if let Some(vidx) = vidx.or(waves.focused_item) {
+                        if let Some(node) = waves.items_tree.get_visible(vidx) {
+                            waves
+                                .displayed_items
+                                .entry(node.item_ref)
+                                .and_modify(|item| item.set_render_mode(render_mode));
+                        }
+                    }
+                }
+                self.invalidate_draw_commands();
+            }
             Message::MoveCursorToTransition {
                 next,
                 variable,
@@ -1029,6 +1046,44 @@ impl SystemState {
                 }
             }
             Message::SetupCxxrtl(kind) => self.connect_to_cxxrtl(kind, false),
+            Message::CxxrtlDisconnected => {
+                error!("Lost the connection to cxxrtl");
+                self.user.show_logs = true;
+                if let Some(WaveSource::Cxxrtl(kind)) = self.user.waves.as_ref().map(|w| &w.source)
+                {
+                    self.reconnect_to_cxxrtl(kind.clone(), 0);
+                }
+            }
+            Message::CxxrtlReconnect { kind, attempt } => self.reconnect_to_cxxrtl(kind, attempt),
+            Message::CxxrtlLoading(kind) => {
+                info!("Waiting for {kind:?} from cxxrtl");
+                match &mut self.cxxrtl_progress {
+                    Some(progress) => progress.progress = LoadProgressStatus::Cxxrtl(kind),
+                    None => {
+                        self.cxxrtl_progress =
+                            Some(LoadProgress::new(LoadProgressStatus::Cxxrtl(kind)))
+                    }
+                }
+            }
+            Message::CxxrtlProtocolError { got, expected } => {
+                error!("cxxrtl responded with {got} where {expected} was expected");
+                self.user.show_logs = true;
+            }
+            Message::CxxrtlRunUntilValue(outcome) => match outcome {
+                RunUntilValueOutcome::Reached { time } => {
+                    info!("Stopped the simulation at {time} fs where the value was reached");
+                }
+                RunUntilValueOutcome::StepLimit { time } => {
+                    warn!("Stopped the simulation at {time} fs without reaching the value");
+                }
+                RunUntilValueOutcome::Finished { time } => {
+                    warn!("The simulation finished at {time} fs without reaching the value");
+                }
+                RunUntilValueOutcome::Failed => {
+                    error!("Stopped the simulation, the value could not be checked");
+                    self.user.show_logs = true;
+                }
+            },
             Message::SurferServerStatus(_start, server, status) => {
                 self.server_status_to_progress(server, status);
             }
@@ -1120,6 +1175,7 @@ impl SystemState {
                     });
 
                 if self.wcp_greeted_signal.load(Ordering::Relaxed)
+                    && self.wcp_version.is_some()
                     && self.wcp_client_capabilities.waveforms_loaded
                 {
                     let source = match source {
@@ -1128,9 +1184,9 @@ impl SystemState {
                         _ => "".to_string(),
                     };
                     self.channels.wcp_s2c_sender.as_ref().map(|ch| {
-                        block_on(
-                            ch.send(WcpSCMessage::event(WcpEvent::waveforms_loaded { source })),
-                        )
+                        block_on(ch.send(
+                            WcpSCMessage::event(WcpEvent::waveforms_loaded { source }).into(),
+                        ))
                     });
                 }
 
@@ -1576,6 +1632,11 @@ impl SystemState {
                     waves.inner.as_waves().unwrap().pause_simulation();
                 }
             }
+            Message::ResetSimulation => {
+                if let Some(waves) = &self.user.waves {
+                    waves.inner.as_waves().unwrap().reset_simulation();
+                }
+            }
             Message::Batch(messages) => {
                 for message in messages {
                     self.update(message);
@@ -1842,6 +1903,32 @@ impl SystemState {
 
                 waves.items_tree.remove_dissolve(item_index);
             }
+            Message::MoveItem { item, to_index } => {
+                self.save_current_canvas("Move item".to_owned());
+                self.invalidate_draw_commands();
+                let Some(waves) = self.user.waves.as_mut() else {
+                    return;
+                };
+                let Some(item_index) = waves.index_for_ref_or_focus(Some(item)) else {
+                    return;
+                };
+
+                let focused_item_ref = waves
+                    .focused_item
+                    .and_then(|vidx| waves.items_tree.get_visible(vidx))
+                    .map(|node| node.item_ref);
+                if let Err(e) = waves.items_tree.move_to_position(item_index, to_index) {
+                    warn!("Failed to move item: {e:?}");
+                }
+                waves.focused_item = focused_item_ref
+                    .and_then(|item_ref| {
+                        waves
+                            .items_tree
+                            .iter_visible()
+                            .position(|node| node.item_ref == item_ref)
+                    })
+                    .map(VisibleItemIndex);
+            }
             Message::GroupFold(item_ref)
             | Message::GroupUnfold(item_ref)
             | Message::GroupFoldRecursive(item_ref)
@@ -1983,6 +2070,11 @@ impl SystemState {
                     waves.graphics.retain(|k, _| k != &id)
                 }
             }
+            Message::ClearGraphics => {
+                if let Some(waves) = &mut self.user.waves {
+                    waves.graphics.clear()
+                }
+            }
             Message::ExpandDrawnItem { item, levels } => {
                 self.items_to_expand.borrow_mut().push((item, levels))
             }
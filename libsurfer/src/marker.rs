@@ -84,6 +84,42 @@ impl WaveData {
         }
     }
 
+    /// Returns the name and time of every marker, ordered by time
+    pub fn marker_times(&self) -> Vec<(String, BigInt)> {
+        self.displayed_items
+            .values()
+            .filter_map(|item| match item {
+                DisplayedItem::Marker(marker) => {
+                    Some((item.name(), self.markers.get(&marker.idx)?.clone()))
+                }
+                _ => None,
+            })
+            .sorted_by(|(a_name, a_time), (b_name, b_time)| {
+                a_time.cmp(b_time).then_with(|| a_name.cmp(b_name))
+            })
+            .collect()
+    }
+
+    /// Returns the times of the markers named `from` and `to`, or an error if either does
+    /// not exist or `to` is not after `from`
+    pub fn marker_span(&self, from: &str, to: &str) -> Result<(BigInt, BigInt), String> {
+        let markers = self.marker_times();
+        let time_of = |name: &str| {
+            markers
+                .iter()
+                .find(|(marker, _)| marker == name)
+                .map(|(_, time)| time.clone())
+                .ok_or_else(|| format!("No marker named '{name}'"))
+        };
+        let (start, end) = (time_of(from)?, time_of(to)?);
+        if end <= start {
+            return Err(format!(
+                "Marker '{to}' at {end} is not after marker '{from}' at {start}"
+            ));
+        }
+        Ok((start, end))
+    }
+
     pub fn can_add_marker(&self) -> bool {
         self.markers.len() < 255
     }
@@ -465,29 +465,33 @@ impl SystemState {
                 }
             });
 
-            if self.wcp_greeted_signal.load(Ordering::Relaxed) {
+            if self.wcp_greeted_signal.load(Ordering::Relaxed) && self.wcp_version.is_some() {
                 if self.wcp_client_capabilities.goto_declaration
                     && ui.button("Go to declaration").clicked()
                 {
                     let variable = variable.variable_ref.full_path_string();
                     self.channels.wcp_s2c_sender.as_ref().map(|ch| {
-                        block_on(
-                            ch.send(WcpSCMessage::event(WcpEvent::goto_declaration { variable })),
-                        )
+                        block_on(ch.send(
+                            WcpSCMessage::event(WcpEvent::goto_declaration { variable }).into(),
+                        ))
                     });
                     ui.close_menu();
                 }
                 if self.wcp_client_capabilities.add_drivers && ui.button("Add drivers").clicked() {
                     let variable = variable.variable_ref.full_path_string();
                     self.channels.wcp_s2c_sender.as_ref().map(|ch| {
-                        block_on(ch.send(WcpSCMessage::event(WcpEvent::add_drivers { variable })))
+                        block_on(
+                            ch.send(WcpSCMessage::event(WcpEvent::add_drivers { variable }).into()),
+                        )
                     });
                     ui.close_menu();
                 }
                 if self.wcp_client_capabilities.add_loads && ui.button("Add loads").clicked() {
                     let variable = variable.variable_ref.full_path_string();
                     self.channels.wcp_s2c_sender.as_ref().map(|ch| {
-                        block_on(ch.send(WcpSCMessage::event(WcpEvent::add_loads { variable })))
+                        block_on(
+                            ch.send(WcpSCMessage::event(WcpEvent::add_loads { variable }).into()),
+                        )
                     });
                     ui.close_menu();
                 }
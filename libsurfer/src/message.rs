@@ -9,6 +9,7 @@ use serde::Deserialize;
 use std::path::PathBuf;
 use surver::Status;
 
+use crate::cxxrtl_container::{CxxrtlLoadKind, RunUntilValueOutcome};
 use crate::displayed_item_tree::{ItemIndex, VisibleItemIndex};
 use crate::graphics::{Graphic, GraphicId};
 use crate::state::UserState;
@@ -22,7 +23,7 @@ use crate::{
     clock_highlighting::ClockHighlightType,
     config::ArrowKeyBindings,
     dialog::{OpenSiblingStateFileDialog, ReloadWaveformDialog},
-    displayed_item::{DisplayedFieldRef, DisplayedItemRef},
+    displayed_item::{DisplayedFieldRef, DisplayedItemRef, VariableRenderMode},
     hierarchy::HierarchyStyle,
     time::{TimeStringFormatting, TimeUnit},
     variable_filter::VariableIOFilterType,
@@ -89,11 +90,16 @@ pub enum Message {
     ItemBackgroundColorChange(Option<VisibleItemIndex>, Option<String>),
     ItemNameChange(Option<VisibleItemIndex>, Option<String>),
     ItemHeightScalingFactorChange(Option<VisibleItemIndex>, f32),
+    ItemRenderModeChange(Option<VisibleItemIndex>, VariableRenderMode),
     ChangeVariableNameType(Option<VisibleItemIndex>, VariableNameType),
     ForceVariableNameTypes(VariableNameType),
     SetNameAlignRight(bool),
     SetClockHighlightType(ClockHighlightType),
     SetFillHighValues(bool),
+    /// Set the full path of the program counter used to find the current instruction
+    SetPcSignal(Option<String>),
+    /// Set the full path of the instruction memory used to find the current instruction
+    SetImemSignal(Option<String>),
     // Reset the translator for this variable back to default. Sub-variables,
     // i.e. those with the variable idx and a shared path are also reset
     ResetVariableFormat(DisplayedFieldRef),
@@ -126,6 +132,29 @@ pub enum Message {
         state: String,
     },
     SetupCxxrtl(CxxrtlKind),
+    /// The connection to the cxxrtl agent was lost
+    #[serde(skip)]
+    CxxrtlDisconnected,
+    /// Attempts to connect to cxxrtl again after the connection was lost. `attempt` is the
+    /// number of attempts which have failed so far
+    #[serde(skip)]
+    CxxrtlReconnect {
+        kind: CxxrtlKind,
+        attempt: u32,
+    },
+    /// A request for data of the given kind was sent to the cxxrtl agent
+    #[serde(skip)]
+    CxxrtlLoading(CxxrtlLoadKind),
+    /// The cxxrtl agent responded to a command with a response of the wrong kind. `got`
+    /// is the response received and `expected` the pattern it should have matched
+    #[serde(skip)]
+    CxxrtlProtocolError {
+        got: String,
+        expected: String,
+    },
+    /// Running the cxxrtl simulation until a variable has a value has stopped
+    #[serde(skip)]
+    CxxrtlRunUntilValue(RunUntilValueOutcome),
     #[serde(skip)]
     WaveHeaderLoaded(
         web_time::Instant,
@@ -292,6 +321,8 @@ pub enum Message {
     InvalidateDrawCommands,
     AddGraphic(GraphicId, Graphic),
     RemoveGraphic(GraphicId),
+    /// Remove all graphics
+    ClearGraphics,
 
     /// Variable dragging messages
     VariableDragStarted(VisibleItemIndex),
@@ -304,6 +335,9 @@ pub enum Message {
     /// Pause the simulation if the wave source supports this kind of interactivity. Otherwise
     /// does nothing
     PauseSimulation,
+    /// Restart the simulation from time zero if the wave source supports this kind of
+    /// interactivity. Otherwise does nothing
+    ResetSimulation,
     /// Expand the displayed item into subfields. Levels controls how many layers of subfields
     /// are expanded. 0 unexpands it completely
     ExpandDrawnItem {
@@ -332,6 +366,12 @@ pub enum Message {
         items: Option<Vec<DisplayedItemRef>>,
     },
     GroupDissolve(Option<DisplayedItemRef>),
+    /// Move the item, along with its subfields and group members, to `to_index` in the list
+    /// of all displayed items. Indices past the end move it to the end.
+    MoveItem {
+        item: DisplayedItemRef,
+        to_index: usize,
+    },
     GroupFold(Option<DisplayedItemRef>),
     GroupUnfold(Option<DisplayedItemRef>),
     GroupFoldRecursive(Option<DisplayedItemRef>),
@@ -352,3 +392,44 @@ pub enum Message {
     Exit,
     AsyncDone(AsyncJob),
 }
+
+/// Deserializes a JSON encoded message. Errors name the field which could not be
+/// deserialized, e.g. ``at `ZoomToFit.viewport_idx`: invalid type: ...``.
+pub fn parse_message(json: &str) -> Result<Message, String> {
+    let mut deserializer = serde_json::Deserializer::from_str(json);
+    let message = serde_path_to_error::deserialize(&mut deserializer).map_err(describe_error)?;
+    deserializer.end().map_err(|e| e.to_string())?;
+    Ok(message)
+}
+
+fn describe_error(e: serde_path_to_error::Error<serde_json::Error>) -> String {
+    let path = e.path().to_string();
+    if path == "." {
+        e.inner().to_string()
+    } else {
+        format!("at `{path}`: {}", e.inner())
+    }
+}
+
+/// Deserializes a JSON array of messages. If an element can not be deserialized, the
+/// messages before it are returned along with a description of the error.
+pub fn deserialize_message_batch(json: &str) -> (Vec<Message>, Option<String>) {
+    let elements = match serde_json::from_str::<Vec<serde_json::Value>>(json) {
+        Ok(elements) => elements,
+        Err(e) => return (vec![], Some(format!("Expected a list of messages: {e}"))),
+    };
+
+    let mut messages = vec![];
+    for (idx, element) in elements.into_iter().enumerate() {
+        match serde_path_to_error::deserialize(element).map_err(describe_error) {
+            Ok(message) => messages.push(message),
+            Err(e) => {
+                return (
+                    messages,
+                    Some(format!("Failed to deserialize message {idx}: {e}")),
+                )
+            }
+        }
+    }
+    (messages, None)
+}
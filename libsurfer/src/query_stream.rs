@@ -0,0 +1,162 @@
+//! Queries whose results are delivered to embedders in chunks, so that large results
+//! neither have to be built in one go nor block a single frame, along with the range
+//! queries they are built from.
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use num::{BigUint, Zero};
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+use crate::{displayed_item::DisplayedItem, SystemState};
+
+/// Coalesces the repaints requested by queued queries, so that a burst of queries only
+/// requests a single frame which then runs all of them
+#[derive(Default)]
+pub struct RepaintCoalescer {
+    pending: AtomicBool,
+}
+
+impl RepaintCoalescer {
+    pub const fn new() -> Self {
+        Self {
+            pending: AtomicBool::new(false),
+        }
+    }
+
+    /// Calls `repaint` unless a repaint was already requested since the last call to
+    /// [`RepaintCoalescer::frame_started`]
+    pub fn request(&self, repaint: impl FnOnce()) {
+        if !self.pending.swap(true, Ordering::AcqRel) {
+            repaint();
+        }
+    }
+
+    /// Must be called before the queued queries are run, so that queries which are queued
+    /// while they run request a new frame
+    pub fn frame_started(&self) {
+        self.pending.store(false, Ordering::Release);
+    }
+}
+
+/// Produces the next chunk of a query result each time it is called, and `None` once the
+/// result is exhausted
+pub type ChunkProducer<T> = Box<dyn FnMut(&SystemState) -> Option<Vec<T>> + Send + Sync>;
+
+/// A query which is advanced by one chunk at a time, sending each chunk to the receiver
+/// returned by [`QueryStream::new`]
+pub struct QueryStream<T> {
+    producer: ChunkProducer<T>,
+    chunks: mpsc::UnboundedSender<Vec<T>>,
+}
+
+impl<T> QueryStream<T> {
+    pub fn new(producer: ChunkProducer<T>) -> (Self, mpsc::UnboundedReceiver<Vec<T>>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        (
+            Self {
+                producer,
+                chunks: tx,
+            },
+            rx,
+        )
+    }
+
+    /// Produces and sends the next chunk. Returns false once the query is exhausted or
+    /// nobody is receiving the chunks any more, after which the stream should be dropped
+    /// to close the channel.
+    pub fn advance(&mut self, state: &SystemState) -> bool {
+        if self.chunks.is_closed() {
+            return false;
+        }
+        match (self.producer)(state) {
+            Some(chunk) => self.chunks.send(chunk).is_ok(),
+            None => false,
+        }
+    }
+}
+
+/// The value of a variable at a point in time
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Sample {
+    pub time: String,
+    pub value: Option<String>,
+}
+
+/// Samples the displayed variable `name` every `step` timestamps from `start` to `end`,
+/// formatted the same way as in the variable value column, `chunk_size` samples at a time
+pub fn variable_samples(
+    name: String,
+    start: BigUint,
+    end: BigUint,
+    step: BigUint,
+    chunk_size: usize,
+) -> ChunkProducer<Sample> {
+    let mut time = start;
+    Box::new(move |state| {
+        if step.is_zero() || time > end {
+            return None;
+        }
+        let waves = state.user.waves.as_ref()?;
+        let item_ref = waves.item_ref_of_name(&name)?;
+
+        let mut chunk = vec![];
+        while time <= end && chunk.len() < chunk_size.max(1) {
+            chunk.push(Sample {
+                time: time.to_string(),
+                value: state.get_variable_value(waves, &item_ref.into(), &Some(time.clone())),
+            });
+            time += &step;
+        }
+        Some(chunk)
+    })
+}
+
+/// Returns the points in `start..=end` where the formatted value of the displayed variable
+/// `name` changes, as `(time, value)`. The first point is the value at `start`. Returns
+/// an empty vec if `name` is not a displayed variable.
+pub fn variable_transitions(
+    state: &SystemState,
+    name: &str,
+    start: &BigUint,
+    end: &BigUint,
+) -> Vec<(BigUint, String)> {
+    let Some(waves) = state.user.waves.as_ref() else {
+        return vec![];
+    };
+    let Some(item_ref) = waves.item_ref_of_name(name) else {
+        return vec![];
+    };
+    let Some(DisplayedItem::Variable(variable)) = waves.displayed_items.get(&item_ref) else {
+        return vec![];
+    };
+    let Some(raw_transitions) = waves.variable_transitions(&variable.variable_ref, start, end)
+    else {
+        return vec![];
+    };
+
+    // Different raw values can be formatted the same, so only keep the changes of the
+    // formatted value
+    let mut transitions: Vec<(BigUint, String)> = vec![];
+    for (time, _) in raw_transitions {
+        if let Some(value) = state.get_variable_value(waves, &item_ref.into(), &Some(time.clone()))
+        {
+            if transitions.last().is_none_or(|(_, last)| *last != value) {
+                transitions.push((time, value));
+            }
+        }
+    }
+    transitions
+}
+
+/// Picks at most `max_points` of `points`, spread evenly over them. The first and last
+/// points are always kept so the boundaries of the range stay accurate.
+pub fn downsample<T: Clone>(points: &[T], max_points: usize) -> Vec<T> {
+    match max_points {
+        _ if points.len() <= max_points => points.to_vec(),
+        0 => vec![],
+        1 => points[..1].to_vec(),
+        _ => (0..max_points)
+            .map(|i| points[i * (points.len() - 1) / (max_points - 1)].clone())
+            .collect(),
+    }
+}
@@ -0,0 +1,70 @@
+//! Capturing the rendered frame as a PNG image for embedders
+use std::io::Cursor;
+
+use egui::{ColorImage, Event, ViewportCommand};
+use log::{error, warn};
+use tokio::sync::oneshot;
+
+use crate::SystemState;
+
+/// Encodes `image` as PNG. Returns `None` if encoding fails.
+pub fn encode_png(image: &ColorImage) -> Option<Vec<u8>> {
+    let [width, height] = image.size;
+    let Some(buffer) = image::RgbaImage::from_raw(
+        width as u32,
+        height as u32,
+        image.pixels.iter().flat_map(|p| p.to_array()).collect(),
+    ) else {
+        error!("Screenshot size does not match its pixel data");
+        return None;
+    };
+    let mut png = Cursor::new(vec![]);
+    match buffer.write_to(&mut png, image::ImageFormat::Png) {
+        Ok(()) => Some(png.into_inner()),
+        Err(e) => {
+            error!("Failed to encode screenshot ({e})");
+            None
+        }
+    }
+}
+
+impl SystemState {
+    /// Requests a screenshot of the next frame, which is sent to `tx` as PNG once egui has
+    /// captured it. The sender is dropped if the frame can not be encoded.
+    pub(crate) fn request_screenshot(&mut self, tx: oneshot::Sender<Vec<u8>>) {
+        self.screenshot_requests.push(tx);
+        if let Some(ctx) = &self.context {
+            ctx.send_viewport_cmd(ViewportCommand::Screenshot(Default::default()));
+            ctx.request_repaint();
+        } else {
+            warn!("Requested a screenshot before the egui context is available");
+        }
+    }
+
+    /// Delivers screenshots captured by egui to the pending requesters
+    pub(crate) fn handle_screenshots(&mut self, ctx: &egui::Context) {
+        if self.screenshot_requests.is_empty() {
+            return;
+        }
+        let image = ctx.input(|i| {
+            i.raw.events.iter().find_map(|e| match e {
+                Event::Screenshot { image, .. } => Some(image.clone()),
+                _ => None,
+            })
+        });
+        if let Some(image) = image {
+            self.deliver_screenshot(&image);
+        }
+    }
+
+    /// Sends `image` as PNG to everybody waiting for a screenshot
+    pub(crate) fn deliver_screenshot(&mut self, image: &ColorImage) {
+        let png = encode_png(image);
+        for tx in self.screenshot_requests.drain(..) {
+            if let Some(png) = &png {
+                // The requester may have given up waiting
+                let _ = tx.send(png.clone());
+            }
+        }
+    }
+}
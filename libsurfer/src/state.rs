@@ -14,6 +14,7 @@ use crate::{
     data_container::DataContainer,
     dialog::OpenSiblingStateFileDialog,
     dialog::ReloadWaveformDialog,
+    displayed_item::DisplayedItemRef,
     displayed_item_tree::DisplayedItemTree,
     message::Message,
     system_state::SystemState,
@@ -230,6 +231,30 @@ impl SystemState {
         self.invalidate_draw_commands();
     }
 
+    /// Adds `variables` to the displayed items and starts loading their values. Returns
+    /// the references of the new items.
+    pub(crate) fn add_variables(&mut self, variables: Vec<VariableRef>) -> Vec<DisplayedItemRef> {
+        if variables.is_empty() {
+            return vec![];
+        }
+        let undo_msg = if variables.len() == 1 {
+            format!("Add variable {}", variables[0].name)
+        } else {
+            format!("Add {} variables", variables.len())
+        };
+        self.save_current_canvas(undo_msg);
+        let Some(waves) = self.user.waves.as_mut() else {
+            error!("Could not load signals, no waveform loaded");
+            return vec![];
+        };
+        let (cmd, item_refs) = waves.add_variables(&self.translators, variables, None);
+        if let Some(cmd) = cmd {
+            self.load_variables(cmd);
+        }
+        self.invalidate_draw_commands();
+        item_refs
+    }
+
     pub(crate) fn on_waves_loaded(
         &mut self,
         filename: WaveSource,
@@ -410,7 +435,7 @@ impl SystemState {
             }
         }
 
-        while let Some(msg) = msgs.pop() {
+        for msg in coalesce_draw_invalidations(msgs.into_iter().rev()) {
             self.update(msg);
         }
     }
@@ -689,3 +714,53 @@ impl SystemState {
         }
     }
 }
+
+/// Removes all but one `InvalidateDrawCommands` from `msgs`. Since draw commands are only
+/// regenerated when drawing, invalidating them once after all other messages have been
+/// handled is equivalent to invalidating them after each message.
+pub(crate) fn coalesce_draw_invalidations(msgs: impl IntoIterator<Item = Message>) -> Vec<Message> {
+    let mut invalidate = false;
+    let mut result = msgs
+        .into_iter()
+        .filter(|msg| {
+            let is_invalidation = matches!(msg, Message::InvalidateDrawCommands);
+            invalidate |= is_invalidation;
+            !is_invalidation
+        })
+        .collect::<Vec<_>>();
+    if invalidate {
+        result.push(Message::InvalidateDrawCommands);
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn draw_invalidations_are_coalesced() {
+        let msgs = std::iter::repeat_with(|| Message::InvalidateDrawCommands)
+            .take(100)
+            .chain([Message::ZoomToFit { viewport_idx: 0 }])
+            .chain(std::iter::repeat_with(|| Message::InvalidateDrawCommands).take(100));
+
+        let coalesced = coalesce_draw_invalidations(msgs);
+        assert_eq!(coalesced.len(), 2);
+        assert!(matches!(
+            coalesced[0],
+            Message::ZoomToFit { viewport_idx: 0 }
+        ));
+        assert!(matches!(coalesced[1], Message::InvalidateDrawCommands));
+    }
+
+    #[test]
+    fn messages_without_invalidations_are_unchanged() {
+        let coalesced = coalesce_draw_invalidations([Message::ZoomToFit { viewport_idx: 0 }]);
+        assert_eq!(coalesced.len(), 1);
+        assert!(matches!(
+            coalesced[0],
+            Message::ZoomToFit { viewport_idx: 0 }
+        ));
+    }
+}
@@ -46,7 +46,11 @@ impl SystemState {
             }
 
             ui.add_space(10.0);
-            if let Some(progress_data) = &self.progress_tracker {
+            if let Some(progress_data) = self
+                .progress_tracker
+                .as_ref()
+                .or(self.cxxrtl_progress.as_ref())
+            {
                 if Instant::now().duration_since(progress_data.started) > Duration::from_millis(100)
                 {
                     draw_progress_information(ui, progress_data);
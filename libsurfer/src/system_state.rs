@@ -1,7 +1,7 @@
 use color_eyre::eyre::{Context, Result};
 use std::{
     cell::RefCell,
-    collections::{HashSet, VecDeque},
+    collections::{BTreeMap, HashSet, VecDeque},
     sync::{atomic::AtomicBool, Arc},
 };
 use tokio::task::JoinHandle;
@@ -12,6 +12,7 @@ use crate::{
     command_prompt,
     config::SurferConfig,
     displayed_item::DisplayedItemRef,
+    graphics::GraphicId,
     message::Message,
     state::UserState,
     time::TimeUnit,
@@ -34,6 +35,8 @@ pub struct SystemState {
 
     /// Tracks progress of file/variable loading operations.
     pub(crate) progress_tracker: Option<LoadProgress>,
+    /// Tracks data requested from cxxrtl until all of it has arrived
+    pub(crate) cxxrtl_progress: Option<LoadProgress>,
 
     /// Buffer for the command input
     pub(crate) command_prompt: command_prompt::CommandPrompt,
@@ -56,6 +59,10 @@ pub struct SystemState {
     pub(crate) wcp_running_signal: Arc<AtomicBool>,
     pub(crate) wcp_greeted_signal: Arc<AtomicBool>,
     pub(crate) wcp_client_capabilities: WcpClientCapabilities,
+    /// The WCP protocol version agreed on with the client during the greeting
+    pub(crate) wcp_version: Option<usize>,
+    /// The id of the WCP request currently being handled, copied onto its response
+    pub(crate) wcp_request_id: Option<u64>,
 
     /// The draw commands for every variable currently selected
     // For performance reasons, these need caching so we have them in a RefCell for interior
@@ -70,6 +77,9 @@ pub struct SystemState {
     pub(crate) url: RefCell<String>,
     pub(crate) command_prompt_text: RefCell<String>,
     pub(crate) last_canvas_rect: RefCell<Option<Rect>>,
+    /// The screen space bounds of the graphics drawn in each viewport in the last frame,
+    /// by viewport index
+    pub(crate) graphic_bounds: RefCell<BTreeMap<usize, Vec<(GraphicId, Rect)>>>,
     pub(crate) item_renaming_string: RefCell<String>,
 
     /// These items should be expanded into subfields in the next frame. Cleared after each
@@ -91,6 +101,15 @@ pub struct SystemState {
     pub(crate) undo_stack: Vec<CanvasState>,
     pub(crate) redo_stack: Vec<CanvasState>,
 
+    /// The program counter and instruction memory which the current instruction is
+    /// decoded from
+    pub(crate) pc_signal: Option<String>,
+    pub(crate) imem_signal: Option<String>,
+
+    /// Requesters waiting for a PNG screenshot of the next captured frame
+    #[cfg(any(target_arch = "wasm32", test))]
+    pub(crate) screenshot_requests: Vec<tokio::sync::oneshot::Sender<Vec<u8>>>,
+
     // Only used for testing
     pub(crate) expand_parameter_section: bool,
 }
@@ -162,6 +181,7 @@ impl SystemState {
             translators,
             channels,
             progress_tracker: None,
+            cxxrtl_progress: None,
             command_prompt: command_prompt::CommandPrompt {
                 visible: false,
                 suggestions: vec![],
@@ -177,6 +197,8 @@ impl SystemState {
             wcp_running_signal: Arc::new(AtomicBool::new(false)),
             wcp_greeted_signal: Arc::new(AtomicBool::new(false)),
             wcp_client_capabilities: WcpClientCapabilities::new(),
+            wcp_version: None,
+            wcp_request_id: None,
             gesture_start_location: None,
             measure_start_location: None,
             batch_commands: VecDeque::new(),
@@ -185,10 +207,15 @@ impl SystemState {
             command_prompt_text: RefCell::new(String::new()),
             draw_data: RefCell::new(vec![None]),
             last_canvas_rect: RefCell::new(None),
+            graphic_bounds: RefCell::new(BTreeMap::new()),
             item_renaming_string: RefCell::new(String::new()),
 
             items_to_expand: RefCell::new(vec![]),
             char_to_add_to_prompt: RefCell::new(None),
+            pc_signal: None,
+            imem_signal: None,
+            #[cfg(any(target_arch = "wasm32", test))]
+            screenshot_requests: vec![],
             expand_parameter_section: false,
 
             continuous_redraw: false,
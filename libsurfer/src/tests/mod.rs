@@ -1,4 +1,5 @@
 mod remote;
 pub(crate) mod snapshot;
+mod wasm_api;
 mod wcp;
 mod wcp_tcp;
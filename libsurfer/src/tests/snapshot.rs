@@ -2415,6 +2415,9 @@ snapshot_ui!(arrow_drawing, || {
                 Direction::East,
             ),
             text: "A".to_string(),
+            color: None,
+            font_size: None,
+            text_color: None,
         },
     ));
     state.update(Message::AddGraphic(
@@ -2441,6 +2444,9 @@ snapshot_ui!(arrow_drawing, || {
                 Direction::West,
             ),
             text: "B".to_string(),
+            color: None,
+            font_size: None,
+            text_color: None,
         },
     ));
     state.update(Message::AddGraphic(
@@ -2467,6 +2473,9 @@ snapshot_ui!(arrow_drawing, || {
                 Direction::West,
             ),
             text: "C".to_string(),
+            color: None,
+            font_size: None,
+            text_color: None,
         },
     ));
     state.update(Message::AddGraphic(
@@ -2493,6 +2502,9 @@ snapshot_ui!(arrow_drawing, || {
                 Direction::North,
             ),
             text: "D".to_string(),
+            color: None,
+            font_size: None,
+            text_color: None,
         },
     ));
     state.update(Message::AddGraphic(
@@ -2519,6 +2531,9 @@ snapshot_ui!(arrow_drawing, || {
                 Direction::South,
             ),
             text: "E".to_string(),
+            color: None,
+            font_size: None,
+            text_color: None,
         },
     ));
     wait_for_waves_fully_loaded(&mut state, 10);
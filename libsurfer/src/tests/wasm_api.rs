@@ -0,0 +1,1154 @@
+//! Tests for the state queries which back the functions exported in `wasm_api`. The
+//! exports themselves only exist in wasm builds, so the queries are tested directly.
+use std::collections::HashMap;
+
+use egui_skia_renderer::draw_onto_surface;
+use emath::{Pos2, Vec2};
+use num::{BigInt, BigUint};
+use project_root::get_project_root;
+use test_log::test;
+
+use crate::{
+    displayed_item::VariableRenderMode,
+    displayed_item_tree::{ItemIndex, VisibleItemIndex},
+    graphics::{Anchor, Direction, GrPoint, Graphic, GraphicId, GraphicsY, LineStyle},
+    message::{deserialize_message_batch, parse_message},
+    query_stream::{
+        downsample, variable_samples, variable_transitions, QueryStream, RepaintCoalescer,
+    },
+    tests::snapshot::wait_for_waves_fully_loaded,
+    wave_container::{VariableRef, VariableRefExt},
+    wave_data::EnumValue,
+    wave_source::CxxrtlKind,
+    Message, StartupParams, SystemState, WaveSource,
+};
+
+/// Runs `f` with a tokio runtime entered, which is required for loading waves
+fn with_runtime<T>(f: impl FnOnce() -> T) -> T {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .worker_threads(1)
+        .enable_all()
+        .build()
+        .unwrap();
+
+    let _enter = runtime.enter();
+
+    std::thread::spawn(move || {
+        runtime.block_on(async {
+            loop {
+                tokio::time::sleep(tokio::time::Duration::from_secs(3600)).await;
+            }
+        });
+    });
+
+    f()
+}
+
+fn state_with_variables(file: &str, variables: &[&str]) -> SystemState {
+    let mut state = SystemState::new_default_config()
+        .unwrap()
+        .with_params(StartupParams {
+            waves: Some(WaveSource::File(
+                get_project_root().unwrap().join(file).try_into().unwrap(),
+            )),
+            ..Default::default()
+        });
+    wait_for_waves_fully_loaded(&mut state, 10);
+
+    state.add_startup_message(Message::AddVariables(
+        variables
+            .iter()
+            .map(|v| VariableRef::from_hierarchy_string(v))
+            .collect(),
+    ));
+    wait_for_waves_fully_loaded(&mut state, 10);
+    state
+}
+
+fn first_value_difference(state: &SystemState, a: &str, b: &str) -> Option<Option<BigUint>> {
+    let waves = state.user.waves.as_ref().unwrap();
+    waves.first_value_difference(
+        &waves.displayed_variable_ref(a)?,
+        &waves.displayed_variable_ref(b)?,
+        &BigUint::from(0u32),
+        &BigUint::from(100u32),
+    )
+}
+
+#[test]
+fn aliased_signals_are_equal() {
+    with_runtime(|| {
+        let state = state_with_variables("examples/counter.vcd", &["tb.clk", "tb.dut.clk"]);
+        assert_eq!(
+            first_value_difference(&state, "tb.clk", "tb.dut.clk"),
+            Some(None)
+        );
+    })
+}
+
+#[test]
+fn first_difference_between_signals_is_found() {
+    with_runtime(|| {
+        let state =
+            state_with_variables("examples/counter.vcd", &["tb.dut.clk", "tb.dut.overflow"]);
+        assert_eq!(
+            first_value_difference(&state, "tb.dut.clk", "tb.dut.overflow"),
+            Some(Some(BigUint::from(10u32)))
+        );
+    })
+}
+
+#[test]
+fn comparing_undisplayed_signals_fails() {
+    with_runtime(|| {
+        let state = state_with_variables("examples/counter.vcd", &["tb.dut.clk"]);
+        assert_eq!(
+            first_value_difference(&state, "tb.dut.clk", "tb.reset"),
+            None
+        );
+    })
+}
+
+#[test]
+fn built_in_translators_are_available() {
+    let state = SystemState::new_default_config().unwrap();
+    let names = state.translators.all_translator_names();
+    for name in ["Hexadecimal", "Binary", "Unsigned", "Signed"] {
+        assert!(names.contains(&name), "{name} is missing from {names:?}");
+    }
+}
+
+#[test]
+fn item_translator_follows_format_changes() {
+    with_runtime(|| {
+        let mut state = state_with_variables("examples/counter.vcd", &["tb.dut.counter"]);
+        let item_ref = state
+            .user
+            .waves
+            .as_ref()
+            .unwrap()
+            .item_ref_of_name("tb.dut.counter")
+            .unwrap();
+        state.update(Message::VariableFormatChange(
+            Some(item_ref.into()),
+            "Unsigned".to_string(),
+        ));
+
+        let waves = state.user.waves.as_ref().unwrap();
+        assert_eq!(
+            waves.item_translator_name("tb.dut.counter", &state.translators),
+            Some("Unsigned".to_string())
+        );
+        assert_eq!(
+            waves.item_translator_name("tb.dut.missing", &state.translators),
+            None
+        );
+    })
+}
+
+#[test]
+fn item_translator_can_be_set_to_gheith_asm() {
+    with_runtime(|| {
+        let mut state = state_with_variables("examples/gheith_cpu.vcd", &["cpu.pc", "cpu.imem"]);
+        for name in ["cpu.pc", "cpu.imem"] {
+            let item_ref = state
+                .user
+                .waves
+                .as_ref()
+                .unwrap()
+                .item_ref_of_name(name)
+                .unwrap();
+            state.update(Message::VariableFormatChange(
+                Some(item_ref.into()),
+                "Gheith Asm".to_string(),
+            ));
+        }
+
+        let waves = state.user.waves.as_ref().unwrap();
+        assert_eq!(
+            waves.item_translator_name("cpu.pc", &state.translators),
+            Some("Gheith Asm".to_string())
+        );
+        // The instruction memory is not a single instruction word
+        assert_eq!(
+            waves.item_translator_name("cpu.imem", &state.translators),
+            Some(state.translators.default.clone())
+        );
+    })
+}
+
+#[test]
+fn chunked_query_delivers_all_items_in_order() {
+    with_runtime(|| {
+        let state = state_with_variables("examples/counter.vcd", &["tb.clk"]);
+        let (mut stream, mut chunks) = QueryStream::new(variable_samples(
+            "tb.clk".to_string(),
+            BigUint::from(0u32),
+            BigUint::from(10u32),
+            BigUint::from(1u32),
+            3,
+        ));
+
+        let mut advances = 0;
+        while stream.advance(&state) {
+            advances += 1;
+        }
+        drop(stream);
+        assert_eq!(advances, 4);
+
+        let mut received = vec![];
+        while let Ok(chunk) = chunks.try_recv() {
+            assert!(chunk.len() <= 3);
+            received.extend(chunk);
+        }
+        let times = received.iter().map(|s| s.time.clone()).collect::<Vec<_>>();
+        assert_eq!(
+            times,
+            (0..=10).map(|t: u32| t.to_string()).collect::<Vec<_>>()
+        );
+        for sample in &received {
+            assert_eq!(
+                sample.value,
+                variable_value_at(&state, "tb.clk", sample.time.parse().unwrap())
+            );
+        }
+        assert!(chunks.try_recv().is_err());
+    })
+}
+
+#[test]
+fn chunked_query_stops_when_nobody_receives() {
+    let state = SystemState::new_default_config().unwrap();
+    let mut produced = 0;
+    let (mut stream, chunks) = QueryStream::new(Box::new(move |_state: &SystemState| {
+        produced += 1;
+        Some(vec![produced])
+    }));
+    assert!(stream.advance(&state));
+    drop(chunks);
+    assert!(!stream.advance(&state));
+}
+
+#[test]
+fn source_info_describes_loaded_file() {
+    with_runtime(|| {
+        let state = state_with_variables("examples/counter.vcd", &[]);
+        let info = state.user.waves.as_ref().unwrap().source.source_info();
+        assert_eq!(info.kind, "file");
+        assert!(info.detail.ends_with("examples/counter.vcd"));
+    })
+}
+
+#[test]
+fn source_info_describes_cxxrtl_connection() {
+    let info = WaveSource::Cxxrtl(CxxrtlKind::Tcp {
+        url: "localhost:6618".to_string(),
+    })
+    .source_info();
+    assert_eq!(info.kind, "cxxrtl-tcp");
+    assert_eq!(info.detail, "localhost:6618");
+
+    let info = WaveSource::Cxxrtl(CxxrtlKind::WebSocket {
+        url: "ws://localhost:6618".to_string(),
+    })
+    .source_info();
+    assert_eq!(info.kind, "cxxrtl-websocket");
+    assert_eq!(info.detail, "ws://localhost:6618");
+
+    let info = WaveSource::Cxxrtl(CxxrtlKind::Mailbox).source_info();
+    assert_eq!(info.kind, "cxxrtl-mailbox");
+    assert_eq!(info.detail, "");
+}
+
+#[test]
+fn cxxrtl_transports_are_validated() {
+    assert_eq!(
+        CxxrtlKind::tcp("localhost:6618"),
+        Ok(CxxrtlKind::Tcp {
+            url: "localhost:6618".to_string()
+        })
+    );
+    assert!(CxxrtlKind::tcp("localhost").is_err());
+    assert!(CxxrtlKind::tcp(":6618").is_err());
+    assert!(CxxrtlKind::tcp("localhost:port").is_err());
+    assert!(CxxrtlKind::tcp("localhost:0").is_err());
+
+    assert_eq!(
+        CxxrtlKind::websocket("wss://example.com/sim"),
+        Ok(CxxrtlKind::WebSocket {
+            url: "wss://example.com/sim".to_string()
+        })
+    );
+    assert!(CxxrtlKind::websocket("http://example.com").is_err());
+    assert!(CxxrtlKind::websocket("ws://").is_err());
+
+    assert_eq!(
+        CxxrtlKind::stdio("./sim"),
+        Ok(CxxrtlKind::Stdio {
+            binary: "./sim".to_string()
+        })
+    );
+    assert!(CxxrtlKind::stdio(" ").is_err());
+
+    let info = WaveSource::Cxxrtl(CxxrtlKind::stdio("./sim").unwrap()).source_info();
+    assert_eq!(info.kind, "cxxrtl-stdio");
+    assert_eq!(info.detail, "./sim");
+}
+
+#[test]
+fn valid_cxxrtl_transports_queue_their_setup() {
+    assert!(matches!(
+        CxxrtlKind::setup_message(CxxrtlKind::websocket("ws://localhost:6618")),
+        Some(Message::SetupCxxrtl(CxxrtlKind::WebSocket { url })) if url == "ws://localhost:6618"
+    ));
+    assert!(matches!(
+        CxxrtlKind::setup_message(CxxrtlKind::tcp("localhost:6618")),
+        Some(Message::SetupCxxrtl(CxxrtlKind::Tcp { url })) if url == "localhost:6618"
+    ));
+    assert!(matches!(
+        CxxrtlKind::setup_message(CxxrtlKind::stdio("./sim")),
+        Some(Message::SetupCxxrtl(CxxrtlKind::Stdio { binary })) if binary == "./sim"
+    ));
+}
+
+#[test]
+fn malformed_cxxrtl_transports_queue_nothing() {
+    assert!(CxxrtlKind::setup_message(CxxrtlKind::websocket("http://localhost")).is_none());
+    assert!(CxxrtlKind::setup_message(CxxrtlKind::tcp("localhost")).is_none());
+    assert!(CxxrtlKind::setup_message(CxxrtlKind::stdio("")).is_none());
+}
+
+#[test]
+fn lost_tcp_connections_are_retried_with_backoff() {
+    let tcp = CxxrtlKind::tcp("localhost:6618").unwrap();
+    let delays = (0..10)
+        .map_while(|attempt| tcp.reconnect_delay_ms(attempt))
+        .collect::<Vec<_>>();
+    assert_eq!(delays, vec![500, 1000, 2000, 4000, 8000]);
+
+    // A simulation which has exited can not be reconnected to
+    let stdio = CxxrtlKind::stdio("./sim").unwrap();
+    assert_eq!(stdio.reconnect_delay_ms(0), None);
+}
+
+/// Draws `state` and returns the laid out height of each visible item
+fn laid_out_item_heights(state: &mut SystemState) -> Vec<(VisibleItemIndex, f32)> {
+    let size = Vec2::new(800., 600.);
+    let mut surface = skia_safe::surfaces::raster_n32_premul((800, 600)).unwrap();
+    draw_onto_surface(
+        &mut surface,
+        |ctx| {
+            state.draw(ctx, Some(size));
+        },
+        Some(egui_skia_renderer::RasterizeOptions {
+            frames_before_screenshot: 2,
+            ..Default::default()
+        }),
+    );
+
+    state
+        .user
+        .waves
+        .as_ref()
+        .unwrap()
+        .drawing_infos
+        .iter()
+        .map(|info| (info.item_list_idx(), info.bottom() - info.top()))
+        .collect()
+}
+
+#[test]
+fn screenshots_are_delivered_as_png() {
+    with_runtime(|| {
+        let mut state = state_with_variables("examples/counter.vcd", &["tb.clk"]);
+        let (tx, mut rx) = tokio::sync::oneshot::channel();
+        state.request_screenshot(tx);
+
+        // Headless rendering does not capture frames itself, so the frame is read back
+        // from the surface and handed over like a screenshot taken by egui
+        let mut surface = skia_safe::surfaces::raster_n32_premul((400, 300)).unwrap();
+        draw_onto_surface(
+            &mut surface,
+            |ctx| {
+                state.draw(ctx, Some(Vec2::new(400., 300.)));
+            },
+            None,
+        );
+        let data = surface
+            .image_snapshot()
+            .encode(None, skia_safe::EncodedImageFormat::PNG, None)
+            .unwrap();
+        let frame = image::load_from_memory(&data).unwrap().to_rgba8();
+        state.deliver_screenshot(&egui::ColorImage::from_rgba_unmultiplied(
+            [400, 300],
+            frame.as_raw(),
+        ));
+
+        let png = rx.try_recv().unwrap();
+        assert!(png.starts_with(b"\x89PNG"));
+        let decoded = image::load_from_memory(&png).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (400, 300));
+        assert!(state.screenshot_requests.is_empty());
+    })
+}
+
+#[test]
+fn item_height_changes_row_height() {
+    with_runtime(|| {
+        let mut state = state_with_variables("examples/counter.vcd", &["tb.clk", "tb.reset"]);
+        let heights = laid_out_item_heights(&mut state);
+
+        let vidx = state
+            .user
+            .waves
+            .as_ref()
+            .unwrap()
+            .visible_index_of_variable("tb.reset")
+            .unwrap();
+        assert_eq!(vidx, VisibleItemIndex(1));
+        state.update(Message::ItemHeightScalingFactorChange(Some(vidx), 2.0));
+        let scaled_heights = laid_out_item_heights(&mut state);
+
+        assert_eq!(scaled_heights[0], heights[0]);
+        assert_eq!(scaled_heights[1].0, vidx);
+        assert_eq!(scaled_heights[1].1, heights[1].1 * 2.);
+    })
+}
+
+#[test]
+fn item_height_is_clamped() {
+    with_runtime(|| {
+        let mut state = state_with_variables("examples/counter.vcd", &["tb.clk"]);
+        state.update(Message::ItemHeightScalingFactorChange(
+            Some(VisibleItemIndex(0)),
+            1000.,
+        ));
+        let waves = state.user.waves.as_ref().unwrap();
+        let item = waves
+            .displayed_items
+            .get(
+                &waves
+                    .items_tree
+                    .get_visible(VisibleItemIndex(0))
+                    .unwrap()
+                    .item_ref,
+            )
+            .unwrap();
+        assert_eq!(item.height_scaling_factor(), 16.);
+    })
+}
+
+fn export_csv(state: &SystemState, start: u32, end: u32, step: u32, max_rows: usize) -> String {
+    state
+        .user
+        .waves
+        .as_ref()
+        .unwrap()
+        .export_csv(
+            &BigUint::from(start),
+            &BigUint::from(end),
+            &BigUint::from(step),
+            max_rows,
+        )
+        .unwrap()
+}
+
+#[test]
+fn csv_export_samples_displayed_variables() {
+    with_runtime(|| {
+        let state = state_with_variables("examples/counter.vcd", &["tb.clk", "tb.dut.counter"]);
+        assert_eq!(
+            export_csv(&state, 100, 160, 30, 100),
+            "time,tb.clk,tb.dut.counter\n100,0,0\n130,1,2\n160,0,3\n"
+        );
+    })
+}
+
+#[test]
+fn csv_export_is_truncated() {
+    with_runtime(|| {
+        let state = state_with_variables("examples/counter.vcd", &["tb.dut.counter"]);
+        assert_eq!(
+            export_csv(&state, 100, 160, 30, 2),
+            "time,tb.dut.counter\n100,0\n130,2\n# truncated after 2 rows\n"
+        );
+    })
+}
+
+#[test]
+fn analog_mode_can_be_toggled() {
+    with_runtime(|| {
+        let mut state = state_with_variables("examples/counter.vcd", &["tb.dut.counter"]);
+        let render_mode = |state: &SystemState| {
+            let waves = state.user.waves.as_ref().unwrap();
+            let vidx = waves.visible_index_of_variable("tb.dut.counter").unwrap();
+            waves.displayed_items[&waves.items_tree.get_visible(vidx).unwrap().item_ref]
+                .render_mode()
+        };
+        assert_eq!(render_mode(&state), VariableRenderMode::Default);
+
+        state.update(Message::ItemRenderModeChange(
+            Some(VisibleItemIndex(0)),
+            VariableRenderMode::Analog,
+        ));
+        assert_eq!(render_mode(&state), VariableRenderMode::Analog);
+        // Make sure that the analog drawing path works
+        laid_out_item_heights(&mut state);
+
+        state.update(Message::ItemRenderModeChange(
+            Some(VisibleItemIndex(0)),
+            VariableRenderMode::Default,
+        ));
+        assert_eq!(render_mode(&state), VariableRenderMode::Default);
+    })
+}
+
+#[test]
+fn enum_values_are_sorted_by_value() {
+    let enum_map = HashMap::from([
+        ("10".to_string(), "Running".to_string()),
+        ("00".to_string(), "Idle".to_string()),
+        ("01".to_string(), "Starting".to_string()),
+    ]);
+    let values = EnumValue::from_enum_map(&enum_map).unwrap();
+    assert_eq!(
+        serde_json::to_value(values).unwrap(),
+        serde_json::json!([
+            {"value": "00", "label": "Idle"},
+            {"value": "01", "label": "Starting"},
+            {"value": "10", "label": "Running"},
+        ])
+    );
+}
+
+#[test]
+fn variables_without_enum_map_have_no_enum_values() {
+    assert_eq!(EnumValue::from_enum_map(&HashMap::new()), None);
+    with_runtime(|| {
+        let state = state_with_variables("examples/counter.vcd", &["tb.dut.counter"]);
+        let waves = state.user.waves.as_ref().unwrap();
+        let counter = waves.displayed_variable_ref("tb.dut.counter").unwrap();
+        assert_eq!(waves.enum_values(&counter), None);
+    })
+}
+
+#[test]
+fn message_batches_keep_their_order() {
+    let (messages, error) = deserialize_message_batch(
+        r#"["ToggleMenu", {"AddDivider": [null, null]}, {"ZoomToFit": {"viewport_idx": 0}}]"#,
+    );
+    assert!(error.is_none());
+    assert!(matches!(
+        messages.as_slice(),
+        [
+            Message::ToggleMenu,
+            Message::AddDivider(None, None),
+            Message::ZoomToFit { viewport_idx: 0 }
+        ]
+    ));
+}
+
+#[test]
+fn message_batches_keep_the_valid_prefix() {
+    let (messages, error) =
+        deserialize_message_batch(r#"["ToggleMenu", "NotAMessage", "ToggleMenu"]"#);
+    assert!(matches!(messages.as_slice(), [Message::ToggleMenu]));
+    assert!(error.unwrap().contains("message 1"));
+
+    let (messages, error) = deserialize_message_batch(r#"{"not": "a list"}"#);
+    assert!(messages.is_empty());
+    assert!(error.is_some());
+}
+
+#[test]
+fn valid_messages_parse() {
+    assert!(matches!(
+        parse_message(r#"{"ZoomToFit": {"viewport_idx": 0}}"#),
+        Ok(Message::ZoomToFit { viewport_idx: 0 })
+    ));
+    assert!(parse_message(r#""ToggleMenu" trailing"#).is_err());
+}
+
+#[test]
+fn message_errors_name_the_offending_field() {
+    let error = parse_message(r#"{"ZoomToFit": {"viewport_idx": "zero"}}"#).unwrap_err();
+    assert!(
+        error.starts_with("at `ZoomToFit.viewport_idx`: invalid type: string \"zero\""),
+        "{error}"
+    );
+    assert!(error.contains("expected usize"), "{error}");
+
+    let (_, error) = deserialize_message_batch(r#"["ToggleMenu", {"AddDivider": [3, null]}]"#);
+    assert!(error
+        .unwrap()
+        .starts_with("Failed to deserialize message 1: at `AddDivider[0]`"),);
+}
+
+fn instruction_at_pc(state: &SystemState) -> Option<String> {
+    state
+        .user
+        .waves
+        .as_ref()?
+        .instruction_at_pc(state.pc_signal.as_deref()?, state.imem_signal.as_deref()?)
+}
+
+#[test]
+fn instruction_at_pc_is_decoded_from_memory() {
+    with_runtime(|| {
+        let mut state = state_with_variables("examples/gheith_cpu.vcd", &["cpu.pc", "cpu.imem"]);
+        state.update(Message::CursorSet(BigInt::from(5)));
+        assert_eq!(instruction_at_pc(&state), None);
+
+        state.update(Message::SetPcSignal(Some("cpu.pc".to_string())));
+        state.update(Message::SetImemSignal(Some("cpu.imem".to_string())));
+        assert_eq!(instruction_at_pc(&state), Some("movl r1, #8".to_string()));
+
+        state.update(Message::CursorSet(BigInt::from(15)));
+        assert_eq!(instruction_at_pc(&state), Some("jz r1, r2".to_string()));
+
+        // The pc points at an illegal instruction
+        state.update(Message::CursorSet(BigInt::from(25)));
+        assert_eq!(instruction_at_pc(&state), None);
+    })
+}
+
+fn graphic_ids(state: &SystemState) -> Vec<usize> {
+    state.user.waves.as_ref().unwrap().graphic_ids()
+}
+
+fn vline(time: u32) -> Graphic {
+    Graphic::VLine {
+        time: BigInt::from(time),
+        color: None,
+        label: None,
+        style: LineStyle::Solid,
+    }
+}
+
+#[test]
+fn graphics_can_be_removed() {
+    with_runtime(|| {
+        let mut state = state_with_variables("examples/counter.vcd", &["tb.dut.counter"]);
+        state.update(Message::AddGraphic(GraphicId(3), vline(5)));
+        state.update(Message::AddGraphic(GraphicId(1), vline(10)));
+        assert_eq!(graphic_ids(&state), vec![1, 3]);
+
+        state.update(Message::RemoveGraphic(GraphicId(3)));
+        assert_eq!(graphic_ids(&state), vec![1]);
+
+        // Removing a graphic which does not exist is a no-op
+        state.update(Message::RemoveGraphic(GraphicId(3)));
+        assert_eq!(graphic_ids(&state), vec![1]);
+    })
+}
+
+#[test]
+fn graphics_can_be_cleared() {
+    with_runtime(|| {
+        let mut state = state_with_variables("examples/counter.vcd", &["tb.dut.counter"]);
+        state.update(Message::AddGraphic(GraphicId(0), vline(5)));
+        state.update(Message::AddGraphic(GraphicId(1), vline(10)));
+
+        state.update(Message::ClearGraphics);
+        assert!(graphic_ids(&state).is_empty());
+    })
+}
+
+#[test]
+fn vlines_are_drawn_over_the_full_height_with_their_label() {
+    with_runtime(|| {
+        let mut state = state_with_variables("examples/counter.vcd", &["tb.dut.counter"]);
+        state.update(Message::AddGraphic(
+            GraphicId(2),
+            Graphic::VLine {
+                time: BigInt::from(100),
+                color: None,
+                label: Some("a rather long label".to_string()),
+                style: LineStyle::Dotted,
+            },
+        ));
+        // Past the end of the visible time range
+        state.update(Message::AddGraphic(GraphicId(3), vline(1_000_000)));
+        laid_out_item_heights(&mut state);
+
+        let bounds = state.graphic_bounds.borrow()[&0].clone();
+        assert_eq!(bounds.len(), 1);
+        let (id, rect) = bounds[0];
+        assert_eq!(id, GraphicId(2));
+        // The label widens the bounds of the line to the right
+        assert!(rect.width() > 20.);
+        assert!(rect.height() > 100.);
+    })
+}
+
+#[test]
+fn graphics_are_listed_as_messages_which_restore_them() {
+    with_runtime(|| {
+        let mut state = state_with_variables("examples/counter.vcd", &["tb.dut.counter"]);
+        state.update(Message::AddGraphic(GraphicId(4), vline(5)));
+        state.update(Message::AddGraphic(GraphicId(2), vline(10)));
+
+        let json = state.user.waves.as_ref().unwrap().graphics_json().unwrap();
+        let listed: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let ids = listed
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|message| message["AddGraphic"][0].as_u64().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![2, 4]);
+
+        state.update(Message::ClearGraphics);
+        let (messages, error) = deserialize_message_batch(&json);
+        assert_eq!(error, None);
+        for message in messages {
+            state.update(message);
+        }
+        let waves = state.user.waves.as_ref().unwrap();
+        assert_eq!(waves.graphic_ids(), vec![2, 4]);
+        assert_eq!(waves.graphics[&GraphicId(4)], vline(5));
+    })
+}
+
+fn activity_histogram(state: &SystemState, name: &str, bins: usize) -> Option<Vec<u64>> {
+    let waves = state.user.waves.as_ref().unwrap();
+    waves.activity_histogram(&waves.displayed_variable_ref(name)?, bins)
+}
+
+#[test]
+fn activity_histogram_counts_clustered_transitions() {
+    with_runtime(|| {
+        let state = state_with_variables("examples/clustered_edges.vcd", &["top.burst"]);
+        assert_eq!(
+            activity_histogram(&state, "top.burst", 10),
+            Some(vec![0, 5, 0, 0, 0, 0, 0, 0, 0, 1])
+        );
+        assert_eq!(activity_histogram(&state, "top.burst", 1), Some(vec![6]));
+    })
+}
+
+#[test]
+fn activity_histogram_of_unknown_variable_is_none() {
+    with_runtime(|| {
+        let state = state_with_variables("examples/clustered_edges.vcd", &["top.burst"]);
+        assert_eq!(activity_histogram(&state, "top.missing", 10), None);
+        assert_eq!(activity_histogram(&state, "top.burst", 0), None);
+    })
+}
+
+#[test]
+fn duplicate_names_are_resolved_in_drawing_order() {
+    with_runtime(|| {
+        let mut state = state_with_variables("examples/counter.vcd", &["tb.clk", "tb.dut.counter"]);
+        state.update(Message::AddDivider(
+            Some("same".to_string()),
+            Some(VisibleItemIndex(0)),
+        ));
+        state.update(Message::AddDivider(
+            Some("same".to_string()),
+            Some(VisibleItemIndex(2)),
+        ));
+        let waves = state.user.waves.as_ref().unwrap();
+        assert_eq!(
+            waves.displayed_item_names(),
+            vec!["tb.clk", "same", "tb.dut.counter", "same"]
+        );
+
+        let refs = waves.item_refs_of_name("same");
+        assert_eq!(refs.len(), 2);
+        assert_eq!(
+            refs,
+            vec![
+                waves.items_tree.get(ItemIndex(1)).unwrap().item_ref,
+                waves.items_tree.get(ItemIndex(3)).unwrap().item_ref
+            ]
+        );
+        assert_eq!(waves.item_ref_of_name("same"), Some(refs[0]));
+
+        assert_eq!(waves.item_index_of_name("same", 0), Some(1));
+        assert_eq!(waves.item_index_of_name("same", 1), Some(3));
+        assert_eq!(waves.item_index_of_name("same", 2), None);
+        assert_eq!(waves.item_index_of_name("tb.clk", 0), Some(0));
+        assert!(waves.item_refs_of_name("tb.missing").is_empty());
+    })
+}
+
+#[test]
+fn displayed_item_names_are_in_drawing_order() {
+    with_runtime(|| {
+        let mut state = state_with_variables("examples/counter.vcd", &["tb.clk", "tb.dut.counter"]);
+        state.update(Message::AddDivider(
+            Some("between".to_string()),
+            Some(VisibleItemIndex(0)),
+        ));
+        assert_eq!(
+            state.user.waves.as_ref().unwrap().displayed_item_names(),
+            vec!["tb.clk", "between", "tb.dut.counter"]
+        );
+
+        // Folding a group hides its members but keeps them in the list
+        let waves = state.user.waves.as_ref().unwrap();
+        let counter = waves.item_ref_of_name("tb.dut.counter").unwrap();
+        state.update(Message::GroupNew {
+            name: Some("group".to_string()),
+            before: Some(ItemIndex(2)),
+            items: Some(vec![counter]),
+        });
+        let group = state.user.waves.as_ref().unwrap().item_ref_of_name("group");
+        state.update(Message::GroupFold(group));
+        let waves = state.user.waves.as_ref().unwrap();
+        assert_eq!(waves.items_tree.iter_visible().count(), 3);
+        assert_eq!(
+            waves.displayed_item_names(),
+            vec!["tb.clk", "between", "group", "tb.dut.counter"]
+        );
+    })
+}
+
+#[test]
+fn moved_items_change_the_displayed_order() {
+    with_runtime(|| {
+        let mut state = state_with_variables("examples/counter.vcd", &["tb.clk", "tb.dut.counter"]);
+        state.update(Message::AddDivider(
+            Some("divider".to_string()),
+            Some(VisibleItemIndex(1)),
+        ));
+        let names = |state: &SystemState| state.user.waves.as_ref().unwrap().displayed_item_names();
+        let item_ref = |state: &SystemState, name: &str| {
+            state
+                .user
+                .waves
+                .as_ref()
+                .unwrap()
+                .item_ref_of_name(name)
+                .unwrap()
+        };
+
+        state.update(Message::MoveItem {
+            item: item_ref(&state, "tb.clk"),
+            to_index: 1,
+        });
+        assert_eq!(names(&state), vec!["tb.dut.counter", "tb.clk", "divider"]);
+
+        state.update(Message::MoveItem {
+            item: item_ref(&state, "divider"),
+            to_index: 0,
+        });
+        assert_eq!(names(&state), vec!["divider", "tb.dut.counter", "tb.clk"]);
+
+        // Indices past the end are clamped
+        state.update(Message::MoveItem {
+            item: item_ref(&state, "divider"),
+            to_index: 10,
+        });
+        assert_eq!(names(&state), vec!["tb.dut.counter", "tb.clk", "divider"]);
+    })
+}
+
+fn variable_value_at(state: &SystemState, name: &str, time: u32) -> Option<String> {
+    let waves = state.user.waves.as_ref().unwrap();
+    let item_ref = waves.item_ref_of_name(name)?;
+    state.get_variable_value(waves, &item_ref.into(), &Some(BigUint::from(time)))
+}
+
+#[test]
+fn several_variable_values_are_queried_at_once() {
+    with_runtime(|| {
+        let state = state_with_variables("examples/counter.vcd", &["tb.clk", "tb.dut.counter"]);
+        let names = ["tb.dut.counter", "tb.missing", "tb.clk"].map(String::from);
+        let values = state.variable_values_at(&names, &BigUint::from(300u32));
+        assert_eq!(
+            values,
+            vec![
+                variable_value_at(&state, "tb.dut.counter", 300),
+                None,
+                variable_value_at(&state, "tb.clk", 300),
+            ]
+        );
+        assert_eq!(values[0], Some("a".to_string()));
+        assert!(values[2].is_some());
+    })
+}
+
+#[test]
+fn variable_values_are_formatted_as_displayed() {
+    with_runtime(|| {
+        let mut state = state_with_variables("examples/counter.vcd", &["tb.dut.counter"]);
+        assert_eq!(
+            variable_value_at(&state, "tb.dut.counter", 300),
+            Some("a".to_string())
+        );
+
+        let item_ref = state
+            .user
+            .waves
+            .as_ref()
+            .unwrap()
+            .item_ref_of_name("tb.dut.counter")
+            .unwrap();
+        state.update(Message::VariableFormatChange(
+            Some(item_ref.into()),
+            "Unsigned".to_string(),
+        ));
+        assert_eq!(
+            variable_value_at(&state, "tb.dut.counter", 300),
+            Some("10".to_string())
+        );
+    })
+}
+
+fn transitions(state: &SystemState, name: &str, start: u32, end: u32) -> Vec<(u32, String)> {
+    variable_transitions(state, name, &BigUint::from(start), &BigUint::from(end))
+        .into_iter()
+        .map(|(time, value)| (time.try_into().unwrap(), value))
+        .collect()
+}
+
+#[test]
+fn variable_transitions_cover_the_range() {
+    with_runtime(|| {
+        let state = state_with_variables("examples/counter.vcd", &["tb.dut.counter"]);
+        let expected = |points: &[(u32, &str)]| {
+            points
+                .iter()
+                .map(|(t, v)| (*t, v.to_string()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            transitions(&state, "tb.dut.counter", 0, 150),
+            expected(&[(0, "0"), (110, "1"), (130, "2"), (150, "3")])
+        );
+        // The value at the start of the range is included even if it changed earlier
+        assert_eq!(
+            transitions(&state, "tb.dut.counter", 120, 155),
+            expected(&[(120, "1"), (130, "2"), (150, "3")])
+        );
+        assert!(transitions(&state, "tb.missing", 0, 150).is_empty());
+    })
+}
+
+#[test]
+fn queued_queries_share_one_repaint() {
+    let coalescer = RepaintCoalescer::new();
+    let repaints = std::cell::Cell::new(0);
+    for _ in 0..3 {
+        coalescer.request(|| repaints.set(repaints.get() + 1));
+    }
+    assert_eq!(repaints.get(), 1);
+
+    // Queries queued while a frame runs the queue need another frame
+    coalescer.frame_started();
+    coalescer.request(|| repaints.set(repaints.get() + 1));
+    assert_eq!(repaints.get(), 2);
+}
+
+#[test]
+fn downsampling_keeps_the_boundaries() {
+    let points = (0..10).collect::<Vec<_>>();
+    assert_eq!(downsample(&points, 20), points);
+    assert_eq!(downsample(&points, 4), vec![0, 3, 6, 9]);
+    assert_eq!(downsample(&points, 2), vec![0, 9]);
+    assert_eq!(downsample(&points, 1), vec![0]);
+    assert!(downsample(&points, 0).is_empty());
+}
+
+#[test]
+fn non_variables_have_no_value() {
+    with_runtime(|| {
+        let mut state = state_with_variables("examples/counter.vcd", &["tb.dut.counter"]);
+        state.update(Message::AddDivider(Some("divider".to_string()), None));
+        assert_eq!(variable_value_at(&state, "divider", 300), None);
+        assert_eq!(variable_value_at(&state, "tb.missing", 300), None);
+    })
+}
+
+#[test]
+fn cursor_time_round_trips() {
+    with_runtime(|| {
+        let mut state = state_with_variables("examples/counter.vcd", &["tb.dut.counter"]);
+        let cursor_time = |state: &SystemState| {
+            state
+                .user
+                .waves
+                .as_ref()?
+                .cursor
+                .as_ref()
+                .map(BigInt::to_string)
+        };
+
+        state.update(Message::CursorSet("12345678901234567890".parse().unwrap()));
+        assert_eq!(
+            cursor_time(&state),
+            Some("12345678901234567890".to_string())
+        );
+    })
+}
+
+#[test]
+fn markers_can_be_added_and_read_back() {
+    with_runtime(|| {
+        let mut state = state_with_variables("examples/counter.vcd", &["tb.dut.counter"]);
+        state.update(Message::AddMarker {
+            time: BigInt::from(300),
+            name: Some("end".to_string()),
+            move_focus: false,
+        });
+        state.update(Message::AddMarker {
+            time: BigInt::from(100),
+            name: Some("start".to_string()),
+            move_focus: false,
+        });
+
+        assert_eq!(
+            state.user.waves.as_ref().unwrap().marker_times(),
+            vec![
+                ("start".to_string(), BigInt::from(100)),
+                ("end".to_string(), BigInt::from(300))
+            ]
+        );
+    })
+}
+
+#[test]
+fn zooming_to_markers_fits_their_span() {
+    with_runtime(|| {
+        let mut state = state_with_variables("examples/counter.vcd", &["tb.dut.counter"]);
+        for (name, time) in [("start", 100), ("end", 300)] {
+            state.update(Message::AddMarker {
+                time: BigInt::from(time),
+                name: Some(name.to_string()),
+                move_focus: false,
+            });
+        }
+        let waves = state.user.waves.as_ref().unwrap();
+        assert_eq!(
+            waves.marker_span("start", "end"),
+            Ok((BigInt::from(100), BigInt::from(300)))
+        );
+        assert_eq!(
+            waves.marker_span("end", "start"),
+            Err("Marker 'start' at 100 is not after marker 'end' at 300".to_string())
+        );
+        assert_eq!(
+            waves.marker_span("start", "middle"),
+            Err("No marker named 'middle'".to_string())
+        );
+
+        let (start, end) = waves.marker_span("start", "end").unwrap();
+        let full_range = waves.viewport_range(0);
+        state.update(Message::ZoomToRange {
+            start,
+            end,
+            viewport_idx: 0,
+        });
+        let waves = state.user.waves.as_mut().unwrap();
+        waves.viewports[0].move_viewport(f32::INFINITY);
+        assert_eq!(
+            waves.viewport_range(0),
+            Some((BigInt::from(100), BigInt::from(300)))
+        );
+
+        state.update(Message::ZoomToFit { viewport_idx: 0 });
+        let waves = state.user.waves.as_mut().unwrap();
+        waves.viewports[0].move_viewport(f32::INFINITY);
+        assert_eq!(waves.viewport_range(0), full_range);
+    })
+}
+
+#[test]
+fn viewport_range_round_trips() {
+    with_runtime(|| {
+        let mut state = state_with_variables("examples/counter.vcd", &["tb.dut.counter"]);
+        state.update(Message::ZoomToRange {
+            start: BigInt::from(100),
+            end: BigInt::from(300),
+            viewport_idx: 0,
+        });
+        let waves = state.user.waves.as_mut().unwrap();
+        // Finish any animation of the viewport
+        waves.viewports[0].move_viewport(f32::INFINITY);
+
+        assert_eq!(
+            waves.viewport_range(0),
+            Some((BigInt::from(100), BigInt::from(300)))
+        );
+    })
+}
+
+#[test]
+fn added_variables_return_their_item_ref() {
+    with_runtime(|| {
+        let mut state = state_with_variables("examples/counter.vcd", &["tb.clk"]);
+        let item_refs =
+            state.add_variables(vec![VariableRef::from_hierarchy_string("tb.dut.counter")]);
+        assert_eq!(item_refs.len(), 1);
+        assert_eq!(
+            state
+                .user
+                .waves
+                .as_ref()
+                .unwrap()
+                .item_ref_of_name("tb.dut.counter"),
+            Some(item_refs[0])
+        );
+
+        assert!(state
+            .add_variables(vec![VariableRef::from_hierarchy_string("tb.missing")])
+            .is_empty());
+    })
+}
+
+#[test]
+fn graphics_are_hit_tested_after_drawing() {
+    with_runtime(|| {
+        let mut state = state_with_variables("examples/counter.vcd", &["tb.clk", "tb.dut.counter"]);
+        let item_ref = |state: &SystemState, name| {
+            state
+                .user
+                .waves
+                .as_ref()
+                .unwrap()
+                .item_ref_of_name(name)
+                .unwrap()
+        };
+        let point = |item, time: u32| {
+            (
+                GrPoint {
+                    x: BigInt::from(time),
+                    y: GraphicsY {
+                        item,
+                        anchor: Anchor::Center,
+                    },
+                },
+                Direction::East,
+            )
+        };
+        state.update(Message::AddGraphic(
+            GraphicId(7),
+            Graphic::TextArrow {
+                from: point(item_ref(&state, "tb.clk"), 200),
+                to: point(item_ref(&state, "tb.dut.counter"), 400),
+                text: "here".to_string(),
+                color: None,
+                font_size: None,
+                text_color: None,
+            },
+        ));
+        laid_out_item_heights(&mut state);
+
+        let arrow_rect = state.graphic_bounds.borrow()[&0][0].1;
+        assert_eq!(state.graphic_at(arrow_rect.center()), Some(GraphicId(7)));
+        assert_eq!(state.graphic_at(Pos2::new(-100., -100.)), None);
+
+        // The graphics of removed viewports are forgotten in the next frame
+        state.update(Message::AddViewport);
+        laid_out_item_heights(&mut state);
+        assert!(state.graphic_bounds.borrow().contains_key(&1));
+        state.update(Message::RemoveViewport);
+        laid_out_item_heights(&mut state);
+        assert_eq!(
+            state.graphic_bounds.borrow().keys().collect::<Vec<_>>(),
+            vec![&0]
+        );
+    })
+}
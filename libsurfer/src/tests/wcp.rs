@@ -2,7 +2,9 @@ use std::path::PathBuf;
 
 use crate::message::Message;
 use crate::tests::snapshot::render_and_compare;
-use crate::wcp::proto::{self, WcpCSMessage, WcpCommand, WcpEvent, WcpResponse, WcpSCMessage};
+use crate::wcp::proto::{
+    self, WcpCSMessage, WcpCommand, WcpEvent, WcpResponse, WcpSCMessage, WcpTagged,
+};
 use crate::SystemState;
 use itertools::Itertools;
 
@@ -24,7 +26,10 @@ macro_rules! expect_response {
             }
         };
 
-        let Some($expected) = received else {
+        let Some(WcpTagged {
+            message: $expected, ..
+        }) = received
+        else {
             bail!(
                 "Got unexpected response {received:?} expected {}",
                 stringify!(expected)
@@ -33,7 +38,7 @@ macro_rules! expect_response {
     };
 }
 
-async fn expect_ack(rx: &mut tokio::sync::mpsc::Receiver<WcpSCMessage>) -> Result<()> {
+async fn expect_ack(rx: &mut tokio::sync::mpsc::Receiver<WcpTagged<WcpSCMessage>>) -> Result<()> {
     expect_response! {
         rx, WcpSCMessage::response(WcpResponse::ack)
     }
@@ -42,7 +47,11 @@ async fn expect_ack(rx: &mut tokio::sync::mpsc::Receiver<WcpSCMessage>) -> Resul
 
 fn run_wcp_test<C, F>(test_name: String, client: C)
 where
-    C: Fn(Sender<WcpCSMessage>, Receiver<WcpSCMessage>) -> F + Sync + Send + Clone + 'static,
+    C: Fn(Sender<WcpTagged<WcpCSMessage>>, Receiver<WcpTagged<WcpSCMessage>>) -> F
+        + Sync
+        + Send
+        + Clone
+        + 'static,
     F: Future<Output = Result<()>> + Send + Sync,
 {
     let test_name = format!("wcp/{test_name}");
@@ -120,29 +129,35 @@ macro_rules! wcp_test {
     ($test_name:ident, ($tx:ident, $rx:ident) $body:tt) => {
         #[test]
         fn $test_name() {
-            async fn client($tx: Sender<WcpCSMessage>, mut $rx: Receiver<WcpSCMessage>) -> color_eyre::Result<()> $body
+            async fn client($tx: Sender<WcpTagged<WcpCSMessage>>, mut $rx: Receiver<WcpTagged<WcpSCMessage>>) -> color_eyre::Result<()> $body
 
             run_wcp_test(stringify!($test_name).to_string(), client)
         }
     };
 }
 
-async fn send_commands(tx: &Sender<WcpCSMessage>, cmds: Vec<WcpCommand>) -> Result<()> {
+async fn send_commands(tx: &Sender<WcpTagged<WcpCSMessage>>, cmds: Vec<WcpCommand>) -> Result<()> {
     for cmd in cmds {
-        tx.send(WcpCSMessage::command(cmd)).await?
+        tx.send(WcpCSMessage::command(cmd).into()).await?
     }
     Ok(())
 }
 
-async fn greet(tx: &Sender<WcpCSMessage>, rx: &mut Receiver<WcpSCMessage>) -> Result<()> {
+async fn greet(
+    tx: &Sender<WcpTagged<WcpCSMessage>>,
+    rx: &mut Receiver<WcpTagged<WcpSCMessage>>,
+) -> Result<()> {
     let commands = vec!["waveforms_loaded", "goto_declaration"]
         .into_iter()
         .map(str::to_string)
         .collect_vec();
-    tx.send(WcpCSMessage::greeting {
-        version: "0".to_string(),
-        commands,
-    })
+    tx.send(
+        WcpCSMessage::greeting {
+            version: "0".to_string(),
+            commands,
+        }
+        .into(),
+    )
     .await?;
 
     expect_response!(
@@ -174,15 +189,18 @@ async fn greet(tx: &Sender<WcpCSMessage>, rx: &mut Receiver<WcpSCMessage>) -> Re
 }
 
 async fn load_file(
-    tx: &Sender<WcpCSMessage>,
-    rx: &mut Receiver<WcpSCMessage>,
+    tx: &Sender<WcpTagged<WcpCSMessage>>,
+    rx: &mut Receiver<WcpTagged<WcpSCMessage>>,
     file: &str,
 ) -> Result<()> {
     greet(tx, rx).await?;
 
-    tx.send(WcpCSMessage::command(proto::WcpCommand::load {
-        source: file.to_string(),
-    }))
+    tx.send(
+        WcpCSMessage::command(proto::WcpCommand::load {
+            source: file.to_string(),
+        })
+        .into(),
+    )
     .await?;
     expect_ack(rx).await?;
 
@@ -213,7 +231,7 @@ wcp_test! {
                 "tb.overflow",
                 "tb.reset"
             ].into_iter().map(str::to_string).collect()
-        })).await?;
+        }).into()).await?;
         expect_response!(rx, WcpSCMessage::response(WcpResponse::add_variables{
             ids: indices
         }));
@@ -231,14 +249,14 @@ wcp_test! {
 
         tx.send(WcpCSMessage::command(proto::WcpCommand::load {
             source: "../examples/counter.vcd".to_string()
-        })).await?;
+        }).into()).await?;
         expect_ack(&mut rx).await?;
 
         expect_response!(rx, WcpSCMessage::event(WcpEvent::waveforms_loaded{source}));
         assert_eq!(source, "../examples/counter.vcd".to_string());
 
         tx.send(WcpCSMessage::command(
-            proto::WcpCommand::add_scope {scope: "tb".to_string()})).await?;
+            proto::WcpCommand::add_scope {scope: "tb".to_string()}).into()).await?;
         expect_response!(rx, WcpSCMessage::response(WcpResponse::add_scope{ ids: indices }));
 
         assert_eq!(indices.len(), 4);
@@ -259,14 +277,14 @@ wcp_test! {
                 "tb.overflow",
                 "tb.reset"
             ].into_iter().map(str::to_string).collect()
-        })).await?;
+        }).into()).await?;
 
         expect_response!(rx, WcpSCMessage::response(WcpResponse::add_variables{ ids: refs }));
 
         for (i, c) in [(1, "Gray"), (2, "Yellow"), (3, "Blue")] {
             tx.send(WcpCSMessage::command(
                 proto::WcpCommand::set_item_color { id: refs[i], color: c.to_string() }
-            )).await?;
+            ).into()).await?;
             expect_ack(&mut rx).await?;
         }
 
@@ -431,3 +449,80 @@ wcp_test! {
         Ok(())
     }
 }
+
+wcp_test! {
+    unsupported_version_is_rejected,
+    (tx, rx) {
+        tx.send(WcpCSMessage::greeting {
+            version: "7".to_string(),
+            commands: vec![],
+        }.into())
+        .await?;
+        expect_response!(rx, WcpSCMessage::error{error, arguments: _, message});
+        assert_eq!(error, "greeting");
+        assert_eq!(message, "Surfer only supports WCP version(s) 0, client requested 7");
+
+        // The failed greeting leaves the server ungreeted
+        send_commands(&tx, vec![
+            WcpCommand::clear,
+        ]).await?;
+        expect_response!(rx, WcpSCMessage::error{error, ..});
+        assert_eq!(error, "WCP server has not received greeting messages");
+
+        Ok(())
+    }
+}
+
+wcp_test! {
+    newest_common_version_is_negotiated,
+    (tx, rx) {
+        tx.send(WcpCSMessage::greeting {
+            version: "0, 3".to_string(),
+            commands: vec![],
+        }.into())
+        .await?;
+        expect_response!(rx, WcpSCMessage::greeting{version, commands: _});
+        assert_eq!(version, "0");
+
+        Ok(())
+    }
+}
+
+wcp_test! {
+    responses_carry_request_ids,
+    (tx, rx) {
+        load_file(&tx, &mut rx, "../examples/counter.vcd").await?;
+
+        // Send both requests before waiting for either response
+        tx.send(WcpTagged {
+            id: Some(5),
+            message: WcpCSMessage::command(proto::WcpCommand::add_variables {
+                variables: vec!["tb._tmp", "tb.clk", "tb.overflow", "tb.reset"]
+                    .into_iter()
+                    .map(str::to_string)
+                    .collect(),
+            }),
+        }).await?;
+        tx.send(WcpTagged {
+            id: Some(2),
+            message: WcpCSMessage::command(proto::WcpCommand::get_item_list),
+        }).await?;
+
+        let mut responses = vec![];
+        for _ in 0..2 {
+            let Some(response) = rx.recv().await else {
+                bail!("WCP channel closed while waiting for responses");
+            };
+            responses.push(response);
+        }
+        let Some(WcpTagged { id: Some(5), message: WcpSCMessage::response(WcpResponse::add_variables { ids: added }) }) = responses.first() else {
+            bail!("Unexpected response to add_variables {:?}", responses.first());
+        };
+        let Some(WcpTagged { id: Some(2), message: WcpSCMessage::response(WcpResponse::get_item_list { ids: listed }) }) = responses.get(1) else {
+            bail!("Unexpected response to get_item_list {:?}", responses.get(1));
+        };
+        assert_eq!(added, listed);
+
+        Ok(())
+    }
+}
@@ -97,7 +97,7 @@ impl TimeUnit {
             TimeUnit::Auto => 0,
         }
     }
-    fn from_exponent(exponent: i8) -> Self {
+    pub(crate) fn from_exponent(exponent: i8) -> Self {
         match exponent {
             -15 => TimeUnit::FemtoSeconds,
             -12 => TimeUnit::PicoSeconds,
@@ -0,0 +1,388 @@
+//! Assembly of gheith programs from text, the inverse of the disassembler.
+//!
+//! Each line holds at most one instruction written like the disassembler output, e.g.
+//! `sub r1, r2, r3` or `movl r4, #0x10`, optionally preceded by a `label:`. Everything after
+//! a `;` is a comment. Immediates are decimal, `0x` hexadecimal or `0b` binary numbers, or
+//! the name of a label. `movl` takes the low byte of a label's address and `movh` the high
+//! byte, so a jump to any label is written as
+//!
+//! ```text
+//! movl r1, target
+//! movh r1, target
+//! jz r1, r0
+//! ```
+//!
+//! Labels may be used before they are defined.
+use std::collections::HashMap;
+
+use derive_more::Display;
+
+use super::I;
+
+/// A mistake in the assembly source. Lines and columns start at 1.
+#[derive(Debug, Display, Clone, PartialEq, Eq)]
+#[display("line {line}, column {column}: {message}")]
+pub struct AsmError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl std::error::Error for AsmError {}
+
+/// A piece of a source line along with where it starts
+#[derive(Debug, Clone, Copy)]
+struct Token<'a> {
+    text: &'a str,
+    line: usize,
+    column: usize,
+}
+
+impl Token<'_> {
+    fn error(&self, message: impl Into<String>) -> AsmError {
+        AsmError {
+            line: self.line,
+            column: self.column,
+            message: message.into(),
+        }
+    }
+}
+
+/// The parts of a source line
+struct Line<'a> {
+    label: Option<Token<'a>>,
+    mnemonic: Option<Token<'a>>,
+    operands: Vec<Token<'a>>,
+}
+
+/// Returns `source[start..end]` without surrounding whitespace
+fn token(source: &str, line: usize, start: usize, end: usize) -> Token<'_> {
+    let text = &source[start..end];
+    let trimmed = text.trim_start();
+    Token {
+        text: trimmed.trim_end(),
+        line,
+        column: start + (text.len() - trimmed.len()) + 1,
+    }
+}
+
+fn split_line(source: &str, line: usize) -> Line<'_> {
+    let code_end = source.find(';').unwrap_or(source.len());
+    let (label, start) = match source[..code_end].find(':') {
+        Some(colon) => (Some(token(source, line, 0, colon)), colon + 1),
+        None => (None, 0),
+    };
+
+    let rest = token(source, line, start, code_end);
+    if rest.text.is_empty() {
+        return Line {
+            label,
+            mnemonic: None,
+            operands: vec![],
+        };
+    }
+    let rest_start = rest.column - 1;
+    let rest_end = rest_start + rest.text.len();
+    let mnemonic_end = rest
+        .text
+        .find(char::is_whitespace)
+        .map_or(rest_end, |len| rest_start + len);
+
+    let mut operands = vec![];
+    if !source[mnemonic_end..rest_end].trim().is_empty() {
+        let mut operand_start = mnemonic_end;
+        for operand in source[mnemonic_end..rest_end].split(',') {
+            let operand_end = operand_start + operand.len();
+            operands.push(token(source, line, operand_start, operand_end));
+            operand_start = operand_end + 1;
+        }
+    }
+
+    Line {
+        label,
+        mnemonic: Some(token(source, line, rest_start, mnemonic_end)),
+        operands,
+    }
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn register(operand: &Token) -> Result<u8, AsmError> {
+    let index = operand
+        .text
+        .strip_prefix(['r', 'R'])
+        .and_then(|index| index.parse::<u8>().ok())
+        .ok_or_else(|| operand.error(format!("expected a register, found `{}`", operand.text)))?;
+    if index > 15 {
+        return Err(operand.error(format!(
+            "register r{index} does not exist, registers are r0 to r15"
+        )));
+    }
+    Ok(index)
+}
+
+/// Parses an immediate operand. Labels resolve to the high byte of their address if `high`
+/// is set and to the low byte otherwise.
+fn immediate(operand: &Token, labels: &HashMap<&str, u16>, high: bool) -> Result<u8, AsmError> {
+    let text = operand.text.strip_prefix('#').unwrap_or(operand.text);
+    let value = if let Some(hex) = text.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = text.strip_prefix("0b") {
+        u32::from_str_radix(binary, 2).ok()
+    } else if is_identifier(text) {
+        let address = labels
+            .get(text)
+            .ok_or_else(|| operand.error(format!("undefined label `{text}`")))?;
+        Some(u32::from(if high { address >> 8 } else { address & 0xff }))
+    } else {
+        text.parse::<u32>().ok()
+    }
+    .ok_or_else(|| operand.error(format!("expected an immediate, found `{}`", operand.text)))?;
+
+    u8::try_from(value).map_err(|_| {
+        operand.error(format!(
+            "immediate {value} does not fit in 8 bits, expected 0 to 255"
+        ))
+    })
+}
+
+fn instruction(line: &Line, labels: &HashMap<&str, u16>) -> Result<Option<I>, AsmError> {
+    let Some(mnemonic) = line.mnemonic else {
+        return Ok(None);
+    };
+    let name = mnemonic.text.to_ascii_lowercase();
+    let expected = match name.as_str() {
+        "sub" => 3,
+        "movl" | "movh" | "jz" | "jnz" | "js" | "jns" | "ld" | "st" => 2,
+        _ => {
+            return Err(mnemonic.error(format!("unknown instruction `{}`", mnemonic.text)));
+        }
+    };
+    if line.operands.len() != expected {
+        return Err(mnemonic.error(format!(
+            "{name} takes {expected} operands, found {}",
+            line.operands.len()
+        )));
+    }
+
+    let ops = &line.operands;
+    Ok(Some(match name.as_str() {
+        "sub" => I::SUB {
+            rt: register(&ops[0])?,
+            ra: register(&ops[1])?,
+            rb: register(&ops[2])?,
+        },
+        "movl" => I::MOVL {
+            rt: register(&ops[0])?,
+            i: immediate(&ops[1], labels, false)?,
+        },
+        "movh" => I::MOVH {
+            rt: register(&ops[0])?,
+            i: immediate(&ops[1], labels, true)?,
+        },
+        _ => {
+            let (rt, ra) = (register(&ops[0])?, register(&ops[1])?);
+            match name.as_str() {
+                "jz" => I::JZ { rt, ra },
+                "jnz" => I::JNZ { rt, ra },
+                "js" => I::JS { rt, ra },
+                "jns" => I::JNS { rt, ra },
+                "ld" => I::LD { rt, ra },
+                _ => I::ST { rt, ra },
+            }
+        }
+    }))
+}
+
+/// Assembles `source` into a program which is loaded at address 0, with instruction words
+/// stored little-endian
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    let lines = source
+        .lines()
+        .enumerate()
+        .map(|(idx, line)| split_line(line, idx + 1))
+        .collect::<Vec<_>>();
+
+    let mut labels = HashMap::new();
+    let mut address = 0u16;
+    for line in &lines {
+        if let Some(label) = line.label {
+            if !is_identifier(label.text) {
+                return Err(label.error(format!("invalid label name `{}`", label.text)));
+            }
+            if labels.insert(label.text, address).is_some() {
+                let first = lines
+                    .iter()
+                    .find_map(|line| line.label.filter(|l| l.text == label.text))
+                    .map_or(0, |l| l.line);
+                return Err(label.error(format!(
+                    "label `{}` is already defined on line {first}",
+                    label.text
+                )));
+            }
+        }
+        if line.mnemonic.is_some() {
+            address = address.wrapping_add(2);
+        }
+    }
+
+    let mut bytes = vec![];
+    for line in &lines {
+        if let Some(insn) = instruction(line, &labels)? {
+            bytes.extend(u16::from(insn).to_le_bytes());
+        }
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn assemble_words(source: &str) -> Vec<u16> {
+        assemble(source)
+            .unwrap()
+            .chunks_exact(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .collect()
+    }
+
+    #[test]
+    fn every_instruction_form_is_assembled() {
+        let source = "
+            sub r1, r2, r3
+            movl r4, #0x10
+            movh r5, #255
+            jz r1, r2
+            jnz r3, r4
+            js r5, r6
+            jns r7, r8
+            ld r9, r10
+            st r11, r15
+        ";
+        let decoded = assemble_words(source)
+            .into_iter()
+            .map(|word| I::try_from(word).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            decoded,
+            vec![
+                I::SUB {
+                    rt: 1,
+                    ra: 2,
+                    rb: 3
+                },
+                I::MOVL { rt: 4, i: 0x10 },
+                I::MOVH { rt: 5, i: 255 },
+                I::JZ { rt: 1, ra: 2 },
+                I::JNZ { rt: 3, ra: 4 },
+                I::JS { rt: 5, ra: 6 },
+                I::JNS { rt: 7, ra: 8 },
+                I::LD { rt: 9, ra: 10 },
+                I::ST { rt: 11, ra: 15 },
+            ]
+        );
+    }
+
+    #[test]
+    fn words_are_little_endian() {
+        assert_eq!(assemble("movl r1, #8").unwrap(), vec![0x81, 0x80]);
+    }
+
+    #[test]
+    fn labels_and_comments_are_allowed() {
+        let source = "
+            ; Print 'H' forever
+            start:  movl r1, #72 ; the character
+                    movl r2, start
+            loop:
+                    sub r0, r1, r0
+                    movl r3, loop
+                    jz r3, r0
+        ";
+        assert_eq!(
+            assemble_words(source),
+            vec![0x8481, 0x8002, 0x0100, 0x8043, 0xe003]
+        );
+    }
+
+    #[test]
+    fn labels_resolve_forwards_and_backwards() {
+        let source = "
+            start:  movl r1, end
+                    movh r1, end
+                    jz r1, r0
+                    movl r2, start
+                    movh r2, start
+                    jz r2, r0
+            end:    movl r0, #72
+        ";
+        let words = assemble_words(source);
+        assert_eq!(words[0], u16::from(I::MOVL { rt: 1, i: 12 }));
+        assert_eq!(words[1], u16::from(I::MOVH { rt: 1, i: 0 }));
+        assert_eq!(words[3], u16::from(I::MOVL { rt: 2, i: 0 }));
+    }
+
+    #[test]
+    fn label_addresses_are_split_between_movl_and_movh() {
+        let source = format!(
+            "movl r1, far\nmovh r1, far\n{}far: jz r1, r0",
+            "sub r0, r0, r0\n".repeat(198)
+        );
+        let words = assemble_words(&source);
+        // `far` is at 2 * 200 = 0x190
+        assert_eq!(words[0], u16::from(I::MOVL { rt: 1, i: 0x90 }));
+        assert_eq!(words[1], u16::from(I::MOVH { rt: 1, i: 0x01 }));
+    }
+
+    #[test]
+    fn syntax_errors_have_positions() {
+        assert_eq!(
+            assemble("movl r1, #256"),
+            Err(AsmError {
+                line: 1,
+                column: 10,
+                message: "immediate 256 does not fit in 8 bits, expected 0 to 255".to_string()
+            })
+        );
+        assert_eq!(
+            assemble("sub r1, r2, r3\n  ld r16, r1").unwrap_err(),
+            AsmError {
+                line: 2,
+                column: 6,
+                message: "register r16 does not exist, registers are r0 to r15".to_string()
+            }
+        );
+        let e = assemble("  sub r1, r2").unwrap_err();
+        assert_eq!((e.line, e.column), (1, 3));
+        assert_eq!(e.message, "sub takes 3 operands, found 2");
+    }
+
+    #[test]
+    fn unknown_names_are_rejected() {
+        assert_eq!(
+            assemble("add r1, r2, r3").unwrap_err().message,
+            "unknown instruction `add`"
+        );
+        assert_eq!(
+            assemble("movl r1, nowhere").unwrap_err().message,
+            "undefined label `nowhere`"
+        );
+    }
+
+    #[test]
+    fn duplicate_labels_are_rejected() {
+        assert_eq!(
+            assemble("a: movl r1, #1\n\n  a: movl r1, #2")
+                .unwrap_err()
+                .to_string(),
+            "line 3, column 3: label `a` is already defined on line 1"
+        );
+    }
+}
@@ -0,0 +1,402 @@
+//! Linear disassembly of whole gheith programs, which unlike translating a single
+//! instruction word can make use of the instructions that came before.
+use std::collections::{BTreeSet, HashMap};
+
+use super::{ConversionError, DecodeConfig, I};
+
+/// Names of addresses in a program, used to render jump targets
+pub type SymbolTable = HashMap<u16, String>;
+
+/// Disassembles `words`, which are assumed to be laid out consecutively from address 0.
+///
+/// If a symbol table is given, jumps whose target register holds a constant loaded
+/// by `movl`/`movh` are rendered with the name of the target, i.e. `jz loop, r2` instead
+/// of `jz r1, r2`. Since control flow may enter at any labeled address, constants
+/// are forgotten at each of them.
+pub fn disassemble(words: &[u16], symbols: Option<&SymbolTable>) -> Vec<String> {
+    let mut regs = [None; 16];
+
+    words
+        .iter()
+        .enumerate()
+        .map(|(idx, word)| {
+            let addr = (idx * 2) as u16;
+            if symbols.is_some_and(|s| s.contains_key(&addr)) {
+                regs = [None; 16];
+            }
+            // Reading from r0 always returns 0
+            regs[0] = Some(0);
+
+            let Ok(insn) = I::try_from(*word) else {
+                return format!("ILLEGAL INSN ({word:#x})");
+            };
+
+            let target_label = |rt: u8| {
+                symbols.and_then(|s| regs[rt as usize].and_then(|value| s.get(&(value as u16))))
+            };
+            let line = match insn {
+                I::JZ { rt, ra } | I::JNZ { rt, ra } | I::JS { rt, ra } | I::JNS { rt, ra } => {
+                    match target_label(rt) {
+                        Some(label) => format!("{} {label}, r{ra}", mnemonic(&insn)),
+                        None => format!("{insn:?}"),
+                    }
+                }
+                _ => format!("{insn:?}"),
+            };
+
+            propagate(&mut regs, &insn);
+
+            line
+        })
+        .collect()
+}
+
+/// Names the addresses inside `words` which are the target of a jump whose target register
+/// holds a known constant, `L0`, `L1`, ... in address order. Register values are tracked as
+/// in [`known_regs`].
+pub fn reconstruct_labels(words: &[u16]) -> SymbolTable {
+    let mut regs = [None; 16];
+    regs[0] = Some(0);
+    let mut targets = BTreeSet::new();
+    for insn in words.iter().filter_map(|word| I::try_from(*word).ok()) {
+        if let I::JZ { rt, .. } | I::JNZ { rt, .. } | I::JS { rt, .. } | I::JNS { rt, .. } = insn {
+            let in_program = |target: u16| target % 2 == 0 && usize::from(target / 2) < words.len();
+            if let Some(target) = regs[rt as usize].map(|value| value as u16) {
+                if in_program(target) {
+                    targets.insert(target);
+                }
+            }
+        }
+        propagate(&mut regs, &insn);
+    }
+    targets
+        .into_iter()
+        .enumerate()
+        .map(|(n, addr)| (addr, format!("L{n}")))
+        .collect()
+}
+
+/// Disassembles `words` like [`disassemble`] with the labels found by
+/// [`reconstruct_labels`], prefixing each labeled instruction with `label: `
+pub fn disassemble_labeled(words: &[u16]) -> Vec<String> {
+    let labels = reconstruct_labels(words);
+    disassemble(words, Some(&labels))
+        .into_iter()
+        .enumerate()
+        .map(|(idx, line)| match labels.get(&((idx * 2) as u16)) {
+            Some(label) => format!("{label}: {line}"),
+            None => line,
+        })
+        .collect()
+}
+
+/// Disassembles the program stored in `bytes` from address 0, reading instruction words
+/// in the byte order given by `config`. See [`disassemble`].
+pub fn disassemble_bytes(
+    bytes: &[u8],
+    config: DecodeConfig,
+    symbols: Option<&SymbolTable>,
+) -> Vec<String> {
+    disassemble(&config.words(bytes), symbols)
+}
+
+/// A line of a disassembly listing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisasmLine {
+    /// The instruction word at `addr` and its decoding
+    Word {
+        addr: u16,
+        word: u16,
+        insn: Result<I, ConversionError>,
+    },
+    /// A single byte at `addr` at the end of the buffer which does not form a full word
+    DanglingByte { addr: u16, byte: u8 },
+}
+
+impl DisasmLine {
+    pub fn addr(&self) -> u16 {
+        match self {
+            DisasmLine::Word { addr, .. } | DisasmLine::DanglingByte { addr, .. } => *addr,
+        }
+    }
+}
+
+/// Decodes the instruction words in `bytes`, which are located at `base_addr`, reading
+/// them in the byte order given by `config`. A trailing odd byte is listed as
+/// [`DisasmLine::DanglingByte`].
+pub fn disassemble_listing(bytes: &[u8], base_addr: u16, config: DecodeConfig) -> Vec<DisasmLine> {
+    let chunks = bytes.chunks_exact(2);
+    let dangling = chunks
+        .remainder()
+        .first()
+        .map(|byte| DisasmLine::DanglingByte {
+            addr: base_addr.wrapping_add((bytes.len() - 1) as u16),
+            byte: *byte,
+        });
+    chunks
+        .enumerate()
+        .map(|(idx, pair)| {
+            let word = config.word([pair[0], pair[1]]);
+            DisasmLine::Word {
+                addr: base_addr.wrapping_add((idx * 2) as u16),
+                word,
+                insn: I::try_from(word),
+            }
+        })
+        .chain(dangling)
+        .collect()
+}
+
+/// Returns the register values which are known to hold right before the instruction at
+/// index `up_to` of `words` is executed, assuming that execution runs straight through
+/// from address 0. `r0` is always known to be 0.
+///
+/// Values are tracked through `movl`, `movh` and `sub` with known operands, any other
+/// write makes the register unknown. Illegal instructions are skipped.
+pub fn known_regs(words: &[u16], up_to: usize) -> [Option<i16>; 16] {
+    let mut regs = [None; 16];
+    regs[0] = Some(0);
+    for insn in words
+        .iter()
+        .take(up_to)
+        .filter_map(|word| I::try_from(*word).ok())
+    {
+        propagate(&mut regs, &insn);
+    }
+    regs
+}
+
+/// Disassembles the instruction at the byte address `pc` of `words`, which are laid out
+/// consecutively from address 0. Returns `None` if `pc` is misaligned, outside of `words`
+/// or points at an illegal instruction.
+pub fn instruction_at(words: &[u16], pc: u64) -> Option<String> {
+    if pc % 2 != 0 {
+        return None;
+    }
+    let word = words.get(usize::try_from(pc / 2).ok()?)?;
+    I::try_from(*word).ok().map(|insn| format!("{insn:?}"))
+}
+
+/// Updates the known register values `regs` with the effect of `insn`
+fn propagate(regs: &mut [Option<i16>; 16], insn: &I) {
+    let (rt, value) = match *insn {
+        I::MOVL { rt, i } => (rt, Some(i as i8 as i16)),
+        I::MOVH { rt, i } => (
+            rt,
+            regs[rt as usize].map(|value| (value & 0xff) | ((i as i16) << 8)),
+        ),
+        I::SUB { rt, ra, rb } => (
+            rt,
+            regs[ra as usize]
+                .zip(regs[rb as usize])
+                .map(|(a, b)| a.wrapping_sub(b)),
+        ),
+        I::LD { rt, .. } => (rt, None),
+        I::JZ { .. } | I::JNZ { .. } | I::JS { .. } | I::JNS { .. } | I::ST { .. } => return,
+    };
+    // Writes to r0 print instead of changing the register
+    if rt != 0 {
+        regs[rt as usize] = value;
+    }
+}
+
+fn mnemonic(insn: &I) -> &'static str {
+    match insn {
+        I::SUB { .. } => "sub",
+        I::MOVL { .. } => "movl",
+        I::MOVH { .. } => "movh",
+        I::JZ { .. } => "jz",
+        I::JNZ { .. } => "jnz",
+        I::JS { .. } => "js",
+        I::JNS { .. } => "jns",
+        I::LD { .. } => "ld",
+        I::ST { .. } => "st",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::translation::asm_gheith::Endian;
+
+    const PROGRAM: [u16; 5] = [
+        0x8081, // movl r1, 8
+        0x9001, // movh r1, 0
+        0xe201, // jz r1, r2
+        0xffff, // illegal
+        0x8480, // movl r0, 'H' (address 8)
+    ];
+
+    #[test]
+    fn disassembly_without_symbols_is_unchanged() {
+        assert_eq!(
+            disassemble(&PROGRAM, None),
+            vec![
+                "movl r1, #8",
+                "movh r1, #0",
+                "jz r1, r2",
+                "ILLEGAL INSN (0xffff)",
+                "movl r0, #72",
+            ]
+        );
+    }
+
+    #[test]
+    fn jump_targets_are_labeled_with_symbols() {
+        let symbols = SymbolTable::from([(8, "end".to_string())]);
+        assert_eq!(
+            disassemble(&PROGRAM, Some(&symbols))[2],
+            "jz end, r2".to_string()
+        );
+    }
+
+    #[test]
+    fn unknown_jump_targets_are_not_labeled() {
+        let symbols = SymbolTable::from([(8, "end".to_string())]);
+        let program = [
+            0x8081, // movl r1, 8
+            0xf101, // ld r1, r1
+            0xe201, // jz r1, r2
+        ];
+        assert_eq!(disassemble(&program, Some(&symbols))[2], "jz r1, r2");
+    }
+
+    #[test]
+    fn labels_are_reconstructed_at_jump_targets() {
+        let program = crate::translation::asm_gheith::assemble(
+            "
+            start:  movl r1, end
+                    movl r2, start
+                    jz r1, r0
+                    jz r2, r0
+                    movl r3, #100
+                    jz r3, r0
+            end:    movl r0, #72
+            ",
+        )
+        .unwrap();
+        let words = DecodeConfig::default().words(&program);
+        assert_eq!(
+            reconstruct_labels(&words),
+            SymbolTable::from([(0, "L0".to_string()), (12, "L1".to_string())])
+        );
+        assert_eq!(
+            disassemble_labeled(&words),
+            vec![
+                "L0: movl r1, #12",
+                "movl r2, #0",
+                "jz L1, r0",
+                "jz L0, r0",
+                "movl r3, #100",
+                // Outside of the program
+                "jz r3, r0",
+                "L1: movl r0, #72",
+            ]
+        );
+    }
+
+    #[test]
+    fn known_regs_resolve_jump_target() {
+        let regs = known_regs(&PROGRAM, 2);
+        assert_eq!(regs[0], Some(0));
+        assert_eq!(regs[1], Some(8));
+        assert_eq!(regs[2], None);
+    }
+
+    #[test]
+    fn known_regs_track_sub_and_forget_loads() {
+        let program = [
+            0x8051, // movl r1, 5
+            0x8022, // movl r2, 2
+            0x0123, // sub r3, r1, r2
+            0xf101, // ld r1, r1
+            0x0124, // sub r4, r1, r2
+        ];
+        let regs = known_regs(&program, program.len());
+        assert_eq!(regs[1], None);
+        assert_eq!(regs[2], Some(2));
+        assert_eq!(regs[3], Some(3));
+        assert_eq!(regs[4], None);
+    }
+
+    #[test]
+    fn instruction_at_pc_is_decoded() {
+        assert_eq!(instruction_at(&PROGRAM, 0), Some("movl r1, #8".to_string()));
+        assert_eq!(instruction_at(&PROGRAM, 4), Some("jz r1, r2".to_string()));
+    }
+
+    #[test]
+    fn instruction_at_unknown_pc_is_none() {
+        // Illegal instruction
+        assert_eq!(instruction_at(&PROGRAM, 6), None);
+        // Misaligned
+        assert_eq!(instruction_at(&PROGRAM, 3), None);
+        // Past the end of memory
+        assert_eq!(instruction_at(&PROGRAM, 10), None);
+    }
+
+    #[test]
+    fn byte_streams_are_read_in_configured_byte_order() {
+        let bytes = [0x81, 0x80, 0x01, 0x90];
+        let little = DecodeConfig {
+            endian: Endian::Little,
+        };
+        let big = DecodeConfig {
+            endian: Endian::Big,
+        };
+        assert_eq!(
+            disassemble_bytes(&bytes, little, None),
+            vec!["movl r1, #8", "movh r1, #0"]
+        );
+        assert_eq!(
+            disassemble_bytes(&bytes, big, None),
+            vec!["movl r0, #24", "sub r0, r1, r9"]
+        );
+    }
+
+    #[test]
+    fn listings_record_addresses_and_words() {
+        let bytes = [0x81, 0x80, 0xff, 0xff];
+        assert_eq!(
+            disassemble_listing(&bytes, 0x100, DecodeConfig::default()),
+            vec![
+                DisasmLine::Word {
+                    addr: 0x100,
+                    word: 0x8081,
+                    insn: Ok(I::MOVL { rt: 1, i: 8 }),
+                },
+                DisasmLine::Word {
+                    addr: 0x102,
+                    word: 0xffff,
+                    insn: Err(ConversionError::ReservedBits(0xffff)),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn listings_mark_a_dangling_byte() {
+        let listing = disassemble_listing(&[0x01, 0x90, 0x42], 0x10, DecodeConfig::default());
+        assert_eq!(
+            listing,
+            vec![
+                DisasmLine::Word {
+                    addr: 0x10,
+                    word: 0x9001,
+                    insn: Ok(I::MOVH { rt: 1, i: 0 }),
+                },
+                DisasmLine::DanglingByte {
+                    addr: 0x12,
+                    byte: 0x42
+                },
+            ]
+        );
+        assert_eq!(listing[1].addr(), 0x12);
+    }
+
+    #[test]
+    fn trailing_odd_byte_is_ignored() {
+        let config = DecodeConfig::default();
+        assert_eq!(config.words(&[0x81, 0x80, 0x01]), vec![0x8081]);
+    }
+}
@@ -0,0 +1,484 @@
+//! A small interpreter for the gheith ISA which executes one instruction per cycle.
+//!
+//! Registers hold plain 16-bit words. `sub` wraps around and `js`/`jns` look at the tested
+//! register as a two's complement `i16`, so `0x8000` to `0xffff` count as negative and
+//! `0x0000` to `0x7fff` as non-negative.
+use std::sync::Arc;
+
+use super::{DecodeConfig, I};
+
+/// The outcome of executing a single instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepResult {
+    /// The instruction was executed without any externally visible side effects
+    Continue,
+    /// The instruction wrote to `r0` which printed the contained character
+    Printed(char),
+    /// The machine fetched an instruction which could not be decoded, or the
+    /// program counter left the memory. No state was changed.
+    Halted,
+    /// The instruction could not be executed. No state was changed, so the machine stays
+    /// at the faulting instruction.
+    Trap(TrapKind),
+}
+
+/// The reason an instruction trapped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrapKind {
+    /// `ld` from an address outside of the memory
+    OutOfBoundsLoad { addr: u16 },
+    /// `st` to an address outside of the memory
+    OutOfBoundsStore { addr: u16 },
+}
+
+/// The state of a [`Machine`] at some point of its execution, for inspecting it or going
+/// back to it with [`Machine::restore`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MachineSnapshot {
+    pub regs: [u16; 16],
+    pub pc: u16,
+    pub cycles: u64,
+    /// The memory is shared with the machine until either of them writes to it
+    pub mem: Arc<Vec<u8>>,
+}
+
+impl MachineSnapshot {
+    /// Returns the memory from `before` bytes before `pc` up to `after` bytes after it,
+    /// clipped to the memory, along with the address of the first returned byte
+    pub fn memory_around_pc(&self, before: u16, after: u16) -> (u16, &[u8]) {
+        let start = (self.pc.saturating_sub(before) as usize).min(self.mem.len());
+        let end = (self.pc as usize + after as usize).min(self.mem.len());
+        (start as u16, &self.mem[start..end.max(start)])
+    }
+}
+
+pub struct Machine {
+    pub regs: [u16; 16],
+    /// Copied on write if it is shared with a [`MachineSnapshot`]
+    pub mem: Arc<Vec<u8>>,
+    pub pc: u16,
+    /// The number of instructions which have been executed so far
+    pub cycles: u64,
+    /// How instruction words are laid out in `mem`
+    pub config: DecodeConfig,
+    /// The characters printed by writes to `r0` since the last `take_output`. Bytes which
+    /// are not printable are escaped as `\xNN`.
+    pub printed: String,
+}
+
+impl Machine {
+    /// Create a new machine with all registers cleared and execution starting at
+    /// address 0 of `mem`
+    pub fn new(mem: Vec<u8>) -> Self {
+        Self::with_config(mem, DecodeConfig::default())
+    }
+
+    /// Like [`Machine::new`], but fetching instruction words as described by `config`
+    pub fn with_config(mem: Vec<u8>, config: DecodeConfig) -> Self {
+        Self {
+            regs: [0; 16],
+            mem: Arc::new(mem),
+            pc: 0,
+            cycles: 0,
+            config,
+            printed: String::new(),
+        }
+    }
+
+    fn read_reg(&self, r: u8) -> u16 {
+        // Reading from r0 always returns 0
+        if r == 0 {
+            0
+        } else {
+            self.regs[r as usize]
+        }
+    }
+
+    /// Reads `r` as a two's complement number, as `js` and `jns` do
+    fn read_reg_signed(&self, r: u8) -> i16 {
+        self.read_reg(r) as i16
+    }
+
+    /// Returns a copy of the registers, program counter and memory. Copying the memory is
+    /// deferred until the machine writes to it.
+    pub fn snapshot(&self) -> MachineSnapshot {
+        MachineSnapshot {
+            regs: self.regs,
+            pc: self.pc,
+            cycles: self.cycles,
+            mem: Arc::clone(&self.mem),
+        }
+    }
+
+    /// Returns the machine to the state captured in `snapshot`. Output which was printed
+    /// since then is kept.
+    pub fn restore(&mut self, snapshot: &MachineSnapshot) {
+        self.regs = snapshot.regs;
+        self.pc = snapshot.pc;
+        self.cycles = snapshot.cycles;
+        self.mem = Arc::clone(&snapshot.mem);
+    }
+
+    /// Returns the output printed since the last call and clears it
+    pub fn take_output(&mut self) -> String {
+        std::mem::take(&mut self.printed)
+    }
+
+    fn write_reg(&mut self, r: u8, value: u16) -> StepResult {
+        // Writes to r0 print the least significant byte instead of storing it
+        if r == 0 {
+            let byte = (value & 0xff) as u8;
+            if byte.is_ascii_graphic() || matches!(byte, b' ' | b'\n' | b'\t') {
+                self.printed.push(byte as char);
+            } else {
+                self.printed.push_str(&format!("\\x{byte:02x}"));
+            }
+            StepResult::Printed(byte as char)
+        } else {
+            self.regs[r as usize] = value;
+            StepResult::Continue
+        }
+    }
+
+    fn read_mem(&self, addr: u16) -> Result<u8, TrapKind> {
+        self.mem
+            .get(addr as usize)
+            .copied()
+            .ok_or(TrapKind::OutOfBoundsLoad { addr })
+    }
+
+    fn write_mem(&mut self, addr: u16, value: u8) -> Result<(), TrapKind> {
+        if addr as usize >= self.mem.len() {
+            return Err(TrapKind::OutOfBoundsStore { addr });
+        }
+        Arc::make_mut(&mut self.mem)[addr as usize] = value;
+        Ok(())
+    }
+
+    /// Fetch the instruction word at `pc` in the configured byte order
+    fn fetch(&self) -> Option<u16> {
+        let first = *self.mem.get(self.pc as usize)?;
+        let second = *self.mem.get(self.pc as usize + 1)?;
+        Some(self.config.word([first, second]))
+    }
+
+    fn jump_if(&mut self, condition: bool, rt: u8) {
+        self.pc = if condition {
+            self.read_reg(rt)
+        } else {
+            self.pc.wrapping_add(2)
+        };
+    }
+
+    /// Fetch, decode and execute the instruction at `pc`
+    pub fn step(&mut self) -> StepResult {
+        let Some(insn) = self.fetch().and_then(|word| I::try_from(word).ok()) else {
+            return StepResult::Halted;
+        };
+        match self.execute(insn) {
+            Ok(result) => {
+                self.cycles += 1;
+                result
+            }
+            Err(trap) => StepResult::Trap(trap),
+        }
+    }
+
+    fn execute(&mut self, insn: I) -> Result<StepResult, TrapKind> {
+        let next_pc = self.pc.wrapping_add(2);
+        Ok(match insn {
+            I::SUB { rt, ra, rb } => {
+                let value = self.read_reg(ra).wrapping_sub(self.read_reg(rb));
+                self.pc = next_pc;
+                self.write_reg(rt, value)
+            }
+            I::MOVL { rt, i } => {
+                self.pc = next_pc;
+                self.write_reg(rt, i as i8 as i16 as u16)
+            }
+            I::MOVH { rt, i } => {
+                let value = (self.read_reg(rt) & 0xff) | ((i as u16) << 8);
+                self.pc = next_pc;
+                self.write_reg(rt, value)
+            }
+            I::JZ { rt, ra } => {
+                self.jump_if(self.read_reg(ra) == 0, rt);
+                StepResult::Continue
+            }
+            I::JNZ { rt, ra } => {
+                self.jump_if(self.read_reg(ra) != 0, rt);
+                StepResult::Continue
+            }
+            I::JS { rt, ra } => {
+                self.jump_if(self.read_reg_signed(ra) < 0, rt);
+                StepResult::Continue
+            }
+            I::JNS { rt, ra } => {
+                self.jump_if(self.read_reg_signed(ra) >= 0, rt);
+                StepResult::Continue
+            }
+            I::LD { rt, ra } => {
+                let value = self.read_mem(self.read_reg(ra))?;
+                self.pc = next_pc;
+                self.write_reg(rt, value as u16)
+            }
+            I::ST { rt, ra } => {
+                self.write_mem(self.read_reg(ra), (self.read_reg(rt) & 0xff) as u8)?;
+                self.pc = next_pc;
+                StepResult::Continue
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::translation::asm_gheith::Endian;
+
+    fn program(words: &[u16]) -> Vec<u8> {
+        words.iter().flat_map(|w| w.to_le_bytes()).collect()
+    }
+
+    fn run(machine: &mut Machine) -> String {
+        let mut output = String::new();
+        loop {
+            match machine.step() {
+                StepResult::Continue => {}
+                StepResult::Printed(c) => output.push(c),
+                StepResult::Halted | StepResult::Trap(_) => return output,
+            }
+        }
+    }
+
+    #[test]
+    fn prints_hi() {
+        let mut machine = Machine::new(program(&[
+            0x8480, // movl r0, 'H'
+            0x8690, // movl r0, 'i'
+            0xffff, // illegal, halts
+        ]));
+        assert_eq!(run(&mut machine), "Hi");
+        assert_eq!(machine.cycles, 2);
+        assert_eq!(machine.pc, 4);
+    }
+
+    #[test]
+    fn r0_reads_as_zero() {
+        let mut machine = Machine::new(program(&[
+            0x8051, // movl r1, 5
+            0x0101, // sub r1, r1, r0
+            0xffff,
+        ]));
+        machine.regs[0] = 42;
+        run(&mut machine);
+        assert_eq!(machine.regs[1], 5);
+    }
+
+    #[test]
+    fn loads_and_stores_are_byte_sized() {
+        let mut machine = Machine::new(program(&[
+            0x8101, // movl r1, 0x10
+            0x8ff2, // movl r2, -1
+            0xf112, // st r2, r1
+            0xf103, // ld r3, r1
+            0xffff, 0x0000, // (memory at 0x0a..0x10)
+            0x0000, 0x0000, 0x0000, // address 0x10
+        ]));
+        run(&mut machine);
+        assert_eq!(machine.mem[0x10], 0xff);
+        assert_eq!(machine.mem[0x11], 0x00);
+        assert_eq!(machine.regs[3], 0xff);
+    }
+
+    #[test]
+    fn signed_jumps_compare_signed() {
+        let mut machine = Machine::new(program(&[
+            0x8ff1, // movl r1, -1
+            0x80a2, // movl r2, 10
+            0xe122, // js r2, r1
+            0x8580, // movl r0, 'X' (skipped)
+            0xffff, 0x8590, // movl r0, 'Y' (address 10)
+            0xffff,
+        ]));
+        assert_eq!(run(&mut machine), "Y");
+    }
+
+    /// Executes `insn` testing r1 = `value` with r2 holding the jump target 0x40 and
+    /// returns whether the jump was taken
+    fn sign_jump_taken(insn: u16, value: u16) -> bool {
+        let mut machine = Machine::new(program(&[insn]));
+        machine.regs[1] = value;
+        machine.regs[2] = 0x40;
+        assert_eq!(machine.step(), StepResult::Continue);
+        machine.pc == 0x40
+    }
+
+    #[test]
+    fn sign_jumps_at_the_i16_boundaries() {
+        const JS: u16 = 0xe122; // js r2, r1
+        const JNS: u16 = 0xe132; // jns r2, r1
+        for (value, negative) in [
+            (0x8000, true),
+            (0xffff, true),
+            (0x7fff, false),
+            (0x0001, false),
+            (0x0000, false),
+        ] {
+            assert_eq!(sign_jump_taken(JS, value), negative, "js with {value:#06x}");
+            assert_eq!(
+                sign_jump_taken(JNS, value),
+                !negative,
+                "jns with {value:#06x}"
+            );
+        }
+    }
+
+    #[test]
+    fn fetch_honors_configured_byte_order() {
+        // Little-endian: movl r0, 'H'; movh r4, 8. Big-endian: movl r4, 8; movl r0, 'I'
+        let mem = vec![0x80, 0x84, 0x84, 0x90, 0xff, 0xff];
+        let mut little = Machine::new(mem.clone());
+        assert_eq!(run(&mut little), "H");
+
+        let mut big = Machine::with_config(
+            mem,
+            DecodeConfig {
+                endian: Endian::Big,
+            },
+        );
+        assert_eq!(run(&mut big), "I");
+    }
+
+    #[test]
+    fn out_of_bounds_load_traps() {
+        let mut machine = Machine::new(program(&[
+            0x8401, // movl r1, 0x40
+            0xf102, // ld r2, r1
+        ]));
+        machine.regs[2] = 7;
+        assert_eq!(machine.step(), StepResult::Continue);
+        assert_eq!(
+            machine.step(),
+            StepResult::Trap(TrapKind::OutOfBoundsLoad { addr: 0x40 })
+        );
+        assert_eq!(machine.regs[2], 7);
+        assert_eq!(machine.pc, 2);
+        assert_eq!(machine.cycles, 1);
+    }
+
+    #[test]
+    fn out_of_bounds_store_traps() {
+        let mut machine = Machine::new(program(&[
+            0x8ff1, // movl r1, -1
+            0xf112, // st r2, r1
+        ]));
+        let mem_before = machine.mem.clone();
+        assert_eq!(machine.step(), StepResult::Continue);
+        assert_eq!(
+            machine.step(),
+            StepResult::Trap(TrapKind::OutOfBoundsStore { addr: 0xffff })
+        );
+        assert_eq!(machine.mem, mem_before);
+        // The machine stays at the faulting instruction
+        assert_eq!(
+            machine.step(),
+            StepResult::Trap(TrapKind::OutOfBoundsStore { addr: 0xffff })
+        );
+    }
+
+    #[test]
+    fn printed_output_is_captured() {
+        let mut machine = Machine::new(program(&[
+            0x8410, // movl r0, 'A'
+            0x8420, // movl r0, 'B'
+            0xffff,
+        ]));
+        run(&mut machine);
+        assert_eq!(machine.take_output(), "AB");
+        assert_eq!(machine.take_output(), "");
+    }
+
+    #[test]
+    fn unprintable_output_is_escaped() {
+        let mut machine = Machine::new(program(&[
+            0x8070, // movl r0, 7
+            0x80a0, // movl r0, '\n'
+            0xffff,
+        ]));
+        run(&mut machine);
+        assert_eq!(machine.take_output(), "\\x07\n");
+    }
+
+    /// Counts up r1 and stores it at 0x20 forever
+    fn counting_machine() -> Machine {
+        let mut mem = program(&[
+            0x8ff2, // movl r2, -1
+            0x8203, // movl r3, 0x20
+            0x0121, // sub r1, r1, r2
+            0xf311, // st r1, r3
+            0x8044, // movl r4, 4
+            0xe004, // jz r4, r0
+        ]);
+        mem.resize(0x22, 0);
+        Machine::new(mem)
+    }
+
+    #[test]
+    fn restoring_a_snapshot_undoes_later_steps() {
+        let mut machine = counting_machine();
+        for _ in 0..6 {
+            assert_eq!(machine.step(), StepResult::Continue);
+        }
+        let snapshot = machine.snapshot();
+        assert_eq!(snapshot.regs[1], 1);
+        assert_eq!(snapshot.pc, 4);
+        assert_eq!(snapshot.mem[0x20], 1);
+
+        for _ in 0..8 {
+            machine.step();
+        }
+        assert_eq!(machine.regs[1], 3);
+        assert_eq!(machine.mem[0x20], 3);
+
+        machine.restore(&snapshot);
+        assert_eq!(machine.snapshot(), snapshot);
+        for _ in 0..8 {
+            machine.step();
+        }
+        assert_eq!(machine.regs[1], 3);
+        assert_eq!(machine.cycles, 14);
+    }
+
+    #[test]
+    fn snapshots_share_memory_until_it_is_written() {
+        let mut machine = counting_machine();
+        machine.step();
+        let snapshot = machine.snapshot();
+        assert!(Arc::ptr_eq(&snapshot.mem, &machine.mem));
+
+        while machine.mem[0x20] == 0 {
+            machine.step();
+        }
+        assert!(!Arc::ptr_eq(&snapshot.mem, &machine.mem));
+        assert_eq!(snapshot.mem[0x20], 0);
+    }
+
+    #[test]
+    fn memory_around_pc_is_clipped() {
+        let mut machine = counting_machine();
+        machine.step();
+        let snapshot = machine.snapshot();
+        assert_eq!(
+            snapshot.memory_around_pc(4, 4),
+            (0, &[0xf2, 0x8f, 0x03, 0x82, 0x21, 0x01][..])
+        );
+        machine.pc = 0x20;
+        assert_eq!(
+            machine.snapshot().memory_around_pc(2, 4),
+            (0x1e, &[0, 0, 0, 0][..])
+        );
+        machine.pc = 0x40;
+        assert_eq!(machine.snapshot().memory_around_pc(2, 4), (0x22, &[][..]));
+    }
+}
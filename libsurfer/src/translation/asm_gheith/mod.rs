@@ -1,5 +1,16 @@
 use core::fmt;
 
+mod assembler;
+mod disassembler;
+mod machine;
+
+pub use assembler::{assemble, AsmError};
+pub use disassembler::{
+    disassemble, disassemble_bytes, disassemble_labeled, disassemble_listing, instruction_at,
+    known_regs, reconstruct_labels, DisasmLine, SymbolTable,
+};
+pub use machine::{Machine, MachineSnapshot, StepResult, TrapKind};
+
 /*
 Architecture
 ~~~~~~~~~~~~
@@ -29,9 +40,13 @@ Architecture
   1110aaaa0010tttt  js rt,ra      pc = (regs[ra] < 0) ? regs[rt] : pc + 2
   1110aaaa0011tttt  jns rt,ra     pc = (regs[ra] >= 0) ? regs[rt] : pc + 2
 
+  js and jns interpret regs[ra] as a signed 16 bit number, 0x8000 is the
+  most negative value
+
   1111aaaa0000tttt  ld rt,ra      regs[t] = mem[regs[a]]
   1111aaaa0001tttt  st rt,ra      mem[regs[a]] = regs[t]
 */
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub enum I {
     SUB { rt: u8, ra: u8, rb: u8 },
     MOVL { rt: u8, i: u8 },
@@ -60,11 +75,81 @@ impl fmt::Debug for I {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+impl fmt::Display for I {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+/// Byte order of instruction words in memory
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Endian {
+    #[default]
+    Little,
+    Big,
+}
+
+/// Describes how instruction words are laid out in a byte stream. Decoding of a single
+/// word via `TryFrom<u16>` is unaffected by this.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DecodeConfig {
+    pub endian: Endian,
+}
+
+impl DecodeConfig {
+    /// Assembles the word stored in `bytes`, the byte at the lower address first
+    pub fn word(&self, bytes: [u8; 2]) -> u16 {
+        match self.endian {
+            Endian::Little => u16::from_le_bytes(bytes),
+            Endian::Big => u16::from_be_bytes(bytes),
+        }
+    }
+
+    /// Splits `bytes` into consecutive words. A trailing odd byte is ignored.
+    pub fn words(&self, bytes: &[u8]) -> Vec<u16> {
+        bytes
+            .chunks_exact(2)
+            .map(|pair| self.word([pair[0], pair[1]]))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 /// Error types when converting `u16` to `I`
 pub enum ConversionError {
     /// Unknown opcode
     UnknownOpcode(u16),
+    /// A jump or memory instruction with some of the bits above its sub-opcode set, which
+    /// must be zero
+    ReservedBits(u16),
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConversionError::UnknownOpcode(value) => write!(f, "unknown opcode {value:#06x}"),
+            ConversionError::ReservedBits(value) => {
+                write!(f, "reserved bits set in {value:#06x}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConversionError {}
+
+/// Classifies `value` by its top nibble and, for the jump and memory families, its
+/// sub-opcode. Unlike `TryFrom<u16>` this also gives a hint for malformed encodings.
+pub fn opcode_class(value: u16) -> &'static str {
+    match (value >> 12, (value >> 4) & 0xF) {
+        (0x0, _) => "ALU",
+        (0x1..=0x7, _) => "reserved ALU",
+        (0x8 | 0x9, _) => "move",
+        (0xA..=0xD, _) => "reserved",
+        (0xE, 0x0..=0x3) => "jump family",
+        (0xE, _) => "jump family invalid subop",
+        (_, 0x0..=0x1) => "memory family",
+        (_, _) => "memory family invalid subop",
+    }
 }
 
 impl TryFrom<u16> for I {
@@ -79,11 +164,11 @@ impl TryFrom<u16> for I {
             },
             0b1000 => I::MOVL {
                 rt: (value & 0xF) as u8,
-                i: ((value & 0xFF00) >> 8) as u8,
+                i: ((value & 0xFF0) >> 4) as u8,
             },
             0b1001 => I::MOVH {
                 rt: (value & 0xF) as u8,
-                i: ((value & 0xFF00) >> 8) as u8,
+                i: ((value & 0xFF0) >> 4) as u8,
             },
             0b1110 => match (value & 0xF0) >> 4 {
                 0b0000 => I::JZ {
@@ -102,7 +187,7 @@ impl TryFrom<u16> for I {
                     rt: (value & 0xF) as u8,
                     ra: ((value & 0xF00) >> 8) as u8,
                 },
-                _ => return Err(ConversionError::UnknownOpcode(value)),
+                _ => return Err(ConversionError::ReservedBits(value)),
             },
             0b1111 => match (value & 0xF0) >> 4 {
                 0b0000 => I::LD {
@@ -113,9 +198,76 @@ impl TryFrom<u16> for I {
                     rt: (value & 0xF) as u8,
                     ra: ((value & 0xF00) >> 8) as u8,
                 },
-                _ => return Err(ConversionError::UnknownOpcode(value)),
+                _ => return Err(ConversionError::ReservedBits(value)),
             },
             _ => return Err(ConversionError::UnknownOpcode(value)),
         })
     }
 }
+
+impl From<I> for u16 {
+    fn from(insn: I) -> u16 {
+        let reg = |r: u8| u16::from(r & 0xF);
+        match insn {
+            I::SUB { rt, ra, rb } => (reg(ra) << 8) | (reg(rb) << 4) | reg(rt),
+            I::MOVL { rt, i } => 0x8000 | (u16::from(i) << 4) | reg(rt),
+            I::MOVH { rt, i } => 0x9000 | (u16::from(i) << 4) | reg(rt),
+            I::JZ { rt, ra } => 0xE000 | (reg(ra) << 8) | reg(rt),
+            I::JNZ { rt, ra } => 0xE010 | (reg(ra) << 8) | reg(rt),
+            I::JS { rt, ra } => 0xE020 | (reg(ra) << 8) | reg(rt),
+            I::JNS { rt, ra } => 0xE030 | (reg(ra) << 8) | reg(rt),
+            I::LD { rt, ra } => 0xF000 | (reg(ra) << 8) | reg(rt),
+            I::ST { rt, ra } => 0xF010 | (reg(ra) << 8) | reg(rt),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reserved_top_nibbles_are_classified() {
+        assert_eq!(opcode_class(0x1234), "reserved ALU");
+        assert_eq!(opcode_class(0x7fff), "reserved ALU");
+        assert_eq!(opcode_class(0xa000), "reserved");
+        assert_eq!(opcode_class(0xdfff), "reserved");
+    }
+
+    #[test]
+    fn invalid_subops_are_classified_by_family() {
+        assert_eq!(opcode_class(0xe140), "jump family invalid subop");
+        assert_eq!(opcode_class(0xf120), "memory family invalid subop");
+        assert_eq!(opcode_class(0xffff), "memory family invalid subop");
+    }
+
+    #[test]
+    fn conversion_errors_are_displayed() {
+        let error = I::try_from(0xa123).unwrap_err();
+        assert_eq!(error, ConversionError::UnknownOpcode(0xa123));
+        assert_eq!(error.to_string(), "unknown opcode 0xa123");
+
+        let error = I::try_from(0xe140).unwrap_err();
+        assert_eq!(error, ConversionError::ReservedBits(0xe140));
+        assert_eq!(error.to_string(), "reserved bits set in 0xe140");
+        assert_eq!(
+            I::try_from(0xf120),
+            Err(ConversionError::ReservedBits(0xf120))
+        );
+    }
+
+    #[test]
+    fn move_immediates_are_decoded_from_the_middle_byte() {
+        // The immediate of movl and movh sits between the opcode and the target register
+        assert_eq!(I::try_from(0x8ab3), Ok(I::MOVL { rt: 3, i: 0xab }));
+        assert_eq!(I::try_from(0x9ab3), Ok(I::MOVH { rt: 3, i: 0xab }));
+    }
+
+    #[test]
+    fn valid_encodings_are_classified_by_family() {
+        assert_eq!(opcode_class(0x0123), "ALU");
+        assert_eq!(opcode_class(0x8081), "move");
+        assert_eq!(opcode_class(0xe231), "jump family");
+        assert_eq!(opcode_class(0xf112), "memory family");
+    }
+}
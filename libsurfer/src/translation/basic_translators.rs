@@ -1,6 +1,9 @@
 use super::{asm_gheith, check_single_wordlength, TranslationPreference, ValueKind, VariableInfo};
 use crate::wave_container::{ScopeId, VarId, VariableMeta};
 
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
 use color_eyre::Result;
 use itertools::Itertools;
 use num::Zero;
@@ -186,6 +189,38 @@ impl BasicTranslator<VarId, ScopeId> for HexTranslator {
     }
 }
 
+/// The name of [`RawHexTranslator`]
+pub const RAW_HEX_TRANSLATOR: &str = "Raw Hex";
+
+/// Shows any value as hexadecimal. It applies to all variables, so it is what values are
+/// shown with when the selected translator does not apply, see
+/// [`TranslatorList::fallback`](super::TranslatorList::fallback).
+pub struct RawHexTranslator {}
+
+impl BasicTranslator<VarId, ScopeId> for RawHexTranslator {
+    fn name(&self) -> String {
+        String::from(RAW_HEX_TRANSLATOR)
+    }
+
+    fn basic_translate(&self, num_bits: u64, value: &VariableValue) -> (String, ValueKind) {
+        match value {
+            VariableValue::BigUint(v) => (
+                format!("{v:0width$x}", width = no_of_digits(num_bits, 4)),
+                ValueKind::Normal,
+            ),
+            // Only bit strings can be grouped into hex digits, anything else is shown as is
+            VariableValue::String(s) if s.chars().all(|c| "01xzuwhl-".contains(c)) => {
+                map_to_radix(s, 4, num_bits)
+            }
+            VariableValue::String(s) => (s.clone(), ValueKind::Normal),
+        }
+    }
+
+    fn translates(&self, _variable: &VariableMeta) -> Result<TranslationPreference> {
+        Ok(TranslationPreference::Yes)
+    }
+}
+
 pub struct BitTranslator {}
 
 impl BasicTranslator<VarId, ScopeId> for BitTranslator {
@@ -325,7 +360,48 @@ impl BasicTranslator<VarId, ScopeId> for ASCIITranslator {
     }
 }
 
-pub struct GheithTranslator {}
+/// The number of distinct instruction words whose translation [`GheithTranslator`] keeps
+const GHEITH_CACHE_SIZE: usize = 4096;
+
+#[derive(Default)]
+pub struct GheithTranslator {
+    /// Translations of previously seen words. Instruction buses tend to carry few distinct
+    /// words, so this saves decoding and formatting them for every sample. The cache is
+    /// cleared once it holds `GHEITH_CACHE_SIZE` words.
+    cache: Mutex<HashMap<u16, (Arc<str>, ValueKind)>>,
+}
+
+impl GheithTranslator {
+    fn translate_word(&self, word: u16) -> (String, ValueKind) {
+        let mut cache = self.cache.lock().unwrap();
+        if let Some((text, kind)) = cache.get(&word) {
+            return (text.to_string(), *kind);
+        }
+
+        let (text, kind) = match asm_gheith::I::try_from(word) {
+            Ok(insn) => (insn.to_string(), ValueKind::Normal),
+            Err(asm_gheith::ConversionError::UnknownOpcode(value)) => (
+                format!(
+                    "{value:#06x} (unknown opcode, {})",
+                    asm_gheith::opcode_class(value)
+                ),
+                ValueKind::Warn,
+            ),
+            Err(asm_gheith::ConversionError::ReservedBits(value)) => (
+                format!(
+                    "{value:#06x} (reserved bits set, {})",
+                    asm_gheith::opcode_class(value)
+                ),
+                ValueKind::Warn,
+            ),
+        };
+        if cache.len() >= GHEITH_CACHE_SIZE {
+            cache.clear();
+        }
+        cache.insert(word, (Arc::from(text.as_str()), kind));
+        (text, kind)
+    }
+}
 
 impl BasicTranslator<VarId, ScopeId> for GheithTranslator {
     fn name(&self) -> String {
@@ -334,23 +410,30 @@ impl BasicTranslator<VarId, ScopeId> for GheithTranslator {
 
     fn basic_translate(&self, _num_bits: u64, value: &VariableValue) -> (String, ValueKind) {
         let u16_value: u16 = match value {
-            VariableValue::BigUint(v) => v.to_u32_digits().last().cloned(),
+            VariableValue::BigUint(v) => v.to_u32_digits().first().cloned(),
             VariableValue::String(s) => match check_vector_variable(s) {
                 Some(v) => return v,
-                None => s.parse().ok(),
+                None => u32::from_str_radix(s, 2).ok(),
             },
         }
         .unwrap_or(0) as u16;
 
-        match asm_gheith::I::try_from(u16_value) {
-            Ok(insn) => (format!("{:?}", insn), ValueKind::Normal),
-            Err(_) => (format!("ILLEGAL INSN ({:#x})", u16_value), ValueKind::Warn),
-        }
+        self.translate_word(u16_value)
     }
 
     fn translates(&self, variable: &VariableMeta) -> Result<TranslationPreference> {
         check_single_wordlength(variable.num_bits, 16)
     }
+
+    fn variable_info(&self, variable: &VariableMeta) -> Result<VariableInfo> {
+        if variable.num_bits == Some(16) {
+            Ok(VariableInfo::Bits)
+        } else {
+            Err(color_eyre::eyre::anyhow!(
+                "Gheith Asm only applies to 16-bit variables"
+            ))
+        }
+    }
 }
 
 fn decode_lebxxx(value: &num::BigUint) -> Result<num::BigUint, &'static str> {
@@ -565,6 +648,90 @@ mod test {
 
     use super::*;
 
+    #[test]
+    fn raw_hex_translator_shows_any_value() {
+        assert_eq!(
+            RawHexTranslator {}.basic_translate(5, &VariableValue::BigUint(BigUint::from(0x1fu32))),
+            ("1f".to_string(), ValueKind::Normal)
+        );
+        assert_eq!(
+            RawHexTranslator {}.basic_translate(8, &VariableValue::String("1010xxxx".to_string())),
+            ("ax".to_string(), ValueKind::Undef)
+        );
+        assert_eq!(
+            RawHexTranslator {}.basic_translate(0, &VariableValue::String("3.25".to_string())),
+            ("3.25".to_string(), ValueKind::Normal)
+        );
+    }
+
+    #[test]
+    fn gheith_translator_decodes_movl() {
+        assert_eq!(
+            GheithTranslator::default()
+                .basic_translate(16, &VariableValue::BigUint(BigUint::from(0x8081u32))),
+            ("movl r1, #8".to_string(), ValueKind::Normal)
+        );
+        assert_eq!(
+            GheithTranslator::default()
+                .basic_translate(16, &VariableValue::String("1000000010000001".to_string())),
+            ("movl r1, #8".to_string(), ValueKind::Normal)
+        );
+    }
+
+    #[test]
+    fn gheith_translations_are_cached_per_word() {
+        let translator = GheithTranslator::default();
+        let movl = VariableValue::BigUint(BigUint::from(0x8081u32));
+        for _ in 0..1000 {
+            translator.basic_translate(16, &movl);
+        }
+        assert_eq!(translator.cache.lock().unwrap().len(), 1);
+
+        // Repeated translations are served from the cache without decoding
+        translator
+            .cache
+            .lock()
+            .unwrap()
+            .insert(0x8081, (Arc::from("cached"), ValueKind::Normal));
+        assert_eq!(
+            translator.basic_translate(16, &movl),
+            ("cached".to_string(), ValueKind::Normal)
+        );
+    }
+
+    #[test]
+    fn gheith_cache_is_bounded() {
+        let translator = GheithTranslator::default();
+        for word in 0..=u16::MAX {
+            translator.basic_translate(16, &VariableValue::BigUint(BigUint::from(word)));
+        }
+        assert!(translator.cache.lock().unwrap().len() <= GHEITH_CACHE_SIZE);
+    }
+
+    #[test]
+    fn gheith_translator_classifies_reserved_opcodes() {
+        assert_eq!(
+            GheithTranslator::default()
+                .basic_translate(16, &VariableValue::BigUint(BigUint::from(0xa000u32))),
+            (
+                "0xa000 (unknown opcode, reserved)".to_string(),
+                ValueKind::Warn
+            )
+        );
+    }
+
+    #[test]
+    fn gheith_translator_marks_reserved_bits() {
+        assert_eq!(
+            GheithTranslator::default()
+                .basic_translate(16, &VariableValue::BigUint(BigUint::from(0xffffu32))),
+            (
+                "0xffff (reserved bits set, memory family invalid subop)".to_string(),
+                ValueKind::Warn
+            )
+        );
+    }
+
     #[test]
     fn hexadecimal_translation_groups_digits_correctly_string() {
         assert_eq!(
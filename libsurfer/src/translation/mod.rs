@@ -1,7 +1,8 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 #[cfg(not(target_arch = "wasm32"))]
 use std::path::Path;
 use std::sync::mpsc::Sender;
+use std::sync::Mutex;
 
 use color_eyre::Result;
 #[cfg(not(target_arch = "wasm32"))]
@@ -12,7 +13,7 @@ use log::warn;
 #[cfg(not(target_arch = "wasm32"))]
 use toml::Table;
 
-mod asm_gheith;
+pub mod asm_gheith;
 mod basic_translators;
 pub mod clock;
 mod enum_translator;
@@ -39,7 +40,7 @@ use surfer_translation_types::{
 
 use crate::config::SurferTheme;
 use crate::translation::enum_translator::EnumTranslator;
-use crate::wave_container::{ScopeId, VarId};
+use crate::wave_container::{ScopeId, VarId, VariableRefExt};
 use crate::{message::Message, wave_container::VariableMeta};
 
 pub type DynTranslator = dyn Translator<VarId, ScopeId, Message>;
@@ -257,7 +258,7 @@ pub fn all_translators() -> TranslatorList {
         Box::new(PositQuire16Translator {}),
         Box::new(E5M2Translator {}),
         Box::new(E4M3Translator {}),
-        Box::new(GheithTranslator {}),
+        Box::new(GheithTranslator::default()),
         Box::new(NumberOfOnesTranslator {}),
         Box::new(LeadingOnesTranslator {}),
         Box::new(TrailingOnesTranslator {}),
@@ -283,11 +284,16 @@ pub fn all_translators() -> TranslatorList {
     )
 }
 
-#[derive(Default)]
 pub struct TranslatorList {
     inner: HashMap<String, AnyTranslator>,
     #[cfg(feature = "python")]
     python_translator: Option<(camino::Utf8PathBuf, String, AnyTranslator)>,
+    /// Applies to every variable, kept apart from `inner` so that it is not offered in
+    /// the format menus
+    fallback: AnyTranslator,
+    /// The variables and translators which a warning was logged for in
+    /// [`TranslatorList::fallback`]
+    fallback_warnings: Mutex<HashSet<(String, String)>>,
     pub default: String,
 }
 
@@ -306,6 +312,8 @@ impl TranslatorList {
                 .collect(),
             #[cfg(feature = "python")]
             python_translator: None,
+            fallback: AnyTranslator::Basic(Box::new(RawHexTranslator {})),
+            fallback_warnings: Mutex::new(HashSet::new()),
         }
     }
 
@@ -352,9 +360,26 @@ impl TranslatorList {
         self.inner
             .get(name)
             .or_else(python_translator)
+            .or_else(|| (name == RAW_HEX_TRANSLATOR).then_some(&self.fallback))
             .unwrap_or_else(|| panic!("No translator called {name}"))
     }
 
+    /// Returns the translator to use for `meta` when `name` does not apply to it. A
+    /// warning is logged the first time this happens for a variable and translator, since
+    /// this is called whenever the variable is drawn.
+    pub fn fallback(&self, meta: &VariableMeta, name: &str) -> &AnyTranslator {
+        let variable = meta.var.full_path_string();
+        if self
+            .fallback_warnings
+            .lock()
+            .unwrap()
+            .insert((variable.clone(), name.to_string()))
+        {
+            warn!("{name} can not translate {variable}, showing it as {RAW_HEX_TRANSLATOR}");
+        }
+        &self.fallback
+    }
+
     pub fn add_or_replace(&mut self, t: AnyTranslator) {
         self.inner.insert(t.name(), t);
     }
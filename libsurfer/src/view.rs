@@ -38,7 +38,7 @@ use crate::variable_filter::VariableFilter;
 use crate::wave_container::{
     FieldRef, FieldRefExt, ScopeRef, ScopeRefExt, VariableRef, VariableRefExt, WaveContainer,
 };
-use crate::wave_data::ScopeType;
+use crate::wave_data::{variable_translator, ScopeType};
 use crate::wave_source::LoadOptions;
 use crate::{
     command_prompt::show_command_prompt, hierarchy, hierarchy::HierarchyStyle, wave_data::WaveData,
@@ -217,6 +217,8 @@ impl eframe::App for SystemState {
         self.handle_batch_commands();
         #[cfg(target_arch = "wasm32")]
         self.handle_wasm_external_messages();
+        #[cfg(any(target_arch = "wasm32", test))]
+        self.handle_screenshots(ctx);
 
         let viewport_is_moving = if let Some(waves) = &mut self.user.waves {
             let mut is_moving = false;
@@ -232,7 +234,10 @@ impl eframe::App for SystemState {
         };
 
         if let Some(waves) = self.user.waves.as_ref().and_then(|w| w.inner.as_waves()) {
-            waves.tick()
+            waves.tick();
+            if !waves.is_loading() {
+                self.cxxrtl_progress = None;
+            }
         }
 
         if viewport_is_moving {
@@ -251,6 +256,7 @@ impl eframe::App for SystemState {
         // we'll let egui manage repainting. In practice
         if self.continuous_redraw
             || self.progress_tracker.is_some()
+            || self.cxxrtl_progress.is_some()
             || self.user.show_performance
             || OUTSTANDING_TRANSACTIONS.load(std::sync::atomic::Ordering::SeqCst) != 0
         {
@@ -277,6 +283,9 @@ impl SystemState {
 
         let mut msgs = vec![];
 
+        // Viewports which are not drawn in this frame must not leave graphics behind
+        self.graphic_bounds.borrow_mut().clear();
+
         if self.user.show_about {
             draw_about_window(ctx, &mut msgs);
         }
@@ -1976,9 +1985,18 @@ impl SystemState {
                 return None;
             };
             let variable = &displayed_variable.variable_ref;
-            let translator =
-                waves.variable_translator(&displayed_field_ref.without_field(), &self.translators);
-            let meta = waves.inner.as_waves().unwrap().variable_meta(variable);
+            let meta = waves
+                .inner
+                .as_waves()
+                .unwrap()
+                .variable_meta(variable)
+                .ok()?;
+            let translator = variable_translator(
+                displayed_variable.get_format(&[]),
+                &[],
+                &self.translators,
+                || Ok(meta.clone()),
+            );
 
             let translation_result = waves
                 .inner
@@ -1988,7 +2006,7 @@ impl SystemState {
                 .ok()
                 .flatten()
                 .and_then(|q| q.current)
-                .map(|(_time, value)| meta.and_then(|meta| translator.translate(&meta, &value)));
+                .map(|(_time, value)| translator.translate(&meta, &value));
 
             if let Some(Ok(s)) = translation_result {
                 let fields = s.format_flat(
@@ -2018,6 +2036,23 @@ impl SystemState {
         }
     }
 
+    /// Returns the values of the displayed variables `names` at `time`, in the same order
+    /// and formatted like [`SystemState::get_variable_value`]. Names which are not displayed
+    /// variables give `None`.
+    pub fn variable_values_at(&self, names: &[String], time: &num::BigUint) -> Vec<Option<String>> {
+        let Some(waves) = self.user.waves.as_ref() else {
+            return vec![None; names.len()];
+        };
+        let time = Some(time.clone());
+        names
+            .iter()
+            .map(|name| {
+                let item_ref = waves.item_ref_of_name(name)?;
+                self.get_variable_value(waves, &item_ref.into(), &time)
+            })
+            .collect()
+    }
+
     pub fn draw_background(
         &self,
         drawing_info: &ItemDrawingInfo,
@@ -1,31 +1,43 @@
 // The functions here are only used
 #![cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
 
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 use std::sync::Arc;
 
+use eframe::{emath::Pos2, epaint::Color32};
 use futures::executor::block_on;
 use lazy_static::lazy_static;
 use log::{error, warn};
-use num::BigInt;
+use num::{BigInt, BigUint};
+use serde::Serialize;
 use tokio::sync::Mutex;
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 
 use crate::channels::{GlobalChannelTx, IngressHandler};
 use crate::displayed_item::DisplayedItemRef;
+use crate::displayed_item::VariableRenderMode;
 use crate::graphics::Anchor;
 use crate::graphics::Direction;
 use crate::graphics::GrPoint;
 use crate::graphics::Graphic;
 use crate::graphics::GraphicId;
 use crate::graphics::GraphicsY;
+use crate::graphics::LineStyle;
 use crate::logs;
+use crate::message::{deserialize_message_batch, parse_message};
+use crate::query_stream::{
+    downsample, variable_samples, variable_transitions, QueryStream, RepaintCoalescer, Sample,
+};
 use crate::setup_custom_font;
+use crate::state::coalesce_draw_invalidations;
 use crate::wasm_panic;
 use crate::wasm_util;
-use crate::wave_container::VariableRefExt;
+use crate::wave_container::{VariableRef, VariableRefExt};
 use crate::wave_source::CxxrtlKind;
+use crate::wcp::proto::{WcpCSMessage, WcpSCMessage, WcpTagged};
+use crate::wcp::wcp_router::WcpRequestRouter;
+use crate::wcp::wcp_subscription::deliver_wcp_messages;
 use crate::DisplayedItem;
 use crate::Message;
 use crate::StartupParams;
@@ -41,10 +53,26 @@ lazy_static! {
     // TODO: Let's make these take CXXRTL messages instead of strings
     pub(crate) static ref CXXRTL_SC_HANDLER: IngressHandler<String> = IngressHandler::new();
     pub(crate) static ref CXXRTL_CS_HANDLER: GlobalChannelTx<String> = GlobalChannelTx::new();
+    static ref WCP_REQUESTS: Mutex<WcpRequestRouter> = Mutex::new(WcpRequestRouter::new());
+    /// Server to client messages which were not responses to a [wcp_request]
+    static ref WCP_UNSOLICITED: Mutex<VecDeque<WcpSCMessage>> = Mutex::new(VecDeque::new());
+    /// Streaming queries which still have chunks to produce
+    static ref QUERY_STREAMS: Mutex<VecDeque<QueryStream<Sample>>> = Mutex::new(VecDeque::new());
+    /// The receiving ends of streaming queries, by the id returned when starting them
+    static ref QUERY_CHUNKS: Mutex<HashMap<usize, tokio::sync::mpsc::UnboundedReceiver<Vec<Sample>>>> =
+        Mutex::new(HashMap::new());
+    static ref NEXT_QUERY_STREAM_ID: std::sync::atomic::AtomicUsize =
+        std::sync::atomic::AtomicUsize::new(0);
 }
 
+/// Makes queries which are queued in the same frame share a single repaint
+static QUERY_REPAINT: RepaintCoalescer = RepaintCoalescer::new();
+
+/// The number of samples per chunk of a streaming query
+const QUERY_CHUNK_SIZE: usize = 1000;
+
 struct Callback {
-    function: Box<dyn FnOnce(&SystemState) + Send + Sync>,
+    function: Box<dyn FnOnce(&mut SystemState) + Send + Sync>,
     executed: tokio::sync::oneshot::Sender<()>,
 }
 
@@ -126,52 +154,173 @@ impl WebHandle {
 
 // NOTE: Remember to add WASM_bindgen'd functions to the exports in Trunk.toml
 
+/// Checks that `message` is a valid JSON encoded `Message` without applying it. The error
+/// names the offending field and what was expected there.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub fn validate_message(message: &str) -> Result<(), String> {
+    parse_message(message).map(|_| ())
+}
+
+/// Adds a JSON encoded `Message` to the message queue. Returns an error if the message
+/// could not be deserialized, see [`validate_message`].
 #[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
-pub fn inject_message(message: &str) {
-    let deser = serde_json::from_str(message);
+pub fn inject_message(message: &str) -> Result<(), JsError> {
+    let deser = parse_message(message);
 
     match deser {
         Ok(message) => {
             block_on(MESSAGE_QUEUE.lock()).push(message);
 
-            try_repaint()
+            try_repaint();
+            Ok(())
         }
         Err(e) => {
             error!("When injecting message {message}:");
-            error!(" Injection failed{e:#?}")
+            error!(" Injection failed: {e}");
+            Err(JsError::new(&format!("Failed to deserialize message: {e}")))
         }
     }
 }
 
+/// Adds a JSON encoded list of `Message`s to the message queue, to be applied in order.
+/// If a message can not be deserialized, the messages before it are still added and an
+/// error containing the index of the failing message is returned.
 #[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
-pub async fn id_of_name(name: String) -> Option<usize> {
-    let (tx, rx) = tokio::sync::oneshot::channel();
-    let result = Arc::new(tokio::sync::Mutex::new(None));
-    let result_clone = result.clone();
-    QUERY_QUEUE.lock().await.push_back(Callback {
-        function: Box::new(move |state| {
-            if let Some(waves) = &state.user.waves {
-                *block_on(result_clone.lock()) = waves
-                    .displayed_items
-                    .iter()
-                    .find(|(_id, item)| {
-                        let item_name = match item {
-                            DisplayedItem::Variable(var) => var.variable_ref.full_path_string(),
-                            _ => item.name().to_string(),
-                        };
-                        item_name == name
-                    })
-                    .map(|(id, _)| *id)
-            }
-        }),
-        executed: tx,
-    });
-    try_repaint();
-    rx.await.unwrap();
-    let ret = block_on(result.lock());
-    ret.map(|x| x.0)
+pub fn inject_messages(messages: &str) -> Result<(), JsError> {
+    let (deserialized, error) = deserialize_message_batch(messages);
+
+    if !deserialized.is_empty() {
+        block_on(MESSAGE_QUEUE.lock()).extend(deserialized);
+        try_repaint();
+    }
+
+    match error {
+        Some(e) => {
+            error!("When injecting messages {messages}:");
+            error!(" Injection failed: {e}");
+            Err(JsError::new(&e))
+        }
+        None => Ok(()),
+    }
+}
+
+/// Returns the id of the displayed item named `name`, using the full path for variables.
+/// If several items share the name, `occurrence` selects which one counting from 0 in
+/// drawing order, defaulting to the first.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub async fn id_of_name(name: String, occurrence: Option<usize>) -> Option<usize> {
+    ids_of_name(name)
+        .await
+        .get(occurrence.unwrap_or(0))
+        .copied()
+}
+
+/// Returns the ids of all displayed items named `name` in drawing order, using the full
+/// path for variables
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub async fn ids_of_name(name: String) -> Vec<usize> {
+    perform_query(Box::new(move |state| {
+        Some(
+            state
+                .user
+                .waves
+                .as_ref()?
+                .item_refs_of_name(&name)
+                .into_iter()
+                .map(|item_ref| item_ref.0)
+                .collect(),
+        )
+    }))
+    .await
+    .unwrap_or_default()
+}
+
+/// Returns the value of the displayed variable `name` at `time`, formatted the same way as
+/// in the variable value column. Returns `None` if `name` is not a displayed variable or
+/// has no value at `time`.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub async fn variable_value_at(name: String, time: u64) -> Option<String> {
+    perform_query(Box::new(move |state| {
+        let waves = state.user.waves.as_ref()?;
+        let item_ref = waves.item_ref_of_name(&name)?;
+        state.get_variable_value(waves, &item_ref.into(), &Some(BigUint::from(time)))
+    }))
+    .await
+}
+
+/// Returns the values of the displayed variables `names` at `time` in the same order, as
+/// [`variable_value_at`] would for each of them, but in a single query. Names which are not
+/// displayed variables give `None`.
+///
+/// Optional strings can not cross the wasm boundary in a list, JavaScript callers use
+/// [`variable_values_at_json`] instead.
+pub async fn variable_values_at(names: Vec<String>, time: u64) -> Vec<Option<String>> {
+    let count = names.len();
+    perform_query(Box::new(move |state| {
+        Some(state.variable_values_at(&names, &BigUint::from(time)))
+    }))
+    .await
+    .unwrap_or_else(|| vec![None; count])
+}
+
+/// Like [`variable_values_at`], but returns the values as a JSON encoded list in which
+/// missing values are `null`
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub async fn variable_values_at_json(names: Vec<String>, time: u64) -> String {
+    let values = variable_values_at(names, time).await;
+    serde_json::to_string(&values).unwrap_or_default()
+}
+
+/// Returns the points in `start..=end` where the formatted value of the displayed variable
+/// `name` changes, as `(time, value)`, starting with the value at `start`. At most
+/// `max_points` are returned, picked evenly while keeping the first and last transition.
+/// Returns an empty vec if `name` is not a displayed variable.
+///
+/// Tuples can not cross the wasm boundary, JavaScript callers use
+/// [`variable_values_in_range_json`] instead.
+pub async fn variable_values_in_range(
+    name: String,
+    start: u64,
+    end: u64,
+    max_points: usize,
+) -> Vec<(String, String)> {
+    perform_query(Box::new(move |state| {
+        let transitions =
+            variable_transitions(state, &name, &BigUint::from(start), &BigUint::from(end));
+        Some(
+            downsample(&transitions, max_points)
+                .into_iter()
+                .map(|(time, value)| (time.to_string(), value))
+                .collect(),
+        )
+    }))
+    .await
+    .unwrap_or_default()
 }
 
+/// Like [`variable_values_in_range`], but returns the points as a JSON encoded list of
+/// `[time, value]` pairs
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub async fn variable_values_in_range_json(
+    name: String,
+    start: u64,
+    end: u64,
+    max_points: usize,
+) -> String {
+    let points = variable_values_in_range(name, start, end, max_points).await;
+    serde_json::to_string(&points).unwrap_or_default()
+}
+
+/// Draws an arrow labeled `text` from `from_item` at `from_time` to `to_item` at
+/// `to_time`.
+///
+/// The anchors (`Top`, `Center` or `Bottom`) select where on the items the arrow starts and
+/// ends, defaulting to `Center`. The directions (`North`, `East`, `South` or `West`) select
+/// which way the arrow leaves and enters the endpoints, defaulting to `East` and `West`.
+/// Use `South` and `North` to connect an item to one stacked below it.
+/// `color` is packed as `0xRRGGBBAA`, the theme color is used if it is omitted. The text
+/// is drawn with `font_size` and `text_color`, defaulting to 15 and `color`.
+#[allow(clippy::too_many_arguments)]
 #[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
 pub async fn draw_text_arrow(
     id: usize,
@@ -180,9 +329,29 @@ pub async fn draw_text_arrow(
     to_item: String,
     to_time: u64,
     text: String,
-) {
-    let from_id = id_of_name(from_item).await.map(DisplayedItemRef);
-    let to_id = id_of_name(to_item).await.map(DisplayedItemRef);
+    from_anchor: Option<String>,
+    to_anchor: Option<String>,
+    from_direction: Option<String>,
+    to_direction: Option<String>,
+    color: Option<u32>,
+    font_size: Option<f32>,
+    text_color: Option<u32>,
+) -> Result<(), JsError> {
+    let parse_anchor =
+        |anchor: Option<String>| anchor.map_or(Ok(Anchor::Center), |a| a.parse::<Anchor>());
+    let parse_direction = |direction: Option<String>, default: Direction| {
+        direction.map_or(Ok(default), |d| d.parse::<Direction>())
+    };
+    let from_anchor = parse_anchor(from_anchor).map_err(|e| JsError::new(&e))?;
+    let to_anchor = parse_anchor(to_anchor).map_err(|e| JsError::new(&e))?;
+    let from_direction =
+        parse_direction(from_direction, Direction::East).map_err(|e| JsError::new(&e))?;
+    let to_direction =
+        parse_direction(to_direction, Direction::West).map_err(|e| JsError::new(&e))?;
+    let color = color.map(color_from_rgba);
+
+    let from_id = id_of_name(from_item, None).await.map(DisplayedItemRef);
+    let to_id = id_of_name(to_item, None).await.map(DisplayedItemRef);
 
     if let (Some(from_id), Some(to_id)) = (from_id, to_id) {
         block_on(MESSAGE_QUEUE.lock()).push(Message::AddGraphic(
@@ -193,22 +362,118 @@ pub async fn draw_text_arrow(
                         x: BigInt::from(from_time),
                         y: GraphicsY {
                             item: from_id,
-                            anchor: Anchor::Center,
+                            anchor: from_anchor,
                         },
                     },
-                    Direction::East,
+                    from_direction,
                 ),
                 to: (
                     GrPoint {
                         x: BigInt::from(to_time),
                         y: GraphicsY {
                             item: to_id,
-                            anchor: Anchor::Center,
+                            anchor: to_anchor,
                         },
                     },
-                    Direction::West,
+                    to_direction,
                 ),
                 text,
+                color,
+                font_size,
+                text_color: text_color.map(color_from_rgba),
+            },
+        ));
+
+        try_repaint()
+    }
+    Ok(())
+}
+
+/// Converts a color packed as `0xRRGGBBAA`
+fn color_from_rgba(rgba: u32) -> Color32 {
+    let [r, g, b, a] = rgba.to_be_bytes();
+    Color32::from_rgba_unmultiplied(r, g, b, a)
+}
+
+/// Draws a straight line from the center of `from_item` at `from_time` to the center of
+/// `to_item` at `to_time`. `color` is packed as `0xRRGGBBAA`, the theme color is used if
+/// it is omitted. The line is 2 pixels wide unless `thickness` is given.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub async fn draw_line(
+    id: usize,
+    from_item: String,
+    from_time: u64,
+    to_item: String,
+    to_time: u64,
+    color: Option<u32>,
+    thickness: Option<f32>,
+) {
+    let from_id = id_of_name(from_item, None).await.map(DisplayedItemRef);
+    let to_id = id_of_name(to_item, None).await.map(DisplayedItemRef);
+
+    if let (Some(from_id), Some(to_id)) = (from_id, to_id) {
+        MESSAGE_QUEUE.lock().await.push(Message::AddGraphic(
+            GraphicId(id),
+            Graphic::Line {
+                from: GrPoint {
+                    x: BigInt::from(from_time),
+                    y: GraphicsY {
+                        item: from_id,
+                        anchor: Anchor::Center,
+                    },
+                },
+                to: GrPoint {
+                    x: BigInt::from(to_time),
+                    y: GraphicsY {
+                        item: to_id,
+                        anchor: Anchor::Center,
+                    },
+                },
+                color: color.map(color_from_rgba),
+                thickness: thickness.unwrap_or(2.),
+            },
+        ));
+
+        try_repaint()
+    }
+}
+
+/// Draws a rectangle from the top of `top_item` at `start_time` to the bottom of
+/// `bottom_item` at `end_time`. The colors are packed as `0xRRGGBBAA`. The outline uses the
+/// theme color if `color` is omitted, and the rectangle is only filled if `fill` is given.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub async fn draw_rect(
+    id: usize,
+    top_item: String,
+    start_time: u64,
+    bottom_item: String,
+    end_time: u64,
+    color: Option<u32>,
+    fill: Option<u32>,
+) {
+    let top_id = id_of_name(top_item, None).await.map(DisplayedItemRef);
+    let bottom_id = id_of_name(bottom_item, None).await.map(DisplayedItemRef);
+
+    if let (Some(top_id), Some(bottom_id)) = (top_id, bottom_id) {
+        MESSAGE_QUEUE.lock().await.push(Message::AddGraphic(
+            GraphicId(id),
+            Graphic::Rect {
+                top_left: GrPoint {
+                    x: BigInt::from(start_time),
+                    y: GraphicsY {
+                        item: top_id,
+                        anchor: Anchor::Top,
+                    },
+                },
+                bottom_right: GrPoint {
+                    x: BigInt::from(end_time),
+                    y: GraphicsY {
+                        item: bottom_id,
+                        anchor: Anchor::Bottom,
+                    },
+                },
+                color: color.map(color_from_rgba),
+                fill: fill.map(color_from_rgba),
             },
         ));
 
@@ -216,9 +481,55 @@ pub async fn draw_text_arrow(
     }
 }
 
+/// Draws a vertical line spanning the whole canvas at `time`, with an optional `label`
+/// at the top. `color` is given as `0xRRGGBBAA` and defaults to the foreground color of
+/// the theme.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub async fn draw_vline(id: usize, time: u64, label: Option<String>, color: Option<u32>) {
+    MESSAGE_QUEUE.lock().await.push(Message::AddGraphic(
+        GraphicId(id),
+        Graphic::VLine {
+            time: BigInt::from(time),
+            color: color.map(color_from_rgba),
+            label,
+            style: LineStyle::Solid,
+        },
+    ));
+
+    try_repaint()
+}
+
+/// Returns all graphics as a JSON array of `AddGraphic` messages in increasing order of
+/// id, or `None` if no waves are loaded. Passing the array to [inject_messages] after
+/// clearing the graphics restores them.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub async fn list_graphics() -> Option<String> {
+    perform_query(Box::new(|state| {
+        state
+            .user
+            .waves
+            .as_ref()?
+            .graphics_json()
+            .map_err(|e| error!("Failed to serialize graphics: {e}"))
+            .ok()
+    }))
+    .await
+}
+
 async fn perform_query<T>(
     query: Box<dyn FnOnce(&SystemState) -> Option<T> + Send + Sync>,
 ) -> Option<T>
+where
+    T: Clone + Send + Sync + 'static,
+{
+    perform_mutation(Box::new(move |state: &mut SystemState| query(state))).await
+}
+
+/// Like `perform_query`, but `mutation` may modify the state. It runs in the same queue as
+/// queries, after all pending messages have been handled, so its result reflects them.
+async fn perform_mutation<T>(
+    mutation: Box<dyn FnOnce(&mut SystemState) -> Option<T> + Send + Sync>,
+) -> Option<T>
 where
     T: Clone + Send + Sync + 'static,
 {
@@ -226,39 +537,120 @@ where
     let result = Arc::new(tokio::sync::Mutex::new(None));
     let result_clone = result.clone();
     QUERY_QUEUE.lock().await.push_back(Callback {
-        function: Box::new(move |state| *block_on(result_clone.lock()) = query(state)),
+        function: Box::new(move |state| *block_on(result_clone.lock()) = mutation(state)),
         executed: tx,
     });
-    try_repaint();
+    QUERY_REPAINT.request(try_repaint);
     rx.await.unwrap();
     let ret = block_on(result.lock());
     ret.clone()
 }
 
+/// Resolves once all queries which were started before it have completed. Queries which are
+/// started together are run in the same frame, so scripts issuing several queries can start
+/// all of them and await this once instead of awaiting each in turn.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub async fn flush_queries() {
+    perform_query(Box::new(|_| Some(()))).await;
+}
+
+/// Starts sampling the displayed variable `name` every `step` timestamps from `start` to
+/// `end`. Returns an id to pass to [next_query_chunk] to receive the samples, or `None` if
+/// the times can not be parsed.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub async fn stream_variable_samples(
+    name: String,
+    start: String,
+    end: String,
+    step: String,
+) -> Option<usize> {
+    let start = start.parse::<BigUint>().ok()?;
+    let end = end.parse::<BigUint>().ok()?;
+    let step = step.parse::<BigUint>().ok()?;
+
+    let (stream, chunks) =
+        QueryStream::new(variable_samples(name, start, end, step, QUERY_CHUNK_SIZE));
+    let id = NEXT_QUERY_STREAM_ID.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    QUERY_CHUNKS.lock().await.insert(id, chunks);
+    QUERY_STREAMS.lock().await.push_back(stream);
+    try_repaint();
+    Some(id)
+}
+
+/// Waits for the next chunk of the streaming query `id` and returns it as a JSON encoded
+/// list of `{time, value}`. Returns `None` once all chunks have been received.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub async fn next_query_chunk(id: usize) -> Option<String> {
+    // The receiver is taken out while waiting, so that other streams can be read meanwhile
+    let mut chunks = QUERY_CHUNKS.lock().await.remove(&id)?;
+    let chunk = chunks.recv().await;
+    if chunk.is_some() {
+        QUERY_CHUNKS.lock().await.insert(id, chunks);
+    }
+    serde_json::to_string(&chunk?).ok()
+}
+
+/// Adds the variable with the full path `path` and returns the id of the new item, or
+/// `None` if the variable could not be added
 #[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
-pub async fn index_of_name(name: String) -> Option<usize> {
+pub async fn add_variable_by_path(path: String) -> Option<usize> {
+    perform_mutation(Box::new(move |state| {
+        state
+            .add_variables(vec![VariableRef::from_hierarchy_string(&path)])
+            .first()
+            .map(|item_ref| item_ref.0)
+    }))
+    .await
+}
+
+/// Returns the index in the item list of the displayed item named `name`, using the full
+/// path for variables. If several items share the name, `occurrence` selects which one
+/// counting from 0 in drawing order, defaulting to the first.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub async fn index_of_name(name: String, occurrence: Option<usize>) -> Option<usize> {
     perform_query(Box::new(move |state| {
-        if let Some(waves) = &state.user.waves {
-            let mut result = None;
-            for (idx, node) in waves.items_tree.iter().enumerate() {
-                if let Some(item) = waves.displayed_items.get(&node.item_ref) {
-                    let item_name = match item {
-                        DisplayedItem::Variable(var) => var.variable_ref.full_path_string(),
-                        _ => item.name().to_string(),
-                    };
-                    if item_name == name {
-                        result = Some(idx);
-                    }
-                }
-            }
-            result
-        } else {
-            None
-        }
+        state
+            .user
+            .waves
+            .as_ref()?
+            .item_index_of_name(&name, occurrence.unwrap_or(0))
     }))
     .await
 }
 
+/// Returns the names of all displayed items in the order they are drawn, including items in
+/// folded groups, using the full path for variables
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub async fn displayed_item_names() -> Vec<String> {
+    perform_query(Box::new(move |state| {
+        Some(state.user.waves.as_ref()?.displayed_item_names())
+    }))
+    .await
+    .unwrap_or_default()
+}
+
+/// Moves the first displayed item named `name` to `to_index` in the order returned by
+/// [`displayed_item_names`]. Indices past the end move the item to the end.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub async fn move_item(name: String, to_index: usize) {
+    let item_ref = perform_query(Box::new(move |state| {
+        let item_ref = state.user.waves.as_ref()?.item_ref_of_name(&name);
+        if item_ref.is_none() {
+            warn!("No displayed item named {name}");
+        }
+        item_ref
+    }))
+    .await;
+
+    if let Some(item) = item_ref {
+        MESSAGE_QUEUE
+            .lock()
+            .await
+            .push(Message::MoveItem { item, to_index });
+        try_repaint()
+    }
+}
+
 #[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
 pub async fn waves_loaded() -> bool {
     perform_query(Box::new(move |state| Some(state.user.waves.is_some())))
@@ -281,6 +673,380 @@ pub async fn spade_loaded() -> bool {
     .unwrap_or(false)
 }
 
+/// Returns the names of all translators which can be selected for variables
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub async fn available_translators() -> Vec<String> {
+    perform_query(Box::new(move |state| {
+        Some(
+            state
+                .translators
+                .all_translator_names()
+                .into_iter()
+                .map(str::to_string)
+                .collect(),
+        )
+    }))
+    .await
+    .unwrap_or_default()
+}
+
+/// Returns the name of the translator which formats the displayed variable `name`, or
+/// `None` if `name` is not a displayed variable
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub async fn item_translator(name: String) -> Option<String> {
+    perform_query(Box::new(move |state| {
+        state
+            .user
+            .waves
+            .as_ref()?
+            .item_translator_name(&name, &state.translators)
+    }))
+    .await
+}
+
+/// Formats the displayed variable `item` with the translator named `translator`. If the
+/// translator does not apply to the variable, the default translator is used instead.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub async fn set_item_translator(item: String, translator: String) {
+    let format = translator.clone();
+    let item_ref = perform_query(Box::new(move |state| {
+        if !state
+            .translators
+            .all_translator_names()
+            .contains(&translator.as_str())
+        {
+            warn!("No translator named {translator}");
+            return None;
+        }
+        let item_ref = state.user.waves.as_ref()?.item_ref_of_name(&item);
+        if item_ref.is_none() {
+            warn!("No displayed item named {item}");
+        }
+        item_ref
+    }))
+    .await;
+
+    if let Some(item_ref) = item_ref {
+        MESSAGE_QUEUE
+            .lock()
+            .await
+            .push(Message::VariableFormatChange(Some(item_ref.into()), format));
+        try_repaint()
+    }
+}
+
+#[derive(Serialize, Clone)]
+struct SignalsEqualResult {
+    equal: bool,
+    first_diff_time: Option<String>,
+}
+
+/// Compares the displayed variables `a` and `b` between the `start` and `end` timestamps.
+/// Returns a JSON encoded `{equal, first_diff_time}` or `None` if either variable is not
+/// displayed.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub async fn signals_equal(a: String, b: String, start: String, end: String) -> Option<String> {
+    perform_query(Box::new(move |state| {
+        let waves = state.user.waves.as_ref()?;
+        let a = waves.displayed_variable_ref(&a)?;
+        let b = waves.displayed_variable_ref(&b)?;
+        let start = start.parse::<BigUint>().ok()?;
+        let end = end.parse::<BigUint>().ok()?;
+
+        let first_diff = waves.first_value_difference(&a, &b, &start, &end)?;
+        serde_json::to_string(&SignalsEqualResult {
+            equal: first_diff.is_none(),
+            first_diff_time: first_diff.map(|t| t.to_string()),
+        })
+        .ok()
+    }))
+    .await
+}
+
+/// Returns the time of the cursor as a decimal string, or `None` if there is no cursor
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub async fn get_cursor_time() -> Option<String> {
+    perform_query(Box::new(move |state| {
+        state
+            .user
+            .waves
+            .as_ref()?
+            .cursor
+            .as_ref()
+            .map(BigInt::to_string)
+    }))
+    .await
+}
+
+/// Moves the cursor to `time`, which is a decimal string
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub fn set_cursor_time(time: String) {
+    match time.parse::<BigInt>() {
+        Ok(time) => {
+            block_on(MESSAGE_QUEUE.lock()).push(Message::CursorSet(time));
+            try_repaint()
+        }
+        Err(e) => warn!("Failed to parse cursor time {time}: {e}"),
+    }
+}
+
+/// Zooms the main viewport to show the times from `start` to `end`. Ranges where `end` is
+/// not after `start` are ignored.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub fn set_viewport(start: u64, end: u64) {
+    if end <= start {
+        warn!("Ignoring viewport with end {end} not after start {start}");
+        return;
+    }
+    block_on(MESSAGE_QUEUE.lock()).push(Message::ZoomToRange {
+        start: BigInt::from(start),
+        end: BigInt::from(end),
+        viewport_idx: 0,
+    });
+    try_repaint()
+}
+
+/// Zooms the main viewport to show the times from `start` to `end`, see [set_viewport]
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub fn zoom_to_range(start: u64, end: u64) {
+    set_viewport(start, end)
+}
+
+/// Zooms the main viewport out to show the whole waveform
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub fn zoom_fit() {
+    block_on(MESSAGE_QUEUE.lock()).push(Message::ZoomToFit { viewport_idx: 0 });
+    try_repaint()
+}
+
+/// Zooms the main viewport to the span from the marker named `from` to the marker named
+/// `to`. Nothing happens if either marker does not exist or `to` is not after `from`.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub async fn zoom_to_markers(from: String, to: String) {
+    let span = perform_query(Box::new(move |state| {
+        Some(state.user.waves.as_ref()?.marker_span(&from, &to))
+    }))
+    .await;
+    match span {
+        Some(Ok((start, end))) => {
+            MESSAGE_QUEUE.lock().await.push(Message::ZoomToRange {
+                start,
+                end,
+                viewport_idx: 0,
+            });
+            try_repaint()
+        }
+        Some(Err(e)) => warn!("Not zooming to markers: {e}"),
+        None => warn!("Not zooming to markers: no waves are loaded"),
+    }
+}
+
+/// Returns the times at the left and right edges of the main viewport as a JSON encoded
+/// `[start, end]` pair of decimal strings, or `None` if no waves are loaded
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub async fn get_viewport() -> Option<String> {
+    perform_query(Box::new(move |state| {
+        let (start, end) = state.user.waves.as_ref()?.viewport_range(0)?;
+        serde_json::to_string(&(start.to_string(), end.to_string())).ok()
+    }))
+    .await
+}
+
+/// Adds a marker labeled `name` at `time`
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub fn add_marker(name: String, time: u64) {
+    block_on(MESSAGE_QUEUE.lock()).push(Message::AddMarker {
+        time: BigInt::from(time),
+        name: Some(name),
+        move_focus: false,
+    });
+    try_repaint()
+}
+
+/// Returns the markers as a JSON encoded list of `[name, time]` pairs ordered by time, with
+/// the times as decimal strings. Returns `None` if no waves are loaded.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub async fn marker_times() -> Option<String> {
+    perform_query(Box::new(move |state| {
+        let markers = state
+            .user
+            .waves
+            .as_ref()?
+            .marker_times()
+            .into_iter()
+            .map(|(name, time)| (name, time.to_string()))
+            .collect::<Vec<_>>();
+        serde_json::to_string(&markers).ok()
+    }))
+    .await
+}
+
+/// Returns the id of the topmost graphic at the screen position (`x`, `y`) in the last
+/// drawn frame, or `None` if there is no graphic there
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub async fn graphic_at(x: f32, y: f32) -> Option<usize> {
+    perform_query(Box::new(move |state| {
+        state.graphic_at(Pos2::new(x, y)).map(|id| id.0)
+    }))
+    .await
+}
+
+/// Removes the graphic with `id`. Removing a graphic which does not exist does nothing.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub async fn remove_graphic(id: usize) {
+    MESSAGE_QUEUE
+        .lock()
+        .await
+        .push(Message::RemoveGraphic(GraphicId(id)));
+    try_repaint()
+}
+
+/// Removes all graphics
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub async fn clear_graphics() {
+    MESSAGE_QUEUE.lock().await.push(Message::ClearGraphics);
+    try_repaint()
+}
+
+/// Returns the ids of all graphics in increasing order
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub async fn graphic_ids() -> Vec<usize> {
+    perform_query(Box::new(move |state| {
+        Some(state.user.waves.as_ref()?.graphic_ids())
+    }))
+    .await
+    .unwrap_or_default()
+}
+
+/// Returns a JSON encoded list of the number of transitions of the displayed variable
+/// `name` in each of `bins` equally wide time bins spanning the whole trace, or `None` if
+/// the variable is not displayed.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub async fn activity_histogram(name: String, bins: usize) -> Option<String> {
+    perform_query(Box::new(move |state| {
+        let waves = state.user.waves.as_ref()?;
+        let histogram = waves.activity_histogram(&waves.displayed_variable_ref(&name)?, bins)?;
+        serde_json::to_string(&histogram).ok()
+    }))
+    .await
+}
+
+/// Returns a JSON encoded `{kind, detail}` describing where the currently loaded waves
+/// come from, or `None` if no waves are loaded.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub async fn source_info() -> Option<String> {
+    perform_query(Box::new(move |state| {
+        let waves = state.user.waves.as_ref()?;
+        serde_json::to_string(&waves.source.source_info()).ok()
+    }))
+    .await
+}
+
+/// Sets the height of the displayed variable `name` to `rows` times the normal row height.
+/// The height is clamped to between half and 16 rows.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub async fn set_item_height(name: String, rows: f32) {
+    let vidx = perform_query(Box::new(move |state| {
+        state.user.waves.as_ref()?.visible_index_of_variable(&name)
+    }))
+    .await;
+
+    if let Some(vidx) = vidx {
+        MESSAGE_QUEUE
+            .lock()
+            .await
+            .push(Message::ItemHeightScalingFactorChange(Some(vidx), rows));
+        try_repaint()
+    }
+}
+
+/// The maximum number of samples written by `export_csv`
+const CSV_EXPORT_MAX_ROWS: usize = 100_000;
+
+/// Samples the displayed variables every `step` timestamps between `start` and `end` and
+/// returns them as CSV, or `None` if no waves are loaded.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub async fn export_csv(start: String, end: String, step: String) -> Option<String> {
+    perform_query(Box::new(move |state| {
+        let start = start.parse::<BigUint>().ok()?;
+        let end = end.parse::<BigUint>().ok()?;
+        let step = step.parse::<BigUint>().ok()?;
+        state
+            .user
+            .waves
+            .as_ref()?
+            .export_csv(&start, &end, &step, CSV_EXPORT_MAX_ROWS)
+    }))
+    .await
+}
+
+/// Enables or disables drawing the displayed variable `name` as an analog line
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub async fn set_item_analog(name: String, enabled: bool) {
+    let vidx = perform_query(Box::new(move |state| {
+        state.user.waves.as_ref()?.visible_index_of_variable(&name)
+    }))
+    .await;
+
+    if let Some(vidx) = vidx {
+        let render_mode = if enabled {
+            VariableRenderMode::Analog
+        } else {
+            VariableRenderMode::Default
+        };
+        MESSAGE_QUEUE
+            .lock()
+            .await
+            .push(Message::ItemRenderModeChange(Some(vidx), render_mode));
+        try_repaint()
+    }
+}
+
+/// Returns the values of the enum type of the displayed variable `name` as a JSON encoded
+/// list of `{value, label}`, or `None` if the variable is not an enum.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub async fn enum_values(name: String) -> Option<String> {
+    perform_query(Box::new(move |state| {
+        let waves = state.user.waves.as_ref()?;
+        let values = waves.enum_values(&waves.displayed_variable_ref(&name)?)?;
+        serde_json::to_string(&values).ok()
+    }))
+    .await
+}
+
+/// Sets the full path of the displayed variable holding the program counter used by
+/// `instruction_at_pc`
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub async fn set_pc_signal(name: Option<String>) {
+    MESSAGE_QUEUE.lock().await.push(Message::SetPcSignal(name));
+    try_repaint()
+}
+
+/// Sets the full path of the displayed variable holding the instruction memory used by
+/// `instruction_at_pc`
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub async fn set_imem_signal(name: Option<String>) {
+    MESSAGE_QUEUE
+        .lock()
+        .await
+        .push(Message::SetImemSignal(name));
+    try_repaint()
+}
+
+/// Returns the gheith instruction pointed to by the program counter at the cursor, or
+/// `None` if the signals are not configured or the instruction is unknown.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub async fn instruction_at_pc() -> Option<String> {
+    perform_query(Box::new(move |state| {
+        state
+            .user
+            .waves
+            .as_ref()?
+            .instruction_at_pc(state.pc_signal.as_deref()?, state.imem_signal.as_deref()?)
+    }))
+    .await
+}
+
 #[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
 pub async fn start_cxxrtl() {
     MESSAGE_QUEUE
@@ -289,6 +1055,41 @@ pub async fn start_cxxrtl() {
         .push(Message::SetupCxxrtl(CxxrtlKind::Mailbox));
 }
 
+/// Connects to a cxxrtl simulation over a WebSocket at `url`, which starts with `ws://` or
+/// `wss://`
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub async fn start_cxxrtl_websocket(url: String) {
+    if let Some(message) = CxxrtlKind::setup_message(CxxrtlKind::websocket(&url)) {
+        MESSAGE_QUEUE.lock().await.push(message);
+        try_repaint()
+    }
+}
+
+/// Captures the next rendered frame, including the waveform canvas with its overlays,
+/// and returns it as PNG. Returns `None` if no waves are loaded or the frame could not be
+/// captured.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub async fn export_png() -> Option<Vec<u8>> {
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    perform_mutation(Box::new(move |state| {
+        state.user.waves.as_ref()?;
+        state.request_screenshot(tx);
+        Some(())
+    }))
+    .await?;
+    rx.await.ok()
+}
+
+/// Returns true if the waves come from a cxxrtl simulation which is still connected
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub async fn cxxrtl_connected() -> bool {
+    perform_query(Box::new(|state| {
+        state.user.waves.as_ref()?.inner.as_waves()?.is_connected()
+    }))
+    .await
+    .unwrap_or(false)
+}
+
 #[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
 pub async fn cxxrtl_cs_message() -> Option<String> {
     CXXRTL_CS_HANDLER.rx.write().await.recv().await
@@ -304,36 +1105,135 @@ pub async fn start_wcp() {
     MESSAGE_QUEUE.lock().await.push(Message::SetupChannelWCP);
 }
 
+/// Returns the next server to client message which is not the response to a [wcp_request]
 #[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
 pub async fn next_wcp_sc_message() -> Result<Option<String>, JsError> {
-    WCP_SC_HANDLER
-        .rx
-        .write()
-        .await
-        .recv()
-        .await
-        .map(|msg| serde_json::to_string(&msg))
-        .transpose()
+    let Some(message) = next_unsolicited_wcp_message().await else {
+        return Ok(None);
+    };
+    serde_json::to_string(&message)
+        .map(Some)
         .map_err(|e| JsError::new(&format!("{e}")))
 }
 
+/// Waits for the next server to client message which is not the response to a
+/// [wcp_request]. Returns `None` once the channel is closed.
+async fn next_unsolicited_wcp_message() -> Option<WcpSCMessage> {
+    loop {
+        let mut rx = WCP_SC_HANDLER.rx.write().await;
+        // A request may have received an event while we were waiting for the channel
+        if let Some(message) = WCP_UNSOLICITED.lock().await.pop_front() {
+            return Some(message);
+        }
+        let message = rx.recv().await?;
+        if let Some(message) = WCP_REQUESTS.lock().await.route(message) {
+            return Some(message);
+        }
+    }
+}
+
+/// Calls `callback` with every server to client message which is not the response to a
+/// [wcp_request], as a JSON string. This replaces polling [next_wcp_sc_message], which
+/// should not be used at the same time. Exceptions thrown by `callback` are logged.
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+pub fn subscribe_wcp_sc_messages(callback: js_sys::Function) {
+    wasm_bindgen_futures::spawn_local(deliver_wcp_messages(
+        next_unsolicited_wcp_message,
+        move |message| {
+            callback
+                .call1(&JsValue::NULL, &JsValue::from_str(&message))
+                .map(|_| ())
+                .map_err(|e| format!("{e:?}"))
+        },
+    ));
+}
+
 // TODO: Unify the names with cxxrtl here
 #[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
 pub async fn handle_wcp_cs_message(message: String) -> Result<(), JsError> {
-    let encoded = serde_json::from_str(&message).map_err(|e| JsError::new(&format!("{e}")))?;
+    let encoded: WcpTagged<WcpCSMessage> =
+        serde_json::from_str(&message).map_err(|e| JsError::new(&format!("{e}")))?;
+    if encoded.id.is_some_and(WcpRequestRouter::is_router_id) {
+        return Err(JsError::new(&format!(
+            "WCP ids from {} upward are reserved for wcp_request",
+            WcpRequestRouter::FIRST_ID
+        )));
+    }
     WCP_CS_HANDLER.tx.send(encoded).await?;
     Ok(())
 }
 
+/// Sends a WCP message to Surfer and waits for the response to it. Unlike
+/// [handle_wcp_cs_message], several requests can be awaited concurrently. Events received
+/// while waiting are left for [next_wcp_sc_message].
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub async fn wcp_request(message: String) -> Result<String, JsError> {
+    let message: WcpCSMessage =
+        serde_json::from_str(&message).map_err(|e| JsError::new(&format!("{e}")))?;
+    let (id, mut response) = WCP_REQUESTS.lock().await.register();
+    WCP_CS_HANDLER
+        .tx
+        .send(WcpTagged {
+            id: Some(id),
+            message,
+        })
+        .await?;
+    try_repaint();
+
+    let response = loop {
+        // Another caller may route our response while we wait for the channel,
+        // so keep listening for it throughout
+        let mut rx = tokio::select! {
+            response = &mut response => break response,
+            rx = WCP_SC_HANDLER.rx.write() => rx,
+        };
+        tokio::select! {
+            response = &mut response => break response,
+            message = rx.recv() => {
+                let Some(message) = message else {
+                    return Err(JsError::new("WCP channel was closed"));
+                };
+                if let Some(unsolicited) = WCP_REQUESTS.lock().await.route(message) {
+                    WCP_UNSOLICITED.lock().await.push_back(unsolicited);
+                }
+            }
+        }
+    };
+    let response = response.map_err(|_| JsError::new("WCP request was dropped"))?;
+    serde_json::to_string(&response).map_err(|e| JsError::new(&format!("{e}")))
+}
+
 impl SystemState {
     pub(crate) fn handle_wasm_external_messages(&mut self) {
-        while let Some(msg) = block_on(MESSAGE_QUEUE.lock()).pop() {
-            self.update(msg);
+        loop {
+            let msgs = std::mem::take(&mut *block_on(MESSAGE_QUEUE.lock()));
+            if msgs.is_empty() {
+                break;
+            }
+            for msg in coalesce_draw_invalidations(msgs) {
+                self.update(msg);
+            }
         }
 
+        QUERY_REPAINT.frame_started();
         while let Some(cb) = block_on(QUERY_QUEUE.lock()).pop_front() {
             (cb.function)(self);
             let _ = cb.executed.send(());
         }
+
+        // Every streaming query produces one chunk per frame, so that long queries do not
+        // stall the UI. Unfinished streams are kept for the next frame.
+        let streams = std::mem::take(&mut *block_on(QUERY_STREAMS.lock()));
+        let mut unfinished = VecDeque::new();
+        for mut stream in streams {
+            if stream.advance(self) {
+                unfinished.push_back(stream);
+            }
+        }
+        if !unfinished.is_empty() {
+            block_on(QUERY_STREAMS.lock()).extend(unfinished);
+            try_repaint();
+        }
     }
 }
@@ -5,6 +5,7 @@ use color_eyre::{eyre::bail, Result};
 use num::BigUint;
 use surfer_translation_types::VariableValue;
 
+use crate::cxxrtl::sc_message::AgentInfo;
 use crate::cxxrtl_container::CxxrtlContainer;
 use crate::message::BodyResult;
 use crate::time::{TimeScale, TimeUnit};
@@ -108,7 +109,7 @@ impl ScopeRefExt for ScopeRef {
     }
 
     fn cxxrtl_repr(&self) -> String {
-        self.strs.join(" ")
+        crate::cxxrtl::names::path_repr(&self.strs)
     }
 
     fn has_empty_strs(&self) -> bool {
@@ -181,7 +182,7 @@ impl VariableRefExt for VariableRef {
     }
 
     fn cxxrtl_repr(&self) -> String {
-        self.full_path().join(" ")
+        crate::cxxrtl::names::path_repr(&self.full_path())
     }
 }
 
@@ -238,6 +239,15 @@ impl WaveContainer {
         }
     }
 
+    /// Returns whether data requested from the simulator is still on its way
+    pub fn is_loading(&self) -> bool {
+        match self {
+            WaveContainer::Wellen(_) => false,
+            WaveContainer::Empty => false,
+            WaveContainer::Cxxrtl(c) => c.lock().unwrap().is_loading(),
+        }
+    }
+
     pub fn wants_anti_aliasing(&self) -> bool {
         match self {
             WaveContainer::Wellen(_) => true,
@@ -285,6 +295,16 @@ impl WaveContainer {
         }
     }
 
+    /// Returns the number of variables in `scope`, or `None` if it is not known without
+    /// listing the variables of the scope first
+    pub fn variable_count(&self, scope: &ScopeRef) -> Option<usize> {
+        match self {
+            WaveContainer::Wellen(f) => Some(f.variables_in_scope(scope).len()),
+            WaveContainer::Empty => Some(0),
+            WaveContainer::Cxxrtl(c) => c.lock().unwrap().variable_count(scope),
+        }
+    }
+
     /// Return all parameters in a scope.
     pub fn parameters_in_scope(&self, scope: &ScopeRef) -> Vec<VariableRef> {
         match self {
@@ -318,6 +338,14 @@ impl WaveContainer {
             }
         }
     }
+    /// Stops loading values of `variables` which are no longer displayed. Only has an effect
+    /// for cxxrtl, where loaded variables are queried as the simulation runs.
+    pub fn unload_variables<I: Iterator<Item = VariableRef>>(&mut self, variables: I) {
+        if let WaveContainer::Cxxrtl(c) = self {
+            c.get_mut().unwrap().unload_variables(variables);
+        }
+    }
+
     /// Load all the parameters in the design so that the value can be displayed.
     pub fn load_parameters(&mut self) -> Result<Option<LoadSignalsCmd>> {
         match self {
@@ -474,6 +502,41 @@ impl WaveContainer {
         }
     }
 
+    /// Returns the unit the backend suggests for displaying time, if it reports one.
+    /// Falls back to the unit of the timescale for cxxrtl backends which do not.
+    pub fn time_unit(&self) -> Option<TimeUnit> {
+        match self {
+            WaveContainer::Wellen(_) => None,
+            WaveContainer::Empty => None,
+            WaveContainer::Cxxrtl(c) => Some(
+                c.lock()
+                    .unwrap()
+                    .time_unit()
+                    .unwrap_or(TimeUnit::FemtoSeconds),
+            ),
+        }
+    }
+
+    /// Returns what the backend of a live simulation reported about itself, or `None` for
+    /// other waves and before the backend has greeted us
+    pub fn agent_info(&self) -> Option<AgentInfo> {
+        match self {
+            WaveContainer::Wellen(_) => None,
+            WaveContainer::Empty => None,
+            WaveContainer::Cxxrtl(c) => c.lock().unwrap().agent_info(),
+        }
+    }
+
+    /// Returns whether the backend of a live simulation is still connected, or `None` if
+    /// the waves are not backed by a connection
+    pub fn is_connected(&self) -> Option<bool> {
+        match self {
+            WaveContainer::Wellen(_) => None,
+            WaveContainer::Empty => None,
+            WaveContainer::Cxxrtl(c) => Some(c.lock().unwrap().is_connected()),
+        }
+    }
+
     /// If [`WaveContainer::simulation_status`] is `Some(SimulationStatus::Paused)`, attempt to unpause the
     /// simulation otherwise does nothing
     pub fn unpause_simulation(&self) {
@@ -493,6 +556,36 @@ impl WaveContainer {
         }
     }
 
+    /// Restarts the simulation from time zero if the container is connected to a simulator,
+    /// otherwise does nothing
+    pub fn reset_simulation(&self) {
+        match self {
+            WaveContainer::Wellen(_) => {}
+            WaveContainer::Empty => {}
+            WaveContainer::Cxxrtl(c) => c.lock().unwrap().reset(),
+        }
+    }
+
+    /// Returns true if `variable` is a memory which should be shown in a memory view
+    /// rather than as a waveform
+    pub fn variable_is_memory(&self, variable: &VariableRef) -> bool {
+        match self {
+            WaveContainer::Wellen(_) => false,
+            WaveContainer::Empty => false,
+            WaveContainer::Cxxrtl(c) => c.lock().unwrap().is_memory(variable),
+        }
+    }
+
+    /// How far a running simulation has progressed towards the time it runs until, between
+    /// 0 and 1. `None` if the simulation is not running or the source is not a simulation.
+    pub fn simulation_run_progress(&self) -> Option<f64> {
+        match self {
+            WaveContainer::Wellen(_) => None,
+            WaveContainer::Empty => None,
+            WaveContainer::Cxxrtl(c) => c.lock().unwrap().run_progress(),
+        }
+    }
+
     /// Called for `wellen` container, when the body of the waveform file has been parsed.
     pub fn wellen_add_body(&mut self, body: BodyResult) -> Result<Option<LoadSignalsCmd>> {
         match self {
@@ -3,7 +3,7 @@ use std::collections::HashMap;
 use color_eyre::eyre::{Result, WrapErr};
 use log::{error, info, warn};
 use num::bigint::ToBigInt as _;
-use num::{BigInt, BigUint, Zero};
+use num::{BigInt, BigUint, ToPrimitive, Zero};
 use serde::{Deserialize, Serialize};
 use surfer_translation_types::{TranslationPreference, Translator, VariableValue};
 
@@ -15,7 +15,9 @@ use crate::displayed_item::{
 use crate::displayed_item_tree::{DisplayedItemTree, ItemIndex, TargetPosition, VisibleItemIndex};
 use crate::graphics::{Graphic, GraphicId};
 use crate::transaction_container::{StreamScopeRef, TransactionRef, TransactionStreamRef};
-use crate::translation::{DynTranslator, TranslatorList, VariableInfoExt};
+use crate::translation::{
+    asm_gheith, DynTranslator, TranslatorList, VariableInfoExt, RAW_HEX_TRANSLATOR,
+};
 use crate::variable_name_type::VariableNameType;
 use crate::view::ItemDrawingInfo;
 use crate::viewport::Viewport;
@@ -45,6 +47,33 @@ impl std::fmt::Display for ScopeType {
     }
 }
 
+/// A value of an enum type and its name
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EnumValue {
+    pub value: String,
+    pub label: String,
+}
+
+impl EnumValue {
+    /// Converts an enum map into a list sorted by value. Returns `None` if the map is empty.
+    pub fn from_enum_map(enum_map: &HashMap<String, String>) -> Option<Vec<EnumValue>> {
+        if enum_map.is_empty() {
+            return None;
+        }
+        Some(
+            enum_map
+                .iter()
+                .map(|(value, label)| EnumValue {
+                    value: value.clone(),
+                    label: label.clone(),
+                })
+                // Values are bit strings, which only sort numerically if they are equally long
+                .sorted_by(|a, b| (a.value.len(), &a.value).cmp(&(b.value.len(), &b.value)))
+                .collect(),
+        )
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct WaveData {
     #[serde(skip, default = "DataContainer::__new_empty")]
@@ -114,9 +143,13 @@ fn select_preferred_translator(var: &VariableMeta, translators: &TranslatorList)
         }
     }
     // make sure we always pick the same translator, at least
-    preferred
-        .pop()
-        .unwrap_or_else(|| translators.default.clone())
+    preferred.pop().unwrap_or_else(|| {
+        if translators.is_valid_translator(var, &translators.default) {
+            translators.default.clone()
+        } else {
+            RAW_HEX_TRANSLATOR.to_string()
+        }
+    })
 }
 
 pub fn variable_translator<'a, F>(
@@ -128,10 +161,19 @@ pub fn variable_translator<'a, F>(
 where
     F: FnOnce() -> Result<VariableMeta>,
 {
-    let translator_name = translator
-        .cloned()
-        .or_else(|| {
-            Some(if field.is_empty() {
+    let translator_name = match translator {
+        // A translator which does not apply to the variable, for example one which was
+        // selected in a state file for a variable which has since changed width, would
+        // produce garbage, so fall back to showing the raw value
+        Some(name) if field.is_empty() => match meta() {
+            Ok(meta) if !translators.is_valid_translator(&meta, name) => {
+                return translators.fallback(&meta, name);
+            }
+            _ => name.clone(),
+        },
+        Some(name) => name.clone(),
+        None => {
+            if field.is_empty() {
                 meta()
                     .as_ref()
                     .map(|meta| select_preferred_translator(meta, translators).clone())
@@ -141,9 +183,9 @@ where
                     })
             } else {
                 translators.default.clone()
-            })
-        })
-        .unwrap();
+            }
+        }
+    };
 
     let translator = translators.get_translator(&translator_name);
     translator
@@ -431,6 +473,7 @@ impl WaveData {
                 format: None,
                 field_formats: vec![],
                 height_scaling_factor: None,
+                render_mode: Default::default(),
             });
 
             indices.push(self.insert_item(new_variable, Some(target_position), true));
@@ -460,11 +503,26 @@ impl WaveData {
             .and_then(|vidx| self.items_tree.get_visible(vidx))
             .map(|node| node.item_ref);
 
+        let mut removed_variables = vec![];
         for removed_ref in self.items_tree.remove_recursive(idx) {
-            if let Some(DisplayedItem::Marker(m)) = self.displayed_items.remove(&removed_ref) {
-                self.markers.remove(&m.idx);
+            match self.displayed_items.remove(&removed_ref) {
+                Some(DisplayedItem::Marker(m)) => {
+                    self.markers.remove(&m.idx);
+                }
+                Some(DisplayedItem::Variable(var)) => removed_variables.push(var.variable_ref),
+                _ => {}
             }
         }
+        // The same variable may still be displayed by another item
+        removed_variables.retain(|var| {
+            !self
+                .displayed_items
+                .values()
+                .any(|item| matches!(item, DisplayedItem::Variable(v) if &v.variable_ref == var))
+        });
+        if let Some(waves) = self.inner.as_waves_mut() {
+            waves.unload_variables(removed_variables.into_iter());
+        }
 
         self.focused_item = focused_item_ref.and_then(|focused_item_ref| {
             match self
@@ -948,6 +1006,307 @@ impl WaveData {
                 }
             })
     }
+
+    /// Returns the reference of the displayed variable whose full path is `name`
+    pub fn displayed_variable_ref(&self, name: &str) -> Option<VariableRef> {
+        self.displayed_items.values().find_map(|item| match item {
+            DisplayedItem::Variable(var) if var.variable_ref.full_path_string() == name => {
+                Some(var.variable_ref.clone())
+            }
+            _ => None,
+        })
+    }
+
+    /// Decodes the gheith instruction which the displayed variable `pc` points to in the
+    /// displayed instruction memory `imem` at the cursor. The memory is a vector of 16 bit
+    /// words, with the word at address 0 in the least significant bits.
+    pub fn instruction_at_pc(&self, pc: &str, imem: &str) -> Option<String> {
+        let waves = self.inner.as_waves()?;
+        let time = self.cursor.as_ref()?.to_biguint()?;
+        let value_at = |var: &VariableRef| match waves.query_variable(var, &time).ok()??.current?.1
+        {
+            VariableValue::BigUint(v) => Some(v),
+            VariableValue::String(_) => None,
+        };
+
+        let pc = value_at(&self.displayed_variable_ref(pc)?)?.to_u64()?;
+        let imem_ref = self.displayed_variable_ref(imem)?;
+        let imem_bits = waves.variable_meta(&imem_ref).ok()?.num_bits?;
+        let imem = value_at(&imem_ref)?;
+        let words = (0..imem_bits / 16)
+            .map(|idx| ((&imem >> (idx * 16)) & BigUint::from(0xffffu32)).to_u16())
+            .collect::<Option<Vec<_>>>()?;
+
+        asm_gheith::instruction_at(&words, pc)
+    }
+
+    /// Returns the displayed item named `name`. Variables are named by their full path,
+    /// other items by their display name. If several items share the name, the first one
+    /// in drawing order is returned.
+    pub fn item_ref_of_name(&self, name: &str) -> Option<DisplayedItemRef> {
+        self.items_named(name).next().map(|(_, item_ref)| item_ref)
+    }
+
+    /// Returns all displayed items named `name` in drawing order. See
+    /// [`WaveData::item_ref_of_name`].
+    pub fn item_refs_of_name(&self, name: &str) -> Vec<DisplayedItemRef> {
+        self.items_named(name)
+            .map(|(_, item_ref)| item_ref)
+            .collect()
+    }
+
+    /// Returns the index in the item tree of the `occurrence`th item named `name`, counting
+    /// from 0 in drawing order
+    pub fn item_index_of_name(&self, name: &str, occurrence: usize) -> Option<usize> {
+        self.items_named(name).nth(occurrence).map(|(idx, _)| idx)
+    }
+
+    /// Returns the tree index and reference of each item named `name`, in drawing order
+    fn items_named<'a>(
+        &'a self,
+        name: &'a str,
+    ) -> impl Iterator<Item = (usize, DisplayedItemRef)> + 'a {
+        self.items_tree
+            .iter()
+            .enumerate()
+            .filter(move |(_, node)| {
+                self.displayed_items
+                    .get(&node.item_ref)
+                    .is_some_and(|item| {
+                        let item_name = match item {
+                            DisplayedItem::Variable(var) => var.variable_ref.full_path_string(),
+                            _ => item.name().to_string(),
+                        };
+                        item_name == name
+                    })
+            })
+            .map(|(idx, node)| (idx, node.item_ref))
+    }
+
+    /// Returns the name of the translator which formats the displayed variable `name`, or
+    /// `None` if no such variable is displayed
+    pub fn item_translator_name(&self, name: &str, translators: &TranslatorList) -> Option<String> {
+        let item_ref = self.item_ref_of_name(name)?;
+        let Some(DisplayedItem::Variable(_)) = self.displayed_items.get(&item_ref) else {
+            return None;
+        };
+        Some(
+            self.variable_translator(&item_ref.into(), translators)
+                .name(),
+        )
+    }
+
+    /// Returns the names of all displayed items in the order of the item tree, which is the
+    /// order they are drawn in except that items in folded groups are included. Indices
+    /// into this list are the indices [`Message::MoveItem`] moves items to. Variables are
+    /// named by their full path, other items by their display name.
+    ///
+    /// [`Message::MoveItem`]: crate::message::Message::MoveItem
+    pub fn displayed_item_names(&self) -> Vec<String> {
+        self.items_tree
+            .iter()
+            .filter_map(|node| self.displayed_items.get(&node.item_ref))
+            .map(|item| match item {
+                DisplayedItem::Variable(var) => var.variable_ref.full_path_string(),
+                _ => item.name(),
+            })
+            .collect()
+    }
+
+    /// Returns the times at the left and right edges of the viewport with index
+    /// `viewport_idx`
+    pub fn viewport_range(&self, viewport_idx: usize) -> Option<(BigInt, BigInt)> {
+        let num_timestamps = self.num_timestamps()?;
+        let viewport = self.viewports.get(viewport_idx)?;
+        Some((
+            viewport.left_edge_time(&num_timestamps),
+            viewport.right_edge_time(&num_timestamps),
+        ))
+    }
+
+    /// Returns the ids of all graphics in increasing order
+    pub fn graphic_ids(&self) -> Vec<usize> {
+        self.graphics.keys().map(|id| id.0).sorted().collect()
+    }
+
+    /// Serializes all graphics in increasing order of id as a JSON array of `AddGraphic`
+    /// messages, which recreate them when injected as a message batch
+    pub fn graphics_json(&self) -> serde_json::Result<String> {
+        #[derive(Serialize)]
+        enum GraphicMessage<'a> {
+            AddGraphic(&'a GraphicId, &'a Graphic),
+        }
+        let messages = self
+            .graphics
+            .iter()
+            .sorted_by_key(|(id, _)| **id)
+            .map(|(id, graphic)| GraphicMessage::AddGraphic(id, graphic))
+            .collect::<Vec<_>>();
+        serde_json::to_string(&messages)
+    }
+
+    /// Returns the visible index of the displayed variable with the full path `name`
+    pub fn visible_index_of_variable(&self, name: &str) -> Option<VisibleItemIndex> {
+        self.items_tree.iter_visible_extra().find_map(|info| {
+            match self.displayed_items.get(&info.node.item_ref)? {
+                DisplayedItem::Variable(var) if var.variable_ref.full_path_string() == name => {
+                    Some(info.vidx)
+                }
+                _ => None,
+            }
+        })
+    }
+
+    /// Returns the value of `variable` at `start` followed by each change of value up to
+    /// and including `end`. Returns `None` if the variable has not been loaded.
+    pub fn variable_transitions(
+        &self,
+        variable: &VariableRef,
+        start: &BigUint,
+        end: &BigUint,
+    ) -> Option<Vec<(BigUint, VariableValue)>> {
+        let waves = self.inner.as_waves()?;
+        let mut result = vec![];
+        let mut time = start.clone();
+        loop {
+            let query = waves.query_variable(variable, &time).ok()??;
+            if let Some((_, value)) = query.current {
+                result.push((time.clone(), value));
+            }
+            match query.next {
+                Some(next) if next > time && next <= *end => time = next,
+                _ => break,
+            }
+        }
+        Some(result)
+    }
+
+    /// Counts the transitions of `variable` in each of `bins` equally wide bins spanning
+    /// `[0, max_timestamp]`. The initial value does not count as a transition. Returns `None`
+    /// if the variable has not been loaded or `bins` is zero.
+    pub fn activity_histogram(&self, variable: &VariableRef, bins: usize) -> Option<Vec<u64>> {
+        if bins == 0 {
+            return None;
+        }
+        let max_time = self.inner.as_waves()?.max_timestamp()?;
+        let transitions = self.variable_transitions(variable, &BigUint::zero(), &max_time)?;
+
+        let mut histogram = vec![0; bins];
+        for (time, _) in transitions.iter().skip(1) {
+            let bin = if max_time.is_zero() {
+                0
+            } else {
+                (time * bins / &max_time).to_usize().unwrap_or(bins)
+            };
+            histogram[bin.min(bins - 1)] += 1;
+        }
+        Some(histogram)
+    }
+
+    /// Compares the values of `a` and `b` between `start` and `end`, returning the first
+    /// time at which they differ, or `Some(None)` if they are equal during the whole
+    /// window. Returns `None` if either variable has not been loaded.
+    pub fn first_value_difference(
+        &self,
+        a: &VariableRef,
+        b: &VariableRef,
+        start: &BigUint,
+        end: &BigUint,
+    ) -> Option<Option<BigUint>> {
+        let a = self.variable_transitions(a, start, end)?;
+        let b = self.variable_transitions(b, start, end)?;
+
+        // The transitions are sorted by time, so the value at a time is found by bisection
+        fn value_at<'a>(
+            transitions: &'a [(BigUint, VariableValue)],
+            time: &BigUint,
+        ) -> Option<&'a VariableValue> {
+            let after = transitions.partition_point(|(t, _)| t <= time);
+            after.checked_sub(1).map(|idx| &transitions[idx].1)
+        }
+
+        Some(
+            a.iter()
+                .chain(b.iter())
+                .map(|(t, _)| t)
+                .sorted()
+                .dedup()
+                .find(|time| value_at(&a, time) != value_at(&b, time))
+                .cloned(),
+        )
+    }
+
+    /// Returns the values of the enum type of `variable` and their names, or `None` if the
+    /// variable is not an enum
+    pub fn enum_values(&self, variable: &VariableRef) -> Option<Vec<EnumValue>> {
+        let meta = self.inner.as_waves()?.variable_meta(variable).ok()?;
+        EnumValue::from_enum_map(&meta.enum_map)
+    }
+
+    /// Samples the displayed variables every `step` from `start` to `end` and formats the
+    /// result as CSV, with one row per sample and one column per variable. Values are
+    /// written in the radix of the variable's format, defaulting to decimal. At most
+    /// `max_rows` samples are written, after which a final `# truncated` line is added.
+    /// Returns `None` if no waves are loaded or `step` is zero.
+    pub fn export_csv(
+        &self,
+        start: &BigUint,
+        end: &BigUint,
+        step: &BigUint,
+        max_rows: usize,
+    ) -> Option<String> {
+        let waves = self.inner.as_waves()?;
+        if step.is_zero() {
+            return None;
+        }
+
+        let variables = self
+            .items_tree
+            .iter_visible()
+            .filter_map(|node| match self.displayed_items.get(&node.item_ref)? {
+                DisplayedItem::Variable(var) => Some(var),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        let mut csv = std::iter::once("time".to_string())
+            .chain(variables.iter().map(|v| v.variable_ref.full_path_string()))
+            .join(",");
+        csv.push('\n');
+
+        let mut time = start.clone();
+        let mut rows = 0;
+        while &time <= end {
+            if rows == max_rows {
+                csv.push_str(&format!("# truncated after {max_rows} rows\n"));
+                break;
+            }
+            let values = variables.iter().map(|var| {
+                let value = waves
+                    .query_variable(&var.variable_ref, &time)
+                    .ok()
+                    .flatten()
+                    .and_then(|query| query.current)
+                    .map(|(_, value)| value);
+                match value {
+                    Some(VariableValue::BigUint(v)) => match var.format.as_deref() {
+                        Some("Hexadecimal") => format!("{v:x}"),
+                        Some("Octal") => format!("{v:o}"),
+                        Some("Binary") => format!("{v:b}"),
+                        _ => v.to_string(),
+                    },
+                    Some(VariableValue::String(s)) => s,
+                    None => String::new(),
+                }
+            });
+            csv.push_str(&std::iter::once(time.to_string()).chain(values).join(","));
+            csv.push('\n');
+
+            rows += 1;
+            time += step;
+        }
+        Some(csv)
+    }
 }
 
 fn calculate_rows_of_stream(
@@ -970,3 +1329,59 @@ fn calculate_rows_of_stream(
         last_times_on_row[curr_row] = (start_time, end_time);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use surfer_translation_types::VariableEncoding;
+
+    use super::*;
+    use crate::translation::all_translators;
+
+    fn meta_with_width(num_bits: u32) -> VariableMeta {
+        VariableMeta {
+            var: VariableRef::from_hierarchy_string("top.x"),
+            num_bits: Some(num_bits),
+            variable_type: None,
+            variable_type_name: None,
+            index: None,
+            direction: None,
+            enum_map: Default::default(),
+            encoding: VariableEncoding::BitVector,
+        }
+    }
+
+    #[test]
+    fn applicable_translator_is_used() {
+        let translators = all_translators();
+        let translator =
+            variable_translator(Some(&"Gheith Asm".to_string()), &[], &translators, || {
+                Ok(meta_with_width(16))
+            });
+        assert_eq!(translator.name(), "Gheith Asm");
+    }
+
+    #[test]
+    fn inapplicable_translator_falls_back_to_hex() {
+        let translators = all_translators();
+        let translator =
+            variable_translator(Some(&"Gheith Asm".to_string()), &[], &translators, || {
+                Ok(meta_with_width(5))
+            });
+        assert_eq!(translator.name(), RAW_HEX_TRANSLATOR);
+    }
+
+    #[test]
+    fn variables_no_translator_applies_to_are_shown_as_raw_hex() {
+        let translators = all_translators();
+        let meta = VariableMeta {
+            encoding: VariableEncoding::Real,
+            num_bits: None,
+            ..meta_with_width(0)
+        };
+        let translator =
+            variable_translator(Some(&"Hexadecimal".to_string()), &[], &translators, || {
+                Ok(meta.clone())
+            });
+        assert_eq!(translator.name(), RAW_HEX_TRANSLATOR);
+    }
+}
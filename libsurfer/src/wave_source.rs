@@ -7,7 +7,7 @@ use std::sync::mpsc::Sender;
 use std::sync::Arc;
 use std::sync::Mutex;
 
-use crate::cxxrtl_container::CxxrtlContainer;
+use crate::cxxrtl_container::{CxxrtlContainer, CxxrtlLoadKind};
 use crate::util::get_multi_extension;
 use crate::wasm_util::{perform_async_work, perform_work, sleep_ms};
 use camino::{Utf8Path, Utf8PathBuf};
@@ -27,16 +27,97 @@ use crate::wellen::{LoadSignalPayload, LoadSignalsCmd, LoadSignalsResult};
 use crate::{message::Message, SystemState};
 use surver::{Status, HTTP_SERVER_KEY, HTTP_SERVER_VALUE_SURFER, WELLEN_SURFER_DEFAULT_OPTIONS};
 
+/// How many times a lost tcp connection to cxxrtl is re-established before giving up
+const CXXRTL_RECONNECT_ATTEMPTS: u32 = 5;
+/// The delay before the first reconnect attempt, doubled for every further attempt
+const CXXRTL_RECONNECT_DELAY_MS: u64 = 500;
+
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
 pub enum CxxrtlKind {
-    Tcp { url: String },
+    Tcp {
+        url: String,
+    },
+    /// A WebSocket url, including the `ws://` or `wss://` scheme
+    WebSocket {
+        url: String,
+    },
     Mailbox,
+    /// A simulation binary which speaks the protocol over its stdin and stdout
+    Stdio {
+        binary: String,
+    },
 }
 impl std::fmt::Display for CxxrtlKind {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
             CxxrtlKind::Tcp { url } => write!(f, "cxxrtl+tcp://{url}"),
+            CxxrtlKind::WebSocket { url } => write!(f, "cxxrtl+{url}"),
             CxxrtlKind::Mailbox => write!(f, "cxxrtl mailbox"),
+            CxxrtlKind::Stdio { binary } => write!(f, "cxxrtl stdio ({binary})"),
+        }
+    }
+}
+
+impl CxxrtlKind {
+    /// A tcp connection to `addr`, which must be of the form `host:port`
+    pub fn tcp(addr: &str) -> Result<Self, String> {
+        let (host, port) = addr
+            .rsplit_once(':')
+            .ok_or_else(|| format!("Expected host:port, got '{addr}'"))?;
+        if host.is_empty() {
+            return Err(format!("Missing host in '{addr}'"));
+        }
+        match port.parse::<u16>() {
+            Ok(port) if port != 0 => Ok(CxxrtlKind::Tcp {
+                url: addr.to_string(),
+            }),
+            _ => Err(format!("Invalid port '{port}' in '{addr}'")),
+        }
+    }
+
+    /// A WebSocket connection to `url`, which must start with `ws://` or `wss://`
+    pub fn websocket(url: &str) -> Result<Self, String> {
+        let host = url
+            .strip_prefix("ws://")
+            .or_else(|| url.strip_prefix("wss://"))
+            .ok_or_else(|| format!("Expected a ws:// or wss:// url, got '{url}'"))?;
+        if host.is_empty() || host.starts_with('/') {
+            return Err(format!("Missing host in '{url}'"));
+        }
+        Ok(CxxrtlKind::WebSocket {
+            url: url.to_string(),
+        })
+    }
+
+    /// A simulation started by running `binary`
+    pub fn stdio(binary: &str) -> Result<Self, String> {
+        if binary.trim().is_empty() {
+            return Err("Missing simulation binary".to_string());
+        }
+        Ok(CxxrtlKind::Stdio {
+            binary: binary.to_string(),
+        })
+    }
+
+    /// How long to wait before reconnecting for the `attempt`th time after the connection
+    /// was lost. `None` once the attempts are used up, or if connections of this kind can
+    /// not be re-established, like simulations running over stdio which have exited.
+    pub fn reconnect_delay_ms(&self, attempt: u32) -> Option<u64> {
+        let reconnectable =
+            cfg!(not(target_arch = "wasm32")) && matches!(self, CxxrtlKind::Tcp { .. });
+        (reconnectable && attempt < CXXRTL_RECONNECT_ATTEMPTS)
+            .then(|| CXXRTL_RECONNECT_DELAY_MS << attempt)
+    }
+
+    /// Returns the message which connects to `kind`, or logs why `kind` is invalid and
+    /// returns `None`
+    pub fn setup_message(kind: Result<Self, String>) -> Option<Message> {
+        match kind {
+            Ok(kind) => Some(Message::SetupCxxrtl(kind)),
+            Err(e) => {
+                warn!("Not starting cxxrtl: {e}");
+                None
+            }
         }
     }
 }
@@ -52,6 +133,13 @@ pub enum WaveSource {
 
 pub const STATE_FILE_EXTENSION: &str = "surf.ron";
 
+/// A short description of a [`WaveSource`] for use by embedding applications
+#[derive(Debug, Serialize, PartialEq, Clone)]
+pub struct SourceInfo {
+    pub kind: &'static str,
+    pub detail: String,
+}
+
 impl WaveSource {
     pub fn as_file(&self) -> Option<&Utf8Path> {
         match self {
@@ -68,6 +156,23 @@ impl WaveSource {
         }
     }
 
+    pub fn source_info(&self) -> SourceInfo {
+        let (kind, detail) = match self {
+            WaveSource::File(path) => ("file", path.to_string()),
+            WaveSource::DragAndDrop(path) => (
+                "file",
+                path.as_ref().map(|p| p.to_string()).unwrap_or_default(),
+            ),
+            WaveSource::Data => ("data", String::new()),
+            WaveSource::Url(url) => ("url", url.clone()),
+            WaveSource::Cxxrtl(CxxrtlKind::Tcp { url }) => ("cxxrtl-tcp", url.clone()),
+            WaveSource::Cxxrtl(CxxrtlKind::WebSocket { url }) => ("cxxrtl-websocket", url.clone()),
+            WaveSource::Cxxrtl(CxxrtlKind::Mailbox) => ("cxxrtl-mailbox", String::new()),
+            WaveSource::Cxxrtl(CxxrtlKind::Stdio { binary }) => ("cxxrtl-stdio", binary.clone()),
+        };
+        SourceInfo { kind, detail }
+    }
+
     pub fn sibling_state_file(&self) -> Option<Utf8PathBuf> {
         let path = self.path()?;
         let directory = path.parent()?;
@@ -106,6 +211,21 @@ pub fn url_to_wavesource(url: &str) -> Option<WaveSource> {
             log::warn!("Loading waves from cxxrtl via tcp is unsupported in WASM builds.");
             None
         }
+    } else if url.starts_with("cxxrtl+ws://") || url.starts_with("cxxrtl+wss://") {
+        #[cfg(target_arch = "wasm32")]
+        {
+            info!("Wave source is cxxrtl websocket");
+            Some(WaveSource::Cxxrtl(CxxrtlKind::WebSocket {
+                url: url.replacen("cxxrtl+", "", 1),
+            }))
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            log::warn!(
+                "Loading waves from cxxrtl via websockets is only supported in WASM builds."
+            );
+            None
+        }
     } else {
         None
     }
@@ -129,7 +249,11 @@ impl Display for WaveSource {
             WaveSource::DragAndDrop(Some(filename)) => write!(f, "Dropped file ({filename})"),
             WaveSource::Url(url) => write!(f, "{url}"),
             WaveSource::Cxxrtl(CxxrtlKind::Tcp { url }) => write!(f, "cxxrtl+tcp://{url}"),
+            WaveSource::Cxxrtl(CxxrtlKind::WebSocket { url }) => write!(f, "cxxrtl+{url}"),
             WaveSource::Cxxrtl(CxxrtlKind::Mailbox) => write!(f, "cxxrtl mailbox"),
+            WaveSource::Cxxrtl(CxxrtlKind::Stdio { binary }) => {
+                write!(f, "cxxrtl stdio ({binary})")
+            }
         }
     }
 }
@@ -196,6 +320,7 @@ pub enum LoadProgressStatus {
     ReadingHeader(WaveSource),
     ReadingBody(WaveSource, u64, Arc<AtomicU64>),
     LoadingVariables(u64),
+    Cxxrtl(CxxrtlLoadKind),
 }
 
 macro_rules! spawn {
@@ -562,12 +687,30 @@ impl SystemState {
                     return;
                 }
                 #[cfg(not(target_arch = "wasm32"))]
+                CxxrtlKind::WebSocket { .. } => {
+                    error!("Cxxrtl websockets are only supported on wasm");
+                    return;
+                }
+                #[cfg(target_arch = "wasm32")]
+                CxxrtlKind::WebSocket { url } => {
+                    CxxrtlContainer::new_websocket(url, sender.clone()).await
+                }
+                #[cfg(not(target_arch = "wasm32"))]
                 CxxrtlKind::Mailbox => {
                     error!("CXXRTL mailboxes are only supported on wasm for now");
                     return;
                 }
                 #[cfg(target_arch = "wasm32")]
                 CxxrtlKind::Mailbox => CxxrtlContainer::new_wasm_mailbox(sender.clone()).await,
+                #[cfg(not(target_arch = "wasm32"))]
+                CxxrtlKind::Stdio { binary } => {
+                    CxxrtlContainer::new_stdio(binary, &[], &[], sender.clone()).await
+                }
+                #[cfg(target_arch = "wasm32")]
+                CxxrtlKind::Stdio { .. } => {
+                    error!("Cxxrtl simulations over stdio are not supported on wasm");
+                    return;
+                }
             };
 
             match container {
@@ -580,7 +723,7 @@ impl SystemState {
                         keep_unavailable: false,
                     },
                 )),
-                Err(e) => sender.send(Message::Error(e)),
+                Err(e) => sender.send(Message::Error(e.into())),
             }
             .unwrap()
         };
@@ -590,6 +733,48 @@ impl SystemState {
         wasm_bindgen_futures::spawn_local(task);
     }
 
+    /// Connects to `kind` again after the connection was lost, keeping the displayed
+    /// variables. Failed attempts are retried with an exponential backoff.
+    pub fn reconnect_to_cxxrtl(&mut self, kind: CxxrtlKind, attempt: u32) {
+        let Some(delay_ms) = kind.reconnect_delay_ms(attempt) else {
+            if attempt > 0 {
+                error!("Giving up reconnecting to {kind} after {attempt} attempts");
+            }
+            return;
+        };
+        info!("Reconnecting to {kind} in {delay_ms} ms");
+        let sender = self.channels.msg_sender.clone();
+
+        let task = async move {
+            sleep_ms(delay_ms).await;
+            let container = match &kind {
+                #[cfg(not(target_arch = "wasm32"))]
+                CxxrtlKind::Tcp { url } => CxxrtlContainer::new_tcp(url, sender.clone()).await,
+                _ => return,
+            };
+
+            let _ = match container {
+                Ok(c) => sender.send(Message::WavesLoaded(
+                    WaveSource::Cxxrtl(kind),
+                    WaveFormat::CxxRtl,
+                    Box::new(WaveContainer::Cxxrtl(Mutex::new(c))),
+                    LoadOptions {
+                        keep_variables: true,
+                        keep_unavailable: false,
+                    },
+                )),
+                Err(e) => {
+                    warn!("Failed to reconnect to {kind}: {e}");
+                    sender.send(Message::CxxrtlReconnect {
+                        kind,
+                        attempt: attempt + 1,
+                    })
+                }
+            };
+        };
+        spawn!(task);
+    }
+
     pub fn load_wave_from_bytes(
         &mut self,
         source: WaveSource,
@@ -934,6 +1119,10 @@ pub fn draw_progress_information(ui: &mut egui::Ui, progress_data: &LoadProgress
             ui.spinner();
             ui.monospace(format!("Loading {num} variables"));
         }
+        LoadProgressStatus::Cxxrtl(kind) => {
+            ui.spinner();
+            ui.monospace(format!("Waiting for {} from cxxrtl", kind.description()));
+        }
         LoadProgressStatus::ReadingBody(source, total, bytes_done) => {
             let num_bytes = bytes_done.load(std::sync::atomic::Ordering::SeqCst);
             let progress = num_bytes as f32 / *total as f32;
@@ -1,4 +1,6 @@
 pub mod proto;
 pub mod wcp_handler;
+pub mod wcp_router;
 #[cfg(not(target_arch = "wasm32"))]
 pub mod wcp_server;
+pub mod wcp_subscription;
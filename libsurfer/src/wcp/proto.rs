@@ -59,6 +59,60 @@ pub enum WcpResponse {
     ack,
 }
 
+/// A WCP message along with the id of the request it belongs to.
+/// Clients may attach an `id` to the messages they send, which is then copied onto the
+/// response so that several requests can be in flight at once. Unsolicited messages,
+/// like events, have no id.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct WcpTagged<M> {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    #[serde(flatten)]
+    pub message: M,
+}
+
+impl<M> From<M> for WcpTagged<M> {
+    fn from(message: M) -> Self {
+        Self { id: None, message }
+    }
+}
+
+/// WCP protocol versions supported by Surfer, oldest first
+pub const SUPPORTED_VERSIONS: &[usize] = &[0];
+
+/// Picks the newest version in [SUPPORTED_VERSIONS] that is also present in
+/// the comma separated list of versions `advertised` by the peer.
+pub fn negotiate_version(advertised: &str) -> Option<usize> {
+    advertised
+        .split(',')
+        .filter_map(|v| v.trim().parse::<usize>().ok())
+        .filter(|v| SUPPORTED_VERSIONS.contains(v))
+        .max()
+}
+
+/// The commands Surfer accepts when speaking the given protocol `version`
+pub fn supported_commands(version: usize) -> Vec<String> {
+    let commands: &[&str] = match version {
+        0 => &[
+            "add_variables",
+            "set_viewport_to",
+            "cursor_set",
+            "reload",
+            "add_scopes",
+            "get_item_list",
+            "set_item_color",
+            "get_item_info",
+            "clear_item",
+            "focus_item",
+            "clear",
+            "load",
+            "zoom_to_fit",
+        ],
+        _ => &[],
+    };
+    commands.iter().map(|s| s.to_string()).collect()
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 #[serde(tag = "event")]
 #[allow(non_camel_case_types)]
@@ -9,11 +9,14 @@ use crate::{
 
 use futures::executor::block_on;
 use itertools::Itertools;
-use log::{trace, warn};
+use log::{error, trace, warn};
 use std::sync::atomic::Ordering;
 use surfer_translation_types::ScopeRef;
 
-use super::proto::{ItemInfo, WcpCSMessage, WcpCommand, WcpResponse, WcpSCMessage};
+use super::proto::{
+    negotiate_version, supported_commands, ItemInfo, WcpCSMessage, WcpCommand, WcpResponse,
+    WcpSCMessage, WcpTagged, SUPPORTED_VERSIONS,
+};
 
 impl SystemState {
     pub fn handle_wcp_commands(&mut self) {
@@ -35,12 +38,15 @@ impl SystemState {
             }
         }
         for message in messages {
-            self.handle_wcp_cs_message(&message);
+            self.wcp_request_id = message.id;
+            self.handle_wcp_cs_message(&message.message);
         }
+        self.wcp_request_id = None;
     }
 
     fn handle_wcp_cs_message(&mut self, message: &WcpCSMessage) {
-        if !self.wcp_greeted_signal.load(Ordering::Relaxed) {
+        // A greeting which failed to agree on a version also counts as not greeted
+        if !self.wcp_greeted_signal.load(Ordering::Relaxed) || self.wcp_version.is_none() {
             match message {
                 WcpCSMessage::greeting { .. } => (),
                 _ => {
@@ -267,80 +273,72 @@ impl SystemState {
                 };
             }
             WcpCSMessage::greeting { version, commands } => {
-                if version != "0" {
+                let Some(negotiated) = negotiate_version(version) else {
+                    let supported = SUPPORTED_VERSIONS.iter().join(", ");
+                    // The greeting has been answered even though it failed, which `wcp_version`
+                    // being unset tells apart from a successful one
+                    self.wcp_version = None;
+                    self.wcp_greeted_signal.store(true, Ordering::Relaxed);
+                    error!(
+                        "WCP client requested unsupported protocol version(s) {version}, \
+                        Surfer supports {supported}"
+                    );
                     self.send_error(
                         "greeting",
                         vec![],
                         &format!(
-                            "Surfer only supports WCP version 0, client requested {}",
-                            version
+                            "Surfer only supports WCP version(s) {supported}, client requested {version}"
                         ),
-                    )
-                } else {
-                    self.wcp_client_capabilities = WcpClientCapabilities::new();
-                    if commands.iter().any(|s| s == "waveforms_loaded") {
-                        self.wcp_client_capabilities.waveforms_loaded = true;
-                    }
-                    if commands.iter().any(|s| s == "goto_declaration") {
-                        self.wcp_client_capabilities.goto_declaration = true;
-                    }
-                    if commands.iter().any(|s| s == "add_drivers") {
-                        self.wcp_client_capabilities.add_drivers = true;
-                    }
-                    if commands.iter().any(|s| s == "add_loads") {
-                        self.wcp_client_capabilities.add_loads = true;
-                    }
-                    self.wcp_greeted_signal.store(true, Ordering::Relaxed);
-                    self.wcp_greeted_signal.store(true, Ordering::Relaxed);
-                    self.send_greeting()
+                    );
+                    return;
+                };
+                self.wcp_version = Some(negotiated);
+                self.wcp_client_capabilities = WcpClientCapabilities::new();
+                if commands.iter().any(|s| s == "waveforms_loaded") {
+                    self.wcp_client_capabilities.waveforms_loaded = true;
+                }
+                if commands.iter().any(|s| s == "goto_declaration") {
+                    self.wcp_client_capabilities.goto_declaration = true;
+                }
+                if commands.iter().any(|s| s == "add_drivers") {
+                    self.wcp_client_capabilities.add_drivers = true;
+                }
+                if commands.iter().any(|s| s == "add_loads") {
+                    self.wcp_client_capabilities.add_loads = true;
                 }
+                self.wcp_greeted_signal.store(true, Ordering::Relaxed);
+                self.send_greeting(negotiated)
             }
         }
     }
 
-    fn send_greeting(&self) {
-        let commands = vec![
-            "add_variables",
-            "set_viewport_to",
-            "cursor_set",
-            "reload",
-            "add_scopes",
-            "get_item_list",
-            "set_item_color",
-            "get_item_info",
-            "clear_item",
-            "focus_item",
-            "clear",
-            "load",
-            "zoom_to_fit",
-        ]
-        .into_iter()
-        .map(str::to_string)
-        .collect_vec();
+    fn send_greeting(&self, version: usize) {
+        let greeting = WcpSCMessage::create_greeting(version, supported_commands(version));
+        self.send_reply(greeting);
+    }
 
-        let greeting = WcpSCMessage::create_greeting(0, commands);
+    fn send_response(&self, result: WcpResponse) {
+        self.send_reply(WcpSCMessage::response(result));
+    }
 
-        self.channels
-            .wcp_s2c_sender
-            .as_ref()
-            .map(|ch| block_on(ch.send(greeting)));
+    fn send_error(&self, error: &str, arguments: Vec<String>, message: &str) {
+        self.send_reply(WcpSCMessage::create_error(
+            error.to_string(),
+            arguments,
+            message.to_string(),
+        ));
     }
 
-    fn send_response(&self, result: WcpResponse) {
+    /// Sends `message` tagged with the id of the request currently being handled
+    fn send_reply(&self, message: WcpSCMessage) {
+        let reply = WcpTagged {
+            id: self.wcp_request_id,
+            message,
+        };
         self.channels
             .wcp_s2c_sender
             .as_ref()
-            .map(|ch| block_on(ch.send(WcpSCMessage::response(result))));
-    }
-
-    fn send_error(&self, error: &str, arguments: Vec<String>, message: &str) {
-        self.channels.wcp_s2c_sender.as_ref().map(|ch| {
-            block_on(ch.send(WcpSCMessage::create_error(
-                error.to_string(),
-                arguments,
-                message.to_string(),
-            )))
-        });
+            .map(|ch| block_on(ch.send(reply)));
     }
 
     fn get_displayed_items(&self, waves: &WaveData) -> Vec<DisplayedItemRef> {
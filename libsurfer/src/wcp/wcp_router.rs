@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+
+use tokio::sync::oneshot;
+
+use super::proto::{WcpSCMessage, WcpTagged};
+
+/// Matches server to client messages with the requests they respond to.
+///
+/// Every request registered with the router is given a fresh id which is attached to the
+/// outgoing message. Responses carrying that id are then delivered to the channel
+/// returned on registration.
+///
+/// Clients may attach their own ids to the messages they send directly. To keep those
+/// apart, the router only hands out ids from [`WcpRequestRouter::FIRST_ID`] upward.
+pub struct WcpRequestRouter {
+    next_id: u64,
+    pending: HashMap<u64, oneshot::Sender<WcpSCMessage>>,
+}
+
+impl Default for WcpRequestRouter {
+    fn default() -> Self {
+        Self {
+            next_id: Self::FIRST_ID,
+            pending: HashMap::new(),
+        }
+    }
+}
+
+impl WcpRequestRouter {
+    /// The first id handed out by the router. JavaScript numbers are only exact below
+    /// 2^53, so ids chosen by clients on the web never reach this range.
+    pub const FIRST_ID: u64 = 1 << 53;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether `id` is in the range of ids handed out by the router
+    pub fn is_router_id(id: u64) -> bool {
+        id >= Self::FIRST_ID
+    }
+
+    /// Allocates an id for a new request and returns it along with the channel on which
+    /// the response will be delivered
+    pub fn register(&mut self) -> (u64, oneshot::Receiver<WcpSCMessage>) {
+        let id = self.next_id;
+        self.next_id += 1;
+        let (tx, rx) = oneshot::channel();
+        self.pending.insert(id, tx);
+        (id, rx)
+    }
+
+    /// Delivers `message` to the request it responds to. Messages that do not belong to
+    /// a pending request, such as events, are returned to the caller.
+    pub fn route(&mut self, message: WcpTagged<WcpSCMessage>) -> Option<WcpSCMessage> {
+        let Some(sender) = message.id.and_then(|id| self.pending.remove(&id)) else {
+            return Some(message.message);
+        };
+        // The requester may have given up waiting, in which case the response is dropped
+        let _ = sender.send(message.message);
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::wcp::proto::{WcpEvent, WcpResponse};
+
+    #[test]
+    fn responses_are_routed_to_their_requests() {
+        let mut router = WcpRequestRouter::new();
+        let (first_id, mut first) = router.register();
+        let (second_id, mut second) = router.register();
+        assert_ne!(first_id, second_id);
+
+        let unrouted = router.route(WcpTagged {
+            id: Some(second_id),
+            message: WcpSCMessage::response(WcpResponse::ack),
+        });
+        assert_eq!(unrouted, None);
+        let unrouted = router.route(WcpTagged {
+            id: Some(first_id),
+            message: WcpSCMessage::response(WcpResponse::get_item_list { ids: vec![] }),
+        });
+        assert_eq!(unrouted, None);
+
+        assert_eq!(
+            first.try_recv().unwrap(),
+            WcpSCMessage::response(WcpResponse::get_item_list { ids: vec![] })
+        );
+        assert_eq!(
+            second.try_recv().unwrap(),
+            WcpSCMessage::response(WcpResponse::ack)
+        );
+    }
+
+    #[test]
+    fn client_ids_are_not_routed() {
+        let mut router = WcpRequestRouter::new();
+        let (id, mut pending) = router.register();
+        assert!(WcpRequestRouter::is_router_id(id));
+
+        for client_id in [0, 1, WcpRequestRouter::FIRST_ID - 1] {
+            assert!(!WcpRequestRouter::is_router_id(client_id));
+            let unrouted = router.route(WcpTagged {
+                id: Some(client_id),
+                message: WcpSCMessage::response(WcpResponse::ack),
+            });
+            assert_eq!(unrouted, Some(WcpSCMessage::response(WcpResponse::ack)));
+        }
+        assert!(pending.try_recv().is_err());
+    }
+
+    #[test]
+    fn unsolicited_messages_are_returned() {
+        let mut router = WcpRequestRouter::new();
+        let (id, mut pending) = router.register();
+
+        let event = WcpSCMessage::event(WcpEvent::waveforms_loaded {
+            source: "counter.vcd".to_string(),
+        });
+        assert_eq!(
+            router.route(event.into()).unwrap(),
+            WcpSCMessage::event(WcpEvent::waveforms_loaded {
+                source: "counter.vcd".to_string(),
+            })
+        );
+        let unknown = router.route(WcpTagged {
+            id: Some(id + 1),
+            message: WcpSCMessage::response(WcpResponse::ack),
+        });
+        assert_eq!(unknown, Some(WcpSCMessage::response(WcpResponse::ack)));
+        assert!(pending.try_recv().is_err());
+    }
+}
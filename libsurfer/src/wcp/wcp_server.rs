@@ -21,7 +21,7 @@ use tokio::sync::mpsc::Sender;
 use tokio_stream::wrappers::ReceiverStream;
 use tokio_stream::StreamExt;
 
-use super::{proto::WcpCSMessage, proto::WcpCommand, proto::WcpSCMessage};
+use super::{proto::WcpCSMessage, proto::WcpCommand, proto::WcpSCMessage, proto::WcpTagged};
 
 struct WcpCSReader<'a> {
     reader: BufReader<ReadHalf<'a>>,
@@ -36,7 +36,7 @@ impl<'a> WcpCSReader<'a> {
         }
     }
 
-    pub async fn read_frame(&mut self) -> Result<Option<WcpCSMessage>, serde_Error> {
+    pub async fn read_frame(&mut self) -> Result<Option<WcpTagged<WcpCSMessage>>, serde_Error> {
         loop {
             if let Some(frame) = self.try_decode_frame()? {
                 return Ok(Some(frame));
@@ -55,12 +55,12 @@ impl<'a> WcpCSReader<'a> {
         }
     }
 
-    fn try_decode_frame(&mut self) -> Result<Option<WcpCSMessage>, serde_Error> {
+    fn try_decode_frame(&mut self) -> Result<Option<WcpTagged<WcpCSMessage>>, serde_Error> {
         match self.buffer.iter().position(|&x| x == 0) {
             Some(position) => {
                 let frame_data = self.buffer.split_to(position);
                 self.buffer.advance(1);
-                let msg: Result<WcpCSMessage, _> = serde_json::from_slice(&frame_data);
+                let msg: Result<WcpTagged<WcpCSMessage>, _> = serde_json::from_slice(&frame_data);
                 match msg {
                     Ok(msg) => Ok(Some(msg)),
                     Err(e) => Err(e),
@@ -75,10 +75,10 @@ pub struct WcpServer {
     listener: Option<TcpListener>,
     stream: Option<TcpStream>,
     #[cfg(target_arch = "wasm32")]
-    sender: IngressSender<WcpCSMessage>,
+    sender: IngressSender<WcpTagged<WcpCSMessage>>,
     #[cfg(not(target_arch = "wasm32"))]
-    sender: Sender<WcpCSMessage>,
-    receiver: ReceiverStream<WcpSCMessage>,
+    sender: Sender<WcpTagged<WcpCSMessage>>,
+    receiver: ReceiverStream<WcpTagged<WcpSCMessage>>,
     stop_signal: Arc<AtomicBool>,
     running_signal: Arc<AtomicBool>,
     greeted_signal: Arc<AtomicBool>,
@@ -90,9 +90,9 @@ impl WcpServer {
     pub async fn new(
         address: String,
         initiate: bool,
-        #[cfg(target_arch = "wasm32")] c2s_sender: IngressSender<WcpCSMessage>,
-        #[cfg(not(target_arch = "wasm32"))] c2s_sender: Sender<WcpCSMessage>,
-        s2c_receiver: Receiver<WcpSCMessage>,
+        #[cfg(target_arch = "wasm32")] c2s_sender: IngressSender<WcpTagged<WcpCSMessage>>,
+        #[cfg(not(target_arch = "wasm32"))] c2s_sender: Sender<WcpTagged<WcpCSMessage>>,
+        s2c_receiver: Receiver<WcpTagged<WcpSCMessage>>,
         stop_signal: Arc<AtomicBool>,
         running_signal: Arc<AtomicBool>,
         greeted_signal: Arc<AtomicBool>,
@@ -225,7 +225,7 @@ impl WcpServer {
                         None => continue,
                     };
 
-                    if let WcpCSMessage::command(WcpCommand::shutdowmn) = msg {
+                    if let WcpCSMessage::command(WcpCommand::shutdowmn) = msg.message {
                         return Ok(());
                     }
 
@@ -0,0 +1,81 @@
+use std::future::Future;
+
+use log::error;
+
+use super::proto::WcpSCMessage;
+
+/// Passes every message returned by `next` to `callback` as JSON, until `next` returns
+/// `None`. This lets an embedding page be notified of messages instead of polling for them.
+///
+/// A failing callback is logged and does not end the subscription.
+pub async fn deliver_wcp_messages<N, F, C>(mut next: N, mut callback: C)
+where
+    N: FnMut() -> F,
+    F: Future<Output = Option<WcpSCMessage>>,
+    C: FnMut(String) -> Result<(), String>,
+{
+    while let Some(message) = next().await {
+        let encoded = match serde_json::to_string(&message) {
+            Ok(encoded) => encoded,
+            Err(e) => {
+                error!("Failed to encode WCP message {message:?}: {e}");
+                continue;
+            }
+        };
+        if let Err(e) = callback(encoded) {
+            error!("WCP message callback failed: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::VecDeque;
+
+    use futures::{executor::block_on, future::ready};
+
+    use super::*;
+    use crate::wcp::proto::{WcpEvent, WcpResponse};
+
+    fn deliver_all(
+        messages: Vec<WcpSCMessage>,
+        mut callback: impl FnMut(String) -> Result<(), String>,
+    ) -> Vec<String> {
+        let mut queue = VecDeque::from(messages);
+        let mut delivered = vec![];
+        block_on(deliver_wcp_messages(
+            || ready(queue.pop_front()),
+            |message| {
+                delivered.push(message.clone());
+                callback(message)
+            },
+        ));
+        delivered
+    }
+
+    #[test]
+    fn queued_messages_are_delivered_as_json() {
+        let event = WcpSCMessage::event(WcpEvent::waveforms_loaded {
+            source: "counter.vcd".to_string(),
+        });
+        let expected = serde_json::to_string(&event).unwrap();
+        assert_eq!(deliver_all(vec![event], |_| Ok(())), vec![expected]);
+    }
+
+    #[test]
+    fn failing_callbacks_do_not_end_the_subscription() {
+        let messages = vec![
+            WcpSCMessage::response(WcpResponse::ack),
+            WcpSCMessage::event(WcpEvent::add_loads {
+                variable: "top.a".to_string(),
+            }),
+        ];
+        let mut calls = 0;
+        let delivered = deliver_all(messages, |_| {
+            calls += 1;
+            Err("callback threw".to_string())
+        });
+        assert_eq!(calls, 2);
+        assert_eq!(delivered.len(), 2);
+    }
+}